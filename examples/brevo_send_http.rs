@@ -1,11 +1,19 @@
 use letter::configuration::get_configuration;
 use letter::domain::Person;
 use letter::email::Brevo;
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
 
 #[tokio::main]
 async fn main() {
     let config = get_configuration().expect("Failed to read configuration.");
-    let brevo = Brevo::from(config.email.unwrap());
+    let pool = PgPool::connect_lazy(config.database.connection_string().expose_secret())
+        .expect("Failed to connect to Postgres.");
+    let brevo = Brevo::from_settings(
+        config.email.unwrap(),
+        pool,
+        config.application.email_hash_key.unwrap(),
+    );
 
     let time = chrono::Local::now().format("%Y-%m-%d %H:%M").to_string();
     let recipient = Person::parse("Yuki".to_string(), "yuki07yuki@gmail.com".to_string()).unwrap();
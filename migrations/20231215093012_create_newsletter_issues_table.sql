@@ -0,0 +1,12 @@
+-- Published issues are kept so they can be served from the public archive
+CREATE TABLE newsletter_issues(
+   newsletter_issue_id uuid NOT NULL,
+   PRIMARY KEY (newsletter_issue_id),
+   slug TEXT NOT NULL UNIQUE,
+   title TEXT NOT NULL,
+   html_content TEXT NOT NULL,
+   is_public BOOLEAN NOT NULL DEFAULT TRUE,
+   published_at timestamptz NOT NULL
+);
+
+CREATE INDEX newsletter_issues_published_at_idx ON newsletter_issues (published_at DESC);
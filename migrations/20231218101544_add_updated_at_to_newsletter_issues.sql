@@ -0,0 +1,6 @@
+-- Feeds report when an issue last changed, not only when it was published
+BEGIN;
+    ALTER TABLE newsletter_issues ADD COLUMN updated_at timestamptz NULL;
+    UPDATE newsletter_issues SET updated_at = published_at;
+    ALTER TABLE newsletter_issues ALTER COLUMN updated_at SET NOT NULL;
+COMMIT;
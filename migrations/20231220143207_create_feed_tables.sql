@@ -0,0 +1,16 @@
+-- Feeds mailed out by the feed poller, and every entry it has already seen
+CREATE TABLE feed_sources(
+   feed_url TEXT NOT NULL,
+   PRIMARY KEY (feed_url),
+   first_polled_at timestamptz NOT NULL
+);
+
+CREATE TABLE feed_entries(
+   feed_url TEXT NOT NULL
+      REFERENCES feed_sources (feed_url),
+   entry_id TEXT NOT NULL,
+   seen_at timestamptz NOT NULL,
+   newsletter_issue_id uuid NULL
+      REFERENCES newsletter_issues (newsletter_issue_id),
+   PRIMARY KEY (feed_url, entry_id)
+);
@@ -0,0 +1,25 @@
+-- Drafts are issues that have not been published yet, and every save of an
+-- issue is kept as a revision
+BEGIN;
+    ALTER TABLE newsletter_issues ALTER COLUMN published_at DROP NOT NULL;
+
+    CREATE TABLE newsletter_issue_revisions(
+       revision_id uuid NOT NULL,
+       PRIMARY KEY (revision_id),
+       newsletter_issue_id uuid NOT NULL
+          REFERENCES newsletter_issues (newsletter_issue_id),
+       title TEXT NOT NULL,
+       html_content TEXT NOT NULL,
+       author_id uuid NULL
+          REFERENCES users (user_id),
+       created_at timestamptz NOT NULL
+    );
+    CREATE INDEX newsletter_issue_revisions_issue_idx
+       ON newsletter_issue_revisions (newsletter_issue_id, created_at);
+
+    INSERT INTO newsletter_issue_revisions (
+       revision_id, newsletter_issue_id, title, html_content, author_id, created_at
+    )
+    SELECT gen_random_uuid(), newsletter_issue_id, title, html_content, NULL, updated_at
+    FROM newsletter_issues;
+COMMIT;
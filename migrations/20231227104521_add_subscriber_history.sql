@@ -0,0 +1,17 @@
+-- Admins can see when each token was issued and which issues reached a subscriber
+BEGIN;
+    ALTER TABLE subscription_tokens ADD COLUMN created_at timestamptz NULL;
+    CREATE INDEX subscription_tokens_subscriber_idx
+       ON subscription_tokens (subscriber_id);
+
+    CREATE TABLE newsletter_issue_deliveries(
+       newsletter_issue_id uuid NOT NULL
+          REFERENCES newsletter_issues (newsletter_issue_id),
+       subscriber_id uuid NOT NULL
+          REFERENCES subscriptions (id),
+       delivered_at timestamptz NOT NULL,
+       PRIMARY KEY (newsletter_issue_id, subscriber_id)
+    );
+    CREATE INDEX newsletter_issue_deliveries_subscriber_idx
+       ON newsletter_issue_deliveries (subscriber_id);
+COMMIT;
@@ -0,0 +1,15 @@
+-- Imported subscribers record where their consent came from, and each import
+-- keeps its error report
+BEGIN;
+    ALTER TABLE subscriptions ADD COLUMN consent_source TEXT NULL;
+
+    CREATE TABLE subscriber_imports(
+       import_id uuid NOT NULL,
+       PRIMARY KEY (import_id),
+       imported INTEGER NOT NULL,
+       duplicates INTEGER NOT NULL,
+       errors INTEGER NOT NULL,
+       error_report TEXT NOT NULL,
+       created_at timestamptz NOT NULL
+    );
+COMMIT;
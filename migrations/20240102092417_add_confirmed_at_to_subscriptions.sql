@@ -0,0 +1,3 @@
+-- Exports report when each subscriber confirmed. Earlier confirmations were
+-- not timed, so they stay NULL.
+ALTER TABLE subscriptions ADD COLUMN confirmed_at timestamptz NULL;
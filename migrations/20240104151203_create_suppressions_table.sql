@@ -0,0 +1,9 @@
+-- Addresses that must not be mailed or imported again, e.g. because their
+-- owner asked for their data to be erased. Only a hash of the address is kept.
+CREATE TABLE suppressions(
+   email_hash TEXT NOT NULL,
+   PRIMARY KEY (email_hash),
+   reason TEXT NOT NULL,
+   source TEXT NOT NULL,
+   created_at timestamptz NOT NULL
+);
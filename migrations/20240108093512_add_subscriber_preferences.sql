@@ -0,0 +1,28 @@
+-- Subscribers manage what they receive from a preference center: topics they
+-- opted out of, how often they get mail, pauses and why they left
+BEGIN;
+    ALTER TABLE subscriptions ADD COLUMN frequency TEXT NOT NULL DEFAULT 'every_issue';
+    ALTER TABLE subscriptions ADD COLUMN paused_until timestamptz NULL;
+    ALTER TABLE subscriptions ADD COLUMN last_digest_at timestamptz NULL;
+    ALTER TABLE subscriptions ADD COLUMN unsubscribed_at timestamptz NULL;
+    ALTER TABLE subscriptions ADD COLUMN unsubscribe_reason TEXT NULL;
+
+    CREATE TABLE topics(
+       topic_id uuid NOT NULL,
+       PRIMARY KEY (topic_id),
+       name TEXT NOT NULL UNIQUE,
+       created_at timestamptz NOT NULL
+    );
+
+    -- Subscribers get every topic unless they opt out, so new topics reach everyone
+    CREATE TABLE subscriber_topic_opt_outs(
+       subscriber_id uuid NOT NULL
+          REFERENCES subscriptions (id),
+       topic_id uuid NOT NULL
+          REFERENCES topics (topic_id),
+       PRIMARY KEY (subscriber_id, topic_id)
+    );
+
+    ALTER TABLE newsletter_issues ADD COLUMN topic_id uuid NULL
+       REFERENCES topics (topic_id);
+COMMIT;
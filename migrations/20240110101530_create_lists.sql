@@ -0,0 +1,58 @@
+-- One instance can run several newsletters. Subscribers join each list with
+-- its own double opt-in, and every issue goes out to one or more lists
+BEGIN;
+    CREATE TABLE lists(
+       list_id uuid NOT NULL,
+       PRIMARY KEY (list_id),
+       slug TEXT NOT NULL UNIQUE,
+       name TEXT NOT NULL,
+       -- Both NULL to send as the sender from the email settings
+       sender_name TEXT NULL,
+       sender_email TEXT NULL,
+       -- NULL to use the default confirmation email
+       confirmation_subject TEXT NULL,
+       confirmation_html TEXT NULL,
+       created_at timestamptz NOT NULL
+    );
+
+    -- Everything that existed before lists belongs to the default list
+    INSERT INTO lists (list_id, slug, name, created_at)
+    VALUES (gen_random_uuid(), 'default', 'Newsletter', now());
+
+    CREATE TABLE list_subscriptions(
+       list_id uuid NOT NULL
+          REFERENCES lists (list_id),
+       subscriber_id uuid NOT NULL
+          REFERENCES subscriptions (id),
+       status TEXT NOT NULL,
+       subscribed_at timestamptz NOT NULL,
+       confirmed_at timestamptz NULL,
+       PRIMARY KEY (list_id, subscriber_id)
+    );
+    CREATE INDEX list_subscriptions_subscriber_idx ON list_subscriptions (subscriber_id);
+
+    INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at, confirmed_at)
+    SELECT l.list_id, s.id, s.status, s.subscribed_at, s.confirmed_at
+    FROM subscriptions s, lists l
+    WHERE l.slug = 'default';
+
+    -- A confirmation link confirms the subscription to one list
+    ALTER TABLE subscription_tokens ADD COLUMN list_id uuid NULL
+       REFERENCES lists (list_id);
+    UPDATE subscription_tokens
+       SET list_id = (SELECT list_id FROM lists WHERE slug = 'default');
+    ALTER TABLE subscription_tokens ALTER COLUMN list_id SET NOT NULL;
+
+    CREATE TABLE newsletter_issue_lists(
+       newsletter_issue_id uuid NOT NULL
+          REFERENCES newsletter_issues (newsletter_issue_id),
+       list_id uuid NOT NULL
+          REFERENCES lists (list_id),
+       PRIMARY KEY (newsletter_issue_id, list_id)
+    );
+
+    INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+    SELECT i.newsletter_issue_id, l.list_id
+    FROM newsletter_issues i, lists l
+    WHERE l.slug = 'default';
+COMMIT;
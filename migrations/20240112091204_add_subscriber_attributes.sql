@@ -0,0 +1,32 @@
+-- Tags and typed custom fields on subscribers, and the segment an issue is
+-- restricted to
+BEGIN;
+    CREATE TABLE subscriber_tags(
+       subscriber_id uuid NOT NULL
+          REFERENCES subscriptions (id),
+       tag TEXT NOT NULL,
+       PRIMARY KEY (subscriber_id, tag)
+    );
+    CREATE INDEX subscriber_tags_tag_idx ON subscriber_tags (tag);
+
+    CREATE TABLE custom_fields(
+       field_id uuid NOT NULL,
+       PRIMARY KEY (field_id),
+       name TEXT NOT NULL UNIQUE,
+       -- One of 'string', 'number', 'date' or 'boolean'
+       field_type TEXT NOT NULL,
+       created_at timestamptz NOT NULL
+    );
+
+    -- Values are validated against the field type before they are stored
+    CREATE TABLE subscriber_field_values(
+       subscriber_id uuid NOT NULL
+          REFERENCES subscriptions (id),
+       field_id uuid NOT NULL
+          REFERENCES custom_fields (field_id),
+       value TEXT NOT NULL,
+       PRIMARY KEY (subscriber_id, field_id)
+    );
+
+    ALTER TABLE newsletter_issues ADD COLUMN segment TEXT NULL;
+COMMIT;
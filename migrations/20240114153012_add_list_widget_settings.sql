@@ -0,0 +1,8 @@
+-- Copy of the embeddable sign-up form, and where plain HTML forms posting
+-- from other sites are sent after signing up
+BEGIN;
+    ALTER TABLE lists ADD COLUMN widget_heading TEXT NULL;
+    ALTER TABLE lists ADD COLUMN widget_button_label TEXT NULL;
+    ALTER TABLE lists ADD COLUMN widget_success_message TEXT NULL;
+    ALTER TABLE lists ADD COLUMN redirect_url TEXT NULL;
+COMMIT;
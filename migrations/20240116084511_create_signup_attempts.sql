@@ -0,0 +1,15 @@
+-- Every sign-up that reached the spam checks and how it ended, used for rate
+-- limiting and to see what the checks turn away
+BEGIN;
+    CREATE TABLE signup_attempts(
+       attempt_id uuid NOT NULL,
+       PRIMARY KEY (attempt_id),
+       client_ip TEXT NULL,
+       email_domain TEXT NOT NULL,
+       -- 'accepted' or the check that rejected the sign-up
+       outcome TEXT NOT NULL,
+       created_at timestamptz NOT NULL
+    );
+    CREATE INDEX signup_attempts_client_ip_idx ON signup_attempts (client_ip, created_at);
+    CREATE INDEX signup_attempts_email_domain_idx ON signup_attempts (email_domain, created_at);
+COMMIT;
@@ -0,0 +1,74 @@
+-- Addresses differing only in case belong to the same subscriber. `email`
+-- keeps the address as it was typed, `email_key` identifies the mailbox.
+-- The application also converts internationalised domains to punycode, which
+-- can't be done here: addresses saved before are keyed in their Unicode form
+BEGIN;
+    ALTER TABLE subscriptions ADD COLUMN email_key TEXT NULL;
+    UPDATE subscriptions SET email_key = lower(email);
+
+    -- Duplicates are merged into the confirmed, then the oldest, subscriber
+    CREATE TEMPORARY TABLE merged_subscribers ON COMMIT DROP AS
+    SELECT id AS duplicate_id, survivor_id
+    FROM (
+        SELECT id, first_value(id) OVER (
+            PARTITION BY email_key
+            ORDER BY status = 'confirmed' DESC, subscribed_at, id
+        ) AS survivor_id
+        FROM subscriptions
+    ) ranked
+    WHERE id <> survivor_id;
+
+    UPDATE subscription_tokens t
+       SET subscriber_id = m.survivor_id
+    FROM merged_subscribers m
+    WHERE t.subscriber_id = m.duplicate_id;
+
+    INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at, confirmed_at)
+    SELECT l.list_id, m.survivor_id, l.status, l.subscribed_at, l.confirmed_at
+    FROM list_subscriptions l
+    JOIN merged_subscribers m ON l.subscriber_id = m.duplicate_id
+    ON CONFLICT (list_id, subscriber_id) DO UPDATE
+    SET status = EXCLUDED.status, confirmed_at = EXCLUDED.confirmed_at
+    WHERE EXCLUDED.status = 'confirmed' AND list_subscriptions.status <> 'confirmed';
+
+    INSERT INTO newsletter_issue_deliveries (newsletter_issue_id, subscriber_id, delivered_at)
+    SELECT d.newsletter_issue_id, m.survivor_id, d.delivered_at
+    FROM newsletter_issue_deliveries d
+    JOIN merged_subscribers m ON d.subscriber_id = m.duplicate_id
+    ON CONFLICT DO NOTHING;
+
+    INSERT INTO subscriber_topic_opt_outs (subscriber_id, topic_id)
+    SELECT m.survivor_id, o.topic_id
+    FROM subscriber_topic_opt_outs o
+    JOIN merged_subscribers m ON o.subscriber_id = m.duplicate_id
+    ON CONFLICT DO NOTHING;
+
+    INSERT INTO subscriber_tags (subscriber_id, tag)
+    SELECT m.survivor_id, t.tag
+    FROM subscriber_tags t
+    JOIN merged_subscribers m ON t.subscriber_id = m.duplicate_id
+    ON CONFLICT DO NOTHING;
+
+    INSERT INTO subscriber_field_values (subscriber_id, field_id, value)
+    SELECT m.survivor_id, v.field_id, v.value
+    FROM subscriber_field_values v
+    JOIN merged_subscribers m ON v.subscriber_id = m.duplicate_id
+    ON CONFLICT DO NOTHING;
+
+    DELETE FROM list_subscriptions
+    WHERE subscriber_id IN (SELECT duplicate_id FROM merged_subscribers);
+    DELETE FROM newsletter_issue_deliveries
+    WHERE subscriber_id IN (SELECT duplicate_id FROM merged_subscribers);
+    DELETE FROM subscriber_topic_opt_outs
+    WHERE subscriber_id IN (SELECT duplicate_id FROM merged_subscribers);
+    DELETE FROM subscriber_tags
+    WHERE subscriber_id IN (SELECT duplicate_id FROM merged_subscribers);
+    DELETE FROM subscriber_field_values
+    WHERE subscriber_id IN (SELECT duplicate_id FROM merged_subscribers);
+    DELETE FROM subscriptions
+    WHERE id IN (SELECT duplicate_id FROM merged_subscribers);
+
+    ALTER TABLE subscriptions ALTER COLUMN email_key SET NOT NULL;
+    ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_email_key;
+    CREATE UNIQUE INDEX subscriptions_email_key_idx ON subscriptions (email_key);
+COMMIT;
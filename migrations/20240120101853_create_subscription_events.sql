@@ -0,0 +1,33 @@
+-- Subscriber statuses become a Postgres enum, and every change of status is
+-- kept with what caused it
+BEGIN;
+    CREATE TYPE subscription_status AS ENUM (
+       'pending_confirmation',
+       'confirmed',
+       'unsubscribed',
+       'bounced',
+       'complained',
+       'suppressed'
+    );
+    ALTER TABLE subscriptions
+       ALTER COLUMN status TYPE subscription_status USING status::subscription_status;
+
+    CREATE TABLE subscription_events(
+       event_id uuid NOT NULL,
+       PRIMARY KEY (event_id),
+       subscriber_id uuid NOT NULL
+          REFERENCES subscriptions (id),
+       -- NULL when the subscriber was created
+       from_status subscription_status NULL,
+       to_status subscription_status NOT NULL,
+       cause TEXT NOT NULL,
+       created_at timestamptz NOT NULL
+    );
+    CREATE INDEX subscription_events_subscriber_idx
+       ON subscription_events (subscriber_id, created_at);
+
+    -- The history of existing subscribers starts with their current status
+    INSERT INTO subscription_events (event_id, subscriber_id, from_status, to_status, cause, created_at)
+    SELECT gen_random_uuid(), id, NULL, status, 'migration', now()
+    FROM subscriptions;
+COMMIT;
@@ -0,0 +1,17 @@
+-- Bounces, complaints and unsubscribes reported after an email was sent.
+-- Only a hash of the address is kept, as for suppressions
+CREATE TABLE delivery_feedback(
+   feedback_id uuid NOT NULL,
+   PRIMARY KEY (feedback_id),
+   -- Where the report came from, e.g. 'brevo'
+   source TEXT NOT NULL,
+   -- Identifies the report at its source: reports delivered twice are ignored
+   event_key TEXT NOT NULL,
+   kind TEXT NOT NULL,
+   email_hash TEXT NOT NULL,
+   message_id TEXT NULL,
+   reason TEXT NULL,
+   received_at timestamptz NOT NULL,
+   UNIQUE (source, event_key)
+);
+CREATE INDEX delivery_feedback_email_hash_idx ON delivery_feedback (email_hash, received_at);
@@ -0,0 +1,6 @@
+-- Every email the suppression list stops is counted against its entry
+BEGIN;
+    ALTER TABLE suppressions ADD COLUMN suppressed_sends BIGINT NOT NULL DEFAULT 0;
+    ALTER TABLE suppressions ADD COLUMN last_suppressed_at timestamptz NULL;
+    CREATE INDEX suppressions_created_at_idx ON suppressions (created_at);
+COMMIT;
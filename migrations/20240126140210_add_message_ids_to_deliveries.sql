@@ -0,0 +1,15 @@
+-- Each delivery remembers the emails it went out as, so that bounces and
+-- complaints can be traced back to the subscriber and the issue
+BEGIN;
+    -- The Message-ID we gave the email
+    ALTER TABLE newsletter_issue_deliveries ADD COLUMN message_id TEXT NULL;
+    -- The id the email provider gave it, e.g. Brevo's messageId
+    ALTER TABLE newsletter_issue_deliveries ADD COLUMN provider_message_id TEXT NULL;
+    CREATE INDEX newsletter_issue_deliveries_message_id_idx
+       ON newsletter_issue_deliveries (message_id);
+    CREATE INDEX newsletter_issue_deliveries_provider_message_id_idx
+       ON newsletter_issue_deliveries (provider_message_id);
+
+    ALTER TABLE delivery_feedback ADD COLUMN newsletter_issue_id uuid NULL
+       REFERENCES newsletter_issues (newsletter_issue_id);
+COMMIT;
@@ -0,0 +1,24 @@
+-- Opens and clicks of the issues sent to lists that track them
+BEGIN;
+    ALTER TABLE lists ADD COLUMN track_engagement BOOLEAN NOT NULL DEFAULT FALSE;
+
+    CREATE TABLE engagement_events(
+       event_id uuid NOT NULL,
+       PRIMARY KEY (event_id),
+       -- 'open' or 'click'
+       kind TEXT NOT NULL,
+       newsletter_issue_id uuid NOT NULL
+          REFERENCES newsletter_issues (newsletter_issue_id),
+       subscriber_id uuid NOT NULL
+          REFERENCES subscriptions (id),
+       -- The link that was clicked
+       url TEXT NULL,
+       -- Likely made by a link scanner or an image prefetcher, not a reader
+       automated BOOLEAN NOT NULL,
+       occurred_at timestamptz NOT NULL
+    );
+    CREATE INDEX engagement_events_issue_idx
+       ON engagement_events (newsletter_issue_id, kind);
+    CREATE INDEX engagement_events_subscriber_idx
+       ON engagement_events (subscriber_id, newsletter_issue_id);
+COMMIT;
@@ -0,0 +1,16 @@
+-- Issue reports read deliveries, feedback and engagement by issue
+BEGIN;
+    CREATE INDEX delivery_feedback_issue_idx
+       ON delivery_feedback (newsletter_issue_id, kind)
+       WHERE newsletter_issue_id IS NOT NULL;
+
+    -- Readers only: automated events are never reported
+    CREATE INDEX engagement_events_report_idx
+       ON engagement_events (newsletter_issue_id, subscriber_id, occurred_at)
+       WHERE NOT automated;
+
+    -- Unsubscribes are put down to the last delivery before them
+    DROP INDEX newsletter_issue_deliveries_subscriber_idx;
+    CREATE INDEX newsletter_issue_deliveries_subscriber_idx
+       ON newsletter_issue_deliveries (subscriber_id, delivered_at);
+COMMIT;
@@ -0,0 +1,6 @@
+-- The dashboard counts sign-ups and unsubscribes per day
+BEGIN;
+    CREATE INDEX subscriptions_subscribed_at_idx ON subscriptions (subscribed_at);
+    CREATE INDEX subscription_events_status_idx
+       ON subscription_events (to_status, created_at);
+COMMIT;
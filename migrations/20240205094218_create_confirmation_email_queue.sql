@@ -0,0 +1,9 @@
+-- Opt-in emails sent in the background, e.g. for imported subscribers
+CREATE TABLE confirmation_email_queue(
+   subscription_token TEXT NOT NULL
+      REFERENCES subscription_tokens (subscription_token) ON DELETE CASCADE,
+   PRIMARY KEY (subscription_token),
+   n_retries SMALLINT NOT NULL DEFAULT 0,
+   execute_after timestamptz NOT NULL,
+   enqueued_at timestamptz NOT NULL
+);
@@ -0,0 +1,10 @@
+-- Form stamps that already signed someone up, so each one is only accepted
+-- once. Rows are deleted once the stamp would have expired anyway.
+BEGIN;
+    CREATE TABLE used_form_stamps(
+       form_stamp TEXT NOT NULL,
+       PRIMARY KEY (form_stamp),
+       used_at timestamptz NOT NULL
+    );
+    CREATE INDEX used_form_stamps_used_at_idx ON used_form_stamps (used_at);
+COMMIT;
@@ -0,0 +1,6 @@
+-- Stamps carry a random nonce, so two forms shown in the same second are told
+-- apart. The nonce is what is remembered once a stamp is used.
+BEGIN;
+    DELETE FROM used_form_stamps;
+    ALTER TABLE used_form_stamps RENAME COLUMN form_stamp TO nonce;
+COMMIT;
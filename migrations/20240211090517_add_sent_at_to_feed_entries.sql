@@ -0,0 +1,7 @@
+-- Feed entries are recorded with their issue before it is sent. `sent_at` is
+-- set once every subscriber was sent the issue, so a poll can finish
+-- deliveries that were cut short.
+BEGIN;
+    ALTER TABLE feed_entries ADD COLUMN sent_at timestamptz NULL;
+    UPDATE feed_entries SET sent_at = seen_at WHERE newsletter_issue_id IS NOT NULL;
+COMMIT;
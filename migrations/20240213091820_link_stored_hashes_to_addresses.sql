@@ -0,0 +1,9 @@
+-- Erasing an address also reaches what is only linked to it by its hash:
+-- sign-up attempts and used form stamps are deleted, while delivery feedback
+-- stays in the issue reports without its address, message id and reason.
+BEGIN;
+    ALTER TABLE signup_attempts ADD COLUMN email_hash TEXT NULL;
+    CREATE INDEX signup_attempts_email_hash_idx ON signup_attempts (email_hash);
+    ALTER TABLE used_form_stamps ADD COLUMN email_hash TEXT NULL;
+    ALTER TABLE delivery_feedback ALTER COLUMN email_hash DROP NOT NULL;
+COMMIT;
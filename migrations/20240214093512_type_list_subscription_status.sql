@@ -0,0 +1,15 @@
+-- List memberships get an enum of their own, as subscribers did with
+-- `subscription_status`: a membership is pending, confirmed or unsubscribed.
+BEGIN;
+    CREATE TYPE list_subscription_status AS ENUM (
+        'pending_confirmation',
+        'confirmed',
+        'unsubscribed'
+    );
+    ALTER TABLE list_subscriptions
+       ALTER COLUMN status TYPE list_subscription_status USING (
+          CASE WHEN status IN ('pending_confirmation', 'confirmed') THEN status
+          ELSE 'unsubscribed'
+          END
+       )::list_subscription_status;
+COMMIT;
@@ -0,0 +1,208 @@
+//! src/analytics.rs
+//!
+//! How the audience grows, as shown on the admin dashboard. Days are UTC.
+use crate::domain::SubscriptionStatus;
+use crate::reports::percent;
+use chrono::NaiveDate;
+use sqlx::PgPool;
+
+/// How many days of sign-ups and unsubscribes the dashboard shows.
+pub const GROWTH_DAYS: i32 = 90;
+
+#[derive(Debug)]
+pub struct ListGrowth {
+    pub confirmed: i64,
+    pub pending: i64,
+    /// Sign-ups of the last `GROWTH_DAYS` days.
+    pub recent_signups: i64,
+    /// Those of them who confirmed their address, whatever they did since.
+    pub recent_confirmations: i64,
+}
+
+impl ListGrowth {
+    pub fn confirmation_rate(&self) -> String {
+        percent(self.recent_confirmations, self.recent_signups)
+    }
+}
+
+#[tracing::instrument(name = "Get list growth", skip(pool))]
+pub async fn get_list_growth(pool: &PgPool) -> Result<ListGrowth, sqlx::Error> {
+    sqlx::query_as!(
+        ListGrowth,
+        r#"
+        SELECT
+            COUNT(*) FILTER (WHERE status = $2) AS "confirmed!",
+            COUNT(*) FILTER (WHERE status = $3) AS "pending!",
+            COUNT(*) FILTER (
+                WHERE subscribed_at >= now() - make_interval(days => $1)
+            ) AS "recent_signups!",
+            COUNT(*) FILTER (
+                WHERE subscribed_at >= now() - make_interval(days => $1)
+                    AND confirmed_at IS NOT NULL
+            ) AS "recent_confirmations!"
+        FROM subscriptions
+        "#,
+        GROWTH_DAYS,
+        SubscriptionStatus::Confirmed as SubscriptionStatus,
+        SubscriptionStatus::PendingConfirmation as SubscriptionStatus
+    )
+    .fetch_one(pool)
+    .await
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DailyActivity {
+    pub day: NaiveDate,
+    pub signups: i64,
+    pub unsubscribes: i64,
+}
+
+/// One entry per day of the last `GROWTH_DAYS` days, oldest first, today
+/// included.
+#[tracing::instrument(name = "Get daily activity", skip(pool))]
+pub async fn get_daily_activity(pool: &PgPool) -> Result<Vec<DailyActivity>, sqlx::Error> {
+    sqlx::query_as!(
+        DailyActivity,
+        r#"
+        WITH days AS (
+            SELECT generate_series(
+                (now() AT TIME ZONE 'UTC')::date - ($1 - 1),
+                (now() AT TIME ZONE 'UTC')::date,
+                interval '1 day'
+            )::date AS day
+        ),
+        signups AS (
+            SELECT (subscribed_at AT TIME ZONE 'UTC')::date AS day, COUNT(*) AS count
+            FROM subscriptions
+            WHERE subscribed_at >= (now() AT TIME ZONE 'UTC')::date - ($1 - 1)
+            GROUP BY 1
+        ),
+        unsubscribes AS (
+            SELECT (created_at AT TIME ZONE 'UTC')::date AS day, COUNT(*) AS count
+            FROM subscription_events
+            WHERE to_status = $2
+                AND created_at >= (now() AT TIME ZONE 'UTC')::date - ($1 - 1)
+            GROUP BY 1
+        )
+        SELECT days.day AS "day!",
+            COALESCE(signups.count, 0) AS "signups!",
+            COALESCE(unsubscribes.count, 0) AS "unsubscribes!"
+        FROM days
+        LEFT JOIN signups USING (day)
+        LEFT JOIN unsubscribes USING (day)
+        ORDER BY days.day
+        "#,
+        GROWTH_DAYS,
+        SubscriptionStatus::Unsubscribed as SubscriptionStatus
+    )
+    .fetch_all(pool)
+    .await
+}
+
+const BAR_WIDTH: u32 = 8;
+const BAR_GAP: u32 = 2;
+/// Sign-ups rise above the middle line and unsubscribes hang below it.
+const HALF_HEIGHT: u32 = 80;
+
+/// A bar chart of the daily activity, drawn as SVG by the dashboard.
+#[derive(Debug)]
+pub struct GrowthChart {
+    pub width: u32,
+    pub height: u32,
+    pub baseline: u32,
+    pub bar_width: u32,
+    pub bars: Vec<ChartBar>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ChartBar {
+    pub x: u32,
+    pub signups_height: u32,
+    pub unsubscribes_height: u32,
+    /// Shown when hovering the bar.
+    pub label: String,
+}
+
+impl ChartBar {
+    /// Where the sign-ups bar starts, as SVG counts from the top.
+    pub fn signups_y(&self) -> u32 {
+        HALF_HEIGHT - self.signups_height
+    }
+}
+
+impl GrowthChart {
+    /// Both halves share one scale, so that a day with as many unsubscribes
+    /// as sign-ups looks balanced.
+    pub fn new(days: &[DailyActivity]) -> Self {
+        let highest = days
+            .iter()
+            .map(|day| day.signups.max(day.unsubscribes))
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        let scale = |count: i64| (count * HALF_HEIGHT as i64 / highest) as u32;
+
+        let bars = days
+            .iter()
+            .enumerate()
+            .map(|(index, day)| ChartBar {
+                x: index as u32 * (BAR_WIDTH + BAR_GAP),
+                signups_height: scale(day.signups),
+                unsubscribes_height: scale(day.unsubscribes),
+                label: format!(
+                    "{}: {} sign-ups, {} unsubscribes",
+                    day.day, day.signups, day.unsubscribes
+                ),
+            })
+            .collect::<Vec<_>>();
+
+        Self {
+            width: (bars.len() as u32 * (BAR_WIDTH + BAR_GAP)).max(BAR_WIDTH),
+            height: 2 * HALF_HEIGHT,
+            baseline: HALF_HEIGHT,
+            bar_width: BAR_WIDTH,
+            bars,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(day: u32, signups: i64, unsubscribes: i64) -> DailyActivity {
+        DailyActivity {
+            day: NaiveDate::from_ymd_opt(2024, 1, day).unwrap(),
+            signups,
+            unsubscribes,
+        }
+    }
+
+    #[test]
+    fn the_busiest_day_fills_its_half_of_the_chart() {
+        let chart = GrowthChart::new(&[day(1, 4, 0), day(2, 2, 1), day(3, 0, 0)]);
+
+        let heights: Vec<(u32, u32, u32)> = chart
+            .bars
+            .iter()
+            .map(|bar| (bar.x, bar.signups_height, bar.unsubscribes_height))
+            .collect();
+        assert_eq!(heights, [(0, 80, 0), (10, 40, 20), (20, 0, 0)]);
+        assert_eq!(chart.bars[0].signups_y(), 0);
+        assert_eq!(
+            chart.bars[1].label,
+            "2024-01-02: 2 sign-ups, 1 unsubscribes"
+        );
+        assert_eq!((chart.width, chart.height), (30, 160));
+    }
+
+    #[test]
+    fn quiet_days_draw_no_bars() {
+        let chart = GrowthChart::new(&[day(1, 0, 0), day(2, 0, 0)]);
+
+        assert!(chart
+            .bars
+            .iter()
+            .all(|bar| bar.signups_height == 0 && bar.unsubscribes_height == 0));
+    }
+}
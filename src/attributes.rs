@@ -0,0 +1,315 @@
+//! src/attributes.rs
+//!
+//! Tags and custom fields: what is known about a subscriber beyond their name
+//! and address, used to send issues to a segment of the subscribers.
+use chrono::{DateTime, NaiveDate, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+const MAX_TAG_LENGTH: usize = 64;
+
+/// Tags are matched case-insensitively, so they are stored in lowercase.
+/// Commas and semicolons separate tags in forms and CSV files.
+pub fn parse_tag(tag: &str) -> Result<String, String> {
+    let tag = tag.trim().to_lowercase();
+
+    if tag.is_empty() {
+        return Err("A tag cannot be empty".into());
+    }
+    if tag.chars().count() > MAX_TAG_LENGTH {
+        return Err(format!(
+            "{} is longer than {} characters",
+            tag, MAX_TAG_LENGTH
+        ));
+    }
+    if tag.contains([',', ';', '"']) {
+        return Err(format!("{} contains a comma, semicolon or quote", tag));
+    }
+
+    Ok(tag)
+}
+
+/// Split a comma or semicolon separated list of tags. Empty entries are skipped.
+pub fn parse_tags(tags: &str) -> Result<Vec<String>, String> {
+    let mut parsed: Vec<String> = tags
+        .split([',', ';'])
+        .filter(|tag| !tag.trim().is_empty())
+        .map(parse_tag)
+        .collect::<Result<_, _>>()?;
+    parsed.sort();
+    parsed.dedup();
+
+    Ok(parsed)
+}
+
+/// Field names are used in segments and as CSV headers, so they are limited
+/// to lowercase ASCII letters, digits and underscores.
+pub fn parse_field_name(name: &str) -> Result<String, String> {
+    let name = name.trim().to_lowercase();
+
+    if name.is_empty() {
+        return Err("A field needs a name".into());
+    }
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!(
+            "{} may only contain letters, digits and underscores",
+            name
+        ));
+    }
+
+    Ok(name)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldType {
+    String,
+    Number,
+    /// `YYYY-MM-DD`
+    Date,
+    /// `true` or `false`
+    Boolean,
+}
+
+impl FieldType {
+    pub const ALL: [FieldType; 4] = [
+        FieldType::String,
+        FieldType::Number,
+        FieldType::Date,
+        FieldType::Boolean,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FieldType::String => "string",
+            FieldType::Number => "number",
+            FieldType::Date => "date",
+            FieldType::Boolean => "boolean",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|field_type| field_type.as_str() == value)
+    }
+
+    /// Check that `value` is of this type.
+    pub fn parse_value(&self, value: &str) -> Result<FieldValue, String> {
+        let value = value.trim();
+        match self {
+            FieldType::String => Ok(FieldValue::String(value.to_string())),
+            FieldType::Number => value
+                .parse::<f64>()
+                .ok()
+                .filter(|number| number.is_finite())
+                .map(FieldValue::Number)
+                .ok_or_else(|| format!("{} is not a number", value)),
+            FieldType::Date => NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .map(FieldValue::Date)
+                .map_err(|_| format!("{} is not a date like 2024-01-31", value)),
+            FieldType::Boolean => match value.to_lowercase().as_str() {
+                "true" => Ok(FieldValue::Boolean(true)),
+                "false" => Ok(FieldValue::Boolean(false)),
+                _ => Err(format!("{} is neither true nor false", value)),
+            },
+        }
+    }
+}
+
+/// Values of different types are never compared: segments and stored values
+/// are both checked against the type of their field.
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub enum FieldValue {
+    String(String),
+    Number(f64),
+    Date(NaiveDate),
+    Boolean(bool),
+}
+
+impl std::fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FieldValue::String(value) => write!(f, "{}", value),
+            FieldValue::Number(value) => write!(f, "{}", value),
+            FieldValue::Date(value) => write!(f, "{}", value.format("%Y-%m-%d")),
+            FieldValue::Boolean(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CustomField {
+    pub field_id: Uuid,
+    pub name: String,
+    pub field_type: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl CustomField {
+    /// Unknown types are treated as strings rather than failing.
+    pub fn field_type(&self) -> FieldType {
+        FieldType::parse(&self.field_type).unwrap_or(FieldType::String)
+    }
+
+    /// An empty input clears the field.
+    pub fn parse_input(&self, input: &str) -> Result<Option<FieldValue>, String> {
+        if input.trim().is_empty() {
+            return Ok(None);
+        }
+        self.field_type()
+            .parse_value(input)
+            .map(Some)
+            .map_err(|e| format!("{}: {}", self.name, e))
+    }
+}
+
+/// A field and the value one subscriber has for it, if any.
+#[derive(Debug)]
+pub struct FieldEntry {
+    pub field_id: Uuid,
+    pub name: String,
+    pub field_type: String,
+    pub value: Option<String>,
+}
+
+/// Changes to the tags and custom fields of one subscriber.
+#[derive(Debug, Default)]
+pub struct AttributeChanges {
+    /// Replaces every tag of the subscriber when set.
+    pub tags: Option<Vec<String>>,
+    /// `None` clears the field.
+    pub fields: Vec<(Uuid, Option<FieldValue>)>,
+}
+
+/// In alphabetical order.
+#[tracing::instrument(name = "Get custom fields", skip(pool))]
+pub async fn get_custom_fields(pool: &PgPool) -> Result<Vec<CustomField>, sqlx::Error> {
+    sqlx::query_as!(
+        CustomField,
+        r#"
+        SELECT field_id, name, field_type, created_at
+        FROM custom_fields
+        ORDER BY name
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Returns `None` if a field with this name already exists.
+#[tracing::instrument(name = "Store custom field", skip(pool))]
+pub async fn insert_custom_field(
+    pool: &PgPool,
+    name: &str,
+    field_type: FieldType,
+) -> Result<Option<CustomField>, sqlx::Error> {
+    sqlx::query_as!(
+        CustomField,
+        r#"
+        INSERT INTO custom_fields (field_id, name, field_type, created_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (name) DO NOTHING
+        RETURNING field_id, name, field_type, created_at
+        "#,
+        Uuid::new_v4(),
+        name,
+        field_type.as_str(),
+        Utc::now()
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// In alphabetical order.
+#[tracing::instrument(name = "Get subscriber tags", skip(pool))]
+pub async fn get_tags(pool: &PgPool, subscriber_id: Uuid) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query!(
+        "SELECT tag FROM subscriber_tags WHERE subscriber_id = $1 ORDER BY tag",
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .map(|rows| rows.into_iter().map(|row| row.tag).collect())
+}
+
+/// Every custom field, with the subscriber's value where they have one.
+#[tracing::instrument(name = "Get subscriber field values", skip(pool))]
+pub async fn get_field_entries(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<FieldEntry>, sqlx::Error> {
+    sqlx::query_as!(
+        FieldEntry,
+        r#"
+        SELECT f.field_id, f.name, f.field_type, v.value AS "value?"
+        FROM custom_fields f
+        LEFT JOIN subscriber_field_values v
+            ON v.field_id = f.field_id AND v.subscriber_id = $1
+        ORDER BY f.name
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[tracing::instrument(name = "Save subscriber attributes", skip(transaction))]
+pub async fn apply_attribute_changes(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    changes: &AttributeChanges,
+) -> Result<(), sqlx::Error> {
+    if let Some(tags) = &changes.tags {
+        sqlx::query!(
+            "DELETE FROM subscriber_tags WHERE subscriber_id = $1",
+            subscriber_id
+        )
+        .execute(&mut **transaction)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriber_tags (subscriber_id, tag)
+            SELECT $1, tag FROM unnest($2::text[]) AS tag
+            ON CONFLICT DO NOTHING
+            "#,
+            subscriber_id,
+            &tags[..]
+        )
+        .execute(&mut **transaction)
+        .await?;
+    }
+
+    for (field_id, value) in &changes.fields {
+        match value {
+            Some(value) => {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO subscriber_field_values (subscriber_id, field_id, value)
+                    VALUES ($1, $2, $3)
+                    ON CONFLICT (subscriber_id, field_id) DO UPDATE SET value = EXCLUDED.value
+                    "#,
+                    subscriber_id,
+                    field_id,
+                    value.to_string()
+                )
+                .execute(&mut **transaction)
+                .await?;
+            }
+            None => {
+                sqlx::query!(
+                    r#"
+                    DELETE FROM subscriber_field_values
+                    WHERE subscriber_id = $1 AND field_id = $2
+                    "#,
+                    subscriber_id,
+                    field_id
+                )
+                .execute(&mut **transaction)
+                .await?;
+            }
+        }
+    }
+
+    Ok(())
+}
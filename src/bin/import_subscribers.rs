@@ -0,0 +1,101 @@
+//! src/bin/import_subscribers.rs
+//!
+//! Import subscribers from a CSV file without going through the admin area.
+//!
+//!     import_subscribers <file.csv> [--email-column NAME] [--name-column NAME]
+//!         [--confirmed CONSENT_SOURCE] [--errors REPORT.csv]
+//!
+//! Without `--confirmed`, an opt-in email is queued for every imported
+//! subscriber, to be sent by the server's confirmation worker.
+use anyhow::{bail, Context};
+use letter::configuration::get_configuration;
+use letter::import::{import_subscribers, ColumnMapping, ImportMode};
+use letter::telemetry::{get_subscriber, init_subscriber};
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use std::fs::File;
+use std::io::BufReader;
+
+struct Args {
+    path: String,
+    mapping: ColumnMapping,
+    mode: ImportMode,
+    errors_path: Option<String>,
+}
+
+fn parse_args() -> Result<Args, anyhow::Error> {
+    let mut args = std::env::args().skip(1);
+    let mut path = None;
+    let mut mapping = ColumnMapping::default();
+    let mut mode = ImportMode::PendingConfirmation;
+    let mut errors_path = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .with_context(|| format!("Missing a value for {}", arg))
+        };
+        match arg.as_str() {
+            "--email-column" => mapping.email = value()?,
+            "--name-column" => mapping.name = value()?,
+            "--confirmed" => {
+                mode = ImportMode::Confirmed {
+                    consent_source: value()?,
+                }
+            }
+            "--errors" => errors_path = Some(value()?),
+            _ if arg.starts_with("--") => bail!("Unknown option {}", arg),
+            _ => path = Some(arg),
+        }
+    }
+
+    Ok(Args {
+        path: path.context("Missing the path of the CSV file")?,
+        mapping,
+        mode,
+        errors_path,
+    })
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    let subscriber = get_subscriber("import_subscribers".into(), "info".into(), std::io::stderr);
+    init_subscriber(subscriber);
+
+    let args = parse_args()?;
+    let config = get_configuration().expect("Failed to read configuration.");
+    let pool = PgPool::connect(config.database.connection_string().expose_secret())
+        .await
+        .context("Failed to connect to Postgres")?;
+
+    let email_hash_key = config
+        .application
+        .email_hash_key
+        .context("Missing the email hash key")?;
+
+    let file = File::open(&args.path).with_context(|| format!("Failed to open {}", args.path))?;
+    let report = import_subscribers(
+        &pool,
+        &email_hash_key,
+        BufReader::new(file),
+        &args.mapping,
+        &args.mode,
+    )
+    .await?;
+
+    println!(
+        "Imported {}, skipped {} duplicates, {} errors",
+        report.imported,
+        report.duplicates,
+        report.errors.len()
+    );
+
+    if let Some(errors_path) = args.errors_path {
+        std::fs::write(&errors_path, report.errors_csv()?)
+            .with_context(|| format!("Failed to write {}", errors_path))?;
+    } else if !report.errors.is_empty() {
+        print!("{}", report.errors_csv()?);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,45 @@
+//! src/bin/process_bounces.rs
+//!
+//! Apply the bounces and complaints in the mailbox they are sent to, for
+//! deployments that send through SMTP rather than Brevo.
+//!
+//!     process_bounces <MAILDIR or MBOX>
+//!
+//! Processed Maildir messages are moved from `new` to `cur`, so it can run
+//! from cron. An mbox file can be read again safely.
+use anyhow::Context;
+use letter::configuration::get_configuration;
+use letter::mailbox::process_mailbox;
+use letter::telemetry::{get_subscriber, init_subscriber};
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use std::path::PathBuf;
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    let subscriber = get_subscriber("process_bounces".into(), "info".into(), std::io::stderr);
+    init_subscriber(subscriber);
+
+    let path: PathBuf = std::env::args()
+        .nth(1)
+        .context("Missing the path of the Maildir or mbox file")?
+        .into();
+    let config = get_configuration().expect("Failed to read configuration.");
+    let pool = PgPool::connect(config.database.connection_string().expose_secret())
+        .await
+        .context("Failed to connect to Postgres")?;
+
+    let email_hash_key = config
+        .application
+        .email_hash_key
+        .context("Missing the email hash key")?;
+
+    let report = process_mailbox(&pool, &email_hash_key, &path).await?;
+
+    println!(
+        "Read {} messages, applied {} reports, ignored {} messages",
+        report.messages, report.applied, report.ignored
+    );
+
+    Ok(())
+}
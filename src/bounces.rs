@@ -0,0 +1,282 @@
+//! src/bounces.rs
+//!
+//! What we learn about an email after it left: bounces, spam complaints and
+//! unsubscribes, whichever way they are reported.
+use crate::configuration::EmailHashKey;
+use crate::domain::SubscriptionStatus;
+use crate::privacy::{email_hash, email_key};
+use crate::subscribers::{change_status, StatusError};
+use crate::suppressions::insert_suppression;
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedbackKind {
+    /// The address does not exist, or will never accept our mail.
+    HardBounce,
+    /// Mail could not be delivered this time, e.g. because the mailbox is full.
+    SoftBounce,
+    /// The recipient marked the email as spam.
+    Complaint,
+    /// The recipient unsubscribed through the provider.
+    Unsubscribe,
+    /// The provider refuses to send to the address.
+    Blocked,
+}
+
+impl FeedbackKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FeedbackKind::HardBounce => "hard_bounce",
+            FeedbackKind::SoftBounce => "soft_bounce",
+            FeedbackKind::Complaint => "complaint",
+            FeedbackKind::Unsubscribe => "unsubscribe",
+            FeedbackKind::Blocked => "blocked",
+        }
+    }
+
+    /// Soft bounces are only recorded: the next issue may well get through.
+    fn status(&self) -> Option<SubscriptionStatus> {
+        match self {
+            FeedbackKind::HardBounce => Some(SubscriptionStatus::Bounced),
+            FeedbackKind::SoftBounce => None,
+            FeedbackKind::Complaint => Some(SubscriptionStatus::Complained),
+            FeedbackKind::Unsubscribe => Some(SubscriptionStatus::Unsubscribed),
+            FeedbackKind::Blocked => Some(SubscriptionStatus::Suppressed),
+        }
+    }
+
+    /// Whether the address must never be mailed again, even if it signs up
+    /// or is imported once more.
+    fn suppresses(&self) -> bool {
+        matches!(
+            self,
+            FeedbackKind::HardBounce | FeedbackKind::Complaint | FeedbackKind::Blocked
+        )
+    }
+}
+
+/// One report about one recipient.
+#[derive(Debug, Clone)]
+pub struct Feedback {
+    pub kind: FeedbackKind,
+    pub email: String,
+    /// Identifies the report at its source, so that reports delivered twice
+    /// are only applied once.
+    pub event_key: String,
+    /// The id of the email the report is about, if known: the `Message-ID`
+    /// we gave it or the id the provider gave it.
+    pub message_id: Option<String>,
+    /// The issue named by the VERP envelope sender, for reports whose
+    /// message id doesn't match a delivery.
+    pub newsletter_issue_id: Option<Uuid>,
+    pub reason: Option<String>,
+}
+
+/// Apply the report to the subscriber with that address, if there is one,
+/// and to the suppression list. `source` is where the report came from,
+/// e.g. `brevo`.
+/// Returns `false` if the report had already been applied.
+#[tracing::instrument(
+    name = "Record delivery feedback",
+    skip(pool, email_hash_key, feedback),
+    fields(kind = feedback.kind.as_str())
+)]
+pub async fn record_feedback(
+    pool: &PgPool,
+    email_hash_key: &EmailHashKey,
+    source: &str,
+    feedback: &Feedback,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let mut delivery = match &feedback.message_id {
+        Some(message_id) => find_delivery(&mut transaction, message_id)
+            .await
+            .context("Failed to look up the delivery")?,
+        None => None,
+    };
+    if let (None, Some(newsletter_issue_id)) = (&delivery, feedback.newsletter_issue_id) {
+        delivery = find_issue_delivery(&mut transaction, newsletter_issue_id, &feedback.email)
+            .await
+            .context("Failed to look up the delivery")?;
+    }
+
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO delivery_feedback (
+            feedback_id, source, event_key, kind, email_hash, message_id, reason, received_at,
+            newsletter_issue_id
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        ON CONFLICT (source, event_key) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        source,
+        feedback.event_key,
+        feedback.kind.as_str(),
+        email_hash(&feedback.email, email_hash_key),
+        feedback.message_id,
+        feedback.reason,
+        Utc::now(),
+        delivery
+            .as_ref()
+            .and_then(|delivery| delivery.newsletter_issue_id)
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to record the feedback")?
+    .rows_affected();
+
+    if inserted == 0 {
+        return Ok(false);
+    }
+
+    if let Some(status) = feedback.kind.status() {
+        let cause = format!("{}:{}", source, feedback.kind.as_str());
+        let subscriber_id = delivery.as_ref().map(|delivery| delivery.subscriber_id);
+        update_subscriber(&mut transaction, feedback, subscriber_id, status, &cause).await?;
+    }
+
+    if feedback.kind.suppresses() {
+        insert_suppression(
+            &mut transaction,
+            email_hash_key,
+            &feedback.email,
+            feedback.kind.as_str(),
+            source,
+        )
+        .await
+        .context("Failed to suppress the address")?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+
+    Ok(true)
+}
+
+/// The delivery a report is about.
+struct DeliveryMatch {
+    subscriber_id: Uuid,
+    /// Unknown for digests, which carry several issues in one email.
+    newsletter_issue_id: Option<Uuid>,
+}
+
+/// `message_id` is either the `Message-ID` we gave the email or the id the
+/// provider gave it.
+async fn find_delivery(
+    transaction: &mut Transaction<'_, Postgres>,
+    message_id: &str,
+) -> Result<Option<DeliveryMatch>, sqlx::Error> {
+    let deliveries = sqlx::query!(
+        r#"
+        SELECT subscriber_id, newsletter_issue_id
+        FROM newsletter_issue_deliveries
+        WHERE message_id = $1 OR provider_message_id = $1
+        "#,
+        message_id.trim()
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+
+    Ok(match deliveries.as_slice() {
+        [] => None,
+        [delivery] => Some(DeliveryMatch {
+            subscriber_id: delivery.subscriber_id,
+            newsletter_issue_id: Some(delivery.newsletter_issue_id),
+        }),
+        [delivery, ..] => Some(DeliveryMatch {
+            subscriber_id: delivery.subscriber_id,
+            newsletter_issue_id: None,
+        }),
+    })
+}
+
+/// The delivery of an issue to the subscriber with the address `email`.
+async fn find_issue_delivery(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    email: &str,
+) -> Result<Option<DeliveryMatch>, sqlx::Error> {
+    let delivery = sqlx::query!(
+        r#"
+        SELECT d.subscriber_id
+        FROM newsletter_issue_deliveries d
+        JOIN subscriptions s ON s.id = d.subscriber_id
+        WHERE d.newsletter_issue_id = $1 AND s.email_key = $2
+        "#,
+        newsletter_issue_id,
+        email_key(email)
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+
+    Ok(delivery.map(|delivery| DeliveryMatch {
+        subscriber_id: delivery.subscriber_id,
+        newsletter_issue_id: Some(newsletter_issue_id),
+    }))
+}
+
+/// The subscriber is the one the email was delivered to, if it is known,
+/// and the one with the reported address otherwise. A subscriber who can't
+/// make the move, e.g. a complaint from someone who already bounced, keeps
+/// their status.
+async fn update_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    feedback: &Feedback,
+    subscriber_id: Option<Uuid>,
+    status: SubscriptionStatus,
+    cause: &str,
+) -> Result<(), anyhow::Error> {
+    let subscriber_id = match subscriber_id {
+        Some(subscriber_id) => subscriber_id,
+        None => {
+            let subscriber = sqlx::query!(
+                "SELECT id FROM subscriptions WHERE email_key = $1",
+                email_key(&feedback.email)
+            )
+            .fetch_optional(&mut **transaction)
+            .await
+            .context("Failed to look up the subscriber")?;
+
+            match subscriber {
+                Some(subscriber) => subscriber.id,
+                None => return Ok(()),
+            }
+        }
+    };
+
+    match change_status(transaction, subscriber_id, status, cause).await {
+        Ok(_) => {}
+        Err(StatusError::IllegalTransition(e)) => {
+            tracing::info!("Ignoring feedback: {}", e);
+            return Ok(());
+        }
+        Err(e) => return Err(e).context("Failed to change the status of the subscriber"),
+    }
+
+    if status == SubscriptionStatus::Unsubscribed {
+        sqlx::query!(
+            r#"
+            UPDATE subscriptions
+            SET unsubscribed_at = now(), unsubscribe_reason = $2
+            WHERE id = $1
+            "#,
+            subscriber_id,
+            feedback.reason
+        )
+        .execute(&mut **transaction)
+        .await
+        .context("Failed to record the unsubscription")?;
+    }
+
+    Ok(())
+}
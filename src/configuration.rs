@@ -1,13 +1,22 @@
 //! src/configuration.rs
 use config::{Config, File};
+use ipnet::IpNet;
 use secrecy::{ExposeSecret, Secret};
 use serde::Deserialize;
+use std::path::{Path, PathBuf};
 
 #[derive(Deserialize, Clone, Debug)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email: Option<EmailSettings>,
+    pub feed_poller: FeedPollerSettings,
+    #[serde(default)]
+    pub spam_protection: SpamProtectionSettings,
+    #[serde(default)]
+    pub email_policy: EmailPolicySettings,
+    #[serde(default)]
+    pub webhooks: WebhookSettings,
 }
 
 impl Settings {
@@ -36,12 +45,35 @@ impl HmacSecret {
     }
 }
 
+/// Keys the hashes kept in place of email addresses: suppressions, delivery
+/// feedback and sign-up attempts. Kept apart from `HmacSecret` so that secret
+/// can be rotated: this key must never change, as every stored hash was
+/// computed with it and a new key would silently stop suppressions and
+/// erasures from matching.
+#[derive(Deserialize, Clone, Debug)]
+pub struct EmailHashKey(pub Secret<String>);
+
+impl EmailHashKey {
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.expose_secret().as_bytes()
+    }
+}
+
 #[derive(Deserialize, Clone, Debug)]
 pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub redis_uri: Secret<String>,
+    pub base_url: String,
     pub hmac_secret: Option<HmacSecret>,
+    pub email_hash_key: Option<EmailHashKey>,
+    /// Origins of the sites allowed to call the subscription API and embed
+    /// the sign-up form, e.g. `https://example.com`.
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    /// Directory of templates replacing the bundled ones for the public
+    /// pages, to brand them. See `TemplateOverrides`.
+    pub template_dir: Option<PathBuf>,
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -50,6 +82,173 @@ pub struct EmailSettings {
     pub api_url: String,
     pub sender_name: String,
     pub sender_email: String,
+    /// When set, issue emails carry a VERP return path at this domain naming
+    /// their recipient and issue, for SMTP relays that use it as the envelope
+    /// sender.
+    pub verp_domain: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct FeedPollerSettings {
+    pub urls: Vec<String>,
+    pub poll_interval_seconds: u64,
+    pub mode: FeedPollerMode,
+}
+
+/// How new feed entries are turned into newsletter issues.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedPollerMode {
+    /// All entries found by a poll go out together in a single issue.
+    Digest,
+    /// Every entry is sent as its own issue.
+    PerPost,
+}
+
+/// Limits applied to every sign-up before anything is stored or sent.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SpamProtectionSettings {
+    /// Forms sent back sooner than this after they were shown are rejected.
+    pub min_submit_seconds: i64,
+    /// Forms sent back later than this after they were shown are rejected.
+    pub max_stamp_age_seconds: i64,
+    /// Reject sign-ups through `/subscriptions` and the JSON API that carry
+    /// no stamp. Forms on other sites then have to fetch one from
+    /// `/api/v1/form-stamp`.
+    pub require_form_stamp: bool,
+    /// Sign-ups accepted from one IP address per window.
+    pub max_signups_per_ip: i64,
+    /// Sign-ups accepted for addresses at one domain per window.
+    pub max_signups_per_domain: i64,
+    pub rate_limit_window_seconds: i64,
+    /// Read the client address from `X-Forwarded-For`/`Forwarded`. Only
+    /// enable this behind a proxy that sets them.
+    pub trust_forwarded_for: bool,
+    /// Sign-ups must solve a CAPTCHA when set.
+    pub captcha: Option<CaptchaSettings>,
+}
+
+impl Default for SpamProtectionSettings {
+    fn default() -> Self {
+        Self {
+            min_submit_seconds: 3,
+            max_stamp_age_seconds: 86400,
+            require_form_stamp: false,
+            max_signups_per_ip: 10,
+            max_signups_per_domain: 100,
+            rate_limit_window_seconds: 3600,
+            trust_forwarded_for: false,
+            captcha: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct CaptchaSettings {
+    pub provider: CaptchaProvider,
+    pub site_key: String,
+    pub secret_key: Secret<String>,
+    /// Defaults to the provider's verification endpoint.
+    pub verify_url: Option<String>,
+}
+
+/// Both providers verify tokens the same way, they only differ in the
+/// script and markup of the challenge.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptchaProvider {
+    Hcaptcha,
+    Turnstile,
+}
+
+impl CaptchaProvider {
+    pub fn verify_url(&self) -> &'static str {
+        match self {
+            CaptchaProvider::Hcaptcha => "https://api.hcaptcha.com/siteverify",
+            CaptchaProvider::Turnstile => {
+                "https://challenges.cloudflare.com/turnstile/v0/siteverify"
+            }
+        }
+    }
+
+    pub fn script_url(&self) -> &'static str {
+        match self {
+            CaptchaProvider::Hcaptcha => "https://js.hcaptcha.com/1/api.js",
+            CaptchaProvider::Turnstile => "https://challenges.cloudflare.com/turnstile/v0/api.js",
+        }
+    }
+
+    /// Class of the element the provider's script turns into a challenge.
+    pub fn widget_class(&self) -> &'static str {
+        match self {
+            CaptchaProvider::Hcaptcha => "h-captcha",
+            CaptchaProvider::Turnstile => "cf-turnstile",
+        }
+    }
+}
+
+/// Addresses that are valid but that we don't want on the list.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct EmailPolicySettings {
+    /// Accept non-ASCII local parts, which only reach servers supporting
+    /// SMTPUTF8.
+    pub allow_smtputf8: bool,
+    /// Reject domains on the bundled list of disposable email providers.
+    pub block_disposable: bool,
+    /// More disposable domains, one per line, added to the bundled list.
+    pub disposable_domains_file: Option<String>,
+    /// Reject addresses like `noreply@` or `postmaster@`.
+    pub block_role_accounts: bool,
+    /// Reject domains without an MX, A or AAAA record.
+    pub check_mail_domain: bool,
+    pub resolver: ResolverSettings,
+}
+
+impl Default for EmailPolicySettings {
+    fn default() -> Self {
+        Self {
+            allow_smtputf8: false,
+            block_disposable: true,
+            disposable_domains_file: None,
+            block_role_accounts: true,
+            check_mail_domain: false,
+            resolver: ResolverSettings::System,
+        }
+    }
+}
+
+/// Where mail domains are looked up.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResolverSettings {
+    /// The nameservers of the host.
+    System,
+    /// Only these domains accept mail, for tests and offline setups.
+    Static(Vec<String>),
+}
+
+/// Endpoints through which email providers report bounces and complaints.
+/// Each one is disabled until it is configured.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct WebhookSettings {
+    pub brevo: Option<BrevoWebhookSettings>,
+}
+
+/// Requests must pass every check that is set, and at least one must be.
+#[derive(Deserialize, Clone, Debug)]
+pub struct BrevoWebhookSettings {
+    /// Sent by Brevo as a bearer token, or as the password of the webhook URL.
+    pub secret: Option<Secret<String>>,
+    /// Ranges Brevo sends webhooks from, e.g. `1.179.112.0/20`.
+    #[serde(default)]
+    pub allowed_ips: Vec<IpNet>,
+    /// Read the client address from `X-Forwarded-For`/`Forwarded`. Only
+    /// enable this behind a proxy that sets them.
+    #[serde(default)]
+    pub trust_forwarded_for: bool,
 }
 
 impl DatabaseSettings {
@@ -77,6 +276,18 @@ impl DatabaseSettings {
     }
 }
 
+/// The settings in `base.yml`, overridden by those of `environment`.
+fn read_settings(
+    configuration_directory: &Path,
+    environment: &Environment,
+) -> Result<Settings, config::ConfigError> {
+    Config::builder()
+        .add_source(File::from(configuration_directory.join("base")).required(true))
+        .add_source(File::from(configuration_directory.join(environment.as_str())).required(true))
+        .build()?
+        .try_deserialize()
+}
+
 #[derive(PartialEq)]
 pub enum Environment {
     Local,
@@ -116,12 +327,7 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
         .try_into()
         .expect("Failed to parse APP_ENVIRONMENT.");
 
-    let settings = Config::builder()
-        .add_source(File::from(configuration_directory.join("base")).required(true))
-        .add_source(File::from(configuration_directory.join(environment.as_str())).required(true))
-        .build()?;
-
-    let mut settings: Settings = settings.try_deserialize()?;
+    let mut settings = read_settings(&configuration_directory, &environment)?;
 
     if environment == Environment::Local {
         let secret_file_path = configuration_directory.join("secrets");
@@ -136,5 +342,29 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
     let hmac_secret = std::env::var("HMAC_SECRET").expect("HMAC_SECRET must be set");
     settings.application.hmac_secret = Some(HmacSecret(Secret::new(hmac_secret)));
 
+    let email_hash_key = std::env::var("EMAIL_HASH_KEY").expect("EMAIL_HASH_KEY must be set");
+    settings.application.email_hash_key = Some(EmailHashKey(Secret::new(email_hash_key)));
+
     Ok(settings)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{read_settings, Environment};
+    use std::path::Path;
+
+    #[test]
+    fn the_shipped_configuration_loads_in_every_environment() {
+        let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("configuration");
+
+        for environment in [Environment::Local, Environment::Production] {
+            let settings = read_settings(&directory, &environment);
+            assert!(
+                settings.is_ok(),
+                "{}: {:?}",
+                environment.as_str(),
+                settings.err()
+            );
+        }
+    }
+}
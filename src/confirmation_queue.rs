@@ -0,0 +1,209 @@
+//! src/confirmation_queue.rs
+//!
+//! Opt-in emails sent in the background, for sign-ups that arrive in bulk
+//! such as imports.
+use crate::configuration::Settings;
+use crate::domain::{Person, SubscriptionStatus};
+use crate::email::Brevo;
+use crate::lists::get_list;
+use crate::routes::send_confirmation_email;
+use anyhow::Context;
+use chrono::Utc;
+use secrecy::ExposeSecret;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// How long the worker waits when the queue is empty.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// Emails that still fail after this many attempts are dropped. The
+/// subscriber stays pending and can sign up again.
+const MAX_ATTEMPTS: i16 = 5;
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+pub async fn run_worker_until_stopped(config: Settings) -> Result<(), anyhow::Error> {
+    let pool = PgPool::connect_lazy(config.database.connection_string().expose_secret())
+        .expect("Failed to connect to Postgres.");
+    let email_client = Brevo::from_settings(
+        config.email.expect("Missing email settings"),
+        pool.clone(),
+        config
+            .application
+            .email_hash_key
+            .expect("Missing email hash key"),
+    );
+
+    loop {
+        match try_execute_task(&pool, &email_client, &config.application.base_url).await {
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+            Ok(ExecutionOutcome::EmptyQueue) => tokio::time::sleep(POLL_INTERVAL).await,
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to process the confirmation email queue"
+                );
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+/// The email is sent once the transaction is committed.
+#[tracing::instrument(name = "Enqueue confirmation email", skip(transaction, token))]
+pub(crate) async fn enqueue_confirmation(
+    transaction: &mut Transaction<'_, Postgres>,
+    token: &str,
+) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO confirmation_email_queue (subscription_token, execute_after, enqueued_at)
+        VALUES ($1, $2, $2)
+        "#,
+        token,
+        now
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// Send the oldest email that is due. Several workers can run at once: each
+/// email is locked by the worker sending it.
+#[tracing::instrument(name = "Send a queued confirmation email", skip_all)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &Brevo,
+    base_url: &str,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection")?;
+
+    let task = sqlx::query!(
+        r#"
+        SELECT q.subscription_token, q.n_retries, t.list_id, s.name, s.email,
+            s.status AS "status: SubscriptionStatus"
+        FROM confirmation_email_queue q
+        JOIN subscription_tokens t USING (subscription_token)
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        WHERE q.execute_after <= now()
+        ORDER BY q.enqueued_at
+        FOR UPDATE OF q SKIP LOCKED
+        LIMIT 1
+        "#
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to dequeue a confirmation email")?;
+    let task = match task {
+        Some(task) => task,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+
+    // Subscribers who confirmed some other way meanwhile need no email.
+    if task.status == SubscriptionStatus::PendingConfirmation {
+        if let Err(e) = send(
+            pool,
+            email_client,
+            base_url,
+            task.name,
+            task.email,
+            task.list_id,
+            &task.subscription_token,
+        )
+        .await
+        {
+            let attempts = task.n_retries + 1;
+            if attempts < MAX_ATTEMPTS {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    attempts,
+                    "Failed to send a confirmation email, it will be retried"
+                );
+                retry_later(&mut transaction, &task.subscription_token, attempts).await?;
+                transaction.commit().await?;
+                return Ok(ExecutionOutcome::TaskCompleted);
+            }
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                attempts,
+                "Failed to send a confirmation email, giving up"
+            );
+        }
+    }
+
+    sqlx::query!(
+        "DELETE FROM confirmation_email_queue WHERE subscription_token = $1",
+        task.subscription_token
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to remove a confirmation email from the queue")?;
+    transaction.commit().await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// Emails that can never be sent, to an invalid address or for a deleted
+/// list, are skipped rather than retried.
+async fn send(
+    pool: &PgPool,
+    email_client: &Brevo,
+    base_url: &str,
+    name: String,
+    email: String,
+    list_id: Uuid,
+    token: &str,
+) -> Result<(), anyhow::Error> {
+    let subscriber = match Person::parse(name, email.clone()) {
+        Ok(subscriber) => subscriber,
+        Err(e) => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                "Skipping the confirmation email of {} because {}", email, e);
+            return Ok(());
+        }
+    };
+    let list = match get_list(pool, list_id)
+        .await
+        .context("Failed to retrieve the list")?
+    {
+        Some(list) => list,
+        None => return Ok(()),
+    };
+
+    send_confirmation_email(email_client, &subscriber, &list, base_url, token).await
+}
+
+/// Waits a minute after the first failure, then twice as long each time.
+async fn retry_later(
+    transaction: &mut Transaction<'_, Postgres>,
+    token: &str,
+    attempts: i16,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE confirmation_email_queue
+        SET n_retries = $2, execute_after = now() + make_interval(mins => $3)
+        WHERE subscription_token = $1
+        "#,
+        token,
+        attempts,
+        2_i32.pow(attempts as u32 - 1)
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to reschedule a confirmation email")?;
+
+    Ok(())
+}
@@ -0,0 +1,310 @@
+//! src/digest.rs
+use crate::attributes::get_custom_fields;
+use crate::configuration::{HmacSecret, Settings};
+use crate::domain::{ListSubscriptionStatus, Person, SubscriptionStatus};
+use crate::email::{Brevo, SendError, Sent};
+use crate::issues::PublishedIssue;
+use crate::preferences::preferences_url;
+use crate::segments::{filter_by_segment, Segment};
+use crate::subscribers::{record_delivery, record_send_failure};
+use anyhow::Context;
+use askama::Template;
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use std::collections::HashSet;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// How often the worker looks for digests that are due.
+const POLL_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Template)]
+#[template(path = "email/digest.html")]
+struct DigestTemplate<'a> {
+    base_url: &'a str,
+    issues: &'a [PublishedIssue],
+    preferences_url: &'a str,
+}
+
+pub async fn run_worker_until_stopped(config: Settings) -> Result<(), anyhow::Error> {
+    let pool = PgPool::connect_lazy(config.database.connection_string().expose_secret())
+        .expect("Failed to connect to Postgres.");
+    let hmac_secret = config.application.hmac_secret.expect("Missing HMAC secret");
+    let email_client = Brevo::from_settings(
+        config.email.expect("Missing email settings"),
+        pool.clone(),
+        config
+            .application
+            .email_hash_key
+            .expect("Missing email hash key"),
+    );
+
+    loop {
+        if let Err(e) = send_due_digests(
+            &pool,
+            &email_client,
+            &config.application.base_url,
+            &hmac_secret,
+        )
+        .await
+        {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send digests"
+            );
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Email every weekly digest subscriber whose last digest is at least a week
+/// old the issues they have not received yet.
+/// Returns the number of digests sent.
+#[tracing::instrument(name = "Send due digests", skip(pool, email_client, hmac_secret))]
+pub async fn send_due_digests(
+    pool: &PgPool,
+    email_client: &Brevo,
+    base_url: &str,
+    hmac_secret: &HmacSecret,
+) -> Result<usize, anyhow::Error> {
+    let subscribers = get_due_subscribers(pool)
+        .await
+        .context("Failed to retrieve digest subscribers")?;
+
+    let mut sent = 0;
+    for row in subscribers {
+        let subscriber = match Person::parse(row.name, row.email.clone()) {
+            Ok(subscriber) => subscriber,
+            Err(e) => {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    "Skipping digest subscriber {} because {}", row.email, e);
+                continue;
+            }
+        };
+
+        // A list sender that fails to parse is ignored in favour of the
+        // default one, as for single issues.
+        let sender = match (row.sender_name, row.sender_email) {
+            (Some(name), Some(email)) => Person::parse(name, email).ok(),
+            _ => None,
+        };
+
+        let issues = get_undelivered_issues(pool, row.id)
+            .await
+            .context("Failed to retrieve issues for the digest")?;
+        let issues = keep_segment_matches(pool, row.id, issues).await?;
+        if issues.is_empty() {
+            continue;
+        }
+
+        let preferences_url = preferences_url(base_url, row.id, hmac_secret);
+        let html_content = DigestTemplate {
+            base_url,
+            issues: &issues,
+            preferences_url: &preferences_url,
+        }
+        .render()
+        .context("Failed to render the digest")?;
+
+        let subject = match &issues[..] {
+            [issue] => issue.title.clone(),
+            issues => format!("Your weekly digest: {} new issues", issues.len()),
+        };
+
+        let mut email = email_client.email_builder();
+        if let Some(sender) = &sender {
+            email = email.sender(sender);
+        }
+        let email = email
+            .subject(&subject)
+            .to(&subscriber)
+            .html_content(&html_content)
+            .build();
+
+        let outcome = match email_client.send_email(&email).await {
+            Ok(outcome) => outcome,
+            // The period doesn't end, so the digest is tried again on the
+            // next run.
+            Err(SendError::Provider(e)) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to send the digest to {}", subscriber.email
+                );
+                for issue in &issues {
+                    record_send_failure(
+                        pool,
+                        issue.newsletter_issue_id,
+                        &email_client.email_hash(subscriber.email.as_ref()),
+                        email.message_id(),
+                        &e.to_string(),
+                    )
+                    .await
+                    .context("Failed to record the failed send")?;
+                }
+                continue;
+            }
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to send the digest to {}", subscriber.email))
+            }
+        };
+        // The period ends for suppressed subscribers too, so their digest
+        // isn't tried again on every run.
+        mark_digest_sent(pool, row.id)
+            .await
+            .context("Failed to record the digest")?;
+        let (message_id, provider_message_id) = match outcome {
+            Sent::Accepted {
+                message_id,
+                provider_message_id,
+            } => (message_id, provider_message_id),
+            Sent::Suppressed => continue,
+        };
+
+        for issue in &issues {
+            record_delivery(
+                pool,
+                issue.newsletter_issue_id,
+                row.id,
+                &message_id,
+                provider_message_id.as_deref(),
+            )
+            .await
+            .context("Failed to record the delivery")?;
+        }
+
+        sent += 1;
+    }
+
+    Ok(sent)
+}
+
+struct Row {
+    id: Uuid,
+    name: String,
+    email: String,
+    sender_name: Option<String>,
+    sender_email: Option<String>,
+}
+
+/// Each subscriber appears once, with the sender of the oldest list they
+/// confirmed.
+async fn get_due_subscribers(pool: &PgPool) -> Result<Vec<Row>, sqlx::Error> {
+    sqlx::query_as!(
+        Row,
+        r#"
+        SELECT DISTINCT ON (s.id) s.id, s.email, s.name, l.sender_name, l.sender_email
+        FROM subscriptions s
+        JOIN list_subscriptions ls ON ls.subscriber_id = s.id AND ls.status = $1
+        JOIN lists l ON l.list_id = ls.list_id
+        WHERE s.status = $2
+            AND s.frequency = 'weekly_digest'
+            AND (s.paused_until IS NULL OR s.paused_until <= now())
+            AND (s.last_digest_at IS NULL OR s.last_digest_at <= now() - interval '7 days')
+        ORDER BY s.id, l.created_at
+        "#,
+        ListSubscriptionStatus::Confirmed as ListSubscriptionStatus,
+        SubscriptionStatus::Confirmed as SubscriptionStatus
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Oldest first. Only issues sent to a list the subscriber confirmed are
+/// included. Issues published before the current digest period started, or
+/// while the subscriber was paused, are left out.
+async fn get_undelivered_issues(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<PublishedIssue>, sqlx::Error> {
+    sqlx::query_as!(
+        PublishedIssue,
+        r#"
+        SELECT i.newsletter_issue_id, i.slug, i.title, i.html_content,
+            i.published_at AS "published_at!", i.updated_at
+        FROM newsletter_issues i
+        JOIN subscriptions s ON s.id = $1
+        WHERE i.published_at IS NOT NULL
+            AND i.published_at > COALESCE(s.last_digest_at, s.subscribed_at)
+            AND (s.paused_until IS NULL OR i.published_at > s.paused_until)
+            AND EXISTS (
+                SELECT 1 FROM newsletter_issue_lists il
+                JOIN list_subscriptions ls ON ls.list_id = il.list_id
+                WHERE il.newsletter_issue_id = i.newsletter_issue_id
+                    AND ls.subscriber_id = s.id
+                    AND ls.status = $2
+            )
+            AND NOT EXISTS (
+                SELECT 1 FROM newsletter_issue_deliveries d
+                WHERE d.newsletter_issue_id = i.newsletter_issue_id AND d.subscriber_id = s.id
+            )
+            AND NOT EXISTS (
+                SELECT 1 FROM subscriber_topic_opt_outs o
+                WHERE o.subscriber_id = s.id AND o.topic_id = i.topic_id
+            )
+        ORDER BY i.published_at
+        "#,
+        subscriber_id,
+        ListSubscriptionStatus::Confirmed as ListSubscriptionStatus
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Leave out the issues sent to a segment the subscriber is not part of.
+async fn keep_segment_matches(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    issues: Vec<PublishedIssue>,
+) -> Result<Vec<PublishedIssue>, anyhow::Error> {
+    let issue_ids: Vec<Uuid> = issues.iter().map(|i| i.newsletter_issue_id).collect();
+    let segments = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, segment AS "segment!"
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = ANY($1) AND segment IS NOT NULL
+        "#,
+        &issue_ids
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve issue segments")?;
+    if segments.is_empty() {
+        return Ok(issues);
+    }
+
+    let fields = get_custom_fields(pool)
+        .await
+        .context("Failed to retrieve custom fields")?;
+    let mut excluded = HashSet::new();
+    for row in segments {
+        let segment =
+            Segment::parse(&row.segment, &fields).context("Failed to parse the segment")?;
+        let matching = filter_by_segment(pool, &segment, &[subscriber_id])
+            .await
+            .context("Failed to match the subscriber against the segment")?;
+        if matching.is_empty() {
+            excluded.insert(row.newsletter_issue_id);
+        }
+    }
+
+    Ok(issues
+        .into_iter()
+        .filter(|issue| !excluded.contains(&issue.newsletter_issue_id))
+        .collect())
+}
+
+async fn mark_digest_sent(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE subscriptions SET last_digest_at = now() WHERE id = $1",
+        subscriber_id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,30 @@
+//! src/domain/list_subscription_status.rs
+use serde::{Deserialize, Serialize};
+
+/// Where a subscriber stands with one list, stored as the
+/// `list_subscription_status` Postgres enum. Each list has a double opt-in of
+/// its own, next to the `SubscriptionStatus` of the subscriber.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize, sqlx::Type)]
+#[sqlx(type_name = "list_subscription_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ListSubscriptionStatus {
+    PendingConfirmation,
+    Confirmed,
+    Unsubscribed,
+}
+
+impl ListSubscriptionStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ListSubscriptionStatus::PendingConfirmation => "pending_confirmation",
+            ListSubscriptionStatus::Confirmed => "confirmed",
+            ListSubscriptionStatus::Unsubscribed => "unsubscribed",
+        }
+    }
+}
+
+impl std::fmt::Display for ListSubscriptionStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
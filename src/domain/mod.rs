@@ -2,3 +2,12 @@
 
 pub mod person;
 pub use person::Person;
+
+mod list_subscription_status;
+pub use list_subscription_status::ListSubscriptionStatus;
+
+mod slug;
+pub use slug::Slug;
+
+mod subscription_status;
+pub use subscription_status::{SubscriptionStatus, TransitionError};
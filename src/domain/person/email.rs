@@ -10,6 +10,8 @@ pub enum Error {
     Invalid(String),
 }
 
+/// The local part as it was typed, at the lowercased, punycode form of the
+/// domain.
 #[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct Email(String);
 
@@ -19,11 +21,36 @@ impl Email {
             return Err(Error::Empty);
         }
 
-        if validate_email(&s) {
-            Ok(Self(s))
-        } else {
-            Err(Error::Invalid(format!("Invalid email: {}", s)))
+        let invalid = || Error::Invalid(format!("Invalid email: {}", s));
+        let (local_part, domain) = s.rsplit_once('@').ok_or_else(invalid)?;
+        let domain = idna::domain_to_ascii(domain).map_err(|_| invalid())?;
+
+        // `validate_email` only knows ASCII local parts. Other characters are
+        // allowed by SMTPUTF8 (RFC 6531), whether we accept them is up to the
+        // email policy.
+        let ascii_local_part: String = local_part
+            .chars()
+            .map(|c| match c {
+                c if c.is_ascii() || c.is_whitespace() || c.is_control() => c,
+                _ => 'x',
+            })
+            .collect();
+        if !validate_email(format!("{}@{}", ascii_local_part, domain)) {
+            return Err(invalid());
         }
+
+        Ok(Self(format!("{}@{}", local_part, domain)))
+    }
+
+    /// Identifies the mailbox: addresses differing only in case share a key.
+    pub fn key(&self) -> String {
+        self.0.to_lowercase()
+    }
+
+    /// Whether the local part has characters outside ASCII, which only
+    /// servers supporting SMTPUTF8 accept.
+    pub fn requires_smtputf8(&self) -> bool {
+        !self.0.is_ascii()
     }
 }
 
@@ -83,6 +110,31 @@ mod tests {
         matches!(result, Err(Error::Invalid(_)));
     }
 
+    #[test]
+    fn the_domain_is_normalised() {
+        let email = Email::parse("Ursula.LeGuin@Example.COM".to_string()).unwrap();
+
+        assert_eq!(email.as_ref(), "Ursula.LeGuin@example.com");
+        assert_eq!(email.key(), "ursula.leguin@example.com");
+    }
+
+    #[test]
+    fn internationalised_domains_are_converted_to_punycode() {
+        let email = Email::parse("ursula@Bücher.de".to_string()).unwrap();
+
+        assert_eq!(email.as_ref(), "ursula@xn--bcher-kva.de");
+        assert!(!email.requires_smtputf8());
+    }
+
+    #[test]
+    fn non_ascii_local_parts_require_smtputf8() {
+        let email = Email::parse("üñîçødé@example.com".to_string()).unwrap();
+        assert!(email.requires_smtputf8());
+
+        let result = Email::parse("ursula le guin@example.com".to_string());
+        assert!(std::matches!(result, Err(Error::Invalid(_))));
+    }
+
     #[derive(Debug, Clone)]
     struct ValidEmailFixture(pub String);
 
@@ -3,7 +3,7 @@ mod name;
 use name::Name;
 
 mod email;
-use email::Email;
+pub use email::Email;
 
 use crate::routes::SubscriberForm;
 use serde::{Deserialize, Serialize};
@@ -17,6 +17,16 @@ pub enum Error {
     EmailError(#[from] email::Error),
 }
 
+impl Error {
+    /// The field the error is about, as named in forms and JSON payloads.
+    pub fn field(&self) -> &'static str {
+        match self {
+            Error::NameError(_) => "name",
+            Error::EmailError(_) => "email",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct Person {
     pub name: Name,
@@ -30,6 +40,19 @@ impl Person {
             email: Email::parse(email)?,
         })
     }
+
+    /// Like `parse`, but checks both fields and reports every error.
+    pub fn parse_each(name: String, email: String) -> Result<Self, Vec<Error>> {
+        match (Name::parse(name), Email::parse(email)) {
+            (Ok(name), Ok(email)) => Ok(Self { name, email }),
+            (name, email) => Err(name
+                .err()
+                .map(Error::from)
+                .into_iter()
+                .chain(email.err().map(Error::from))
+                .collect()),
+        }
+    }
 }
 
 impl TryFrom<SubscriberForm> for Person {
@@ -107,4 +130,17 @@ mod test {
 
         matches!(Person::parse(name, email), Err(Error::EmailError(_)));
     }
+
+    #[test]
+    fn every_invalid_field_is_reported() {
+        let name = "".to_string();
+        let email = "this is not an email".to_string();
+
+        let fields: Vec<_> = Person::parse_each(name, email)
+            .unwrap_err()
+            .iter()
+            .map(Error::field)
+            .collect();
+        assert_eq!(fields, ["name", "email"]);
+    }
 }
@@ -0,0 +1,87 @@
+//! src/domain/slug.rs
+use serde::{Deserialize, Serialize};
+
+const MAX_LENGTH: usize = 80;
+const FALLBACK: &str = "issue";
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Slug(String);
+
+impl Slug {
+    /// Derive a URL-safe slug from a title: lowercase ASCII alphanumerics
+    /// separated by single dashes, e.g. `"Hello, World!"` becomes `hello-world`.
+    pub fn from_title(title: &str) -> Self {
+        let mut slug = String::with_capacity(title.len());
+
+        for c in title.chars() {
+            if c.is_ascii_alphanumeric() {
+                slug.push(c.to_ascii_lowercase());
+            } else if !slug.is_empty() && !slug.ends_with('-') {
+                slug.push('-');
+            }
+
+            if slug.len() >= MAX_LENGTH {
+                break;
+            }
+        }
+
+        let slug = slug.trim_end_matches('-');
+        if slug.is_empty() {
+            Self(FALLBACK.to_string())
+        } else {
+            Self(slug.to_string())
+        }
+    }
+
+    /// Used to disambiguate issues sharing the same title: `hello-world-2`.
+    pub fn with_suffix(&self, n: u32) -> Self {
+        Self(format!("{}-{}", self.0, n))
+    }
+}
+
+impl AsRef<str> for Slug {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for Slug {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn punctuation_and_whitespace_become_single_dashes() {
+        let slug = Slug::from_title("  Hello,   World! ");
+        assert_eq!(slug.as_ref(), "hello-world");
+    }
+
+    #[test]
+    fn non_ascii_characters_are_dropped() {
+        let slug = Slug::from_title("Café & crème brûlée");
+        assert_eq!(slug.as_ref(), "caf-cr-me-br-l-e");
+    }
+
+    #[test]
+    fn titles_without_usable_characters_fall_back() {
+        let slug = Slug::from_title("¡¿!?");
+        assert_eq!(slug.as_ref(), FALLBACK);
+    }
+
+    #[test]
+    fn long_titles_are_truncated() {
+        let slug = Slug::from_title(&"a".repeat(500));
+        assert_eq!(slug.as_ref().len(), MAX_LENGTH);
+    }
+
+    #[test]
+    fn suffix_is_appended_with_a_dash() {
+        let slug = Slug::from_title("Weekly update").with_suffix(2);
+        assert_eq!(slug.as_ref(), "weekly-update-2");
+    }
+}
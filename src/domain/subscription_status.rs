@@ -0,0 +1,160 @@
+//! src/domain/subscription_status.rs
+use serde::{Deserialize, Serialize};
+
+/// Where a subscriber stands, stored as the `subscription_status` Postgres enum.
+///
+/// ```text
+/// pending_confirmation --confirm--> confirmed --unsubscribe--> unsubscribed
+///          ^                                                        |
+///          +----------------------resubscribe-----------------------+
+///
+/// any status --bounce / complain / suppress--> bounced / complained / suppressed
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize, sqlx::Type)]
+#[sqlx(type_name = "subscription_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionStatus {
+    PendingConfirmation,
+    Confirmed,
+    Unsubscribed,
+    /// Mail to the address bounced for good.
+    Bounced,
+    /// The subscriber marked a newsletter as spam.
+    Complained,
+    /// The address must not be mailed, e.g. after an erasure request.
+    Suppressed,
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("A subscriber can't go from {from} to {to}")]
+pub struct TransitionError {
+    pub from: SubscriptionStatus,
+    pub to: SubscriptionStatus,
+}
+
+impl SubscriptionStatus {
+    pub const ALL: [SubscriptionStatus; 6] = [
+        SubscriptionStatus::PendingConfirmation,
+        SubscriptionStatus::Confirmed,
+        SubscriptionStatus::Unsubscribed,
+        SubscriptionStatus::Bounced,
+        SubscriptionStatus::Complained,
+        SubscriptionStatus::Suppressed,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionStatus::PendingConfirmation => "pending_confirmation",
+            SubscriptionStatus::Confirmed => "confirmed",
+            SubscriptionStatus::Unsubscribed => "unsubscribed",
+            SubscriptionStatus::Bounced => "bounced",
+            SubscriptionStatus::Complained => "complained",
+            SubscriptionStatus::Suppressed => "suppressed",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|status| status.as_str() == s)
+    }
+
+    /// Whether a subscriber can be moved from this status to `to`.
+    pub fn can_become(self, to: SubscriptionStatus) -> bool {
+        use SubscriptionStatus::*;
+
+        match (self, to) {
+            (PendingConfirmation, Confirmed) => true,
+            (Confirmed, Unsubscribed) => true,
+            (Unsubscribed, PendingConfirmation) => true,
+            (from, Bounced | Complained | Suppressed) => from != to,
+            _ => false,
+        }
+    }
+
+    pub fn transition_to(self, to: SubscriptionStatus) -> Result<Self, TransitionError> {
+        if self.can_become(to) {
+            Ok(to)
+        } else {
+            Err(TransitionError { from: self, to })
+        }
+    }
+
+    pub fn confirm(self) -> Result<Self, TransitionError> {
+        self.transition_to(SubscriptionStatus::Confirmed)
+    }
+
+    pub fn unsubscribe(self) -> Result<Self, TransitionError> {
+        self.transition_to(SubscriptionStatus::Unsubscribed)
+    }
+
+    /// Signing up again after unsubscribing starts a new double opt-in.
+    pub fn resubscribe(self) -> Result<Self, TransitionError> {
+        self.transition_to(SubscriptionStatus::PendingConfirmation)
+    }
+
+    pub fn bounce(self) -> Result<Self, TransitionError> {
+        self.transition_to(SubscriptionStatus::Bounced)
+    }
+
+    pub fn complain(self) -> Result<Self, TransitionError> {
+        self.transition_to(SubscriptionStatus::Complained)
+    }
+
+    pub fn suppress(self) -> Result<Self, TransitionError> {
+        self.transition_to(SubscriptionStatus::Suppressed)
+    }
+}
+
+impl std::fmt::Display for SubscriptionStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriptionStatus::*;
+    use super::*;
+
+    #[test]
+    fn subscribers_confirm_then_unsubscribe() {
+        let status = PendingConfirmation.confirm().unwrap();
+        assert_eq!(status, Confirmed);
+
+        let status = status.unsubscribe().unwrap();
+        assert_eq!(status, Unsubscribed);
+
+        assert_eq!(status.resubscribe(), Ok(PendingConfirmation));
+    }
+
+    #[test]
+    fn illegal_moves_are_rejected() {
+        assert_eq!(
+            Unsubscribed.confirm(),
+            Err(TransitionError {
+                from: Unsubscribed,
+                to: Confirmed
+            })
+        );
+        assert!(Confirmed.confirm().is_err());
+        assert!(PendingConfirmation.unsubscribe().is_err());
+        assert!(Bounced.resubscribe().is_err());
+        assert!(Suppressed.confirm().is_err());
+    }
+
+    #[test]
+    fn any_status_can_bounce_complain_or_be_suppressed() {
+        for status in SubscriptionStatus::ALL {
+            for to in [Bounced, Complained, Suppressed] {
+                assert_eq!(status.can_become(to), status != to, "{} -> {}", status, to);
+            }
+        }
+    }
+
+    #[test]
+    fn statuses_are_parsed_from_their_names() {
+        for status in SubscriptionStatus::ALL {
+            assert_eq!(SubscriptionStatus::parse(status.as_str()), Some(status));
+        }
+        assert_eq!(SubscriptionStatus::parse("active"), None);
+    }
+}
@@ -0,0 +1,316 @@
+//! src/dsn.rs
+//!
+//! Bounces and complaints that arrive as emails: delivery status
+//! notifications (RFC 3464) and abuse reports in the Abuse Reporting Format
+//! (RFC 5965).
+use crate::bounces::{Feedback, FeedbackKind};
+use crate::verp::{self, Verp};
+use mailparse::{parse_headers, parse_mail, MailHeaderMap, MailParseError, ParsedMail};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Headers that carry the address a bounce was delivered to, which is the
+/// envelope sender of the email that bounced.
+const ENVELOPE_HEADERS: [&str; 4] = ["X-Original-To", "Delivered-To", "Envelope-To", "To"];
+
+/// What one report says about one recipient, before we know who they are
+/// for sure.
+struct Report {
+    kind: FeedbackKind,
+    recipient: Option<String>,
+    /// Named with the recipient by a VERP address.
+    newsletter_issue_id: Option<Uuid>,
+    reason: Option<String>,
+}
+
+/// The reports in an email. Emails that are neither delivery status
+/// notifications nor abuse reports, like out of office replies, hold none.
+///
+/// The recipient of each report is taken, in order, from a VERP envelope
+/// sender, from the report itself and from the headers of the returned
+/// email. VERP envelope senders we set also name the issue.
+pub fn parse_report(raw: &[u8]) -> Result<Vec<Feedback>, MailParseError> {
+    let mail = parse_mail(raw)?;
+    if mail.ctype.mimetype != "multipart/report" {
+        return Ok(vec![]);
+    }
+
+    let reports = match report_type(&mail).as_deref() {
+        Some("delivery-status") => delivery_status(&mail)?,
+        Some("feedback-report") => feedback_report(&mail)?,
+        _ => vec![],
+    };
+
+    let envelope = ENVELOPE_HEADERS
+        .iter()
+        .flat_map(|name| mail.headers.get_all_values(name))
+        .find_map(|value| address(&value).and_then(|address| verp::decode(&address)));
+    let (message_id, original_to) = returned_headers(&mail)?;
+    // The same message read twice, e.g. from an mbox, yields the same keys.
+    let digest = hex::encode(Sha256::digest(raw));
+
+    let feedback = reports
+        .into_iter()
+        .filter_map(|report| {
+            let (recipient, newsletter_issue_id) = match envelope.clone() {
+                Some(Verp {
+                    recipient,
+                    newsletter_issue_id,
+                }) => (Some(recipient), newsletter_issue_id),
+                None => (report.recipient, report.newsletter_issue_id),
+            };
+            let email = recipient.or_else(|| original_to.clone())?;
+            Some(Feedback {
+                kind: report.kind,
+                event_key: format!("{}:{}", digest, email.to_lowercase()),
+                email,
+                message_id: message_id.clone(),
+                newsletter_issue_id,
+                reason: report.reason,
+            })
+        })
+        .collect();
+
+    Ok(feedback)
+}
+
+fn report_type(mail: &ParsedMail) -> Option<String> {
+    mail.ctype
+        .params
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("report-type"))
+        .map(|(_, value)| value.to_ascii_lowercase())
+}
+
+fn find_part<'a, 'b>(mail: &'b ParsedMail<'a>, mimetypes: &[&str]) -> Option<&'b ParsedMail<'a>> {
+    mail.subparts
+        .iter()
+        .find(|part| mimetypes.contains(&part.ctype.mimetype.as_str()))
+}
+
+/// One report per recipient that could not be reached. Delays and
+/// successful deliveries are left out.
+fn delivery_status(mail: &ParsedMail) -> Result<Vec<Report>, MailParseError> {
+    let body = match find_part(mail, &["message/delivery-status"]) {
+        Some(part) => part.get_body_raw()?,
+        None => return Ok(vec![]),
+    };
+
+    let mut reports = vec![];
+    // The fields about the message come first, then one group of fields
+    // per recipient, each group ending with a blank line.
+    let mut rest = body.as_slice();
+    let mut first = true;
+    loop {
+        while let Some(line_break) = rest.iter().position(|byte| *byte == b'\n') {
+            if rest[..line_break].iter().all(u8::is_ascii_whitespace) {
+                rest = &rest[line_break + 1..];
+            } else {
+                break;
+            }
+        }
+        let (fields, consumed) = parse_headers(rest)?;
+        if fields.is_empty() || consumed == 0 {
+            break;
+        }
+        rest = &rest[consumed..];
+        if std::mem::take(&mut first) {
+            continue;
+        }
+
+        let action = fields.get_first_value("Action").unwrap_or_default();
+        if !action.trim().eq_ignore_ascii_case("failed") {
+            continue;
+        }
+        let status = fields.get_first_value("Status").unwrap_or_default();
+        let kind = if status.trim().starts_with('4') {
+            FeedbackKind::SoftBounce
+        } else {
+            FeedbackKind::HardBounce
+        };
+        let recipient = fields
+            .get_first_value("Original-Recipient")
+            .or_else(|| fields.get_first_value("Final-Recipient"))
+            .and_then(|value| address(&value));
+        let reason = fields
+            .get_first_value("Diagnostic-Code")
+            .or(Some(status))
+            .map(|reason| reason.trim().to_string())
+            .filter(|reason| !reason.is_empty());
+
+        reports.push(Report {
+            kind,
+            recipient,
+            newsletter_issue_id: None,
+            reason,
+        });
+    }
+
+    Ok(reports)
+}
+
+/// Abuse reports are about a single email. Other feedback, like
+/// `not-spam` or `auth-failure`, is left out.
+fn feedback_report(mail: &ParsedMail) -> Result<Vec<Report>, MailParseError> {
+    let body = match find_part(mail, &["message/feedback-report"]) {
+        Some(part) => part.get_body_raw()?,
+        None => return Ok(vec![]),
+    };
+    let (fields, _) = parse_headers(&body)?;
+
+    let feedback_type = fields
+        .get_first_value("Feedback-Type")
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    if !matches!(
+        feedback_type.as_str(),
+        "abuse" | "fraud" | "virus" | "other"
+    ) {
+        return Ok(vec![]);
+    }
+
+    // Providers often redact the recipient, but not the envelope sender.
+    let envelope = fields
+        .get_first_value("Original-Mail-From")
+        .and_then(|value| address(&value))
+        .and_then(|address| verp::decode(&address));
+    let newsletter_issue_id = envelope
+        .as_ref()
+        .and_then(|envelope| envelope.newsletter_issue_id);
+    let recipient = envelope.map(|envelope| envelope.recipient).or_else(|| {
+        fields
+            .get_first_value("Original-Rcpt-To")
+            .and_then(|value| address(&value))
+    });
+
+    Ok(vec![Report {
+        kind: FeedbackKind::Complaint,
+        recipient,
+        newsletter_issue_id,
+        reason: Some(feedback_type),
+    }])
+}
+
+/// The `Message-ID` and the recipient of the email the report is about, if
+/// it was sent back with the report.
+fn returned_headers(mail: &ParsedMail) -> Result<(Option<String>, Option<String>), MailParseError> {
+    let part = find_part(
+        mail,
+        &[
+            "message/rfc822",
+            "text/rfc822-headers",
+            "message/rfc822-headers",
+        ],
+    );
+    let body = match part {
+        Some(part) => part.get_body_raw()?,
+        None => return Ok((None, None)),
+    };
+    let (headers, _) = parse_headers(&body)?;
+
+    let message_id = headers
+        .get_first_value("Message-ID")
+        .map(|id| id.trim().to_string());
+    // An email sent to several people can't tell which of them bounced.
+    let to = headers
+        .get_first_value("To")
+        .filter(|to| !to.contains(','))
+        .and_then(|to| address(&to));
+
+    Ok((message_id, to))
+}
+
+/// The address in a field like `rfc822; someone@example.com` or a header
+/// like `Someone <someone@example.com>`.
+fn address(value: &str) -> Option<String> {
+    let value = match value.split_once(';') {
+        Some((address_type, address)) if !address_type.contains('@') => address,
+        _ => value,
+    };
+    let value = match (value.find('<'), value.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &value[start + 1..end],
+        _ => value,
+    };
+    let value = value.trim();
+
+    value.contains('@').then(|| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HARD_BOUNCE: &str = include_str!("../tests/api/fixtures/mail/hard_bounce.eml");
+    const MIXED: &str = include_str!("../tests/api/fixtures/mail/mixed_dsn.eml");
+    const COMPLAINT: &str = include_str!("../tests/api/fixtures/mail/complaint.eml");
+
+    #[test]
+    fn the_verp_address_names_the_recipient_of_a_bounce() {
+        let feedback = parse_report(HARD_BOUNCE.as_bytes()).unwrap();
+
+        assert_eq!(feedback.len(), 1);
+        assert_eq!(feedback[0].kind, FeedbackKind::HardBounce);
+        // Not the address the email was forwarded to
+        assert_eq!(feedback[0].email, "ursula_le_guin@gmail.com");
+        assert_eq!(
+            feedback[0].message_id.as_deref(),
+            Some("<issue-1.ursula@letter.example>")
+        );
+        assert!(feedback[0]
+            .reason
+            .as_deref()
+            .unwrap()
+            .contains("User unknown"));
+    }
+
+    #[test]
+    fn without_verp_the_recipients_of_the_report_are_used() {
+        let feedback = parse_report(MIXED.as_bytes()).unwrap();
+
+        // The delayed recipient may still get the email
+        let recipients: Vec<_> = feedback
+            .iter()
+            .map(|feedback| (feedback.kind, feedback.email.as_str()))
+            .collect();
+        assert_eq!(
+            recipients,
+            [
+                (FeedbackKind::HardBounce, "a@example.com"),
+                (FeedbackKind::SoftBounce, "c@example.com")
+            ]
+        );
+        assert_ne!(feedback[0].event_key, feedback[1].event_key);
+    }
+
+    #[test]
+    fn abuse_reports_are_complaints() {
+        let feedback = parse_report(COMPLAINT.as_bytes()).unwrap();
+
+        assert_eq!(feedback.len(), 1);
+        assert_eq!(feedback[0].kind, FeedbackKind::Complaint);
+        assert_eq!(feedback[0].email, "ursula_le_guin@gmail.com");
+        assert_eq!(feedback[0].reason.as_deref(), Some("abuse"));
+    }
+
+    #[test]
+    fn other_emails_hold_no_report() {
+        let reply =
+            b"From: ursula_le_guin@gmail.com\r\nSubject: Out of office\r\n\r\nBack on Monday.\r\n";
+
+        assert!(parse_report(reply).unwrap().is_empty());
+    }
+
+    #[test]
+    fn addresses_are_read_from_fields_and_headers() {
+        assert_eq!(
+            address("rfc822; a@example.com").as_deref(),
+            Some("a@example.com")
+        );
+        assert_eq!(
+            address("le guin <ursula_le_guin@gmail.com>").as_deref(),
+            Some("ursula_le_guin@gmail.com")
+        );
+        assert_eq!(address("rfc822; unknown"), None);
+    }
+}
@@ -4,6 +4,7 @@ use reqwest::Client;
 use secrecy::{ExposeSecret, Secret};
 use serde::Serialize;
 use std::time::Duration;
+use uuid::Uuid;
 
 #[derive(Debug, Serialize)]
 pub struct Email<'a> {
@@ -12,6 +13,19 @@ pub struct Email<'a> {
     pub subject: &'a str,
     #[serde(rename = "htmlContent")]
     pub html_content: &'a str,
+    pub headers: Headers,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Headers {
+    /// Generated for every email, so that reports about it can be traced
+    /// back to it.
+    #[serde(rename = "Message-ID")]
+    pub message_id: String,
+    /// Where bounces go, for relays that take the envelope sender from it.
+    /// Brevo picks its own.
+    #[serde(rename = "Return-Path", skip_serializing_if = "Option::is_none")]
+    pub return_path: Option<String>,
 }
 
 pub struct EmailBuilder<'a> {
@@ -19,6 +33,7 @@ pub struct EmailBuilder<'a> {
     to: Vec<&'a Person>,
     subject: &'a str,
     html_content: &'a str,
+    return_path: Option<String>,
 }
 
 impl<'a> EmailBuilder<'a> {
@@ -28,9 +43,16 @@ impl<'a> EmailBuilder<'a> {
             to: vec![],
             subject: "",
             html_content: "",
+            return_path: None,
         }
     }
 
+    /// Send as someone other than the sender from the email settings.
+    pub fn sender(mut self, sender: &'a Person) -> Self {
+        self.sender = sender;
+        self
+    }
+
     pub fn to(mut self, person: &'a Person) -> Self {
         self.to.push(person);
         self
@@ -46,16 +68,55 @@ impl<'a> EmailBuilder<'a> {
         self
     }
 
+    pub fn return_path(mut self, return_path: Option<String>) -> Self {
+        self.return_path = return_path;
+        self
+    }
+
     pub fn build(self) -> Email<'a> {
+        let sender_domain = self
+            .sender
+            .email
+            .as_ref()
+            .rsplit_once('@')
+            .map(|(_, domain)| domain)
+            .unwrap_or("localhost");
+        let message_id = format!("<{}@{}>", Uuid::new_v4().simple(), sender_domain);
+
         Email {
             sender: self.sender,
             to: self.to,
             subject: self.subject,
             html_content: self.html_content,
+            headers: Headers {
+                message_id,
+                return_path: self.return_path,
+            },
         }
     }
 }
 
+impl<'a> Email<'a> {
+    /// The same email, sent to `to` instead.
+    pub fn with_recipients(&self, to: Vec<&'a Person>) -> Self {
+        Email {
+            sender: self.sender,
+            to,
+            subject: self.subject,
+            html_content: self.html_content,
+            headers: self.headers.clone(),
+        }
+    }
+
+    pub fn message_id(&self) -> &str {
+        &self.headers.message_id
+    }
+
+    pub fn return_path(&self) -> Option<&str> {
+        self.headers.return_path.as_deref()
+    }
+}
+
 #[derive(Debug)]
 pub struct EmailClient {
     http_client: Client,
@@ -234,4 +295,43 @@ mod tests {
         let result = client.send_email(&email).await;
         assert_err!(result);
     }
+
+    #[test]
+    fn every_email_gets_its_own_message_id() {
+        let sender = Person::parse("Letter".into(), "newsletter@letter.example".into()).unwrap();
+        let recipient = person();
+
+        let first = EmailBuilder::new(&sender).to(&recipient).build();
+        let second = EmailBuilder::new(&sender).to(&recipient).build();
+
+        assert!(first.message_id().starts_with('<'));
+        assert!(first.message_id().ends_with("@letter.example>"));
+        assert_ne!(first.message_id(), second.message_id());
+        let json = serde_json::to_value(&first).unwrap();
+        assert_eq!(json["headers"]["Message-ID"], first.message_id());
+    }
+
+    #[test]
+    fn the_return_path_is_only_sent_when_set() {
+        let sender = Person::parse("Letter".into(), "newsletter@letter.example".into()).unwrap();
+        let recipient = person();
+
+        let plain = EmailBuilder::new(&sender).to(&recipient).build();
+        let verp = EmailBuilder::new(&sender)
+            .to(&recipient)
+            .return_path(Some("bounces+a=b.example@letter.example".into()))
+            .build();
+
+        let plain = serde_json::to_value(&plain).unwrap();
+        assert!(plain["headers"].get("Return-Path").is_none());
+        assert_eq!(
+            verp.return_path(),
+            Some("bounces+a=b.example@letter.example")
+        );
+        let verp = serde_json::to_value(&verp).unwrap();
+        assert_eq!(
+            verp["headers"]["Return-Path"],
+            "bounces+a=b.example@letter.example"
+        );
+    }
 }
@@ -1,21 +1,62 @@
 //! src/email/brevo/mod.rs
-use crate::configuration::EmailSettings;
+use crate::configuration::{EmailHashKey, EmailSettings};
 use crate::domain::Person;
-use serde::Serialize;
+use crate::suppressions::SuppressionList;
+use crate::verp;
+use sqlx::PgPool;
+use uuid::Uuid;
 
 mod email;
-use email::{EmailBuilder, EmailClient};
+use email::{Email, EmailBuilder, EmailClient};
 
 mod secret;
 
+#[derive(thiserror::Error, Debug)]
+pub enum SendError {
+    /// Nothing is sent when we can't tell whether the address is suppressed.
+    #[error("Failed to check the suppression list")]
+    SuppressionCheck(#[from] sqlx::Error),
+    /// Brevo answered with an error, or could not be reached.
+    #[error("Brevo did not accept the email")]
+    Provider(#[source] reqwest::Error),
+}
+
+/// What became of an email handed to `Brevo::send_email`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Sent {
+    Accepted {
+        /// The `Message-ID` of the email.
+        message_id: String,
+        /// The `messageId` Brevo answered with, if it could be read.
+        provider_message_id: Option<String>,
+    },
+    /// Every recipient is on the suppression list, so nothing was sent.
+    Suppressed,
+}
+
+/// Brevo's answer to an email it accepted.
+#[derive(serde::Deserialize)]
+struct SendResponse {
+    #[serde(rename = "messageId")]
+    message_id: Option<String>,
+}
+
 #[derive(Debug)]
 pub struct Brevo {
     sender: Person,
     email_client: EmailClient,
+    suppressions: SuppressionList,
+    verp_domain: Option<String>,
 }
 
-impl From<EmailSettings> for Brevo {
-    fn from(email_settings: EmailSettings) -> Self {
+impl Brevo {
+    /// Every email goes through the suppression list kept in `pool`, whose
+    /// addresses are hashed with `email_hash_key`.
+    pub fn from_settings(
+        email_settings: EmailSettings,
+        pool: PgPool,
+        email_hash_key: EmailHashKey,
+    ) -> Self {
         let name = email_settings.sender_name.clone();
         let email = email_settings.sender_email.clone();
 
@@ -26,15 +67,25 @@ impl From<EmailSettings> for Brevo {
             email_settings.api_key.clone(),
         );
 
-        Self::new(sender, email_client)
+        Self::new(
+            sender,
+            email_client,
+            SuppressionList::new(pool, email_hash_key),
+            email_settings.verp_domain,
+        )
     }
-}
 
-impl Brevo {
-    fn new(sender: Person, email_client: EmailClient) -> Self {
+    fn new(
+        sender: Person,
+        email_client: EmailClient,
+        suppressions: SuppressionList,
+        verp_domain: Option<String>,
+    ) -> Self {
         Self {
             sender,
             email_client,
+            suppressions,
+            verp_domain,
         }
     }
 
@@ -42,11 +93,42 @@ impl Brevo {
         EmailBuilder::new(&self.sender)
     }
 
-    pub async fn send_email<T>(&self, email: &T) -> Result<(), reqwest::Error>
-    where
-        T: Serialize,
-    {
-        let _ = self.email_client.send_email(email).await;
-        Ok(())
+    /// The VERP return path of the issue sent to `recipient`, if a VERP
+    /// domain is configured.
+    pub fn return_path(&self, recipient: &Person, newsletter_issue_id: Uuid) -> Option<String> {
+        let domain = self.verp_domain.as_deref()?;
+
+        verp::encode(recipient.email.as_ref(), newsletter_issue_id, domain)
+    }
+
+    /// The hash kept of `email` in place of the address, as the suppression
+    /// list keeps it.
+    pub fn email_hash(&self, email: &str) -> String {
+        self.suppressions.email_hash(email)
+    }
+
+    /// Suppressed recipients are left out of the email.
+    pub async fn send_email(&self, email: &Email<'_>) -> Result<Sent, SendError> {
+        let recipients = self.suppressions.allowed(&email.to).await?;
+        if recipients.is_empty() {
+            return Ok(Sent::Suppressed);
+        }
+
+        let response = self
+            .email_client
+            .send_email(&email.with_recipients(recipients))
+            .await
+            .map_err(SendError::Provider)?;
+        // Without Brevo's id, reports about the email are matched by our own.
+        let provider_message_id = response
+            .json::<SendResponse>()
+            .await
+            .ok()
+            .and_then(|response| response.message_id);
+
+        Ok(Sent::Accepted {
+            message_id: email.message_id().to_string(),
+            provider_message_id,
+        })
     }
 }
@@ -1,2 +1,2 @@
 mod brevo;
-pub use brevo::Brevo;
+pub use brevo::{Brevo, SendError, Sent};
@@ -0,0 +1,329 @@
+//! src/email_policy/mod.rs
+//!
+//! Checks on the address itself, beyond its syntax: whether it belongs to a
+//! disposable provider, is a role account, or has a domain that takes mail.
+use crate::configuration::{EmailPolicySettings, ResolverSettings};
+use anyhow::Context;
+use futures_util::future::BoxFuture;
+use hickory_resolver::error::ResolveErrorKind;
+use hickory_resolver::TokioAsyncResolver;
+use std::collections::HashSet;
+
+/// Update this list by editing the file, or add to it with
+/// `email_policy.disposable_domains_file`.
+const DISPOSABLE_DOMAINS: &str = include_str!("disposable_domains.txt");
+
+/// Local parts of addresses nobody reads, or that a whole team reads.
+const ROLE_ACCOUNTS: &[&str] = &[
+    "abuse",
+    "do-not-reply",
+    "donotreply",
+    "hostmaster",
+    "mailer-daemon",
+    "no-reply",
+    "noreply",
+    "postmaster",
+    "root",
+    "webmaster",
+];
+
+/// Why an address was turned away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    Smtputf8,
+    Disposable,
+    RoleAccount,
+    NoMailHost,
+}
+
+impl Violation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Violation::Smtputf8 => "smtputf8",
+            Violation::Disposable => "disposable",
+            Violation::RoleAccount => "role_account",
+            Violation::NoMailHost => "no_mail_host",
+        }
+    }
+
+    /// Shown to whoever sent the sign-up.
+    pub fn message(&self) -> &'static str {
+        match self {
+            Violation::Smtputf8 => {
+                "Addresses with accents or other non-ASCII characters before the @ are not supported"
+            }
+            Violation::Disposable => {
+                "Disposable email addresses are not accepted, please use a permanent one"
+            }
+            Violation::RoleAccount => {
+                "Please use a personal address rather than one like noreply@ or postmaster@"
+            }
+            Violation::NoMailHost => {
+                "The domain of this email address does not receive mail, please check it for typos"
+            }
+        }
+    }
+}
+
+/// Tells whether a domain can receive mail. Tests use `StaticResolver`
+/// rather than asking real nameservers.
+pub trait MailDomainResolver: Send + Sync + std::fmt::Debug {
+    fn accepts_mail<'a>(&'a self, domain: &'a str) -> BoxFuture<'a, Result<bool, anyhow::Error>>;
+}
+
+/// Looks up MX records, then A and AAAA records for domains without any.
+#[derive(Debug)]
+pub struct DnsResolver(TokioAsyncResolver);
+
+impl DnsResolver {
+    pub fn from_system_conf() -> Result<Self, anyhow::Error> {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf()
+            .context("Failed to read the system's resolver configuration")?;
+
+        Ok(Self(resolver))
+    }
+}
+
+fn no_records(error: &hickory_resolver::error::ResolveError) -> bool {
+    matches!(error.kind(), ResolveErrorKind::NoRecordsFound { .. })
+}
+
+impl MailDomainResolver for DnsResolver {
+    fn accepts_mail<'a>(&'a self, domain: &'a str) -> BoxFuture<'a, Result<bool, anyhow::Error>> {
+        Box::pin(async move {
+            // Fully qualified, so the host's search domains aren't tried.
+            let name = format!("{}.", domain.trim_end_matches('.'));
+
+            match self.0.mx_lookup(name.as_str()).await {
+                // A single MX pointing at the root is a "null MX": the
+                // domain says it takes no mail (RFC 7505).
+                Ok(records) => {
+                    return Ok(records.iter().any(|mx| !mx.exchange().is_root()));
+                }
+                Err(e) if no_records(&e) => {}
+                Err(e) => return Err(e).context("Failed to look up MX records"),
+            }
+
+            // Without MX records, mail goes to the domain's own address.
+            match self.0.lookup_ip(name.as_str()).await {
+                Ok(addresses) => Ok(addresses.iter().next().is_some()),
+                Err(e) if no_records(&e) => Ok(false),
+                Err(e) => Err(e).context("Failed to look up A and AAAA records"),
+            }
+        })
+    }
+}
+
+/// Only the given domains, and their subdomains, accept mail.
+#[derive(Debug)]
+pub struct StaticResolver(HashSet<String>);
+
+impl StaticResolver {
+    pub fn new(domains: &[String]) -> Self {
+        Self(domains.iter().map(|domain| domain.to_lowercase()).collect())
+    }
+}
+
+impl MailDomainResolver for StaticResolver {
+    fn accepts_mail<'a>(&'a self, domain: &'a str) -> BoxFuture<'a, Result<bool, anyhow::Error>> {
+        Box::pin(async move { Ok(in_domains(&self.0, domain)) })
+    }
+}
+
+/// Whether `domain` or one of its parent domains is in the set.
+fn in_domains(domains: &HashSet<String>, domain: &str) -> bool {
+    let mut domain = domain;
+    loop {
+        if domains.contains(domain) {
+            return true;
+        }
+        match domain.split_once('.') {
+            Some((_, parent)) => domain = parent,
+            None => return false,
+        }
+    }
+}
+
+fn parse_domains(list: &str) -> impl Iterator<Item = String> + '_ {
+    list.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_lowercase)
+}
+
+/// The checks configured for this installation.
+#[derive(Debug)]
+pub struct EmailPolicy {
+    allow_smtputf8: bool,
+    disposable_domains: Option<HashSet<String>>,
+    block_role_accounts: bool,
+    resolver: Option<Box<dyn MailDomainResolver>>,
+}
+
+impl EmailPolicy {
+    pub fn from_settings(settings: EmailPolicySettings) -> Result<Self, anyhow::Error> {
+        let disposable_domains = if settings.block_disposable {
+            let mut domains: HashSet<String> = parse_domains(DISPOSABLE_DOMAINS).collect();
+            if let Some(path) = &settings.disposable_domains_file {
+                let extra = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read disposable domains from {}", path))?;
+                domains.extend(parse_domains(&extra));
+            }
+            Some(domains)
+        } else {
+            None
+        };
+
+        let resolver: Option<Box<dyn MailDomainResolver>> = if settings.check_mail_domain {
+            match &settings.resolver {
+                ResolverSettings::System => Some(Box::new(DnsResolver::from_system_conf()?)),
+                ResolverSettings::Static(domains) => Some(Box::new(StaticResolver::new(domains))),
+            }
+        } else {
+            None
+        };
+
+        Ok(Self {
+            allow_smtputf8: settings.allow_smtputf8,
+            disposable_domains,
+            block_role_accounts: settings.block_role_accounts,
+            resolver,
+        })
+    }
+
+    /// The first policy the address breaks, if any. A failed lookup lets the
+    /// address through: we'd rather have a bounce than lose a reader to a
+    /// nameserver outage.
+    #[tracing::instrument(name = "Check email policy", skip(self, email), fields(email_policy = tracing::field::Empty))]
+    pub async fn check(&self, email: &str) -> Option<Violation> {
+        let (local_part, domain) = email.rsplit_once('@')?;
+        let domain = domain.to_lowercase();
+
+        let violation = self.first_violation(local_part, &domain).await;
+        if let Some(violation) = violation {
+            tracing::Span::current().record("email_policy", violation.as_str());
+        }
+        violation
+    }
+
+    async fn first_violation(&self, local_part: &str, domain: &str) -> Option<Violation> {
+        if !self.allow_smtputf8 && !local_part.is_ascii() {
+            return Some(Violation::Smtputf8);
+        }
+
+        if let Some(disposable_domains) = &self.disposable_domains {
+            if in_domains(disposable_domains, domain) {
+                return Some(Violation::Disposable);
+            }
+        }
+
+        if self.block_role_accounts && is_role_account(local_part) {
+            return Some(Violation::RoleAccount);
+        }
+
+        if let Some(resolver) = &self.resolver {
+            match resolver.accepts_mail(domain).await {
+                Ok(true) => {}
+                Ok(false) => return Some(Violation::NoMailHost),
+                Err(e) => tracing::warn!(
+                    error.cause_chain = ?e,
+                    domain,
+                    "Failed to check whether the domain accepts mail"
+                ),
+            }
+        }
+
+        None
+    }
+}
+
+/// `noreply+news@` is as much a role account as `noreply@`.
+fn is_role_account(local_part: &str) -> bool {
+    let local_part = local_part
+        .split_once('+')
+        .map_or(local_part, |(local_part, _)| local_part)
+        .to_lowercase();
+
+    ROLE_ACCOUNTS.contains(&local_part.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(settings: EmailPolicySettings) -> EmailPolicy {
+        EmailPolicy::from_settings(settings).unwrap()
+    }
+
+    #[tokio::test]
+    async fn disposable_domains_and_their_subdomains_are_rejected() {
+        let policy = policy(EmailPolicySettings::default());
+
+        for address in ["ursula@mailinator.com", "ursula@eu.YopMail.com"] {
+            assert_eq!(
+                policy.check(address).await,
+                Some(Violation::Disposable),
+                "{}",
+                address
+            );
+        }
+        assert_eq!(policy.check("ursula@gmail.com").await, None);
+    }
+
+    #[tokio::test]
+    async fn role_accounts_are_rejected() {
+        let policy = policy(EmailPolicySettings::default());
+
+        for address in ["noreply@example.com", "PostMaster+x@example.com"] {
+            assert_eq!(
+                policy.check(address).await,
+                Some(Violation::RoleAccount),
+                "{}",
+                address
+            );
+        }
+        assert_eq!(policy.check("noreplies@example.com").await, None);
+    }
+
+    #[tokio::test]
+    async fn non_ascii_local_parts_need_smtputf8_to_be_allowed() {
+        let strict = policy(EmailPolicySettings::default());
+        let allowing = policy(EmailPolicySettings {
+            allow_smtputf8: true,
+            ..EmailPolicySettings::default()
+        });
+
+        assert_eq!(
+            strict.check("jörg@example.com").await,
+            Some(Violation::Smtputf8)
+        );
+        assert_eq!(allowing.check("jörg@example.com").await, None);
+    }
+
+    #[tokio::test]
+    async fn every_policy_can_be_turned_off() {
+        let policy = policy(EmailPolicySettings {
+            allow_smtputf8: true,
+            block_disposable: false,
+            block_role_accounts: false,
+            ..EmailPolicySettings::default()
+        });
+
+        assert_eq!(policy.check("noreply@mailinator.com").await, None);
+    }
+
+    #[tokio::test]
+    async fn domains_that_do_not_take_mail_are_rejected() {
+        let policy = policy(EmailPolicySettings {
+            check_mail_domain: true,
+            resolver: ResolverSettings::Static(vec!["example.com".into()]),
+            ..EmailPolicySettings::default()
+        });
+
+        assert_eq!(policy.check("ursula@mail.example.com").await, None);
+        assert_eq!(
+            policy.check("ursula@example.con").await,
+            Some(Violation::NoMailHost)
+        );
+    }
+}
@@ -0,0 +1,226 @@
+//! src/export.rs
+use crate::subscribers::{like_pattern, SubscriberFilter};
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::web::Bytes;
+use actix_web::HttpResponse;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures_util::Stream;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Subscribers are read from the database this many at a time.
+const PAGE_SIZE: i64 = 1000;
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    /// One JSON object per line.
+    Ndjson,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "subscribers.csv",
+            ExportFormat::Ndjson => "subscribers.ndjson",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ExportedSubscriber {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+    pub consent_source: Option<String>,
+    pub tags: Vec<String>,
+}
+
+const CSV_HEADER: [&str; 8] = [
+    "id",
+    "email",
+    "name",
+    "status",
+    "subscribed_at",
+    "confirmed_at",
+    "consent_source",
+    "tags",
+];
+
+impl ExportedSubscriber {
+    /// Tags are joined with semicolons, so the file can be imported again.
+    fn csv_record(&self) -> [String; 8] {
+        [
+            self.id.to_string(),
+            self.email.clone(),
+            self.name.clone(),
+            self.status.clone(),
+            self.subscribed_at.to_rfc3339(),
+            self.confirmed_at
+                .map(|at| at.to_rfc3339())
+                .unwrap_or_default(),
+            self.consent_source.clone().unwrap_or_default(),
+            self.tags.join(";"),
+        ]
+    }
+
+    fn json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "id": self.id,
+            "email": self.email,
+            "name": self.name,
+            "status": self.status,
+            "subscribed_at": self.subscribed_at.to_rfc3339(),
+            "confirmed_at": self.confirmed_at.map(|at| at.to_rfc3339()),
+            "consent_source": self.consent_source,
+            "tags": self.tags,
+        })
+    }
+}
+
+/// Subscribers matching `filter`, oldest first, encoded as `format`.
+///
+/// The table is read one page at a time and each page is sent as soon as it
+/// is encoded, so exporting a large list does not hold it all in memory.
+pub fn export_subscribers(
+    pool: PgPool,
+    filter: SubscriberFilter,
+    format: ExportFormat,
+) -> impl Stream<Item = Result<Bytes, anyhow::Error>> {
+    // `None` once the last page has been sent.
+    let first_page = Some(ExportCursor {
+        after: None,
+        is_first_page: true,
+    });
+
+    futures_util::stream::unfold(first_page, move |cursor| {
+        let pool = pool.clone();
+        let filter = filter.clone();
+        async move {
+            let cursor = cursor?;
+            match export_page(&pool, &filter, format, &cursor).await {
+                Ok((chunk, next)) => Some((Ok(chunk), next)),
+                Err(e) => Some((Err(e), None)),
+            }
+        }
+    })
+}
+
+struct ExportCursor {
+    /// The last subscriber sent so far.
+    after: Option<(DateTime<Utc>, Uuid)>,
+    is_first_page: bool,
+}
+
+async fn export_page(
+    pool: &PgPool,
+    filter: &SubscriberFilter,
+    format: ExportFormat,
+    cursor: &ExportCursor,
+) -> Result<(Bytes, Option<ExportCursor>), anyhow::Error> {
+    let subscribers = get_page(pool, filter, cursor.after)
+        .await
+        .context("Failed to read subscribers to export")?;
+
+    let mut chunk = Vec::new();
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(&mut chunk);
+            if cursor.is_first_page {
+                writer.write_record(CSV_HEADER)?;
+            }
+            for subscriber in &subscribers {
+                writer.write_record(subscriber.csv_record())?;
+            }
+            writer.flush()?;
+        }
+        ExportFormat::Ndjson => {
+            for subscriber in &subscribers {
+                serde_json::to_writer(&mut chunk, &subscriber.json())?;
+                chunk.push(b'\n');
+            }
+        }
+    }
+
+    let next = match subscribers.last() {
+        Some(last) if subscribers.len() as i64 == PAGE_SIZE => Some(ExportCursor {
+            after: Some((last.subscribed_at, last.id)),
+            is_first_page: false,
+        }),
+        _ => None,
+    };
+
+    Ok((Bytes::from(chunk), next))
+}
+
+async fn get_page(
+    pool: &PgPool,
+    filter: &SubscriberFilter,
+    after: Option<(DateTime<Utc>, Uuid)>,
+) -> Result<Vec<ExportedSubscriber>, sqlx::Error> {
+    let (after_subscribed_at, after_id) = after.unzip();
+
+    sqlx::query_as!(
+        ExportedSubscriber,
+        r#"
+        SELECT id, email, name, status::text AS "status!", subscribed_at, confirmed_at,
+            consent_source,
+            ARRAY(
+                SELECT tag FROM subscriber_tags t
+                WHERE t.subscriber_id = subscriptions.id
+                ORDER BY tag
+            ) AS "tags!"
+        FROM subscriptions
+        WHERE ($1::text IS NULL OR email ILIKE $1 OR name ILIKE $1)
+            AND ($2::text IS NULL OR status::text = $2)
+            AND ($3::timestamptz IS NULL OR subscribed_at >= $3)
+            AND ($4::timestamptz IS NULL OR subscribed_at < $4)
+            AND ($5::timestamptz IS NULL OR (subscribed_at, id) > ($5, $6))
+        ORDER BY subscribed_at, id
+        LIMIT $7
+        "#,
+        filter.search.as_deref().map(like_pattern),
+        filter.status,
+        filter.subscribed_from,
+        filter.subscribed_until,
+        after_subscribed_at,
+        after_id,
+        PAGE_SIZE
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+/// Stream the export as a file download.
+pub fn export_response(
+    pool: PgPool,
+    filter: SubscriberFilter,
+    format: ExportFormat,
+) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format.file_name().into())],
+        })
+        .streaming(export_subscribers(pool, filter, format))
+}
@@ -0,0 +1,399 @@
+//! src/feed_poller.rs
+use crate::configuration::{FeedPollerMode, FeedPollerSettings, HmacSecret, Settings};
+use crate::email::Brevo;
+use crate::issues::{get_issue, insert_newsletter_issue, IssueContent, NewsletterIssue};
+use crate::publish::deliver_issue;
+use anyhow::Context;
+use askama::Template;
+use chrono::Utc;
+use secrecy::ExposeSecret;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashSet;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// A feed entry, reduced to what ends up in the email.
+pub struct FeedEntry {
+    pub id: String,
+    pub title: String,
+    pub link: String,
+    pub content: String,
+}
+
+impl From<feed_rs::model::Entry> for FeedEntry {
+    fn from(entry: feed_rs::model::Entry) -> Self {
+        let link = entry
+            .links
+            .iter()
+            .find(|link| link.rel.as_deref().unwrap_or("alternate") == "alternate")
+            .or_else(|| entry.links.first())
+            .map(|link| link.href.clone())
+            .unwrap_or_default();
+
+        let content = entry
+            .content
+            .and_then(|content| content.body)
+            .or_else(|| entry.summary.map(|summary| summary.content))
+            .unwrap_or_default();
+
+        Self {
+            id: entry.id,
+            title: entry.title.map(|title| title.content).unwrap_or_default(),
+            link,
+            content,
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "email/feed_entries.html")]
+struct FeedEntriesTemplate<'a> {
+    entries: &'a [FeedEntry],
+}
+
+pub async fn run_worker_until_stopped(config: Settings) -> Result<(), anyhow::Error> {
+    let pool = PgPool::connect_lazy(config.database.connection_string().expose_secret())
+        .expect("Failed to connect to Postgres.");
+    let hmac_secret = config.application.hmac_secret.expect("Missing HMAC secret");
+    let email_client = Brevo::from_settings(
+        config.email.expect("Missing email settings"),
+        pool.clone(),
+        config
+            .application
+            .email_hash_key
+            .expect("Missing email hash key"),
+    );
+    let http_client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .context("Failed to build the feed HTTP client")?;
+
+    worker_loop(
+        &pool,
+        &http_client,
+        &email_client,
+        &config.application.base_url,
+        &hmac_secret,
+        &config.feed_poller,
+    )
+    .await
+}
+
+async fn worker_loop(
+    pool: &PgPool,
+    http_client: &reqwest::Client,
+    email_client: &Brevo,
+    base_url: &str,
+    hmac_secret: &HmacSecret,
+    settings: &FeedPollerSettings,
+) -> Result<(), anyhow::Error> {
+    loop {
+        for feed_url in &settings.urls {
+            if let Err(e) = poll_feed(
+                pool,
+                http_client,
+                email_client,
+                base_url,
+                hmac_secret,
+                feed_url,
+                settings.mode,
+            )
+            .await
+            {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    feed_url = %feed_url,
+                    "Failed to poll feed"
+                );
+            }
+        }
+
+        tokio::time::sleep(Duration::from_secs(settings.poll_interval_seconds)).await;
+    }
+}
+
+/// Fetch `feed_url` and publish every entry that has not been seen before.
+/// Returns the number of issues published.
+///
+/// Entries are recorded together with the issue carrying them, before any
+/// email goes out, so no entry is ever published twice. Issues whose delivery
+/// was cut short, by an error or a restart, are delivered to the remaining
+/// subscribers first. The first poll of a feed only records its existing
+/// entries: subscribers get the posts written from then on, not the blog's
+/// whole history.
+#[tracing::instrument(
+    name = "Poll feed",
+    skip(pool, http_client, email_client, base_url, hmac_secret)
+)]
+pub async fn poll_feed(
+    pool: &PgPool,
+    http_client: &reqwest::Client,
+    email_client: &Brevo,
+    base_url: &str,
+    hmac_secret: &HmacSecret,
+    feed_url: &str,
+    mode: FeedPollerMode,
+) -> Result<usize, anyhow::Error> {
+    let unsent = get_unsent_issues(pool, feed_url)
+        .await
+        .context("Failed to look up the unsent feed issues")?;
+    for newsletter_issue_id in unsent {
+        let issue = get_issue(pool, newsletter_issue_id)
+            .await
+            .context("Failed to retrieve an unsent feed issue")?
+            .context("An unsent feed issue no longer exists")?;
+        send_issue(pool, email_client, base_url, hmac_secret, &issue).await?;
+    }
+
+    let body = http_client
+        .get(feed_url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .context("Failed to fetch the feed")?
+        .bytes()
+        .await
+        .context("Failed to read the feed")?;
+
+    let feed = feed_rs::parser::parse(&body[..]).context("Failed to parse the feed")?;
+    let feed_title = feed.title.map(|title| title.content);
+
+    let mut entries: Vec<_> = feed.entries;
+    // Oldest first, so that per-post issues go out in publication order.
+    entries.sort_by_key(|entry| entry.published.or(entry.updated));
+
+    let is_new_feed = insert_feed_source(pool, feed_url)
+        .await
+        .context("Failed to register the feed")?;
+
+    let entries: Vec<FeedEntry> = entries.into_iter().map(FeedEntry::from).collect();
+    let entry_ids: Vec<String> = entries.iter().map(|entry| entry.id.clone()).collect();
+    let recorded = get_recorded_entries(pool, feed_url, &entry_ids)
+        .await
+        .context("Failed to look up the recorded feed entries")?;
+    let new_entries: Vec<FeedEntry> = entries
+        .into_iter()
+        .filter(|entry| !recorded.contains(&entry.id))
+        .collect();
+
+    if is_new_feed {
+        let entry_ids: Vec<String> = new_entries.iter().map(|entry| entry.id.clone()).collect();
+        let mut transaction = pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection")?;
+        record_feed_entries(&mut transaction, feed_url, &entry_ids, None)
+            .await
+            .context("Failed to record the existing feed entries")?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to record the existing feed entries")?;
+        tracing::info!(
+            entries = new_entries.len(),
+            "First poll of the feed: existing entries were recorded without being sent"
+        );
+        return Ok(0);
+    }
+
+    if new_entries.is_empty() {
+        return Ok(0);
+    }
+
+    match mode {
+        FeedPollerMode::Digest => {
+            let title = match (&new_entries[..], feed_title) {
+                ([entry], _) => entry.title.clone(),
+                (entries, Some(feed_title)) => {
+                    format!("{}: {} new posts", feed_title, entries.len())
+                }
+                (entries, None) => format!("{} new posts", entries.len()),
+            };
+            publish_entries(
+                pool,
+                email_client,
+                base_url,
+                hmac_secret,
+                feed_url,
+                &title,
+                &new_entries,
+            )
+            .await?;
+
+            Ok(1)
+        }
+        FeedPollerMode::PerPost => {
+            for entry in &new_entries {
+                publish_entries(
+                    pool,
+                    email_client,
+                    base_url,
+                    hmac_secret,
+                    feed_url,
+                    &entry.title,
+                    std::slice::from_ref(entry),
+                )
+                .await?;
+            }
+
+            Ok(new_entries.len())
+        }
+    }
+}
+
+async fn publish_entries(
+    pool: &PgPool,
+    email_client: &Brevo,
+    base_url: &str,
+    hmac_secret: &HmacSecret,
+    feed_url: &str,
+    title: &str,
+    entries: &[FeedEntry],
+) -> Result<(), anyhow::Error> {
+    let html_content = FeedEntriesTemplate { entries }
+        .render()
+        .context("Failed to render feed entries")?;
+
+    // Feed entries go to the default list.
+    let content = IssueContent {
+        title,
+        html_content: &html_content,
+        topic_id: None,
+        list_ids: &[],
+        segment: None,
+    };
+    let entry_ids: Vec<String> = entries.iter().map(|entry| entry.id.clone()).collect();
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection")?;
+    let issue = insert_newsletter_issue(&mut transaction, &content, None)
+        .await
+        .context("Failed to store the newsletter issue")?;
+    let claimed = record_feed_entries(
+        &mut transaction,
+        feed_url,
+        &entry_ids,
+        Some(issue.newsletter_issue_id),
+    )
+    .await
+    .context("Failed to record the published feed entries")?;
+    // Another poller got to the entries first: drop the issue with the
+    // transaction.
+    if claimed < entry_ids.len() {
+        return Ok(());
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to store the newsletter issue")?;
+
+    send_issue(pool, email_client, base_url, hmac_secret, &issue).await
+}
+
+/// Deliver a feed issue and mark its entries as sent.
+async fn send_issue(
+    pool: &PgPool,
+    email_client: &Brevo,
+    base_url: &str,
+    hmac_secret: &HmacSecret,
+    issue: &NewsletterIssue,
+) -> Result<(), anyhow::Error> {
+    deliver_issue(pool, email_client, base_url, hmac_secret, issue).await?;
+
+    sqlx::query!(
+        "UPDATE feed_entries SET sent_at = $2 WHERE newsletter_issue_id = $1",
+        issue.newsletter_issue_id,
+        Utc::now()
+    )
+    .execute(pool)
+    .await
+    .context("Failed to mark the feed entries as sent")?;
+
+    Ok(())
+}
+
+/// Issues of `feed_url` whose delivery has not finished, oldest first.
+async fn get_unsent_issues(pool: &PgPool, feed_url: &str) -> Result<Vec<Uuid>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id AS "newsletter_issue_id!"
+        FROM feed_entries
+        WHERE feed_url = $1 AND newsletter_issue_id IS NOT NULL AND sent_at IS NULL
+        GROUP BY newsletter_issue_id
+        ORDER BY min(seen_at)
+        "#,
+        feed_url
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| row.newsletter_issue_id)
+        .collect())
+}
+
+/// Returns `true` the first time `feed_url` is polled.
+async fn insert_feed_source(pool: &PgPool, feed_url: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO feed_sources (feed_url, first_polled_at)
+        VALUES ($1, $2)
+        ON CONFLICT DO NOTHING
+        "#,
+        feed_url,
+        Utc::now()
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// The ids among `entry_ids` that were already sent, or skipped on the
+/// feed's first poll.
+async fn get_recorded_entries(
+    pool: &PgPool,
+    feed_url: &str,
+    entry_ids: &[String],
+) -> Result<HashSet<String>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT entry_id FROM feed_entries WHERE feed_url = $1 AND entry_id = ANY($2)",
+        feed_url,
+        entry_ids
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| row.entry_id).collect())
+}
+
+/// `newsletter_issue_id` is the issue the entries go out with, `None` for
+/// entries skipped on the first poll. Returns how many entries were not
+/// recorded before.
+async fn record_feed_entries(
+    transaction: &mut Transaction<'_, Postgres>,
+    feed_url: &str,
+    entry_ids: &[String],
+    newsletter_issue_id: Option<Uuid>,
+) -> Result<usize, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO feed_entries (feed_url, entry_id, seen_at, newsletter_issue_id)
+        SELECT $1, entry_id, $3, $4
+        FROM UNNEST($2::text[]) AS entry_id
+        ON CONFLICT DO NOTHING
+        "#,
+        feed_url,
+        entry_ids,
+        Utc::now(),
+        newsletter_issue_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(result.rows_affected() as usize)
+}
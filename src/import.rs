@@ -0,0 +1,730 @@
+//! src/import.rs
+use crate::attributes::{
+    apply_attribute_changes, get_custom_fields, parse_tags, AttributeChanges, CustomField,
+};
+use crate::configuration::EmailHashKey;
+use crate::confirmation_queue::enqueue_confirmation;
+use crate::domain::{ListSubscriptionStatus, Person, SubscriptionStatus};
+use crate::lists::{get_default_list, insert_list_subscription};
+use crate::privacy::email_hash;
+use crate::routes::{generate_subscription_token, insert_token};
+use crate::subscribers::record_status_event;
+use crate::suppressions::suppressed_hashes;
+use anyhow::Context;
+use chrono::Utc;
+use csv::{ByteRecord, Position, StringRecord};
+use csv_core::ReadRecordResult;
+use futures_util::{Stream, TryStreamExt};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashSet;
+use std::io::Read;
+use uuid::Uuid;
+
+/// Rows are written in transactions of this many subscribers.
+const BATCH_SIZE: usize = 500;
+
+/// Which CSV columns hold the email address and the name.
+/// Headers are matched case-insensitively.
+///
+/// A `tags` column and columns named after custom fields are imported too
+/// when the file has them.
+#[derive(Debug, Clone)]
+pub struct ColumnMapping {
+    pub email: String,
+    pub name: String,
+}
+
+impl Default for ColumnMapping {
+    fn default() -> Self {
+        Self {
+            email: "email".into(),
+            name: "name".into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ImportMode {
+    /// The subscribers already opted in elsewhere; `consent_source` records where.
+    Confirmed { consent_source: String },
+    /// Every subscriber is sent the usual opt-in email.
+    PendingConfirmation,
+}
+
+impl ImportMode {
+    /// The status imported subscribers start in.
+    pub fn status(&self) -> SubscriptionStatus {
+        match self {
+            ImportMode::Confirmed { .. } => SubscriptionStatus::Confirmed,
+            ImportMode::PendingConfirmation => SubscriptionStatus::PendingConfirmation,
+        }
+    }
+
+    /// The status of their membership of the list they are imported into.
+    pub fn list_status(&self) -> ListSubscriptionStatus {
+        match self {
+            ImportMode::Confirmed { .. } => ListSubscriptionStatus::Confirmed,
+            ImportMode::PendingConfirmation => ListSubscriptionStatus::PendingConfirmation,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RowError {
+    /// Line of the CSV file, starting at 1 for the header.
+    pub line: u64,
+    pub email: String,
+    pub reason: String,
+}
+
+/// A row that passed validation, waiting to be written.
+struct ImportRow {
+    line: u64,
+    person: Person,
+    attributes: AttributeChanges,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub duplicates: usize,
+    pub errors: Vec<RowError>,
+}
+
+impl ImportReport {
+    /// One row per rejected line, to be fixed and imported again.
+    pub fn errors_csv(&self) -> Result<String, anyhow::Error> {
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.write_record(["line", "email", "reason"])?;
+        for error in &self.errors {
+            writer.write_record([&error.line.to_string(), &error.email, &error.reason])?;
+        }
+        let bytes = writer
+            .into_inner()
+            .context("Failed to write the error report")?;
+
+        Ok(String::from_utf8(bytes)?)
+    }
+}
+
+/// Read subscribers from a CSV file and store them.
+///
+/// Each row is validated like a signup from the subscription form. Addresses
+/// that are already subscribed, or appear twice in the file, are skipped
+/// regardless of case.
+#[tracing::instrument(name = "Import subscribers", skip(pool, email_hash_key, reader))]
+pub async fn import_subscribers<R: Read>(
+    pool: &PgPool,
+    email_hash_key: &EmailHashKey,
+    reader: R,
+    mapping: &ColumnMapping,
+    mode: &ImportMode,
+) -> Result<ImportReport, anyhow::Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+
+    let headers = reader.headers().context("Failed to read the CSV header")?;
+    let mut importer = Importer::new(pool, email_hash_key, headers, mapping, mode).await?;
+
+    for record in reader.records() {
+        match record {
+            Ok(record) => importer.add(record).await?,
+            Err(e) => {
+                let line = e.position().map(|p| p.line()).unwrap_or_default();
+                importer.reject(line, String::new(), e.to_string());
+            }
+        }
+    }
+
+    importer.finish().await
+}
+
+/// Like `import_subscribers`, for a file read in chunks as it is uploaded.
+/// Rows past the first `max_size` bytes are not imported and reported as an
+/// error.
+#[tracing::instrument(
+    name = "Import uploaded subscribers",
+    skip(pool, email_hash_key, chunks)
+)]
+pub async fn import_upload<S, B, E>(
+    pool: &PgPool,
+    email_hash_key: &EmailHashKey,
+    mut chunks: S,
+    mapping: &ColumnMapping,
+    mode: &ImportMode,
+    max_size: usize,
+) -> Result<ImportReport, anyhow::Error>
+where
+    S: Stream<Item = Result<B, E>> + Unpin,
+    B: AsRef<[u8]>,
+    E: std::fmt::Display,
+{
+    let mut reader = ChunkedCsv::default();
+    let mut records = Vec::new();
+    let mut importer: Option<Importer> = None;
+    let mut size = 0;
+    let mut too_large = false;
+
+    loop {
+        let chunk = chunks
+            .try_next()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to read the upload: {}", e))?;
+        match &chunk {
+            Some(chunk) => {
+                size += chunk.as_ref().len();
+                if size > max_size {
+                    too_large = true;
+                } else {
+                    reader.read(chunk.as_ref(), &mut records);
+                }
+            }
+            None => reader.finish(&mut records),
+        }
+
+        for record in records.drain(..) {
+            match &mut importer {
+                Some(importer) => importer.add_read(record).await?,
+                None => match record {
+                    Ok(headers) => {
+                        importer = Some(
+                            Importer::new(pool, email_hash_key, &headers, mapping, mode).await?,
+                        )
+                    }
+                    Err(e) => anyhow::bail!("Failed to read the CSV header: {}", e.reason),
+                },
+            }
+        }
+
+        if chunk.is_none() || too_large {
+            break;
+        }
+    }
+
+    let mut importer = importer.context("The CSV file is empty")?;
+    if too_large {
+        importer.reject(
+            reader.line(),
+            String::new(),
+            format!(
+                "The file is larger than {} bytes: this row and the ones after it were not imported",
+                max_size
+            ),
+        );
+    }
+
+    importer.finish().await
+}
+
+/// Validates rows as they are read and writes them in batches.
+struct Importer<'a> {
+    pool: &'a PgPool,
+    email_hash_key: &'a EmailHashKey,
+    mode: &'a ImportMode,
+    email_column: usize,
+    name_column: usize,
+    tags_column: Option<usize>,
+    field_columns: Vec<(usize, CustomField)>,
+    report: ImportReport,
+    seen: HashSet<String>,
+    batch: Vec<ImportRow>,
+}
+
+impl<'a> Importer<'a> {
+    async fn new(
+        pool: &'a PgPool,
+        email_hash_key: &'a EmailHashKey,
+        headers: &StringRecord,
+        mapping: &ColumnMapping,
+        mode: &'a ImportMode,
+    ) -> Result<Importer<'a>, anyhow::Error> {
+        let column = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header.eq_ignore_ascii_case(name))
+                .with_context(|| format!("The CSV file has no `{}` column", name))
+        };
+
+        let custom_fields = get_custom_fields(pool)
+            .await
+            .context("Failed to retrieve the custom fields")?;
+
+        Ok(Self {
+            pool,
+            email_hash_key,
+            mode,
+            email_column: column(&mapping.email)?,
+            name_column: column(&mapping.name)?,
+            tags_column: column("tags").ok(),
+            field_columns: custom_fields
+                .into_iter()
+                .filter_map(|field| column(&field.name).ok().map(|position| (position, field)))
+                .collect(),
+            report: ImportReport::default(),
+            seen: HashSet::new(),
+            batch: Vec::with_capacity(BATCH_SIZE),
+        })
+    }
+
+    fn reject(&mut self, line: u64, email: String, reason: String) {
+        self.report.errors.push(RowError {
+            line,
+            email,
+            reason,
+        });
+    }
+
+    async fn add_read(
+        &mut self,
+        record: Result<StringRecord, RowError>,
+    ) -> Result<(), anyhow::Error> {
+        match record {
+            Ok(record) => self.add(record).await,
+            Err(error) => {
+                self.report.errors.push(error);
+                Ok(())
+            }
+        }
+    }
+
+    async fn add(&mut self, record: StringRecord) -> Result<(), anyhow::Error> {
+        let line = record.position().map(|p| p.line()).unwrap_or_default();
+        let email = record
+            .get(self.email_column)
+            .unwrap_or_default()
+            .to_string();
+        let name = record.get(self.name_column).unwrap_or_default().to_string();
+
+        let person = match Person::parse(name, email.clone()) {
+            Ok(person) => person,
+            Err(e) => {
+                self.reject(line, email, e.to_string());
+                return Ok(());
+            }
+        };
+
+        let mut attributes = AttributeChanges::default();
+        if let Some(position) = self.tags_column {
+            match parse_tags(record.get(position).unwrap_or_default()) {
+                Ok(tags) => attributes.tags = Some(tags),
+                Err(reason) => {
+                    self.reject(line, email, reason);
+                    return Ok(());
+                }
+            }
+        }
+        for (position, field) in &self.field_columns {
+            match field.parse_input(record.get(*position).unwrap_or_default()) {
+                Ok(Some(value)) => attributes.fields.push((field.field_id, Some(value))),
+                // Imported subscribers are new, so there is nothing to clear.
+                Ok(None) => {}
+                Err(reason) => {
+                    self.reject(line, email, reason);
+                    return Ok(());
+                }
+            }
+        }
+
+        if !self.seen.insert(person.email.key()) {
+            self.report.duplicates += 1;
+            return Ok(());
+        }
+
+        self.batch.push(ImportRow {
+            line,
+            person,
+            attributes,
+        });
+        if self.batch.len() == BATCH_SIZE {
+            import_batch(
+                self.pool,
+                self.email_hash_key,
+                &mut self.batch,
+                self.mode,
+                &mut self.report,
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn finish(mut self) -> Result<ImportReport, anyhow::Error> {
+        import_batch(
+            self.pool,
+            self.email_hash_key,
+            &mut self.batch,
+            self.mode,
+            &mut self.report,
+        )
+        .await?;
+
+        Ok(self.report)
+    }
+}
+
+/// Splits CSV handed over in chunks into records, trimmed like
+/// `import_subscribers` does.
+struct ChunkedCsv {
+    reader: csv_core::Reader,
+    output: Vec<u8>,
+    output_len: usize,
+    ends: Vec<usize>,
+    ends_len: usize,
+    /// Where the record being read starts, counting from 1.
+    line: u64,
+}
+
+impl Default for ChunkedCsv {
+    fn default() -> Self {
+        Self {
+            reader: csv_core::Reader::new(),
+            output: vec![0; 1024],
+            output_len: 0,
+            ends: vec![0; 16],
+            ends_len: 0,
+            line: 1,
+        }
+    }
+}
+
+impl ChunkedCsv {
+    fn line(&self) -> u64 {
+        self.line
+    }
+
+    /// Add the records completed by `chunk` to `records`.
+    fn read(&mut self, chunk: &[u8], records: &mut Vec<Result<StringRecord, RowError>>) {
+        // An empty input tells the reader the file ended.
+        if !chunk.is_empty() {
+            self.read_input(chunk, records);
+        }
+    }
+
+    /// Add the last record, if the file does not end with a newline.
+    fn finish(&mut self, records: &mut Vec<Result<StringRecord, RowError>>) {
+        self.read_input(&[], records);
+    }
+
+    fn read_input(&mut self, mut input: &[u8], records: &mut Vec<Result<StringRecord, RowError>>) {
+        let at_end = input.is_empty();
+
+        loop {
+            if self.output_len == self.output.len() {
+                self.output.resize(self.output.len() * 2, 0);
+            }
+            if self.ends_len == self.ends.len() {
+                self.ends.resize(self.ends.len() * 2, 0);
+            }
+
+            let (result, read, written, ends) = self.reader.read_record(
+                input,
+                &mut self.output[self.output_len..],
+                &mut self.ends[self.ends_len..],
+            );
+            input = &input[read..];
+            self.output_len += written;
+            self.ends_len += ends;
+
+            match result {
+                ReadRecordResult::Record => records.push(self.take_record()),
+                ReadRecordResult::End => return,
+                ReadRecordResult::InputEmpty if !at_end => return,
+                ReadRecordResult::InputEmpty
+                | ReadRecordResult::OutputFull
+                | ReadRecordResult::OutputEndsFull => {}
+            }
+            if input.is_empty() && !at_end {
+                return;
+            }
+        }
+    }
+
+    fn take_record(&mut self) -> Result<StringRecord, RowError> {
+        let mut record = ByteRecord::new();
+        let mut start = 0;
+        for &end in &self.ends[..self.ends_len] {
+            record.push_field(&self.output[start..end]);
+            start = end;
+        }
+        let mut position = Position::new();
+        position.set_line(self.line);
+        record.set_position(Some(position));
+
+        self.output_len = 0;
+        self.ends_len = 0;
+        let line = self.line;
+        self.line = self.reader.line();
+
+        match StringRecord::from_byte_record(record) {
+            Ok(mut record) => {
+                record.trim();
+                Ok(record)
+            }
+            Err(e) => Err(RowError {
+                line,
+                email: String::new(),
+                reason: e.to_string(),
+            }),
+        }
+    }
+}
+
+async fn import_batch(
+    pool: &PgPool,
+    email_hash_key: &EmailHashKey,
+    batch: &mut Vec<ImportRow>,
+    mode: &ImportMode,
+    report: &mut ImportReport,
+) -> Result<(), anyhow::Error> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+
+    // Imported subscribers join the default list.
+    let list = get_default_list(pool)
+        .await
+        .context("Failed to retrieve the default list")?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection")?;
+
+    let keys: Vec<String> = batch.iter().map(|row| row.person.email.key()).collect();
+    let existing: HashSet<String> = sqlx::query!(
+        r#"
+        SELECT email_key
+        FROM subscriptions
+        WHERE email_key = ANY($1)
+        "#,
+        &keys
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to look up existing subscribers")?
+    .into_iter()
+    .map(|row| row.email_key)
+    .collect();
+
+    let hashes: Vec<String> = batch
+        .iter()
+        .map(|row| email_hash(row.person.email.as_ref(), email_hash_key))
+        .collect();
+    let suppressed: HashSet<String> = suppressed_hashes(pool, &hashes)
+        .await
+        .context("Failed to look up suppressed addresses")?
+        .into_iter()
+        .collect();
+
+    for ImportRow {
+        line,
+        person,
+        attributes,
+    } in batch.drain(..)
+    {
+        if existing.contains(&person.email.key()) {
+            report.duplicates += 1;
+            continue;
+        }
+
+        if suppressed.contains(&email_hash(person.email.as_ref(), email_hash_key)) {
+            report.errors.push(RowError {
+                line,
+                email: person.email.to_string(),
+                reason: "The address is suppressed and cannot be imported".into(),
+            });
+            continue;
+        }
+
+        let subscriber_id = insert_imported_subscriber(&mut transaction, &person, mode)
+            .await
+            .context("Failed to insert an imported subscriber")?;
+        insert_list_subscription(
+            &mut transaction,
+            list.list_id,
+            subscriber_id,
+            mode.list_status(),
+        )
+        .await
+        .context("Failed to add an imported subscriber to the list")?;
+        apply_attribute_changes(&mut transaction, subscriber_id, &attributes)
+            .await
+            .context("Failed to save the attributes of an imported subscriber")?;
+        report.imported += 1;
+
+        if let ImportMode::PendingConfirmation = mode {
+            let token = generate_subscription_token();
+            insert_token(&mut transaction, subscriber_id, list.list_id, &token)
+                .await
+                .context("Failed to insert a new token in the database")?;
+            enqueue_confirmation(&mut transaction, &token)
+                .await
+                .context("Failed to queue the confirmation email")?;
+        }
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+
+    Ok(())
+}
+
+async fn insert_imported_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    person: &Person,
+    mode: &ImportMode,
+) -> Result<Uuid, sqlx::Error> {
+    let now = Utc::now();
+    let status = mode.status();
+    let (consent_source, confirmed_at) = match mode {
+        ImportMode::Confirmed { consent_source } => (Some(consent_source.as_str()), Some(now)),
+        ImportMode::PendingConfirmation => (None, None),
+    };
+    let id = Uuid::new_v4();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (
+            id, email, email_key, name, subscribed_at, status, consent_source, confirmed_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+        id,
+        person.email.as_ref(),
+        person.email.key(),
+        person.name.as_ref(),
+        now,
+        status as SubscriptionStatus,
+        consent_source,
+        confirmed_at
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    record_status_event(transaction, id, None, status, "import").await?;
+
+    Ok(id)
+}
+
+/// Imports are kept so their error report can be downloaded later.
+#[tracing::instrument(name = "Save subscriber import report", skip(pool, report))]
+pub async fn save_report(pool: &PgPool, report: &ImportReport) -> Result<Uuid, anyhow::Error> {
+    let import_id = Uuid::new_v4();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_imports (
+            import_id, imported, duplicates, errors, error_report, created_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        import_id,
+        report.imported as i32,
+        report.duplicates as i32,
+        report.errors.len() as i32,
+        report.errors_csv()?,
+        Utc::now()
+    )
+    .execute(pool)
+    .await
+    .context("Failed to save the import report")?;
+
+    Ok(import_id)
+}
+
+#[derive(Debug)]
+pub struct SavedImport {
+    pub import_id: Uuid,
+    pub imported: i32,
+    pub duplicates: i32,
+    pub errors: i32,
+    pub error_report: String,
+}
+
+#[tracing::instrument(name = "Get subscriber import report", skip(pool))]
+pub async fn get_report(
+    pool: &PgPool,
+    import_id: Uuid,
+) -> Result<Option<SavedImport>, sqlx::Error> {
+    sqlx::query_as!(
+        SavedImport,
+        r#"
+        SELECT import_id, imported, duplicates, errors, error_report
+        FROM subscriber_imports
+        WHERE import_id = $1
+        "#,
+        import_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_in_chunks(csv: &[u8], chunk_size: usize) -> Vec<Result<StringRecord, RowError>> {
+        let mut reader = ChunkedCsv::default();
+        let mut records = Vec::new();
+        for chunk in csv.chunks(chunk_size) {
+            reader.read(chunk, &mut records);
+        }
+        reader.finish(&mut records);
+        records
+    }
+
+    /// The records and where they start, as `import_subscribers` reads them.
+    fn read_whole(csv: &[u8]) -> Vec<(u64, Vec<String>)> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(csv);
+        reader
+            .records()
+            .map(|record| {
+                let record = record.unwrap();
+                let line = record.position().unwrap().line();
+                (line, record.iter().map(String::from).collect())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn records_are_the_same_however_the_upload_is_split() {
+        let csv = format!(
+            "name, email\n\"Le Guin, Ursula\",ursula@example.com\r\n\"Terry\nPratchett\",terry@example.com\n\n{},long@example.com",
+            "a".repeat(3000)
+        );
+        let expected = read_whole(csv.as_bytes());
+        assert_eq!(expected.len(), 4);
+
+        for chunk_size in [1, 2, 7, 4096] {
+            let records: Vec<(u64, Vec<String>)> = read_in_chunks(csv.as_bytes(), chunk_size)
+                .into_iter()
+                .map(|record| {
+                    let record = record.map_err(|e| e.reason).unwrap();
+                    let line = record.position().unwrap().line();
+                    (line, record.iter().map(String::from).collect())
+                })
+                .collect();
+
+            assert_eq!(records, expected);
+        }
+    }
+
+    #[test]
+    fn rows_that_are_not_utf8_are_reported_with_their_line() {
+        let records = read_in_chunks(b"name,email\n\xff,bad@example.com\nok,ok@example.com\n", 4);
+
+        assert_eq!(records.len(), 3);
+        let error = records[1].as_ref().unwrap_err();
+        assert_eq!(error.line, 2);
+        assert!(records[2].is_ok());
+    }
+}
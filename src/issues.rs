@@ -0,0 +1,433 @@
+//! src/issues.rs
+use crate::domain::Slug;
+use crate::lists::DEFAULT_LIST;
+use askama::Template;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// An issue as seen from the admin area: drafts have no `published_at`.
+#[derive(Debug)]
+pub struct NewsletterIssue {
+    pub newsletter_issue_id: Uuid,
+    pub slug: String,
+    pub title: String,
+    pub html_content: String,
+    pub is_public: bool,
+    pub published_at: Option<DateTime<Utc>>,
+    pub updated_at: DateTime<Utc>,
+    /// Issues without a topic go to every subscriber.
+    pub topic_id: Option<Uuid>,
+    /// Issues without a segment go to every subscriber of their lists.
+    pub segment: Option<String>,
+}
+
+impl NewsletterIssue {
+    pub fn is_draft(&self) -> bool {
+        self.published_at.is_none()
+    }
+}
+
+/// What the author writes: everything about an issue that is saved together.
+#[derive(Debug)]
+pub struct IssueContent<'a> {
+    pub title: &'a str,
+    pub html_content: &'a str,
+    pub topic_id: Option<Uuid>,
+    /// The lists the issue goes out to. Empty for the default list.
+    pub list_ids: &'a [Uuid],
+    /// A segment definition, checked with `Segment::parse` beforehand.
+    pub segment: Option<&'a str>,
+}
+
+/// An issue that went out to subscribers, as shown in the archive and feeds.
+#[derive(Debug, serde::Serialize)]
+pub struct PublishedIssue {
+    pub newsletter_issue_id: Uuid,
+    pub slug: String,
+    pub title: String,
+    pub html_content: String,
+    pub published_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// One save of an issue. Revisions are never modified once written.
+#[derive(Debug)]
+pub struct Revision {
+    pub revision_id: Uuid,
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub html_content: String,
+    pub author: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The full issue, shared by the email sent to subscribers and the archive page
+/// so both look the same.
+#[derive(Template)]
+#[template(path = "email/issue.html")]
+pub struct IssueTemplate<'a> {
+    pub title: &'a str,
+    pub html_content: &'a str,
+    pub view_in_browser_url: Option<&'a str>,
+    /// Only set in emails, which go to a single subscriber.
+    pub preferences_url: Option<&'a str>,
+}
+
+/// Store an issue that is published right away. It is only stored once the
+/// transaction is committed.
+#[tracing::instrument(name = "Store newsletter issue", skip(transaction, content))]
+pub async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    content: &IssueContent<'_>,
+    author_id: Option<Uuid>,
+) -> Result<NewsletterIssue, sqlx::Error> {
+    insert_issue(transaction, content, author_id, Some(Utc::now())).await
+}
+
+/// Store an issue to be published later from the composer.
+#[tracing::instrument(name = "Store newsletter draft", skip(pool, content))]
+pub async fn insert_draft(
+    pool: &PgPool,
+    content: &IssueContent<'_>,
+    author_id: Uuid,
+) -> Result<NewsletterIssue, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let issue = insert_issue(&mut transaction, content, Some(author_id), None).await?;
+    transaction.commit().await?;
+
+    Ok(issue)
+}
+
+async fn insert_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    content: &IssueContent<'_>,
+    author_id: Option<Uuid>,
+    published_at: Option<DateTime<Utc>>,
+) -> Result<NewsletterIssue, sqlx::Error> {
+    let base = Slug::from_title(content.title);
+
+    // Issues sharing a title get a numeric suffix: `weekly-update`,
+    // `weekly-update-2`, ... The unique index settles races with other inserts.
+    let mut n = 1;
+    let issue = loop {
+        let slug = if n == 1 {
+            base.clone()
+        } else {
+            base.with_suffix(n)
+        };
+        let inserted = sqlx::query_as!(
+            NewsletterIssue,
+            r#"
+            INSERT INTO newsletter_issues (
+                newsletter_issue_id, slug, title, html_content, is_public, published_at,
+                updated_at, topic_id, segment
+            )
+            VALUES ($1, $2, $3, $4, TRUE, $5, $6, $7, $8)
+            ON CONFLICT (slug) DO NOTHING
+            RETURNING newsletter_issue_id, slug, title, html_content, is_public, published_at,
+                updated_at, topic_id, segment
+            "#,
+            Uuid::new_v4(),
+            slug.as_ref(),
+            content.title,
+            content.html_content,
+            published_at,
+            Utc::now(),
+            content.topic_id,
+            content.segment
+        )
+        .fetch_optional(&mut **transaction)
+        .await?;
+
+        match inserted {
+            Some(issue) => break issue,
+            None => n += 1,
+        }
+    };
+
+    set_issue_lists(transaction, issue.newsletter_issue_id, content.list_ids).await?;
+    insert_revision(transaction, &issue, author_id).await?;
+
+    Ok(issue)
+}
+
+/// Replace the lists an issue goes out to. No lists means the default list.
+async fn set_issue_lists(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    list_ids: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM newsletter_issue_lists WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+        SELECT $1, list_id
+        FROM lists
+        WHERE list_id = ANY($2) OR (cardinality($2) = 0 AND slug = $3)
+        "#,
+        newsletter_issue_id,
+        list_ids,
+        DEFAULT_LIST
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// The lists an issue goes out to.
+#[tracing::instrument(name = "Get newsletter issue lists", skip(pool))]
+pub async fn get_issue_list_ids(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Vec<Uuid>, sqlx::Error> {
+    sqlx::query!(
+        "SELECT list_id FROM newsletter_issue_lists WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .map(|rows| rows.into_iter().map(|row| row.list_id).collect())
+}
+
+async fn insert_revision(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue: &NewsletterIssue,
+    author_id: Option<Uuid>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_revisions (
+            revision_id, newsletter_issue_id, title, html_content, author_id, created_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        Uuid::new_v4(),
+        issue.newsletter_issue_id,
+        issue.title,
+        issue.html_content,
+        author_id,
+        issue.updated_at
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// Save new content for an issue, keeping the previous version as a revision.
+/// Returns `None` if no issue matches `newsletter_issue_id`.
+#[tracing::instrument(name = "Update newsletter issue", skip(pool, content))]
+pub async fn update_issue(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    content: &IssueContent<'_>,
+    author_id: Uuid,
+) -> Result<Option<NewsletterIssue>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        UPDATE newsletter_issues
+        SET title = $2, html_content = $3, updated_at = $4, topic_id = $5, segment = $6
+        WHERE newsletter_issue_id = $1
+        RETURNING newsletter_issue_id, slug, title, html_content, is_public, published_at,
+            updated_at, topic_id, segment
+        "#,
+        newsletter_issue_id,
+        content.title,
+        content.html_content,
+        Utc::now(),
+        content.topic_id,
+        content.segment
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+
+    if let Some(issue) = &issue {
+        set_issue_lists(
+            &mut transaction,
+            issue.newsletter_issue_id,
+            content.list_ids,
+        )
+        .await?;
+        insert_revision(&mut transaction, issue, Some(author_id)).await?;
+    }
+    transaction.commit().await?;
+
+    Ok(issue)
+}
+
+/// Returns `None` if the issue does not exist or was already published.
+#[tracing::instrument(name = "Mark newsletter draft as published", skip(pool))]
+pub async fn mark_published(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<NewsletterIssue>, sqlx::Error> {
+    sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        UPDATE newsletter_issues
+        SET published_at = $2, updated_at = $2
+        WHERE newsletter_issue_id = $1 AND published_at IS NULL
+        RETURNING newsletter_issue_id, slug, title, html_content, is_public, published_at,
+            updated_at, topic_id, segment
+        "#,
+        newsletter_issue_id,
+        Utc::now()
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Issues from every list, or only those sent to `list_id`.
+#[tracing::instrument(name = "Get public newsletter issues", skip(pool))]
+pub async fn get_public_issues(
+    pool: &PgPool,
+    list_id: Option<Uuid>,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<PublishedIssue>, sqlx::Error> {
+    sqlx::query_as!(
+        PublishedIssue,
+        r#"
+        SELECT i.newsletter_issue_id, i.slug, i.title, i.html_content,
+            i.published_at AS "published_at!", i.updated_at
+        FROM newsletter_issues i
+        WHERE i.is_public AND i.published_at IS NOT NULL
+            AND ($1::uuid IS NULL OR EXISTS (
+                SELECT 1 FROM newsletter_issue_lists il
+                WHERE il.newsletter_issue_id = i.newsletter_issue_id AND il.list_id = $1
+            ))
+        ORDER BY i.published_at DESC
+        LIMIT $2 OFFSET $3
+        "#,
+        list_id,
+        limit,
+        offset
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[tracing::instrument(name = "Get public newsletter issue", skip(pool))]
+pub async fn get_public_issue(
+    pool: &PgPool,
+    slug: &str,
+) -> Result<Option<PublishedIssue>, sqlx::Error> {
+    sqlx::query_as!(
+        PublishedIssue,
+        r#"
+        SELECT newsletter_issue_id, slug, title, html_content,
+            published_at AS "published_at!", updated_at
+        FROM newsletter_issues
+        WHERE slug = $1 AND is_public AND published_at IS NOT NULL
+        "#,
+        slug
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+#[tracing::instrument(name = "Get newsletter issue", skip(pool))]
+pub async fn get_issue(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<NewsletterIssue>, sqlx::Error> {
+    sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT newsletter_issue_id, slug, title, html_content, is_public, published_at,
+            updated_at, topic_id, segment
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Drafts first, then published issues from newest to oldest.
+#[tracing::instrument(name = "Get all newsletter issues", skip(pool))]
+pub async fn get_all_issues(pool: &PgPool) -> Result<Vec<NewsletterIssue>, sqlx::Error> {
+    sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT newsletter_issue_id, slug, title, html_content, is_public, published_at,
+            updated_at, topic_id, segment
+        FROM newsletter_issues
+        ORDER BY published_at DESC NULLS FIRST, updated_at DESC
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Returns `false` if no issue matches `newsletter_issue_id`.
+#[tracing::instrument(name = "Set newsletter issue visibility", skip(pool))]
+pub async fn set_issue_visibility(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    is_public: bool,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET is_public = $2, updated_at = now()
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        is_public
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Most recent change to any published issue, public or not: hiding an issue
+/// changes what the archive and feeds show too.
+#[tracing::instrument(name = "Get last newsletter issue update", skip(pool))]
+pub async fn get_last_update(pool: &PgPool) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    sqlx::query!(
+        r#"
+        SELECT MAX(updated_at) AS last_update
+        FROM newsletter_issues
+        WHERE published_at IS NOT NULL
+        "#,
+    )
+    .fetch_one(pool)
+    .await
+    .map(|row| row.last_update)
+}
+
+/// Newest first.
+#[tracing::instrument(name = "Get newsletter issue revisions", skip(pool))]
+pub async fn get_revisions(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Vec<Revision>, sqlx::Error> {
+    sqlx::query_as!(
+        Revision,
+        r#"
+        SELECT r.revision_id, r.newsletter_issue_id, r.title, r.html_content,
+            u.username AS "author?", r.created_at
+        FROM newsletter_issue_revisions r
+        LEFT JOIN users u ON u.user_id = r.author_id
+        WHERE r.newsletter_issue_id = $1
+        ORDER BY r.created_at DESC
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_all(pool)
+    .await
+}
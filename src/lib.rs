@@ -1,9 +1,35 @@
+pub mod analytics;
+pub mod attributes;
 pub mod authenticate;
+pub mod bounces;
 pub mod configuration;
+pub mod confirmation_queue;
+pub mod digest;
 pub mod domain;
+pub mod dsn;
 pub mod email;
+pub mod email_policy;
+pub mod export;
+pub mod feed_poller;
+pub mod import;
+pub mod issues;
+pub mod lists;
+pub mod mailbox;
+pub mod preferences;
+pub mod privacy;
+pub mod publish;
+pub mod reports;
 pub mod routes;
+pub mod segments;
 pub mod session_state;
+pub mod spam;
 pub mod startup;
+pub mod subscribers;
+pub mod suppressions;
 pub mod telemetry;
+pub mod templates;
+pub mod topics;
+pub mod tracking;
 pub mod utils;
+pub mod verp;
+pub mod webhooks;
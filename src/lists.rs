@@ -0,0 +1,266 @@
+//! src/lists.rs
+//!
+//! The newsletters run from this installation. Subscribers confirm each list
+//! they join separately, and issues go out to one or more lists.
+use crate::domain::{ListSubscriptionStatus, Person, Slug};
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// The list created with the installation. Sign-ups and issues that don't
+/// name a list go to it.
+pub const DEFAULT_LIST: &str = "default";
+
+const DEFAULT_CONFIRMATION_SUBJECT: &str = "Welcome!";
+const DEFAULT_BUTTON_LABEL: &str = "Subscribe";
+const DEFAULT_SUCCESS_MESSAGE: &str =
+    "Thanks for signing up! Check your inbox to confirm your subscription.";
+
+#[derive(Debug)]
+pub struct List {
+    pub list_id: Uuid,
+    pub slug: String,
+    pub name: String,
+    pub sender_name: Option<String>,
+    pub sender_email: Option<String>,
+    pub confirmation_subject: Option<String>,
+    /// Shown above the confirmation link instead of the default copy.
+    pub confirmation_html: Option<String>,
+    /// Shown above the embeddable sign-up form instead of the list name.
+    pub widget_heading: Option<String>,
+    pub widget_button_label: Option<String>,
+    pub widget_success_message: Option<String>,
+    /// Where plain HTML forms posting from other sites land after signing up.
+    pub redirect_url: Option<String>,
+    /// Whether issues sent to the list count opens and clicks.
+    pub track_engagement: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+impl List {
+    /// `None` when the list sends as the sender from the email settings.
+    pub fn sender(&self) -> Option<Person> {
+        match (&self.sender_name, &self.sender_email) {
+            (Some(name), Some(email)) => Person::parse(name.clone(), email.clone()).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn confirmation_subject(&self) -> &str {
+        self.confirmation_subject
+            .as_deref()
+            .unwrap_or(DEFAULT_CONFIRMATION_SUBJECT)
+    }
+
+    pub fn widget_heading(&self) -> &str {
+        self.widget_heading.as_deref().unwrap_or(&self.name)
+    }
+
+    pub fn widget_button_label(&self) -> &str {
+        self.widget_button_label
+            .as_deref()
+            .unwrap_or(DEFAULT_BUTTON_LABEL)
+    }
+
+    pub fn widget_success_message(&self) -> &str {
+        self.widget_success_message
+            .as_deref()
+            .unwrap_or(DEFAULT_SUCCESS_MESSAGE)
+    }
+}
+
+/// What an admin can change about a list. Empty values fall back to the defaults.
+#[derive(Debug)]
+pub struct ListSettings<'a> {
+    pub name: &'a str,
+    pub sender: Option<&'a Person>,
+    pub confirmation_subject: Option<&'a str>,
+    pub confirmation_html: Option<&'a str>,
+    pub widget_heading: Option<&'a str>,
+    pub widget_button_label: Option<&'a str>,
+    pub widget_success_message: Option<&'a str>,
+    pub redirect_url: Option<&'a str>,
+    pub track_engagement: bool,
+}
+
+/// One subscriber's membership of a list.
+#[derive(Debug)]
+pub struct ListSubscription {
+    pub list_id: Uuid,
+    pub name: String,
+    pub status: ListSubscriptionStatus,
+    pub subscribed_at: DateTime<Utc>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+}
+
+/// Oldest first, so the default list comes first.
+#[tracing::instrument(name = "Get lists", skip(pool))]
+pub async fn get_lists(pool: &PgPool) -> Result<Vec<List>, sqlx::Error> {
+    sqlx::query_as!(
+        List,
+        r#"
+        SELECT list_id, slug, name, sender_name, sender_email, confirmation_subject,
+            confirmation_html, widget_heading, widget_button_label, widget_success_message,
+            redirect_url, track_engagement, created_at
+        FROM lists
+        ORDER BY created_at
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[tracing::instrument(name = "Get list", skip(pool))]
+pub async fn get_list(pool: &PgPool, list_id: Uuid) -> Result<Option<List>, sqlx::Error> {
+    sqlx::query_as!(
+        List,
+        r#"
+        SELECT list_id, slug, name, sender_name, sender_email, confirmation_subject,
+            confirmation_html, widget_heading, widget_button_label, widget_success_message,
+            redirect_url, track_engagement, created_at
+        FROM lists
+        WHERE list_id = $1
+        "#,
+        list_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+#[tracing::instrument(name = "Get list by slug", skip(pool))]
+pub async fn get_list_by_slug(pool: &PgPool, slug: &str) -> Result<Option<List>, sqlx::Error> {
+    sqlx::query_as!(
+        List,
+        r#"
+        SELECT list_id, slug, name, sender_name, sender_email, confirmation_subject,
+            confirmation_html, widget_heading, widget_button_label, widget_success_message,
+            redirect_url, track_engagement, created_at
+        FROM lists
+        WHERE slug = $1
+        "#,
+        slug
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// The default list is created by a migration and cannot be removed.
+pub async fn get_default_list(pool: &PgPool) -> Result<List, sqlx::Error> {
+    get_list_by_slug(pool, DEFAULT_LIST)
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)
+}
+
+/// The slug is derived from the name. Returns `None` if another list already
+/// has that slug.
+#[tracing::instrument(name = "Store list", skip(pool))]
+pub async fn insert_list(pool: &PgPool, name: &str) -> Result<Option<List>, sqlx::Error> {
+    let slug = Slug::from_title(name);
+    sqlx::query_as!(
+        List,
+        r#"
+        INSERT INTO lists (list_id, slug, name, created_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (slug) DO NOTHING
+        RETURNING list_id, slug, name, sender_name, sender_email, confirmation_subject,
+            confirmation_html, widget_heading, widget_button_label, widget_success_message,
+            redirect_url, track_engagement, created_at
+        "#,
+        Uuid::new_v4(),
+        slug.as_ref(),
+        name,
+        Utc::now()
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// The slug is kept so links to the list's archive keep working.
+/// Returns `false` if no list matches `list_id`.
+#[tracing::instrument(name = "Update list", skip(pool))]
+pub async fn update_list(
+    pool: &PgPool,
+    list_id: Uuid,
+    settings: &ListSettings<'_>,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE lists
+        SET name = $2, sender_name = $3, sender_email = $4, confirmation_subject = $5,
+            confirmation_html = $6, widget_heading = $7, widget_button_label = $8,
+            widget_success_message = $9, redirect_url = $10, track_engagement = $11
+        WHERE list_id = $1
+        "#,
+        list_id,
+        settings.name,
+        settings.sender.map(|sender| sender.name.as_ref()),
+        settings.sender.map(|sender| sender.email.as_ref()),
+        settings.confirmation_subject,
+        settings.confirmation_html,
+        settings.widget_heading,
+        settings.widget_button_label,
+        settings.widget_success_message,
+        settings.redirect_url,
+        settings.track_engagement
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Every list the subscriber ever joined, oldest list first.
+#[tracing::instrument(name = "Get list subscriptions", skip(pool))]
+pub async fn get_list_subscriptions(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<ListSubscription>, sqlx::Error> {
+    sqlx::query_as!(
+        ListSubscription,
+        r#"
+        SELECT l.list_id, l.name, ls.status AS "status: ListSubscriptionStatus",
+            ls.subscribed_at, ls.confirmed_at
+        FROM list_subscriptions ls
+        JOIN lists l ON l.list_id = ls.list_id
+        WHERE ls.subscriber_id = $1
+        ORDER BY l.created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Add the subscriber to a list, pending their confirmation. A confirmed
+/// membership is left as it is.
+/// Returns the status of the membership.
+#[tracing::instrument(name = "Store list subscription", skip(transaction))]
+pub async fn insert_list_subscription(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+    status: ListSubscriptionStatus,
+) -> Result<ListSubscriptionStatus, sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at, confirmed_at)
+        VALUES (
+            $1, $2, $3::list_subscription_status, now(),
+            CASE WHEN $3::list_subscription_status = $4 THEN now() END
+        )
+        ON CONFLICT (list_id, subscriber_id) DO UPDATE
+        SET status = CASE
+            WHEN list_subscriptions.status = $4 THEN list_subscriptions.status
+            ELSE EXCLUDED.status
+        END
+        RETURNING status AS "status: ListSubscriptionStatus"
+        "#,
+        list_id,
+        subscriber_id,
+        status as ListSubscriptionStatus,
+        ListSubscriptionStatus::Confirmed as ListSubscriptionStatus
+    )
+    .fetch_one(&mut **transaction)
+    .await
+    .map(|row| row.status)
+}
@@ -0,0 +1,172 @@
+//! src/mailbox.rs
+//!
+//! The mailbox bounces and complaints are sent to when we send through
+//! SMTP, as a Maildir or an mbox file.
+use crate::bounces::record_feedback;
+use crate::configuration::EmailHashKey;
+use crate::dsn::parse_report;
+use anyhow::Context;
+use sqlx::PgPool;
+use std::path::{Path, PathBuf};
+
+/// Where the reports came from, as recorded with the feedback.
+const SOURCE: &str = "mailbox";
+
+#[derive(Debug, Default)]
+pub struct MailboxReport {
+    /// Messages read.
+    pub messages: usize,
+    /// Reports applied. Those applied on an earlier run are not counted.
+    pub applied: usize,
+    /// Messages holding no report, like out of office replies.
+    pub ignored: usize,
+}
+
+/// Apply the reports in the Maildir or mbox file at `path`.
+#[tracing::instrument(name = "Process bounce mailbox", skip(pool, email_hash_key))]
+pub async fn process_mailbox(
+    pool: &PgPool,
+    email_hash_key: &EmailHashKey,
+    path: &Path,
+) -> Result<MailboxReport, anyhow::Error> {
+    if path.is_dir() {
+        process_maildir(pool, email_hash_key, path).await
+    } else {
+        process_mbox(pool, email_hash_key, path).await
+    }
+}
+
+/// Only new messages are read. They are moved to `cur` once processed, the
+/// way a mail client marks them as seen.
+async fn process_maildir(
+    pool: &PgPool,
+    email_hash_key: &EmailHashKey,
+    path: &Path,
+) -> Result<MailboxReport, anyhow::Error> {
+    let new = path.join("new");
+    let cur = path.join("cur");
+    let mut report = MailboxReport::default();
+
+    let mut messages = std::fs::read_dir(&new)
+        .with_context(|| format!("Failed to read {}", new.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<PathBuf>, _>>()
+        .with_context(|| format!("Failed to read {}", new.display()))?;
+    messages.sort();
+
+    for message in messages {
+        let name = match message.file_name() {
+            Some(name) if message.is_file() => name.to_string_lossy().into_owned(),
+            _ => continue,
+        };
+        let raw = std::fs::read(&message)
+            .with_context(|| format!("Failed to read {}", message.display()))?;
+
+        process_message(pool, email_hash_key, &raw, &mut report).await?;
+
+        std::fs::rename(&message, cur.join(format!("{}:2,S", name)))
+            .with_context(|| format!("Failed to move {} to cur", message.display()))?;
+    }
+
+    Ok(report)
+}
+
+/// The file is left as it is: reports already applied are recognised when
+/// it is read again.
+async fn process_mbox(
+    pool: &PgPool,
+    email_hash_key: &EmailHashKey,
+    path: &Path,
+) -> Result<MailboxReport, anyhow::Error> {
+    let content =
+        std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut report = MailboxReport::default();
+
+    for message in split_mbox(&content) {
+        process_message(pool, email_hash_key, &message, &mut report).await?;
+    }
+
+    Ok(report)
+}
+
+async fn process_message(
+    pool: &PgPool,
+    email_hash_key: &EmailHashKey,
+    raw: &[u8],
+    report: &mut MailboxReport,
+) -> Result<(), anyhow::Error> {
+    report.messages += 1;
+
+    let feedback = match parse_report(raw) {
+        Ok(feedback) => feedback,
+        Err(e) => {
+            tracing::warn!(error.cause_chain = ?e, "Ignoring a malformed message");
+            vec![]
+        }
+    };
+    if feedback.is_empty() {
+        report.ignored += 1;
+        return Ok(());
+    }
+
+    for feedback in &feedback {
+        if record_feedback(pool, email_hash_key, SOURCE, feedback).await? {
+            report.applied += 1;
+        }
+    }
+
+    Ok(())
+}
+
+/// Each message starts with a `From ` line. Lines of the message starting
+/// with `From ` were escaped with a `>`.
+fn split_mbox(content: &[u8]) -> Vec<Vec<u8>> {
+    let mut messages = vec![];
+    let mut current: Option<Vec<u8>> = None;
+
+    for line in content.split_inclusive(|byte| *byte == b'\n') {
+        if line.starts_with(b"From ") {
+            messages.extend(current.replace(vec![]));
+            continue;
+        }
+        if let Some(message) = &mut current {
+            let quotes = line.iter().take_while(|byte| **byte == b'>').count();
+            if quotes > 0 && line[quotes..].starts_with(b"From ") {
+                message.extend_from_slice(&line[1..]);
+            } else {
+                message.extend_from_slice(line);
+            }
+        }
+    }
+    messages.extend(current);
+
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mbox_files_are_split_into_messages() {
+        let mbox = b"From MAILER-DAEMON Mon Jan 22 10:00:00 2024\n\
+            Subject: One\n\
+            \n\
+            >From here on\n\
+            \n\
+            From MAILER-DAEMON Mon Jan 22 11:00:00 2024\n\
+            Subject: Two\n\
+            \n\
+            >>From there\n";
+
+        let messages = split_mbox(mbox);
+
+        assert_eq!(
+            messages,
+            [
+                b"Subject: One\n\nFrom here on\n\n".to_vec(),
+                b"Subject: Two\n\n>From there\n".to_vec()
+            ]
+        );
+    }
+}
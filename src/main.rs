@@ -1,6 +1,11 @@
 use letter::configuration::get_configuration;
+use letter::confirmation_queue;
+use letter::digest;
+use letter::feed_poller;
 use letter::startup::build;
 use letter::telemetry::{get_subscriber, init_subscriber};
+use std::fmt::{Debug, Display};
+use tokio::task::JoinError;
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
@@ -8,8 +13,43 @@ async fn main() -> Result<(), anyhow::Error> {
     init_subscriber(subscriber);
 
     let config = get_configuration().expect("Failed to read configuration.");
-    let app = build(config).await?;
-    app.run().await?;
+    let app = build(config.clone()).await?;
+
+    let app_task = tokio::spawn(app.run());
+    let feed_poller_task = tokio::spawn(feed_poller::run_worker_until_stopped(config.clone()));
+    let digest_task = tokio::spawn(digest::run_worker_until_stopped(config.clone()));
+    let confirmation_task = tokio::spawn(confirmation_queue::run_worker_until_stopped(config));
+
+    tokio::select! {
+        outcome = app_task => report_exit("API", outcome),
+        outcome = feed_poller_task => report_exit("Feed poller", outcome),
+        outcome = digest_task => report_exit("Digest sender", outcome),
+        outcome = confirmation_task => report_exit("Confirmation email sender", outcome),
+    };
 
     Ok(())
 }
+
+fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name)
+        }
+        Ok(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            )
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} task failed to complete",
+                task_name
+            )
+        }
+    }
+}
@@ -0,0 +1,316 @@
+//! src/preferences.rs
+//!
+//! What each subscriber chose to receive, edited from the preference center.
+use crate::configuration::HmacSecret;
+use crate::domain::{ListSubscriptionStatus, SubscriptionStatus};
+use crate::subscribers::{change_status, StatusError};
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Longest pause a subscriber can ask for.
+pub const MAX_PAUSE_WEEKS: u32 = 52;
+
+/// Preference links go out with every email and must keep working, so unlike
+/// privacy links they do not expire.
+pub fn preferences_token(subscriber_id: Uuid, secret: &HmacSecret) -> String {
+    let signature = hex::encode(mac(subscriber_id, secret).finalize().into_bytes());
+    format!("{}.{}", subscriber_id, signature)
+}
+
+/// The subscriber a token was issued for.
+pub fn verify_preferences_token(token: &str, secret: &HmacSecret) -> Result<Uuid, anyhow::Error> {
+    let (subscriber_id, signature) = token
+        .split_once('.')
+        .ok_or_else(|| anyhow::anyhow!("The token is malformed"))?;
+    let subscriber_id = Uuid::parse_str(subscriber_id)?;
+    let signature = hex::decode(signature)?;
+
+    mac(subscriber_id, secret)
+        .verify_slice(&signature)
+        .map_err(|_| anyhow::anyhow!("The token signature is invalid"))?;
+
+    Ok(subscriber_id)
+}
+
+pub fn preferences_url(base_url: &str, subscriber_id: Uuid, secret: &HmacSecret) -> String {
+    format!(
+        "{}/preferences/{}",
+        base_url,
+        preferences_token(subscriber_id, secret)
+    )
+}
+
+fn mac(subscriber_id: Uuid, secret: &HmacSecret) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(format!("preferences:{}", subscriber_id).as_bytes());
+    mac
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Frequency {
+    #[default]
+    EveryIssue,
+    /// Issues are collected and sent together once a week.
+    WeeklyDigest,
+}
+
+impl Frequency {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Frequency::EveryIssue => "every_issue",
+            Frequency::WeeklyDigest => "weekly_digest",
+        }
+    }
+
+    /// Unknown values fall back to the default rather than failing.
+    pub fn from_column(value: &str) -> Self {
+        match value {
+            "weekly_digest" => Frequency::WeeklyDigest,
+            _ => Frequency::EveryIssue,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TopicChoice {
+    pub topic_id: Uuid,
+    pub name: String,
+    pub subscribed: bool,
+}
+
+/// A list the subscriber confirmed at some point. Leaving a list can be
+/// undone from the preference center without confirming again.
+#[derive(Debug)]
+pub struct ListChoice {
+    pub list_id: Uuid,
+    pub name: String,
+    pub subscribed: bool,
+}
+
+#[derive(Debug)]
+pub struct Preferences {
+    pub subscriber_id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: SubscriptionStatus,
+    pub frequency: Frequency,
+    pub paused_until: Option<DateTime<Utc>>,
+    pub topics: Vec<TopicChoice>,
+    pub lists: Vec<ListChoice>,
+}
+
+impl Preferences {
+    pub fn is_paused(&self) -> bool {
+        self.paused_until.is_some_and(|until| until > Utc::now())
+    }
+
+    pub fn is_unsubscribed(&self) -> bool {
+        self.status == SubscriptionStatus::Unsubscribed
+    }
+}
+
+/// Returns `None` if no subscriber matches `subscriber_id`.
+#[tracing::instrument(name = "Get subscriber preferences", skip(pool))]
+pub async fn get_preferences(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<Preferences>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT id, email, name, status AS "status: SubscriptionStatus", frequency, paused_until
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+
+    let topics = sqlx::query!(
+        r#"
+        SELECT t.topic_id, t.name, o.subscriber_id IS NULL AS "subscribed!"
+        FROM topics t
+        LEFT JOIN subscriber_topic_opt_outs o
+            ON o.topic_id = t.topic_id AND o.subscriber_id = $1
+        ORDER BY t.name
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|topic| TopicChoice {
+        topic_id: topic.topic_id,
+        name: topic.name,
+        subscribed: topic.subscribed,
+    })
+    .collect();
+
+    let lists = sqlx::query!(
+        r#"
+        SELECT l.list_id, l.name, ls.status = $2 AS "subscribed!"
+        FROM list_subscriptions ls
+        JOIN lists l ON l.list_id = ls.list_id
+        WHERE ls.subscriber_id = $1 AND ls.confirmed_at IS NOT NULL
+        ORDER BY l.created_at
+        "#,
+        subscriber_id,
+        ListSubscriptionStatus::Confirmed as ListSubscriptionStatus
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|list| ListChoice {
+        list_id: list.list_id,
+        name: list.name,
+        subscribed: list.subscribed,
+    })
+    .collect();
+
+    Ok(Some(Preferences {
+        subscriber_id: row.id,
+        email: row.email,
+        name: row.name,
+        status: row.status,
+        frequency: Frequency::from_column(&row.frequency),
+        paused_until: row.paused_until,
+        topics,
+        lists,
+    }))
+}
+
+/// Save the name, frequency, topics and lists in one go. Every topic that is
+/// not in `topic_ids` is opted out of, and every confirmed list that is not in
+/// `list_ids` is left.
+///
+/// Switching to the weekly digest starts a digest period: the first digest goes
+/// out a week later with the issues published in between.
+#[tracing::instrument(name = "Update subscriber preferences", skip(pool))]
+pub async fn update_preferences(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    name: &str,
+    frequency: Frequency,
+    topic_ids: &[Uuid],
+    list_ids: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET name = $2,
+            last_digest_at = CASE
+                WHEN frequency <> $3 AND $3 = 'weekly_digest' THEN now()
+                ELSE last_digest_at
+            END,
+            frequency = $3
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        name,
+        frequency.as_str()
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM subscriber_topic_opt_outs WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_topic_opt_outs (subscriber_id, topic_id)
+        SELECT $1, topic_id
+        FROM topics
+        WHERE topic_id <> ALL($2)
+        "#,
+        subscriber_id,
+        topic_ids
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE list_subscriptions
+        SET status = CASE WHEN list_id = ANY($2) THEN $3::list_subscription_status ELSE $4 END
+        WHERE subscriber_id = $1 AND confirmed_at IS NOT NULL
+        "#,
+        subscriber_id,
+        list_ids,
+        ListSubscriptionStatus::Confirmed as ListSubscriptionStatus,
+        ListSubscriptionStatus::Unsubscribed as ListSubscriptionStatus
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await
+}
+
+/// Stop sending anything for `weeks` weeks. Zero weeks resumes delivery.
+#[tracing::instrument(name = "Pause subscriber deliveries", skip(pool))]
+pub async fn pause_deliveries(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    weeks: u32,
+) -> Result<(), sqlx::Error> {
+    let paused_until = (weeks > 0).then(|| Utc::now() + Duration::weeks(weeks.into()));
+
+    sqlx::query!(
+        "UPDATE subscriptions SET paused_until = $2 WHERE id = $1",
+        subscriber_id,
+        paused_until
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Only confirmed subscribers can unsubscribe: anyone else isn't being sent
+/// issues in the first place.
+#[tracing::instrument(name = "Unsubscribe subscriber", skip(pool))]
+pub async fn unsubscribe(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    reason: Option<&str>,
+) -> Result<(), StatusError> {
+    let mut transaction = pool.begin().await?;
+
+    change_status(
+        &mut transaction,
+        subscriber_id,
+        SubscriptionStatus::Unsubscribed,
+        "unsubscribe",
+    )
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET unsubscribed_at = now(), unsubscribe_reason = $2
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        reason
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(())
+}
@@ -0,0 +1,408 @@
+//! src/privacy.rs
+//!
+//! Data subject requests: what we store about an email address, and erasing it.
+use crate::configuration::{EmailHashKey, HmacSecret};
+use crate::domain::person::Email;
+use crate::domain::{ListSubscriptionStatus, SubscriptionStatus};
+use crate::subscribers::delete_subscriber_rows;
+use crate::suppressions::insert_suppression;
+use chrono::{Duration, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+/// How long a self-service link stays valid.
+const LINK_LIFETIME_HOURS: i64 = 48;
+
+/// Addresses are only ever kept hashed once erased, so they can be recognised
+/// without being stored. The hash is keyed, so a leaked table can't be
+/// checked against a list of known addresses, and taken over `email_key` so
+/// every spelling of an address gets the same one.
+pub fn email_hash(email: &str, key: &EmailHashKey) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).unwrap();
+    mac.update(email_key(email).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// The `email_key` of the subscriber with this address, however it is written.
+pub fn email_key(email: &str) -> String {
+    match Email::parse(email.trim().to_string()) {
+        Ok(email) => email.key(),
+        Err(_) => email.trim().to_lowercase(),
+    }
+}
+
+/// A signed, expiring link giving access to the data of one email address.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct PrivacyLink {
+    pub email: String,
+    pub expires: i64,
+    pub signature: String,
+}
+
+impl PrivacyLink {
+    pub fn new(email: &str, secret: &HmacSecret) -> Self {
+        let expires = (Utc::now() + Duration::hours(LINK_LIFETIME_HOURS)).timestamp();
+
+        Self {
+            email: email.to_string(),
+            expires,
+            signature: hex::encode(Self::mac(email, expires, secret).finalize().into_bytes()),
+        }
+    }
+
+    pub fn verify(&self, secret: &HmacSecret) -> Result<(), anyhow::Error> {
+        if self.expires < Utc::now().timestamp() {
+            anyhow::bail!("The link has expired");
+        }
+
+        let signature = hex::decode(&self.signature)?;
+        Self::mac(&self.email, self.expires, secret)
+            .verify_slice(&signature)
+            .map_err(|_| anyhow::anyhow!("The link signature is invalid"))
+    }
+
+    pub fn query_string(&self) -> String {
+        format!(
+            "email={}&expires={}&signature={}",
+            urlencoding::encode(&self.email),
+            self.expires,
+            self.signature
+        )
+    }
+
+    fn mac(email: &str, expires: i64, secret: &HmacSecret) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(format!("privacy:{}:{}", email, expires).as_bytes());
+        mac
+    }
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct SubscriptionData {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: SubscriptionStatus,
+    pub subscribed_at: String,
+    pub confirmed_at: Option<String>,
+    pub consent_source: Option<String>,
+    pub frequency: String,
+    pub paused_until: Option<String>,
+    pub unsubscribed_at: Option<String>,
+    pub unsubscribe_reason: Option<String>,
+    pub opted_out_topics: Vec<String>,
+    pub lists: Vec<ListData>,
+    pub tags: Vec<String>,
+    /// Custom field values by field name.
+    pub fields: BTreeMap<String, String>,
+    pub tokens: Vec<TokenData>,
+    pub deliveries: Vec<DeliveryData>,
+    pub engagement: Vec<EngagementData>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct ListData {
+    pub name: String,
+    pub status: ListSubscriptionStatus,
+    pub subscribed_at: String,
+    pub confirmed_at: Option<String>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct TokenData {
+    pub subscription_token: String,
+    pub created_at: Option<String>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct DeliveryData {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub delivered_at: String,
+}
+
+/// An open or a click of an issue.
+#[derive(serde::Serialize, Debug)]
+pub struct EngagementData {
+    pub kind: String,
+    pub newsletter_issue_id: Uuid,
+    pub url: Option<String>,
+    pub occurred_at: String,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct SuppressionData {
+    pub reason: String,
+    pub source: String,
+    pub created_at: String,
+}
+
+/// Everything stored about one email address, as handed over to its owner.
+#[derive(serde::Serialize, Debug)]
+pub struct PersonalData {
+    pub email: String,
+    pub exported_at: String,
+    pub subscriptions: Vec<SubscriptionData>,
+    pub suppressions: Vec<SuppressionData>,
+}
+
+impl PersonalData {
+    pub fn is_empty(&self) -> bool {
+        self.subscriptions.is_empty() && self.suppressions.is_empty()
+    }
+}
+
+/// Addresses are matched case-insensitively.
+#[tracing::instrument(name = "Collect personal data", skip(pool, email_hash_key))]
+pub async fn collect_personal_data(
+    pool: &PgPool,
+    email_hash_key: &EmailHashKey,
+    email: &str,
+) -> Result<PersonalData, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT id, email, name, status AS "status: SubscriptionStatus", subscribed_at, confirmed_at,
+            consent_source, frequency, paused_until, unsubscribed_at, unsubscribe_reason
+        FROM subscriptions
+        WHERE email_key = $1
+        "#,
+        email_key(email)
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut subscriptions = Vec::new();
+    for row in rows {
+        let opted_out_topics = sqlx::query!(
+            r#"
+            SELECT t.name
+            FROM subscriber_topic_opt_outs o
+            JOIN topics t ON t.topic_id = o.topic_id
+            WHERE o.subscriber_id = $1
+            ORDER BY t.name
+            "#,
+            row.id
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|topic| topic.name)
+        .collect();
+
+        let lists = sqlx::query!(
+            r#"
+            SELECT l.name, ls.status AS "status: ListSubscriptionStatus", ls.subscribed_at,
+                ls.confirmed_at
+            FROM list_subscriptions ls
+            JOIN lists l ON l.list_id = ls.list_id
+            WHERE ls.subscriber_id = $1
+            ORDER BY l.created_at
+            "#,
+            row.id
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|list| ListData {
+            name: list.name,
+            status: list.status,
+            subscribed_at: list.subscribed_at.to_rfc3339(),
+            confirmed_at: list.confirmed_at.map(|at| at.to_rfc3339()),
+        })
+        .collect();
+
+        let tags = sqlx::query!(
+            "SELECT tag FROM subscriber_tags WHERE subscriber_id = $1 ORDER BY tag",
+            row.id
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|tag| tag.tag)
+        .collect();
+
+        let fields = sqlx::query!(
+            r#"
+            SELECT f.name, v.value
+            FROM subscriber_field_values v
+            JOIN custom_fields f ON f.field_id = v.field_id
+            WHERE v.subscriber_id = $1
+            "#,
+            row.id
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|field| (field.name, field.value))
+        .collect();
+
+        let tokens = sqlx::query!(
+            r#"
+            SELECT subscription_token, created_at
+            FROM subscription_tokens
+            WHERE subscriber_id = $1
+            "#,
+            row.id
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|token| TokenData {
+            subscription_token: token.subscription_token,
+            created_at: token.created_at.map(|at| at.to_rfc3339()),
+        })
+        .collect();
+
+        let deliveries = sqlx::query!(
+            r#"
+            SELECT d.newsletter_issue_id, i.title, d.delivered_at
+            FROM newsletter_issue_deliveries d
+            JOIN newsletter_issues i ON i.newsletter_issue_id = d.newsletter_issue_id
+            WHERE d.subscriber_id = $1
+            ORDER BY d.delivered_at
+            "#,
+            row.id
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|delivery| DeliveryData {
+            newsletter_issue_id: delivery.newsletter_issue_id,
+            title: delivery.title,
+            delivered_at: delivery.delivered_at.to_rfc3339(),
+        })
+        .collect();
+
+        let engagement = sqlx::query!(
+            r#"
+            SELECT kind, newsletter_issue_id, url, occurred_at
+            FROM engagement_events
+            WHERE subscriber_id = $1
+            ORDER BY occurred_at
+            "#,
+            row.id
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|event| EngagementData {
+            kind: event.kind,
+            newsletter_issue_id: event.newsletter_issue_id,
+            url: event.url,
+            occurred_at: event.occurred_at.to_rfc3339(),
+        })
+        .collect();
+
+        subscriptions.push(SubscriptionData {
+            id: row.id,
+            email: row.email,
+            name: row.name,
+            status: row.status,
+            subscribed_at: row.subscribed_at.to_rfc3339(),
+            confirmed_at: row.confirmed_at.map(|at| at.to_rfc3339()),
+            consent_source: row.consent_source,
+            frequency: row.frequency,
+            paused_until: row.paused_until.map(|at| at.to_rfc3339()),
+            unsubscribed_at: row.unsubscribed_at.map(|at| at.to_rfc3339()),
+            unsubscribe_reason: row.unsubscribe_reason,
+            opted_out_topics,
+            lists,
+            tags,
+            fields,
+            tokens,
+            deliveries,
+            engagement,
+        });
+    }
+
+    let suppressions = sqlx::query!(
+        r#"
+        SELECT reason, source, created_at
+        FROM suppressions
+        WHERE email_hash = $1
+        "#,
+        email_hash(email, email_hash_key)
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| SuppressionData {
+        reason: row.reason,
+        source: row.source,
+        created_at: row.created_at.to_rfc3339(),
+    })
+    .collect();
+
+    Ok(PersonalData {
+        email: email.trim().to_string(),
+        exported_at: Utc::now().to_rfc3339(),
+        subscriptions,
+        suppressions,
+    })
+}
+
+/// Delete everything stored about `email`, as `delete_subscriber_rows` does,
+/// and remember its hash, so the address is not imported again.
+/// Returns the number of subscriptions that were erased.
+#[tracing::instrument(name = "Erase personal data", skip(pool, email_hash_key, email))]
+pub async fn erase_personal_data(
+    pool: &PgPool,
+    email_hash_key: &EmailHashKey,
+    email: &str,
+    source: &str,
+) -> Result<u64, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let subscriber_ids: Vec<Uuid> = sqlx::query!(
+        "SELECT id FROM subscriptions WHERE email_key = $1",
+        email_key(email)
+    )
+    .fetch_all(&mut *transaction)
+    .await?
+    .into_iter()
+    .map(|row| row.id)
+    .collect();
+
+    let erased = delete_subscriber_rows(
+        &mut transaction,
+        &email_hash(email, email_hash_key),
+        &subscriber_ids,
+    )
+    .await?;
+
+    insert_suppression(&mut transaction, email_hash_key, email, "erased", source).await?;
+
+    transaction.commit().await?;
+
+    Ok(erased)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secrecy::Secret;
+
+    fn key() -> EmailHashKey {
+        EmailHashKey(Secret::new("a-very-secret-key".into()))
+    }
+
+    #[test]
+    fn every_spelling_of_an_address_has_the_same_hash() {
+        let hash = email_hash("ursula@xn--bcher-kva.example", &key());
+
+        assert_eq!(email_hash(" Ursula@BÜCHER.example ", &key()), hash);
+    }
+
+    #[test]
+    fn the_hash_depends_on_the_key() {
+        let other = EmailHashKey(Secret::new("another-key".into()));
+
+        assert_ne!(
+            email_hash("ursula@example.com", &key()),
+            email_hash("ursula@example.com", &other)
+        );
+    }
+}
@@ -0,0 +1,309 @@
+//! src/publish.rs
+use crate::attributes::get_custom_fields;
+use crate::configuration::HmacSecret;
+use crate::domain::{ListSubscriptionStatus, Person as Subscriber, SubscriptionStatus};
+use crate::email::{Brevo, SendError, Sent};
+use crate::issues::{
+    insert_newsletter_issue, mark_published, IssueContent, IssueTemplate, NewsletterIssue,
+};
+use crate::lists::DEFAULT_LIST;
+use crate::preferences::preferences_url;
+use crate::segments::{filter_by_segment, Segment};
+use crate::subscribers::{record_delivery, record_send_failure};
+use crate::tracking::{is_tracked, Tracker};
+use anyhow::Context;
+use askama::Template;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Store a new issue in the archive and email it to the confirmed subscribers
+/// of its lists.
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip(pool, email_client, hmac_secret, content)
+)]
+pub async fn publish_issue(
+    pool: &PgPool,
+    email_client: &Brevo,
+    base_url: &str,
+    hmac_secret: &HmacSecret,
+    content: &IssueContent<'_>,
+    author_id: Option<Uuid>,
+) -> Result<NewsletterIssue, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection")?;
+    let issue = insert_newsletter_issue(&mut transaction, content, author_id)
+        .await
+        .context("Failed to store the newsletter issue")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to store the newsletter issue")?;
+
+    deliver_issue(pool, email_client, base_url, hmac_secret, &issue).await?;
+
+    Ok(issue)
+}
+
+/// Publish a draft saved from the composer.
+/// Returns `None` if the issue does not exist or was already published.
+#[tracing::instrument(
+    name = "Publish a newsletter draft",
+    skip(pool, email_client, hmac_secret)
+)]
+pub async fn publish_draft(
+    pool: &PgPool,
+    email_client: &Brevo,
+    base_url: &str,
+    hmac_secret: &HmacSecret,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<NewsletterIssue>, anyhow::Error> {
+    let issue = match mark_published(pool, newsletter_issue_id)
+        .await
+        .context("Failed to mark the draft as published")?
+    {
+        Some(issue) => issue,
+        None => return Ok(None),
+    };
+
+    deliver_issue(pool, email_client, base_url, hmac_secret, &issue).await?;
+
+    Ok(Some(issue))
+}
+
+/// Subscribers on the weekly digest get the issue later, with the digest.
+/// Those who already got the issue are skipped, so a delivery that was cut
+/// short can be run again to reach the rest.
+pub async fn deliver_issue(
+    pool: &PgPool,
+    email_client: &Brevo,
+    base_url: &str,
+    hmac_secret: &HmacSecret,
+    issue: &NewsletterIssue,
+) -> Result<(), anyhow::Error> {
+    let view_in_browser_url = format!("{}/archive/{}", base_url, issue.slug);
+
+    let confirmed_subscribers = get_confirmed_subscribers(pool, issue)
+        .await
+        .context("Failed to retrieve confirmed subscribers")?;
+
+    let mut parsed_subscribers = parse_confirmed_subscribers(confirmed_subscribers);
+
+    if let Some(segment) = &issue.segment {
+        let fields = get_custom_fields(pool)
+            .await
+            .context("Failed to retrieve custom fields")?;
+        let segment = Segment::parse(segment, &fields).context("Failed to parse the segment")?;
+        let subscriber_ids: Vec<Uuid> = parsed_subscribers.iter().map(|(id, ..)| *id).collect();
+        let matching = filter_by_segment(pool, &segment, &subscriber_ids)
+            .await
+            .context("Failed to match subscribers against the segment")?;
+        parsed_subscribers.retain(|(id, ..)| matching.contains(id));
+    }
+
+    let tracked = is_tracked(pool, issue.newsletter_issue_id)
+        .await
+        .context("Failed to check whether the issue is tracked")?;
+
+    for (subscriber_id, subscriber, sender) in parsed_subscribers {
+        let preferences_url = preferences_url(base_url, subscriber_id, hmac_secret);
+        let issue_content = if tracked {
+            Tracker {
+                base_url,
+                newsletter_issue_id: issue.newsletter_issue_id,
+                subscriber_id,
+                secret: hmac_secret,
+            }
+            .track(&issue.html_content)
+        } else {
+            issue.html_content.clone()
+        };
+        let html_content = IssueTemplate {
+            title: &issue.title,
+            html_content: &issue_content,
+            view_in_browser_url: Some(&view_in_browser_url),
+            preferences_url: Some(&preferences_url),
+        }
+        .render()
+        .context("Failed to render the newsletter issue")?;
+
+        let mut email = email_client.email_builder();
+        if let Some(sender) = &sender {
+            email = email.sender(sender);
+        }
+        let email = email
+            .subject(&issue.title)
+            .to(&subscriber)
+            .html_content(&html_content)
+            .return_path(email_client.return_path(&subscriber, issue.newsletter_issue_id))
+            .build();
+
+        let sent = match email_client.send_email(&email).await {
+            Ok(sent) => sent,
+            // One refused email doesn't keep the issue from the others.
+            Err(SendError::Provider(e)) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to send the issue to {}", subscriber.email
+                );
+                record_send_failure(
+                    pool,
+                    issue.newsletter_issue_id,
+                    &email_client.email_hash(subscriber.email.as_ref()),
+                    email.message_id(),
+                    &e.to_string(),
+                )
+                .await
+                .context("Failed to record the failed send")?;
+                continue;
+            }
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to send email to {}", subscriber.email))
+            }
+        };
+        let (message_id, provider_message_id) = match sent {
+            Sent::Accepted {
+                message_id,
+                provider_message_id,
+            } => (message_id, provider_message_id),
+            Sent::Suppressed => continue,
+        };
+
+        record_delivery(
+            pool,
+            issue.newsletter_issue_id,
+            subscriber_id,
+            &message_id,
+            provider_message_id.as_deref(),
+        )
+        .await
+        .context("Failed to record the delivery")?;
+    }
+
+    Ok(())
+}
+
+/// How many subscribers an issue with this audience would go to, including
+/// those who get it with their weekly digest.
+#[tracing::instrument(name = "Count recipients", skip(pool))]
+pub async fn count_recipients(
+    pool: &PgPool,
+    list_ids: &[Uuid],
+    topic_id: Option<Uuid>,
+    segment: Option<&Segment>,
+) -> Result<usize, anyhow::Error> {
+    let subscriber_ids: Vec<Uuid> = sqlx::query!(
+        r#"
+        SELECT DISTINCT s.id
+        FROM subscriptions s
+        JOIN list_subscriptions ls ON ls.subscriber_id = s.id AND ls.status = $4
+        JOIN lists l ON l.list_id = ls.list_id
+        WHERE s.status = $5
+            AND (l.list_id = ANY($1) OR (cardinality($1) = 0 AND l.slug = $2))
+            AND (s.paused_until IS NULL OR s.paused_until <= now())
+            AND NOT EXISTS (
+                SELECT 1 FROM subscriber_topic_opt_outs o
+                WHERE o.subscriber_id = s.id AND o.topic_id = $3
+            )
+        "#,
+        list_ids,
+        DEFAULT_LIST,
+        topic_id,
+        ListSubscriptionStatus::Confirmed as ListSubscriptionStatus,
+        SubscriptionStatus::Confirmed as SubscriptionStatus
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the recipients")?
+    .into_iter()
+    .map(|row| row.id)
+    .collect();
+
+    match segment {
+        Some(segment) => filter_by_segment(pool, segment, &subscriber_ids)
+            .await
+            .map(|matching| matching.len())
+            .context("Failed to match subscribers against the segment"),
+        None => Ok(subscriber_ids.len()),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct Row {
+    id: Uuid,
+    name: String,
+    email: String,
+    sender_name: Option<String>,
+    sender_email: Option<String>,
+}
+
+/// Confirmed members of the issue's lists who want every issue, are not
+/// paused, have not opted out of its topic and were not sent it yet. Each
+/// subscriber appears once, with the sender of the oldest list they share with
+/// the issue.
+#[tracing::instrument(name = "Get confirmed subscribers", skip(pool, issue))]
+async fn get_confirmed_subscribers(
+    pool: &PgPool,
+    issue: &NewsletterIssue,
+) -> Result<Vec<Row>, sqlx::Error> {
+    sqlx::query_as!(
+        Row,
+        r#"
+        SELECT DISTINCT ON (s.id) s.id, s.email, s.name, l.sender_name, l.sender_email
+        FROM subscriptions s
+        JOIN list_subscriptions ls ON ls.subscriber_id = s.id AND ls.status = $3
+        JOIN newsletter_issue_lists il
+            ON il.list_id = ls.list_id AND il.newsletter_issue_id = $1
+        JOIN lists l ON l.list_id = ls.list_id
+        WHERE s.status = $4
+            AND s.frequency = 'every_issue'
+            AND (s.paused_until IS NULL OR s.paused_until <= now())
+            AND NOT EXISTS (
+                SELECT 1 FROM subscriber_topic_opt_outs o
+                WHERE o.subscriber_id = s.id AND o.topic_id = $2
+            )
+            AND NOT EXISTS (
+                SELECT 1 FROM newsletter_issue_deliveries d
+                WHERE d.newsletter_issue_id = $1 AND d.subscriber_id = s.id
+            )
+        ORDER BY s.id, l.created_at
+        "#,
+        issue.newsletter_issue_id,
+        issue.topic_id,
+        ListSubscriptionStatus::Confirmed as ListSubscriptionStatus,
+        SubscriptionStatus::Confirmed as SubscriptionStatus
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// A list sender that fails to parse is ignored in favour of the default one.
+#[tracing::instrument(name = "Parse confirmed subscribers", skip(rows))]
+fn parse_confirmed_subscribers(rows: Vec<Row>) -> Vec<(Uuid, Subscriber, Option<Subscriber>)> {
+    let mut subscribers = Vec::new();
+
+    for row in rows {
+        let result = Subscriber::parse(row.name, row.email.clone());
+
+        match result {
+            Ok(subscriber) => {
+                let sender = match (row.sender_name, row.sender_email) {
+                    (Some(name), Some(email)) => Subscriber::parse(name, email).ok(),
+                    _ => None,
+                };
+                subscribers.push((row.id, subscriber, sender));
+            }
+            Err(e) => {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    "Skipping confirmed subscriber {} because {}", row.email, e);
+            }
+        }
+    }
+
+    subscribers
+}
@@ -0,0 +1,595 @@
+//! src/reports.rs
+//!
+//! How an issue did once published: who it went to, who it failed to reach,
+//! and what its readers did with it. Only events made by readers count:
+//! those flagged as automated when they were tracked are left out.
+use crate::domain::{ListSubscriptionStatus, SubscriptionStatus};
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::web::Bytes;
+use actix_web::HttpResponse;
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use futures_util::Stream;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Reports on issues younger than this are broken down by the hour.
+const HOURLY_FOR_DAYS: i64 = 3;
+
+/// How many of the most clicked links are reported.
+const TOP_LINKS: i64 = 10;
+
+/// Recipients are read from the database this many at a time.
+const PAGE_SIZE: i64 = 1000;
+
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    Hour,
+    Day,
+}
+
+impl Granularity {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Granularity::Hour => "hour",
+            Granularity::Day => "day",
+        }
+    }
+}
+
+/// Each subscriber is counted once per metric, e.g. when they first opened
+/// the issue however often they did.
+#[derive(serde::Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct IssueCounts {
+    pub sent: i64,
+    /// Soft bounces, blocked sends and emails Brevo refused: the next issue
+    /// may get through.
+    pub failed: i64,
+    pub bounced: i64,
+    /// Clicking a link counts as opening the issue, as many mail clients
+    /// block the tracking image.
+    pub opened: i64,
+    pub clicked: i64,
+    /// Unsubscribes are put down to the last issue the subscriber received.
+    pub unsubscribed: i64,
+}
+
+impl IssueCounts {
+    fn add(&mut self, metric: &str, count: i64) {
+        let counter = match metric {
+            "sent" => &mut self.sent,
+            "failed" => &mut self.failed,
+            "bounced" => &mut self.bounced,
+            "opened" => &mut self.opened,
+            "clicked" => &mut self.clicked,
+            "unsubscribed" => &mut self.unsubscribed,
+            _ => return,
+        };
+        *counter += count;
+    }
+
+    /// `count` as a share of the emails sent, for display. Takes a reference
+    /// because templates pass fields by reference.
+    pub fn share_of_sent(&self, count: &i64) -> String {
+        percent(*count, self.sent)
+    }
+
+    fn merge(&mut self, other: &IssueCounts) {
+        self.sent += other.sent;
+        self.failed += other.failed;
+        self.bounced += other.bounced;
+        self.opened += other.opened;
+        self.clicked += other.clicked;
+        self.unsubscribed += other.unsubscribed;
+    }
+}
+
+/// `count` out of `total`, for display.
+pub fn percent(count: i64, total: i64) -> String {
+    if total == 0 {
+        return "-".to_string();
+    }
+    format!("{:.1}%", count as f64 * 100.0 / total as f64)
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct ReportBucket {
+    pub starts_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub counts: IssueCounts,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct LinkClicks {
+    pub url: String,
+    pub clicks: i64,
+    pub subscribers: i64,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct IssueReport {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub published_at: DateTime<Utc>,
+    /// Weekly digest subscribers who get the issue with their next digest.
+    /// The issue's segment is only matched when the digest goes out, so
+    /// subscribers outside of it are counted too.
+    pub queued: i64,
+    pub totals: IssueCounts,
+    pub granularity: Granularity,
+    /// Only buckets in which something happened.
+    pub timeline: Vec<ReportBucket>,
+    pub top_links: Vec<LinkClicks>,
+}
+
+/// `None` if there is no such issue, or if it is still a draft.
+#[tracing::instrument(name = "Get issue report", skip(pool))]
+pub async fn get_issue_report(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<IssueReport>, anyhow::Error> {
+    let issue = sqlx::query!(
+        r#"
+        SELECT title, published_at
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the issue")?;
+    let (title, published_at) = match issue {
+        Some(issue) => match issue.published_at {
+            Some(published_at) => (issue.title, published_at),
+            None => return Ok(None),
+        },
+        None => return Ok(None),
+    };
+
+    let granularity = if Utc::now() - published_at <= Duration::days(HOURLY_FOR_DAYS) {
+        Granularity::Hour
+    } else {
+        Granularity::Day
+    };
+    let timeline = get_timeline(pool, newsletter_issue_id, granularity)
+        .await
+        .context("Failed to retrieve the timeline of the issue")?;
+    let mut totals = IssueCounts::default();
+    for bucket in &timeline {
+        totals.merge(&bucket.counts);
+    }
+
+    Ok(Some(IssueReport {
+        newsletter_issue_id,
+        title,
+        published_at,
+        queued: count_queued(pool, newsletter_issue_id)
+            .await
+            .context("Failed to count the queued recipients")?,
+        totals,
+        granularity,
+        timeline,
+        top_links: get_top_links(pool, newsletter_issue_id)
+            .await
+            .context("Failed to retrieve the most clicked links")?,
+    }))
+}
+
+/// Every metric at the time it first happened for each subscriber, counted
+/// per bucket.
+async fn get_timeline(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    granularity: Granularity,
+) -> Result<Vec<ReportBucket>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        WITH deliveries AS (
+            SELECT subscriber_id, delivered_at
+            FROM newsletter_issue_deliveries
+            WHERE newsletter_issue_id = $1
+        ),
+        moments AS (
+            SELECT 'sent' AS metric, delivered_at AS at FROM deliveries
+            UNION ALL
+            SELECT metric, MIN(received_at)
+            FROM (
+                SELECT COALESCE(email_hash, feedback_id::text) AS recipient, received_at,
+                    CASE WHEN kind = 'hard_bounce' THEN 'bounced' ELSE 'failed' END AS metric
+                FROM delivery_feedback
+                WHERE newsletter_issue_id = $1
+                    AND kind IN ('hard_bounce', 'soft_bounce', 'blocked', 'send_failed')
+            ) f
+            GROUP BY recipient, metric
+            UNION ALL
+            SELECT 'opened', MIN(occurred_at)
+            FROM engagement_events
+            WHERE newsletter_issue_id = $1 AND NOT automated
+            GROUP BY subscriber_id
+            UNION ALL
+            SELECT 'clicked', MIN(occurred_at)
+            FROM engagement_events
+            WHERE newsletter_issue_id = $1 AND NOT automated AND kind = 'click'
+            GROUP BY subscriber_id
+            UNION ALL
+            SELECT 'unsubscribed', MIN(e.created_at)
+            FROM deliveries d
+            JOIN subscription_events e ON e.subscriber_id = d.subscriber_id
+                AND e.to_status = $3
+                AND e.created_at >= d.delivered_at
+            WHERE NOT EXISTS (
+                SELECT 1 FROM newsletter_issue_deliveries n
+                WHERE n.subscriber_id = d.subscriber_id
+                    AND n.delivered_at > d.delivered_at
+                    AND n.delivered_at <= e.created_at
+            )
+            GROUP BY d.subscriber_id
+        )
+        SELECT date_trunc($2, at) AS "starts_at!", metric AS "metric!", COUNT(*) AS "count!"
+        FROM moments
+        GROUP BY 1, 2
+        ORDER BY 1
+        "#,
+        newsletter_issue_id,
+        granularity.as_str(),
+        SubscriptionStatus::Unsubscribed as SubscriptionStatus
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut timeline: Vec<ReportBucket> = Vec::new();
+    for row in rows {
+        match timeline.last_mut() {
+            Some(bucket) if bucket.starts_at == row.starts_at => {
+                bucket.counts.add(&row.metric, row.count)
+            }
+            _ => {
+                let mut counts = IssueCounts::default();
+                counts.add(&row.metric, row.count);
+                timeline.push(ReportBucket {
+                    starts_at: row.starts_at,
+                    counts,
+                });
+            }
+        }
+    }
+
+    Ok(timeline)
+}
+
+/// The conditions under which the digest includes an issue, see
+/// `digest::get_undelivered_issues`.
+async fn count_queued(pool: &PgPool, newsletter_issue_id: Uuid) -> Result<i64, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "queued!"
+        FROM newsletter_issues i
+        JOIN subscriptions s ON s.status = $2 AND s.frequency = 'weekly_digest'
+        WHERE i.newsletter_issue_id = $1
+            AND i.published_at > COALESCE(s.last_digest_at, s.subscribed_at)
+            AND (s.paused_until IS NULL OR i.published_at > s.paused_until)
+            AND EXISTS (
+                SELECT 1 FROM newsletter_issue_lists il
+                JOIN list_subscriptions ls ON ls.list_id = il.list_id
+                WHERE il.newsletter_issue_id = i.newsletter_issue_id
+                    AND ls.subscriber_id = s.id
+                    AND ls.status = $3
+            )
+            AND NOT EXISTS (
+                SELECT 1 FROM newsletter_issue_deliveries d
+                WHERE d.newsletter_issue_id = i.newsletter_issue_id AND d.subscriber_id = s.id
+            )
+            AND NOT EXISTS (
+                SELECT 1 FROM subscriber_topic_opt_outs o
+                WHERE o.subscriber_id = s.id AND o.topic_id = i.topic_id
+            )
+        "#,
+        newsletter_issue_id,
+        SubscriptionStatus::Confirmed as SubscriptionStatus,
+        ListSubscriptionStatus::Confirmed as ListSubscriptionStatus
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.queued)
+}
+
+/// Most clicked by the number of subscribers who clicked them.
+async fn get_top_links(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Vec<LinkClicks>, sqlx::Error> {
+    sqlx::query_as!(
+        LinkClicks,
+        r#"
+        SELECT url AS "url!", COUNT(*) AS "clicks!",
+            COUNT(DISTINCT subscriber_id) AS "subscribers!"
+        FROM engagement_events
+        WHERE newsletter_issue_id = $1 AND NOT automated AND kind = 'click'
+        GROUP BY url
+        ORDER BY 3 DESC, 2 DESC, 1
+        LIMIT $2
+        "#,
+        newsletter_issue_id,
+        TOP_LINKS
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// An issue and how its readers took it, counted the same way as in its
+/// report.
+#[derive(Debug)]
+pub struct IssueSummary {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub published_at: DateTime<Utc>,
+    /// Opens are only known for issues whose lists track engagement.
+    pub tracked: bool,
+    pub sent: i64,
+    pub opened: i64,
+    pub unsubscribed: i64,
+}
+
+impl IssueSummary {
+    pub fn open_rate(&self) -> String {
+        if !self.tracked {
+            return "-".to_string();
+        }
+        percent(self.opened, self.sent)
+    }
+
+    /// The share of recipients who unsubscribed after this issue.
+    pub fn churn(&self) -> String {
+        percent(self.unsubscribed, self.sent)
+    }
+}
+
+/// The last `limit` published issues, newest first.
+#[tracing::instrument(name = "Get recent issue summaries", skip(pool))]
+pub async fn get_recent_issues(
+    pool: &PgPool,
+    limit: i64,
+) -> Result<Vec<IssueSummary>, sqlx::Error> {
+    sqlx::query_as!(
+        IssueSummary,
+        r#"
+        SELECT i.newsletter_issue_id, i.title, i.published_at AS "published_at!",
+            COALESCE((
+                SELECT bool_and(l.track_engagement)
+                FROM newsletter_issue_lists il
+                JOIN lists l ON l.list_id = il.list_id
+                WHERE il.newsletter_issue_id = i.newsletter_issue_id
+            ), FALSE) AS "tracked!",
+            (
+                SELECT COUNT(*) FROM newsletter_issue_deliveries d
+                WHERE d.newsletter_issue_id = i.newsletter_issue_id
+            ) AS "sent!",
+            (
+                SELECT COUNT(DISTINCT e.subscriber_id) FROM engagement_events e
+                WHERE e.newsletter_issue_id = i.newsletter_issue_id AND NOT e.automated
+            ) AS "opened!",
+            (
+                SELECT COUNT(*) FROM newsletter_issue_deliveries d
+                WHERE d.newsletter_issue_id = i.newsletter_issue_id
+                    AND EXISTS (
+                        SELECT 1 FROM subscription_events e
+                        WHERE e.subscriber_id = d.subscriber_id
+                            AND e.to_status = $2
+                            AND e.created_at >= d.delivered_at
+                            AND NOT EXISTS (
+                                SELECT 1 FROM newsletter_issue_deliveries n
+                                WHERE n.subscriber_id = d.subscriber_id
+                                    AND n.delivered_at > d.delivered_at
+                                    AND n.delivered_at <= e.created_at
+                            )
+                    )
+            ) AS "unsubscribed!"
+        FROM newsletter_issues i
+        WHERE i.published_at IS NOT NULL
+        ORDER BY i.published_at DESC
+        LIMIT $1
+        "#,
+        limit,
+        SubscriptionStatus::Unsubscribed as SubscriptionStatus
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[derive(Debug)]
+pub struct Recipient {
+    pub subscriber_id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub delivered_at: DateTime<Utc>,
+    pub message_id: Option<String>,
+    /// The latest bounce or complaint about the email.
+    pub feedback: Option<String>,
+    pub opened_at: Option<DateTime<Utc>>,
+    pub clicks: i64,
+    pub unsubscribed_at: Option<DateTime<Utc>>,
+}
+
+const CSV_HEADER: [&str; 9] = [
+    "id",
+    "email",
+    "name",
+    "delivered_at",
+    "message_id",
+    "feedback",
+    "opened_at",
+    "clicks",
+    "unsubscribed_at",
+];
+
+impl Recipient {
+    fn csv_record(&self) -> [String; 9] {
+        let timestamp =
+            |at: Option<DateTime<Utc>>| at.map(|at| at.to_rfc3339()).unwrap_or_default();
+        [
+            self.subscriber_id.to_string(),
+            self.email.clone(),
+            self.name.clone(),
+            self.delivered_at.to_rfc3339(),
+            self.message_id.clone().unwrap_or_default(),
+            self.feedback.clone().unwrap_or_default(),
+            timestamp(self.opened_at),
+            self.clicks.to_string(),
+            timestamp(self.unsubscribed_at),
+        ]
+    }
+}
+
+/// Everyone the issue was delivered to, as CSV. Read and sent one page at a
+/// time, like the subscriber export.
+pub fn export_recipients(
+    pool: PgPool,
+    newsletter_issue_id: Uuid,
+) -> impl Stream<Item = Result<Bytes, anyhow::Error>> {
+    // `None` once the last page has been sent.
+    let first_page = Some(RecipientCursor {
+        after: None,
+        is_first_page: true,
+    });
+
+    futures_util::stream::unfold(first_page, move |cursor| {
+        let pool = pool.clone();
+        async move {
+            let cursor = cursor?;
+            match export_page(&pool, newsletter_issue_id, &cursor).await {
+                Ok((chunk, next)) => Some((Ok(chunk), next)),
+                Err(e) => Some((Err(e), None)),
+            }
+        }
+    })
+}
+
+struct RecipientCursor {
+    /// The last recipient sent so far.
+    after: Option<Uuid>,
+    is_first_page: bool,
+}
+
+async fn export_page(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    cursor: &RecipientCursor,
+) -> Result<(Bytes, Option<RecipientCursor>), anyhow::Error> {
+    let recipients = get_recipients(pool, newsletter_issue_id, cursor.after)
+        .await
+        .context("Failed to read the recipients to export")?;
+
+    let mut chunk = Vec::new();
+    let mut writer = csv::Writer::from_writer(&mut chunk);
+    if cursor.is_first_page {
+        writer.write_record(CSV_HEADER)?;
+    }
+    for recipient in &recipients {
+        writer.write_record(recipient.csv_record())?;
+    }
+    writer.flush()?;
+    drop(writer);
+
+    let next = match recipients.last() {
+        Some(last) if recipients.len() as i64 == PAGE_SIZE => Some(RecipientCursor {
+            after: Some(last.subscriber_id),
+            is_first_page: false,
+        }),
+        _ => None,
+    };
+
+    Ok((Bytes::from(chunk), next))
+}
+
+async fn get_recipients(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    after: Option<Uuid>,
+) -> Result<Vec<Recipient>, sqlx::Error> {
+    sqlx::query_as!(
+        Recipient,
+        r#"
+        SELECT d.subscriber_id, s.email, s.name, d.delivered_at, d.message_id,
+            (
+                SELECT f.kind FROM delivery_feedback f
+                WHERE f.newsletter_issue_id = $1
+                    AND f.message_id IN (d.message_id, d.provider_message_id)
+                ORDER BY f.received_at DESC
+                LIMIT 1
+            ) AS feedback,
+            (
+                SELECT MIN(e.occurred_at) FROM engagement_events e
+                WHERE e.newsletter_issue_id = $1 AND e.subscriber_id = d.subscriber_id
+                    AND NOT e.automated
+            ) AS opened_at,
+            (
+                SELECT COUNT(*) FROM engagement_events e
+                WHERE e.newsletter_issue_id = $1 AND e.subscriber_id = d.subscriber_id
+                    AND NOT e.automated AND e.kind = 'click'
+            ) AS "clicks!",
+            (
+                SELECT MIN(e.created_at) FROM subscription_events e
+                WHERE e.subscriber_id = d.subscriber_id
+                    AND e.to_status = $4
+                    AND e.created_at >= d.delivered_at
+                    AND NOT EXISTS (
+                        SELECT 1 FROM newsletter_issue_deliveries n
+                        WHERE n.subscriber_id = d.subscriber_id
+                            AND n.delivered_at > d.delivered_at
+                            AND n.delivered_at <= e.created_at
+                    )
+            ) AS unsubscribed_at
+        FROM newsletter_issue_deliveries d
+        JOIN subscriptions s ON s.id = d.subscriber_id
+        WHERE d.newsletter_issue_id = $1
+            AND ($2::uuid IS NULL OR d.subscriber_id > $2)
+        ORDER BY d.subscriber_id
+        LIMIT $3
+        "#,
+        newsletter_issue_id,
+        after,
+        PAGE_SIZE,
+        SubscriptionStatus::Unsubscribed as SubscriptionStatus
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Stream the recipients of the issue as a file download.
+pub fn recipients_response(pool: PgPool, newsletter_issue_id: Uuid) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "issue-{}-recipients.csv",
+                newsletter_issue_id
+            ))],
+        })
+        .streaming(export_recipients(pool, newsletter_issue_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_metrics_are_not_counted() {
+        let mut counts = IssueCounts::default();
+
+        counts.add("opened", 3);
+        counts.add("opened", 2);
+        counts.add("queued", 7);
+
+        assert_eq!(
+            counts,
+            IssueCounts {
+                opened: 5,
+                ..IssueCounts::default()
+            }
+        );
+    }
+}
@@ -1,11 +1,29 @@
 //! src/routes/admin/dashboard.rs
 
+use crate::analytics::{get_daily_activity, get_list_growth, GrowthChart, ListGrowth, GROWTH_DAYS};
+use crate::reports::{get_recent_issues, IssueSummary};
 use crate::session_state::TypedSession;
+use crate::spam::get_outcome_counts;
 use crate::utils::e500;
 use actix_web::{http::header::ContentType, web, HttpResponse};
 use anyhow::Context;
+use askama::Template;
 use uuid::Uuid;
 
+/// How many of the latest issues the dashboard lists.
+const RECENT_ISSUES: i64 = 5;
+
+#[derive(Template)]
+#[template(path = "admin/dashboard.html")]
+struct DashboardTemplate {
+    username: String,
+    signup_outcomes: Vec<(String, i64)>,
+    growth: ListGrowth,
+    growth_days: i32,
+    chart: GrowthChart,
+    recent_issues: Vec<IssueSummary>,
+}
+
 pub async fn admin_dashboard(
     session: TypedSession,
     pool: web::Data<sqlx::PgPool>,
@@ -21,18 +39,23 @@ pub async fn admin_dashboard(
     let user_id = user_id_from_session.unwrap();
     let username = get_username(user_id, pool.get_ref()).await.map_err(e500)?;
 
-    let body = format!(
-        r#"<!DOCTYPE html>
-<html lang="en">
-<head>
-    <meta http-equiv="content-type" content="text/html; charset=utf-8">
-    <title>Admin dashboard</title>
-</head>
-<body>
-    <p>Welcome {username}!</p>
-</body>
-</html>"#
-    );
+    let signup_outcomes = get_outcome_counts(&pool).await.map_err(e500)?;
+    let growth = get_list_growth(&pool).await.map_err(e500)?;
+    let daily_activity = get_daily_activity(&pool).await.map_err(e500)?;
+    let recent_issues = get_recent_issues(&pool, RECENT_ISSUES)
+        .await
+        .map_err(e500)?;
+
+    let body = DashboardTemplate {
+        username,
+        signup_outcomes,
+        growth,
+        growth_days: GROWTH_DAYS,
+        chart: GrowthChart::new(&daily_activity),
+        recent_issues,
+    }
+    .render()
+    .map_err(e500)?;
 
     Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
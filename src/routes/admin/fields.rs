@@ -0,0 +1,84 @@
+//! src/routes/admin/fields.rs
+use crate::attributes::{
+    get_custom_fields, insert_custom_field, parse_field_name, CustomField, FieldType,
+};
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other};
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use askama::Template;
+use sqlx::PgPool;
+
+#[derive(Template)]
+#[template(path = "admin/fields.html")]
+struct FieldsTemplate<'a> {
+    messages: Vec<&'a str>,
+    fields: Vec<CustomField>,
+    field_types: [FieldType; 4],
+}
+
+pub async fn admin_fields(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let fields = get_custom_fields(&pool).await.map_err(e500)?;
+    let messages = flash_messages.iter().map(|m| m.content()).collect();
+
+    let body = FieldsTemplate {
+        messages,
+        fields,
+        field_types: FieldType::ALL,
+    }
+    .render()
+    .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[derive(serde::Deserialize)]
+pub struct FieldForm {
+    name: String,
+    field_type: String,
+}
+
+pub async fn create_field(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    form: web::Form<FieldForm>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let name = match parse_field_name(&form.name) {
+        Ok(name) => name,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/fields"));
+        }
+    };
+    let field_type = match FieldType::parse(&form.field_type) {
+        Some(field_type) => field_type,
+        None => {
+            FlashMessage::error("Pick one of the field types.").send();
+            return Ok(see_other("/admin/fields"));
+        }
+    };
+
+    match insert_custom_field(&pool, &name, field_type)
+        .await
+        .map_err(e500)?
+    {
+        Some(_) => FlashMessage::info("The field has been added.").send(),
+        None => FlashMessage::error("A field with this name already exists.").send(),
+    }
+
+    Ok(see_other("/admin/fields"))
+}
@@ -0,0 +1,233 @@
+//! src/routes/admin/import.rs
+use crate::configuration::EmailHashKey;
+use crate::import::{get_report, import_upload, save_report, ColumnMapping, ImportMode};
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other};
+use actix_multipart::{Field, Multipart};
+use actix_web::http::header::{ContentDisposition, ContentType, DispositionParam, DispositionType};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use askama::Template;
+use futures_util::TryStreamExt;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Template)]
+#[template(path = "admin/import.html")]
+struct ImportFormTemplate<'a> {
+    messages: Vec<&'a str>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/import_report.html")]
+struct ImportReportTemplate {
+    import_id: Uuid,
+    imported: i32,
+    duplicates: i32,
+    errors: i32,
+}
+
+pub async fn import_form(
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let messages = flash_messages.iter().map(|m| m.content()).collect();
+    let body = ImportFormTemplate { messages }.render().map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// Larger files are only imported up to this size.
+const MAX_UPLOAD_SIZE: usize = 20 * 1024 * 1024;
+/// The other fields only hold a column name or a short description.
+const MAX_FIELD_SIZE: usize = 1024;
+
+/// The fields of the upload form, which must come before the file: it is
+/// imported as it arrives, so nothing after it is read.
+#[derive(Default)]
+struct ImportOptions {
+    email_column: String,
+    name_column: String,
+    mode: String,
+    consent_source: String,
+}
+
+impl ImportOptions {
+    /// Read the fields up to the file, which is left in `payload` to be
+    /// imported as it arrives.
+    async fn read(payload: &mut Multipart) -> Result<(Self, Option<Field>), actix_web::Error> {
+        let mut options = Self::default();
+
+        while let Some(mut field) = payload.try_next().await? {
+            if field.name() == Some("file") {
+                return Ok((options, Some(field)));
+            }
+
+            let mut value = Vec::new();
+            while let Some(chunk) = field.try_next().await? {
+                if value.len() + chunk.len() > MAX_FIELD_SIZE {
+                    return Err(actix_web::error::ErrorPayloadTooLarge(
+                        "A form field is too large.",
+                    ));
+                }
+                value.extend_from_slice(&chunk);
+            }
+
+            let text = String::from_utf8_lossy(&value).trim().to_string();
+            match field.name() {
+                Some("email_column") => options.email_column = text,
+                Some("name_column") => options.name_column = text,
+                Some("mode") => options.mode = text,
+                Some("consent_source") => options.consent_source = text,
+                _ => {}
+            }
+        }
+
+        Ok((options, None))
+    }
+
+    fn mapping(&self) -> ColumnMapping {
+        let default = ColumnMapping::default();
+        let or_default = |column: &str, default: String| {
+            if column.is_empty() {
+                default
+            } else {
+                column.to_string()
+            }
+        };
+
+        ColumnMapping {
+            email: or_default(&self.email_column, default.email),
+            name: or_default(&self.name_column, default.name),
+        }
+    }
+
+    fn mode(&self) -> Result<ImportMode, &'static str> {
+        match self.mode.as_str() {
+            "confirmed" if self.consent_source.is_empty() => {
+                Err("Confirmed subscribers need a consent source.")
+            }
+            "confirmed" => Ok(ImportMode::Confirmed {
+                consent_source: self.consent_source.clone(),
+            }),
+            "pending_confirmation" => Ok(ImportMode::PendingConfirmation),
+            _ => Err("Choose whether the subscribers are confirmed."),
+        }
+    }
+}
+
+pub async fn upload_import(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    email_hash_key: web::Data<EmailHashKey>,
+    mut payload: Multipart,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let (options, file) = ImportOptions::read(&mut payload).await?;
+    // Every form sends a mode, so one missing before the file was sent after it.
+    if file.is_some() && options.mode.is_empty() {
+        FlashMessage::error("The import options must come before the file in the form.").send();
+        return Ok(see_other("/admin/subscribers/import"));
+    }
+    let mode = match options.mode() {
+        Ok(mode) => mode,
+        Err(message) => {
+            FlashMessage::error(message).send();
+            return Ok(see_other("/admin/subscribers/import"));
+        }
+    };
+    let file = match file {
+        Some(file) => file,
+        None => {
+            FlashMessage::error("Choose a CSV file to import.").send();
+            return Ok(see_other("/admin/subscribers/import"));
+        }
+    };
+
+    let report = match import_upload(
+        &pool,
+        &email_hash_key,
+        file,
+        &options.mapping(),
+        &mode,
+        MAX_UPLOAD_SIZE,
+    )
+    .await
+    {
+        Ok(report) => report,
+        Err(e) => {
+            tracing::warn!(error.cause_chain = ?e, "Subscriber import failed");
+            FlashMessage::error(format!("The import failed: {}", e)).send();
+            return Ok(see_other("/admin/subscribers/import"));
+        }
+    };
+    let import_id = save_report(&pool, &report).await.map_err(e500)?;
+
+    Ok(see_other(&format!(
+        "/admin/subscribers/imports/{}",
+        import_id
+    )))
+}
+
+pub async fn import_report(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    import_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let report = match get_report(&pool, *import_id).await.map_err(e500)? {
+        Some(report) => report,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let body = ImportReportTemplate {
+        import_id: report.import_id,
+        imported: report.imported,
+        duplicates: report.duplicates,
+        errors: report.errors,
+    }
+    .render()
+    .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+pub async fn import_errors(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    import_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let report = match get_report(&pool, *import_id).await.map_err(e500)? {
+        Some(report) => report,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "import-{}-errors.csv",
+                report.import_id
+            ))],
+        })
+        .body(report.error_report))
+}
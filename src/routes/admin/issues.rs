@@ -0,0 +1,373 @@
+//! src/routes/admin/issues.rs
+use crate::attributes::get_custom_fields;
+use crate::configuration::HmacSecret;
+use crate::email::Brevo;
+use crate::issues::{
+    self, get_all_issues, get_issue, get_issue_list_ids, insert_draft, update_issue, IssueContent,
+    NewsletterIssue,
+};
+use crate::lists::{get_lists, List, DEFAULT_LIST};
+use crate::publish::{count_recipients, publish_draft};
+use crate::segments::{Segment, SegmentError};
+use crate::session_state::TypedSession;
+use crate::startup::ApplicationBaseUrl;
+use crate::topics::{get_topics, Topic};
+use crate::utils::{e500, see_other};
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use askama::Template;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Template)]
+#[template(path = "admin/issues.html")]
+struct IssuesTemplate<'a> {
+    messages: Vec<&'a str>,
+    issues: Vec<NewsletterIssue>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/issue_form.html")]
+struct IssueFormTemplate<'a> {
+    messages: Vec<&'a str>,
+    issue: Option<NewsletterIssue>,
+    topics: Vec<Topic>,
+    lists: Vec<List>,
+    /// The lists the issue goes out to.
+    list_ids: Vec<Uuid>,
+    /// How many subscribers the issue would reach if it were published now.
+    recipients: Option<usize>,
+}
+
+impl IssueFormTemplate<'_> {
+    fn is_selected(&self, topic_id: &Uuid) -> bool {
+        self.issue
+            .as_ref()
+            .is_some_and(|issue| issue.topic_id == Some(*topic_id))
+    }
+
+    fn is_checked(&self, list_id: &Uuid) -> bool {
+        self.list_ids.contains(list_id)
+    }
+}
+
+pub async fn admin_issues(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let issues = get_all_issues(&pool).await.map_err(e500)?;
+    let messages = flash_messages.iter().map(|m| m.content()).collect();
+
+    let body = IssuesTemplate { messages, issues }.render().map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[derive(serde::Deserialize)]
+pub struct VisibilityForm {
+    is_public: bool,
+}
+
+pub async fn set_issue_visibility(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    issue_id: web::Path<Uuid>,
+    form: web::Form<VisibilityForm>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let updated = issues::set_issue_visibility(&pool, *issue_id, form.is_public)
+        .await
+        .map_err(e500)?;
+
+    if !updated {
+        FlashMessage::error("The issue does not exist.").send();
+    } else if form.is_public {
+        FlashMessage::info("The issue is now public.").send();
+    } else {
+        FlashMessage::info("The issue is now private.").send();
+    }
+
+    Ok(see_other("/admin/issues"))
+}
+
+pub async fn new_issue_form(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let topics = get_topics(&pool).await.map_err(e500)?;
+    let lists = get_lists(&pool).await.map_err(e500)?;
+    // New issues go to the default list unless told otherwise.
+    let list_ids = lists
+        .iter()
+        .filter(|list| list.slug == DEFAULT_LIST)
+        .map(|list| list.list_id)
+        .collect();
+    let messages = flash_messages.iter().map(|m| m.content()).collect();
+    let body = IssueFormTemplate {
+        messages,
+        issue: None,
+        topics,
+        lists,
+        list_ids,
+        recipients: None,
+    }
+    .render()
+    .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+pub async fn edit_issue_form(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    issue_id: web::Path<Uuid>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let issue = match get_issue(&pool, *issue_id).await.map_err(e500)? {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let topics = get_topics(&pool).await.map_err(e500)?;
+    let lists = get_lists(&pool).await.map_err(e500)?;
+    let list_ids = get_issue_list_ids(&pool, issue.newsletter_issue_id)
+        .await
+        .map_err(e500)?;
+    let recipients = if issue.is_draft() {
+        draft_recipients(&pool, &issue, &list_ids).await?
+    } else {
+        None
+    };
+    let messages = flash_messages.iter().map(|m| m.content()).collect();
+    let body = IssueFormTemplate {
+        messages,
+        issue: Some(issue),
+        topics,
+        lists,
+        list_ids,
+        recipients,
+    }
+    .render()
+    .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// `None` if the segment saved with the draft no longer parses, in which case
+/// publishing fails until it is fixed.
+async fn draft_recipients(
+    pool: &PgPool,
+    issue: &NewsletterIssue,
+    list_ids: &[Uuid],
+) -> Result<Option<usize>, actix_web::Error> {
+    let segment = match &issue.segment {
+        Some(source) => match parse_segment(pool, source).await? {
+            Ok(segment) => Some(segment),
+            Err(_) => return Ok(None),
+        },
+        None => None,
+    };
+
+    count_recipients(pool, list_ids, issue.topic_id, segment.as_ref())
+        .await
+        .map(Some)
+        .map_err(e500)
+}
+
+/// The form posts one `list` field per ticked checkbox, so the fields are
+/// read as pairs.
+struct IssueForm {
+    title: String,
+    html_content: String,
+    /// `None` when the issue has no topic.
+    topic_id: Option<Uuid>,
+    list_ids: Vec<Uuid>,
+    /// `None` when the issue goes to every subscriber of its lists.
+    segment: Option<String>,
+}
+
+impl IssueForm {
+    fn content(&self) -> IssueContent<'_> {
+        IssueContent {
+            title: &self.title,
+            html_content: &self.html_content,
+            topic_id: self.topic_id,
+            list_ids: &self.list_ids,
+            segment: self.segment.as_deref(),
+        }
+    }
+
+    /// Check the segment against the custom fields, keeping its trimmed form.
+    /// Returns the reason it was rejected, if it was.
+    async fn check_segment(&mut self, pool: &PgPool) -> Result<Option<String>, actix_web::Error> {
+        let source = match &self.segment {
+            Some(source) => source,
+            None => return Ok(None),
+        };
+        match parse_segment(pool, source).await? {
+            Ok(segment) => {
+                self.segment = Some(segment.as_str().to_string());
+                Ok(None)
+            }
+            Err(e) => Ok(Some(format!("The segment is invalid: {}", e))),
+        }
+    }
+}
+
+async fn parse_segment(
+    pool: &PgPool,
+    source: &str,
+) -> Result<Result<Segment, SegmentError>, actix_web::Error> {
+    let fields = get_custom_fields(pool).await.map_err(e500)?;
+    Ok(Segment::parse(source, &fields))
+}
+
+impl TryFrom<Vec<(String, String)>> for IssueForm {
+    type Error = uuid::Error;
+
+    fn try_from(fields: Vec<(String, String)>) -> Result<Self, Self::Error> {
+        let mut form = IssueForm {
+            title: String::new(),
+            html_content: String::new(),
+            topic_id: None,
+            list_ids: Vec::new(),
+            segment: None,
+        };
+
+        for (key, value) in fields {
+            match key.as_str() {
+                "title" => form.title = value,
+                "html_content" => form.html_content = value,
+                "topic" if value.is_empty() => form.topic_id = None,
+                "topic" => form.topic_id = Some(Uuid::parse_str(&value)?),
+                "list" => form.list_ids.push(Uuid::parse_str(&value)?),
+                "segment" if value.trim().is_empty() => form.segment = None,
+                "segment" => form.segment = Some(value),
+                _ => {}
+            }
+        }
+
+        Ok(form)
+    }
+}
+
+pub async fn create_issue(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    form: web::Form<Vec<(String, String)>>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = match session.get_user_id().map_err(e500)? {
+        Some(user_id) => user_id,
+        None => return Ok(see_other("/login")),
+    };
+
+    let mut form = match IssueForm::try_from(form.into_inner()) {
+        Ok(form) => form,
+        Err(_) => return Ok(HttpResponse::BadRequest().finish()),
+    };
+
+    if form.title.trim().is_empty() {
+        FlashMessage::error("An issue needs a title.").send();
+        return Ok(see_other("/admin/issues/new"));
+    }
+    if let Some(e) = form.check_segment(&pool).await? {
+        FlashMessage::error(e).send();
+        return Ok(see_other("/admin/issues/new"));
+    }
+
+    let issue = insert_draft(&pool, &form.content(), user_id)
+        .await
+        .map_err(e500)?;
+
+    FlashMessage::info("The draft has been saved.").send();
+    Ok(see_other(&format!(
+        "/admin/issues/{}",
+        issue.newsletter_issue_id
+    )))
+}
+
+pub async fn save_issue(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    issue_id: web::Path<Uuid>,
+    form: web::Form<Vec<(String, String)>>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = match session.get_user_id().map_err(e500)? {
+        Some(user_id) => user_id,
+        None => return Ok(see_other("/login")),
+    };
+
+    let edit_page = format!("/admin/issues/{}", issue_id);
+
+    let mut form = match IssueForm::try_from(form.into_inner()) {
+        Ok(form) => form,
+        Err(_) => return Ok(HttpResponse::BadRequest().finish()),
+    };
+
+    if form.title.trim().is_empty() {
+        FlashMessage::error("An issue needs a title.").send();
+        return Ok(see_other(&edit_page));
+    }
+    if let Some(e) = form.check_segment(&pool).await? {
+        FlashMessage::error(e).send();
+        return Ok(see_other(&edit_page));
+    }
+
+    let issue = update_issue(&pool, *issue_id, &form.content(), user_id)
+        .await
+        .map_err(e500)?;
+
+    if issue.is_none() {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    FlashMessage::info("Your changes have been saved.").send();
+    Ok(see_other(&edit_page))
+}
+
+pub async fn publish_issue_draft(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<Brevo>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    issue_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let issue = publish_draft(&pool, &email_client, &base_url.0, &hmac_secret, *issue_id)
+        .await
+        .map_err(e500)?;
+
+    match issue {
+        Some(_) => FlashMessage::info("The issue has been published.").send(),
+        None => FlashMessage::error("The issue does not exist or was already published.").send(),
+    }
+
+    Ok(see_other("/admin/issues"))
+}
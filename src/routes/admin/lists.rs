@@ -0,0 +1,202 @@
+//! src/routes/admin/lists.rs
+use crate::domain::Person;
+use crate::lists::{get_list, get_lists, insert_list, update_list, List, ListSettings};
+use crate::session_state::TypedSession;
+use crate::startup::ApplicationBaseUrl;
+use crate::utils::{e500, see_other};
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use askama::Template;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Template)]
+#[template(path = "admin/lists.html")]
+struct ListsTemplate<'a> {
+    messages: Vec<&'a str>,
+    lists: Vec<List>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/list_form.html")]
+struct ListFormTemplate<'a> {
+    messages: Vec<&'a str>,
+    list: List,
+    base_url: &'a str,
+}
+
+pub async fn admin_lists(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let lists = get_lists(&pool).await.map_err(e500)?;
+    let messages = flash_messages.iter().map(|m| m.content()).collect();
+
+    let body = ListsTemplate { messages, lists }.render().map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[derive(serde::Deserialize)]
+pub struct NewListForm {
+    name: String,
+}
+
+pub async fn create_list(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    form: web::Form<NewListForm>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let name = form.name.trim();
+    if name.is_empty() {
+        FlashMessage::error("A list needs a name.").send();
+        return Ok(see_other("/admin/lists"));
+    }
+
+    match insert_list(&pool, name).await.map_err(e500)? {
+        Some(list) => {
+            FlashMessage::info("The list has been added.").send();
+            Ok(see_other(&format!("/admin/lists/{}", list.list_id)))
+        }
+        None => {
+            FlashMessage::error("A list with this name already exists.").send();
+            Ok(see_other("/admin/lists"))
+        }
+    }
+}
+
+pub async fn edit_list_form(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    list_id: web::Path<Uuid>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let list = match get_list(&pool, *list_id).await.map_err(e500)? {
+        Some(list) => list,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let messages = flash_messages.iter().map(|m| m.content()).collect();
+    let body = ListFormTemplate {
+        messages,
+        list,
+        base_url: &base_url.0,
+    }
+    .render()
+    .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// Empty fields fall back to the defaults.
+#[derive(serde::Deserialize)]
+pub struct ListForm {
+    name: String,
+    #[serde(default)]
+    sender_name: String,
+    #[serde(default)]
+    sender_email: String,
+    #[serde(default)]
+    confirmation_subject: String,
+    #[serde(default)]
+    confirmation_html: String,
+    #[serde(default)]
+    widget_heading: String,
+    #[serde(default)]
+    widget_button_label: String,
+    #[serde(default)]
+    widget_success_message: String,
+    #[serde(default)]
+    redirect_url: String,
+    /// Only sent when the checkbox is ticked.
+    track_engagement: Option<String>,
+}
+
+fn non_empty(value: &str) -> Option<&str> {
+    let value = value.trim();
+    (!value.is_empty()).then_some(value)
+}
+
+pub async fn save_list(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    list_id: web::Path<Uuid>,
+    form: web::Form<ListForm>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let edit_page = format!("/admin/lists/{}", list_id);
+
+    let name = form.name.trim();
+    if name.is_empty() {
+        FlashMessage::error("A list needs a name.").send();
+        return Ok(see_other(&edit_page));
+    }
+
+    // The sender is validated the same way as the one in the email settings.
+    let sender = match (non_empty(&form.sender_name), non_empty(&form.sender_email)) {
+        (None, None) => None,
+        (Some(name), Some(email)) => match Person::parse(name.into(), email.into()) {
+            Ok(sender) => Some(sender),
+            Err(e) => {
+                FlashMessage::error(e.to_string()).send();
+                return Ok(see_other(&edit_page));
+            }
+        },
+        _ => {
+            FlashMessage::error("A sender needs both a name and an email address.").send();
+            return Ok(see_other(&edit_page));
+        }
+    };
+
+    let redirect_url = non_empty(&form.redirect_url);
+    if let Some(redirect_url) = redirect_url {
+        let is_absolute = reqwest::Url::parse(redirect_url)
+            .is_ok_and(|url| matches!(url.scheme(), "http" | "https"));
+        if !is_absolute {
+            FlashMessage::error("The redirect must be a full http or https URL.").send();
+            return Ok(see_other(&edit_page));
+        }
+    }
+
+    let settings = ListSettings {
+        name,
+        sender: sender.as_ref(),
+        confirmation_subject: non_empty(&form.confirmation_subject),
+        confirmation_html: non_empty(&form.confirmation_html),
+        widget_heading: non_empty(&form.widget_heading),
+        widget_button_label: non_empty(&form.widget_button_label),
+        widget_success_message: non_empty(&form.widget_success_message),
+        redirect_url,
+        track_engagement: form.track_engagement.is_some(),
+    };
+    if !update_list(&pool, *list_id, &settings)
+        .await
+        .map_err(e500)?
+    {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    FlashMessage::info("Your changes have been saved.").send();
+    Ok(see_other(&edit_page))
+}
@@ -3,5 +3,32 @@
 mod dashboard;
 pub use dashboard::*;
 
+mod fields;
+pub use fields::*;
+
+mod import;
+pub use import::*;
+
+mod issues;
+pub use issues::*;
+
+mod lists;
+pub use lists::*;
+
 mod password;
 pub use password::*;
+
+mod reports;
+pub use reports::*;
+
+mod revisions;
+pub use revisions::*;
+
+mod subscribers;
+pub use subscribers::*;
+
+mod suppressions;
+pub use suppressions::*;
+
+mod topics;
+pub use topics::*;
@@ -0,0 +1,64 @@
+//! src/routes/admin/reports.rs
+use crate::issues::get_issue;
+use crate::reports::{get_issue_report, recipients_response, Granularity, IssueReport};
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other};
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use askama::Template;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Template)]
+#[template(path = "admin/report.html")]
+struct ReportTemplate {
+    report: IssueReport,
+    /// How the timeline buckets are labelled.
+    bucket_format: &'static str,
+}
+
+pub async fn issue_report(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    issue_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let report = match get_issue_report(&pool, *issue_id).await.map_err(e500)? {
+        Some(report) => report,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let bucket_format = match report.granularity {
+        Granularity::Hour => "%Y-%m-%d %H:00",
+        Granularity::Day => "%Y-%m-%d",
+    };
+
+    let body = ReportTemplate {
+        report,
+        bucket_format,
+    }
+    .render()
+    .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+pub async fn issue_recipients(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    issue_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    match get_issue(&pool, *issue_id).await.map_err(e500)? {
+        Some(issue) if !issue.is_draft() => {}
+        _ => return Ok(HttpResponse::NotFound().finish()),
+    }
+
+    Ok(recipients_response(pool.get_ref().clone(), *issue_id))
+}
@@ -0,0 +1,170 @@
+//! src/routes/admin/revisions.rs
+use crate::issues::{
+    get_issue, get_issue_list_ids, get_revisions, update_issue, IssueContent, NewsletterIssue,
+    Revision,
+};
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other};
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use askama::Template;
+use similar::{ChangeTag, TextDiff};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Template)]
+#[template(path = "admin/revisions.html")]
+struct RevisionsTemplate {
+    issue: NewsletterIssue,
+    revisions: Vec<Revision>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/revision.html")]
+struct RevisionTemplate {
+    revision: Revision,
+    title_diff: Vec<DiffLine>,
+    body_diff: Vec<DiffLine>,
+}
+
+/// One line of a unified diff, as rendered by `admin/revision.html`.
+struct DiffLine {
+    kind: &'static str,
+    sign: char,
+    text: String,
+}
+
+fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    TextDiff::from_lines(old, new)
+        .iter_all_changes()
+        .map(|change| {
+            let (kind, sign) = match change.tag() {
+                ChangeTag::Delete => ("removed", '-'),
+                ChangeTag::Insert => ("added", '+'),
+                ChangeTag::Equal => ("unchanged", ' '),
+            };
+            DiffLine {
+                kind,
+                sign,
+                text: change.value().trim_end_matches('\n').to_string(),
+            }
+        })
+        .collect()
+}
+
+#[tracing::instrument(name = "GET /admin/issues/{issue_id}/revisions", skip(session, pool))]
+pub async fn issue_revisions(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    issue_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let issue = match get_issue(&pool, *issue_id).await.map_err(e500)? {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let revisions = get_revisions(&pool, *issue_id).await.map_err(e500)?;
+
+    let body = RevisionsTemplate { issue, revisions }
+        .render()
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// Shows a revision as a diff against the revision saved just before it.
+/// The first revision of an issue is diffed against an empty issue.
+#[tracing::instrument(
+    name = "GET /admin/issues/{issue_id}/revisions/{revision_id}",
+    skip(session, pool)
+)]
+pub async fn issue_revision(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    path: web::Path<(Uuid, Uuid)>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let (issue_id, revision_id) = path.into_inner();
+    let mut revisions = get_revisions(&pool, issue_id).await.map_err(e500)?;
+
+    let position = match revisions.iter().position(|r| r.revision_id == revision_id) {
+        Some(position) => position,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    // Revisions are sorted newest first, so the previous one comes right after.
+    let (title_diff, body_diff) = match revisions.get(position + 1) {
+        Some(previous) => (
+            diff_lines(&previous.title, &revisions[position].title),
+            diff_lines(&previous.html_content, &revisions[position].html_content),
+        ),
+        None => (
+            diff_lines("", &revisions[position].title),
+            diff_lines("", &revisions[position].html_content),
+        ),
+    };
+    let revision = revisions.swap_remove(position);
+
+    let body = RevisionTemplate {
+        revision,
+        title_diff,
+        body_diff,
+    }
+    .render()
+    .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// Restoring saves the old content as a new revision, so history is never lost.
+#[tracing::instrument(
+    name = "POST /admin/issues/{issue_id}/revisions/{revision_id}/restore",
+    skip(session, pool)
+)]
+pub async fn restore_revision(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    path: web::Path<(Uuid, Uuid)>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = match session.get_user_id().map_err(e500)? {
+        Some(user_id) => user_id,
+        None => return Ok(see_other("/login")),
+    };
+
+    let (issue_id, revision_id) = path.into_inner();
+    let issue = match get_issue(&pool, issue_id).await.map_err(e500)? {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let revisions = get_revisions(&pool, issue_id).await.map_err(e500)?;
+
+    let revision = match revisions.iter().find(|r| r.revision_id == revision_id) {
+        Some(revision) => revision,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    // Revisions only hold the content: the audience stays as it is.
+    let list_ids = get_issue_list_ids(&pool, issue_id).await.map_err(e500)?;
+    let content = IssueContent {
+        title: &revision.title,
+        html_content: &revision.html_content,
+        topic_id: issue.topic_id,
+        list_ids: &list_ids,
+        segment: issue.segment.as_deref(),
+    };
+    update_issue(&pool, issue_id, &content, user_id)
+        .await
+        .map_err(e500)?;
+
+    FlashMessage::info("The revision has been restored.").send();
+    Ok(see_other(&format!("/admin/issues/{}", issue_id)))
+}
@@ -0,0 +1,490 @@
+//! src/routes/admin/subscribers.rs
+use crate::attributes::{
+    apply_attribute_changes, get_custom_fields, get_field_entries, get_tags, parse_tags,
+    AttributeChanges, FieldEntry,
+};
+use crate::configuration::EmailHashKey;
+use crate::domain::{Person, SubscriptionStatus};
+use crate::export::{export_response, ExportQuery};
+use crate::lists::{get_list_subscriptions, ListSubscription};
+use crate::privacy::{collect_personal_data, erase_personal_data};
+use crate::routes::personal_data_response;
+use crate::session_state::TypedSession;
+use crate::subscribers::{
+    self, get_deliveries, get_status_events, get_subscriber, get_tokens, search_subscribers,
+    Delivery, StatusError, StatusEvent, Subscriber, SubscriberFilter, SubscriptionToken,
+};
+use crate::utils::{e500, see_other};
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use askama::Template;
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const SUBSCRIBERS_PER_PAGE: i64 = 50;
+
+#[derive(serde::Deserialize, Debug)]
+pub struct SubscribersQuery {
+    page: Option<u32>,
+    q: Option<String>,
+    status: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+}
+
+impl SubscribersQuery {
+    /// Empty form fields are sent as empty strings: treat them as unset.
+    fn field(value: &Option<String>) -> Option<&str> {
+        value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+    }
+
+    /// `from` and `to` are calendar days, both included.
+    pub(crate) fn filter(&self) -> Result<SubscriberFilter, chrono::ParseError> {
+        let parse_day = |day: &str| NaiveDate::parse_from_str(day, "%Y-%m-%d");
+
+        let subscribed_from = match Self::field(&self.from) {
+            Some(day) => Some(start_of(parse_day(day)?)),
+            None => None,
+        };
+        let subscribed_until = match Self::field(&self.to) {
+            Some(day) => parse_day(day)?.succ_opt().map(start_of),
+            None => None,
+        };
+
+        Ok(SubscriberFilter {
+            search: Self::field(&self.q).map(String::from),
+            status: Self::field(&self.status).map(String::from),
+            subscribed_from,
+            subscribed_until,
+        })
+    }
+
+    /// The filters as a query string, so pagination links keep them.
+    fn filter_query(&self) -> String {
+        [
+            ("q", &self.q),
+            ("status", &self.status),
+            ("from", &self.from),
+            ("to", &self.to),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| {
+            Self::field(value).map(|v| format!("{}={}&", key, urlencoding::encode(v)))
+        })
+        .collect()
+    }
+}
+
+fn start_of(day: NaiveDate) -> DateTime<Utc> {
+    Utc.from_utc_datetime(&day.and_hms_opt(0, 0, 0).unwrap())
+}
+
+#[derive(Template)]
+#[template(path = "admin/subscribers.html")]
+struct SubscribersTemplate<'a> {
+    messages: Vec<&'a str>,
+    subscribers: Vec<Subscriber>,
+    q: &'a str,
+    status: &'a str,
+    from: &'a str,
+    to: &'a str,
+    filter_query: String,
+    previous_page: Option<u32>,
+    next_page: Option<u32>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/subscriber.html")]
+struct SubscriberTemplate<'a> {
+    messages: Vec<&'a str>,
+    subscriber: Subscriber,
+    can_confirm: bool,
+    can_unsubscribe: bool,
+    events: Vec<StatusEvent>,
+    lists: Vec<ListSubscription>,
+    tags: String,
+    fields: Vec<FieldEntry>,
+    tokens: Vec<SubscriptionToken>,
+    deliveries: Vec<Delivery>,
+}
+
+#[tracing::instrument(name = "GET /admin/subscribers", skip(session, pool, flash_messages))]
+pub async fn admin_subscribers(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    query: web::Query<SubscribersQuery>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let filter = match query.filter() {
+        Ok(filter) => filter,
+        Err(_) => return Ok(HttpResponse::BadRequest().finish()),
+    };
+    let page = query.page.unwrap_or(1).max(1);
+    let offset = (i64::from(page) - 1) * SUBSCRIBERS_PER_PAGE;
+
+    // Fetch one extra row to find out whether there is a next page.
+    let mut subscribers = search_subscribers(&pool, &filter, SUBSCRIBERS_PER_PAGE + 1, offset)
+        .await
+        .map_err(e500)?;
+    let has_more_subscribers = subscribers.len() as i64 > SUBSCRIBERS_PER_PAGE;
+    subscribers.truncate(SUBSCRIBERS_PER_PAGE as usize);
+
+    let messages = flash_messages.iter().map(|m| m.content()).collect();
+    let body = SubscribersTemplate {
+        messages,
+        subscribers,
+        q: SubscribersQuery::field(&query.q).unwrap_or_default(),
+        status: SubscribersQuery::field(&query.status).unwrap_or_default(),
+        from: SubscribersQuery::field(&query.from).unwrap_or_default(),
+        to: SubscribersQuery::field(&query.to).unwrap_or_default(),
+        filter_query: query.filter_query(),
+        previous_page: Some(page - 1).filter(|&previous| previous > 0),
+        next_page: page.checked_add(1).filter(|_| has_more_subscribers),
+    }
+    .render()
+    .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// Download the subscribers matching the same filters as the list.
+#[tracing::instrument(name = "GET /admin/subscribers/export", skip(session, pool))]
+pub async fn admin_export_subscribers(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    query: web::Query<SubscribersQuery>,
+    export: web::Query<ExportQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let filter = match query.filter() {
+        Ok(filter) => filter,
+        Err(_) => return Ok(HttpResponse::BadRequest().finish()),
+    };
+
+    Ok(export_response(
+        pool.get_ref().clone(),
+        filter,
+        export.format,
+    ))
+}
+
+#[tracing::instrument(
+    name = "GET /admin/subscribers/{subscriber_id}",
+    skip(session, pool, flash_messages)
+)]
+pub async fn admin_subscriber(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    subscriber_id: web::Path<Uuid>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let subscriber = match get_subscriber(&pool, *subscriber_id).await.map_err(e500)? {
+        Some(subscriber) => subscriber,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let lists = get_list_subscriptions(&pool, *subscriber_id)
+        .await
+        .map_err(e500)?;
+    let tags = get_tags(&pool, *subscriber_id).await.map_err(e500)?;
+    let fields = get_field_entries(&pool, *subscriber_id)
+        .await
+        .map_err(e500)?;
+    let tokens = get_tokens(&pool, *subscriber_id).await.map_err(e500)?;
+    let deliveries = get_deliveries(&pool, *subscriber_id).await.map_err(e500)?;
+    let events = get_status_events(&pool, *subscriber_id)
+        .await
+        .map_err(e500)?;
+
+    let messages = flash_messages.iter().map(|m| m.content()).collect();
+    let body = SubscriberTemplate {
+        messages,
+        can_confirm: subscriber.status.can_become(SubscriptionStatus::Confirmed),
+        can_unsubscribe: subscriber
+            .status
+            .can_become(SubscriptionStatus::Unsubscribed),
+        subscriber,
+        events,
+        lists,
+        tags: tags.join(", "),
+        fields,
+        tokens,
+        deliveries,
+    }
+    .render()
+    .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatusChange {
+    Confirmed,
+    Unsubscribed,
+}
+
+#[derive(serde::Deserialize)]
+pub struct StatusForm {
+    status: StatusChange,
+}
+
+#[tracing::instrument(
+    name = "POST /admin/subscribers/{subscriber_id}/status",
+    skip(session, pool, form)
+)]
+pub async fn set_subscriber_status(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    subscriber_id: web::Path<Uuid>,
+    form: web::Form<StatusForm>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let (status, message) = match form.status {
+        StatusChange::Confirmed => (
+            SubscriptionStatus::Confirmed,
+            "The subscriber has been confirmed.",
+        ),
+        StatusChange::Unsubscribed => (
+            SubscriptionStatus::Unsubscribed,
+            "The subscriber has been unsubscribed.",
+        ),
+    };
+
+    match subscribers::set_status(&pool, *subscriber_id, status, "admin").await {
+        Ok(()) => FlashMessage::info(message).send(),
+        Err(StatusError::UnknownSubscriber(_)) => return Ok(HttpResponse::NotFound().finish()),
+        Err(StatusError::IllegalTransition(e)) => FlashMessage::error(e.to_string()).send(),
+        Err(e) => return Err(e500(e)),
+    }
+
+    Ok(see_other(&format!("/admin/subscribers/{}", subscriber_id)))
+}
+
+#[derive(serde::Deserialize)]
+pub struct NameForm {
+    name: String,
+}
+
+#[tracing::instrument(
+    name = "POST /admin/subscribers/{subscriber_id}/name",
+    skip(session, pool, form)
+)]
+pub async fn rename_subscriber(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    subscriber_id: web::Path<Uuid>,
+    form: web::Form<NameForm>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let subscriber = match get_subscriber(&pool, *subscriber_id).await.map_err(e500)? {
+        Some(subscriber) => subscriber,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let subscriber_page = format!("/admin/subscribers/{}", subscriber_id);
+
+    let person = match Person::parse(form.into_inner().name, subscriber.email) {
+        Ok(person) => person,
+        Err(e) => {
+            FlashMessage::error(e.to_string()).send();
+            return Ok(see_other(&subscriber_page));
+        }
+    };
+
+    subscribers::update_name(&pool, *subscriber_id, person.name.as_ref())
+        .await
+        .map_err(e500)?;
+
+    FlashMessage::info("The name has been updated.").send();
+    Ok(see_other(&subscriber_page))
+}
+
+/// The form sends `tags` as one comma separated value and each custom field
+/// as `field.<name>`, so it is read as a list of pairs.
+#[derive(Debug)]
+struct AttributesForm {
+    tags: String,
+    fields: Vec<(String, String)>,
+}
+
+impl From<Vec<(String, String)>> for AttributesForm {
+    fn from(pairs: Vec<(String, String)>) -> Self {
+        let mut form = AttributesForm {
+            tags: String::new(),
+            fields: Vec::new(),
+        };
+        for (key, value) in pairs {
+            if key == "tags" {
+                form.tags = value;
+            } else if let Some(name) = key.strip_prefix("field.") {
+                form.fields.push((name.to_string(), value));
+            }
+        }
+        form
+    }
+}
+
+#[tracing::instrument(
+    name = "POST /admin/subscribers/{subscriber_id}/attributes",
+    skip(session, pool, form)
+)]
+pub async fn save_subscriber_attributes(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    subscriber_id: web::Path<Uuid>,
+    form: web::Form<Vec<(String, String)>>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    if get_subscriber(&pool, *subscriber_id)
+        .await
+        .map_err(e500)?
+        .is_none()
+    {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let subscriber_page = format!("/admin/subscribers/{}", subscriber_id);
+    let form = AttributesForm::from(form.into_inner());
+
+    let tags = match parse_tags(&form.tags) {
+        Ok(tags) => tags,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&subscriber_page));
+        }
+    };
+
+    let custom_fields = get_custom_fields(&pool).await.map_err(e500)?;
+    let mut changes = AttributeChanges {
+        tags: Some(tags),
+        fields: Vec::new(),
+    };
+    for (name, input) in &form.fields {
+        // A field removed while the form was open is ignored.
+        let field = match custom_fields.iter().find(|field| &field.name == name) {
+            Some(field) => field,
+            None => continue,
+        };
+        match field.parse_input(input) {
+            Ok(value) => changes.fields.push((field.field_id, value)),
+            Err(e) => {
+                FlashMessage::error(e).send();
+                return Ok(see_other(&subscriber_page));
+            }
+        }
+    }
+
+    let mut transaction = pool.begin().await.map_err(e500)?;
+    apply_attribute_changes(&mut transaction, *subscriber_id, &changes)
+        .await
+        .map_err(e500)?;
+    transaction.commit().await.map_err(e500)?;
+
+    FlashMessage::info("The tags and fields have been saved.").send();
+    Ok(see_other(&subscriber_page))
+}
+
+#[tracing::instrument(
+    name = "POST /admin/subscribers/{subscriber_id}/delete",
+    skip(session, pool, email_hash_key)
+)]
+pub async fn delete_subscriber(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    email_hash_key: web::Data<EmailHashKey>,
+    subscriber_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let deleted = subscribers::delete_subscriber(&pool, &email_hash_key, *subscriber_id)
+        .await
+        .map_err(e500)?;
+
+    if !deleted {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    FlashMessage::info("The subscriber has been deleted.").send();
+    Ok(see_other("/admin/subscribers"))
+}
+
+/// Everything stored about the subscriber's address, for answering an access
+/// request received by other means.
+#[tracing::instrument(
+    name = "GET /admin/subscribers/{subscriber_id}/data",
+    skip(session, pool, email_hash_key)
+)]
+pub async fn subscriber_data(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    email_hash_key: web::Data<EmailHashKey>,
+    subscriber_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let subscriber = match get_subscriber(&pool, *subscriber_id).await.map_err(e500)? {
+        Some(subscriber) => subscriber,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let data = collect_personal_data(&pool, &email_hash_key, &subscriber.email)
+        .await
+        .map_err(e500)?;
+
+    personal_data_response(&data)
+}
+
+/// Unlike deleting, erasing also keeps the address from being imported again.
+#[tracing::instrument(
+    name = "POST /admin/subscribers/{subscriber_id}/erase",
+    skip(session, pool, email_hash_key)
+)]
+pub async fn erase_subscriber(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    email_hash_key: web::Data<EmailHashKey>,
+    subscriber_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let subscriber = match get_subscriber(&pool, *subscriber_id).await.map_err(e500)? {
+        Some(subscriber) => subscriber,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    erase_personal_data(&pool, &email_hash_key, &subscriber.email, "admin")
+        .await
+        .map_err(e500)?;
+
+    FlashMessage::info("The subscriber's data has been erased.").send();
+    Ok(see_other("/admin/subscribers"))
+}
@@ -0,0 +1,122 @@
+//! src/routes/admin/suppressions.rs
+use crate::configuration::EmailHashKey;
+use crate::session_state::TypedSession;
+use crate::suppressions::{
+    get_suppression_stats, get_suppressions, remove_suppression, suppress_addresses, Suppression,
+    SuppressionStats,
+};
+use crate::utils::{e500, see_other};
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use askama::Template;
+use sqlx::PgPool;
+
+#[derive(Template)]
+#[template(path = "admin/suppressions.html")]
+struct SuppressionsTemplate<'a> {
+    messages: Vec<&'a str>,
+    stats: SuppressionStats,
+    email: String,
+    suppressions: Vec<Suppression>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct SuppressionsQuery {
+    email: Option<String>,
+}
+
+pub async fn admin_suppressions(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    email_hash_key: web::Data<EmailHashKey>,
+    query: web::Query<SuppressionsQuery>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let email = query
+        .email
+        .as_deref()
+        .map(str::trim)
+        .filter(|email| !email.is_empty());
+    let stats = get_suppression_stats(&pool).await.map_err(e500)?;
+    let suppressions = get_suppressions(&pool, &email_hash_key, email, 100)
+        .await
+        .map_err(e500)?;
+    let messages = flash_messages.iter().map(|m| m.content()).collect();
+
+    let body = SuppressionsTemplate {
+        messages,
+        stats,
+        email: email.unwrap_or_default().to_string(),
+        suppressions,
+    }
+    .render()
+    .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[derive(serde::Deserialize)]
+pub struct SuppressionsForm {
+    /// One address per line.
+    emails: String,
+    reason: String,
+}
+
+pub async fn add_admin_suppressions(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    email_hash_key: web::Data<EmailHashKey>,
+    form: web::Form<SuppressionsForm>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let emails: Vec<String> = form.emails.lines().map(str::to_string).collect();
+    let reason = match form.reason.trim() {
+        "" => "manual",
+        reason => reason,
+    };
+    let report = suppress_addresses(&pool, &email_hash_key, &emails, reason, "admin")
+        .await
+        .map_err(e500)?;
+
+    FlashMessage::info(format!(
+        "{} addresses suppressed, {} already were.",
+        report.added, report.already_suppressed
+    ))
+    .send();
+    if !report.invalid.is_empty() {
+        FlashMessage::error(format!(
+            "These are not email addresses: {}",
+            report.invalid.join(", ")
+        ))
+        .send();
+    }
+
+    Ok(see_other("/admin/suppressions"))
+}
+
+pub async fn delete_admin_suppression(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    email_hash: web::Path<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    if remove_suppression(&pool, &email_hash).await.map_err(e500)? {
+        FlashMessage::info("The address can be emailed again.").send();
+    } else {
+        FlashMessage::error("The address was not suppressed.").send();
+    }
+
+    Ok(see_other("/admin/suppressions"))
+}
@@ -0,0 +1,62 @@
+//! src/routes/admin/topics.rs
+use crate::session_state::TypedSession;
+use crate::topics::{get_topics, insert_topic, Topic};
+use crate::utils::{e500, see_other};
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use askama::Template;
+use sqlx::PgPool;
+
+#[derive(Template)]
+#[template(path = "admin/topics.html")]
+struct TopicsTemplate<'a> {
+    messages: Vec<&'a str>,
+    topics: Vec<Topic>,
+}
+
+pub async fn admin_topics(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let topics = get_topics(&pool).await.map_err(e500)?;
+    let messages = flash_messages.iter().map(|m| m.content()).collect();
+
+    let body = TopicsTemplate { messages, topics }.render().map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[derive(serde::Deserialize)]
+pub struct TopicForm {
+    name: String,
+}
+
+pub async fn create_topic(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    form: web::Form<TopicForm>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let name = form.name.trim();
+    if name.is_empty() {
+        FlashMessage::error("A topic needs a name.").send();
+        return Ok(see_other("/admin/topics"));
+    }
+
+    match insert_topic(&pool, name).await.map_err(e500)? {
+        Some(_) => FlashMessage::info("The topic has been added.").send(),
+        None => FlashMessage::error("A topic with this name already exists.").send(),
+    }
+
+    Ok(see_other("/admin/topics"))
+}
@@ -0,0 +1,117 @@
+//! src/routes/archive.rs
+use crate::issues::{get_public_issue, get_public_issues, IssueTemplate, PublishedIssue};
+use crate::lists::{get_list_by_slug, List};
+use crate::templates::{Overridable, TemplateOverrides};
+use crate::utils::e500;
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use askama::Template;
+use sqlx::PgPool;
+
+const ISSUES_PER_PAGE: i64 = 20;
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ArchiveQuery {
+    page: Option<u32>,
+}
+
+#[derive(Template, serde::Serialize)]
+#[template(path = "archive/list.html")]
+struct ArchiveTemplate<'a> {
+    heading: &'a str,
+    /// Where the pagination links point to.
+    archive_path: &'a str,
+    issues: Vec<PublishedIssue>,
+    previous_page: Option<u32>,
+    next_page: Option<u32>,
+}
+
+impl Overridable for ArchiveTemplate<'_> {
+    const PATH: &'static str = "archive/list.html";
+}
+
+#[tracing::instrument(name = "GET /archive", skip(pool, templates))]
+pub async fn archive(
+    pool: web::Data<PgPool>,
+    query: web::Query<ArchiveQuery>,
+    templates: web::Data<TemplateOverrides>,
+) -> Result<HttpResponse, actix_web::Error> {
+    render_archive(&pool, &templates, None, query.page).await
+}
+
+/// The issues sent to a single list.
+#[tracing::instrument(name = "GET /lists/{slug}/archive", skip(pool, templates))]
+pub async fn list_archive(
+    pool: web::Data<PgPool>,
+    slug: web::Path<String>,
+    query: web::Query<ArchiveQuery>,
+    templates: web::Data<TemplateOverrides>,
+) -> Result<HttpResponse, actix_web::Error> {
+    match get_list_by_slug(&pool, &slug).await.map_err(e500)? {
+        Some(list) => render_archive(&pool, &templates, Some(&list), query.page).await,
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+async fn render_archive(
+    pool: &PgPool,
+    templates: &TemplateOverrides,
+    list: Option<&List>,
+    page: Option<u32>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let page = page.unwrap_or(1).max(1);
+    let offset = (i64::from(page) - 1) * ISSUES_PER_PAGE;
+
+    // Fetch one extra row to find out whether there is an older page.
+    let mut issues = get_public_issues(
+        pool,
+        list.map(|list| list.list_id),
+        ISSUES_PER_PAGE + 1,
+        offset,
+    )
+    .await
+    .map_err(e500)?;
+    let has_older_issues = issues.len() as i64 > ISSUES_PER_PAGE;
+    issues.truncate(ISSUES_PER_PAGE as usize);
+
+    let archive_path = match list {
+        Some(list) => format!("/lists/{}/archive", list.slug),
+        None => "/archive".to_string(),
+    };
+    let body = templates
+        .render(&ArchiveTemplate {
+            heading: list.map_or("Archive", |list| &list.name),
+            archive_path: &archive_path,
+            issues,
+            previous_page: Some(page - 1).filter(|&previous| previous > 0),
+            next_page: page.checked_add(1).filter(|_| has_older_issues),
+        })
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[tracing::instrument(name = "GET /archive/{slug}", skip(pool))]
+pub async fn archive_issue(
+    pool: web::Data<PgPool>,
+    slug: web::Path<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue = match get_public_issue(&pool, &slug).await.map_err(e500)? {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let body = IssueTemplate {
+        title: &issue.title,
+        html_content: &issue.html_content,
+        view_in_browser_url: None,
+        preferences_url: None,
+    }
+    .render()
+    .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
@@ -0,0 +1,156 @@
+//! src/routes/feed.rs
+use crate::issues::{get_last_update, get_public_issues, PublishedIssue};
+use crate::startup::ApplicationBaseUrl;
+use crate::utils::e500;
+use actix_web::http::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use actix_web::{web, HttpRequest, HttpResponse};
+use askama::Template;
+use chrono::{DateTime, TimeZone, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+const FEED_LENGTH: i64 = 50;
+
+#[derive(Template)]
+#[template(path = "feed/rss.xml")]
+struct RssTemplate<'a> {
+    base_url: &'a str,
+    issues: &'a [PublishedIssue],
+    updated: DateTime<Utc>,
+}
+
+#[derive(Template)]
+#[template(path = "feed/atom.xml")]
+struct AtomTemplate<'a> {
+    base_url: &'a str,
+    issues: &'a [PublishedIssue],
+    updated: DateTime<Utc>,
+}
+
+struct Feed {
+    issues: Vec<PublishedIssue>,
+    last_modified: Option<DateTime<Utc>>,
+}
+
+impl Feed {
+    async fn load(pool: &PgPool) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            issues: get_public_issues(pool, None, FEED_LENGTH, 0).await?,
+            last_modified: get_last_update(pool).await?,
+        })
+    }
+
+    /// Feeds need an `updated` date even before the first issue is out.
+    fn updated(&self) -> DateTime<Utc> {
+        self.last_modified
+            .unwrap_or_else(|| Utc.timestamp_opt(0, 0).unwrap())
+    }
+}
+
+#[tracing::instrument(name = "GET /feed.rss", skip(req, pool, base_url))]
+pub async fn rss_feed(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let feed = Feed::load(&pool).await.map_err(e500)?;
+
+    let body = RssTemplate {
+        base_url: &base_url.0,
+        issues: &feed.issues,
+        updated: feed.updated(),
+    }
+    .render()
+    .map_err(e500)?;
+
+    Ok(conditional_response(
+        &req,
+        "application/rss+xml; charset=utf-8",
+        body,
+        feed.last_modified,
+    ))
+}
+
+#[tracing::instrument(name = "GET /feed.atom", skip(req, pool, base_url))]
+pub async fn atom_feed(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let feed = Feed::load(&pool).await.map_err(e500)?;
+
+    let body = AtomTemplate {
+        base_url: &base_url.0,
+        issues: &feed.issues,
+        updated: feed.updated(),
+    }
+    .render()
+    .map_err(e500)?;
+
+    Ok(conditional_response(
+        &req,
+        "application/atom+xml; charset=utf-8",
+        body,
+        feed.last_modified,
+    ))
+}
+
+/// Answer with `304 Not Modified` when the client already has the current feed,
+/// so that feed readers polling every few minutes don't download it again.
+fn conditional_response(
+    req: &HttpRequest,
+    content_type: &str,
+    body: String,
+    last_modified: Option<DateTime<Utc>>,
+) -> HttpResponse {
+    let etag = format!("\"{}\"", hex::encode(Sha256::digest(body.as_bytes())));
+    let fresh = is_fresh(req, &etag, last_modified);
+
+    let mut response = if fresh {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+
+    response.insert_header((ETAG, etag));
+    if let Some(last_modified) = last_modified {
+        response.insert_header((LAST_MODIFIED, http_date(last_modified)));
+    }
+
+    if fresh {
+        response.finish()
+    } else {
+        response.content_type(content_type).body(body)
+    }
+}
+
+/// `If-None-Match` takes precedence over `If-Modified-Since` (RFC 9110, 13.2.2).
+fn is_fresh(req: &HttpRequest, etag: &str, last_modified: Option<DateTime<Utc>>) -> bool {
+    if let Some(if_none_match) = req.headers().get(IF_NONE_MATCH) {
+        return if_none_match
+            .to_str()
+            .map(|tags| {
+                tags.split(',')
+                    .map(str::trim)
+                    .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+            })
+            .unwrap_or(false);
+    }
+
+    let if_modified_since = req
+        .headers()
+        .get(IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| DateTime::parse_from_rfc2822(value).ok());
+
+    match (if_modified_since, last_modified) {
+        // HTTP dates have a one second resolution.
+        (Some(since), Some(last_modified)) => last_modified.timestamp() <= since.timestamp(),
+        _ => false,
+    }
+}
+
+/// IMF-fixdate, the format HTTP uses for dates: `Sun, 06 Nov 1994 08:49:37 GMT`.
+fn http_date(date: DateTime<Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
@@ -1,8 +1,23 @@
 //! src/routes/home/mod.rs
-use actix_web::{http::header::ContentType, HttpResponse};
+use crate::templates::{Overridable, TemplateOverrides};
+use crate::utils::e500;
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use askama::Template;
 
-pub async fn home() -> HttpResponse {
-    HttpResponse::Ok()
+#[derive(Template, serde::Serialize)]
+#[template(path = "home.html")]
+struct HomeTemplate;
+
+impl Overridable for HomeTemplate {
+    const PATH: &'static str = "home.html";
+}
+
+pub async fn home(
+    templates: web::Data<TemplateOverrides>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let body = templates.render(&HomeTemplate).map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
-        .body(include_str!("home.html"))
+        .body(body))
 }
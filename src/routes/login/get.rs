@@ -1,45 +1,36 @@
 //! src/routes/login/get.rs
-use actix_web::{http::header::ContentType, HttpResponse};
+use crate::templates::{Overridable, TemplateOverrides};
+use crate::utils::e500;
+use actix_web::{http::header::ContentType, web, HttpResponse};
 use actix_web_flash_messages::{IncomingFlashMessages, Level};
-use std::fmt::Write;
+use askama::Template;
 
-#[tracing::instrument(name = "GET /login", skip(flash_messages))]
-pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
-    let mut error_html = String::new();
+#[derive(Template, serde::Serialize)]
+#[template(path = "login.html")]
+struct LoginTemplate<'a> {
+    error_messages: Vec<&'a str>,
+}
+
+impl Overridable for LoginTemplate<'_> {
+    const PATH: &'static str = "login.html";
+}
+
+#[tracing::instrument(name = "GET /login", skip(flash_messages, templates))]
+pub async fn login_form(
+    flash_messages: IncomingFlashMessages,
+    templates: web::Data<TemplateOverrides>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let error_messages = flash_messages
+        .iter()
+        .filter(|m| m.level() == Level::Error)
+        .map(|m| m.content())
+        .collect();
 
-    for m in flash_messages.iter().filter(|m| m.level() == Level::Error) {
-        writeln!(error_html, "<p><i>{}</i></p>", m.content()).unwrap();
-    }
+    let body = templates
+        .render(&LoginTemplate { error_messages })
+        .map_err(e500)?;
 
-    HttpResponse::Ok()
+    Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
-        .body(format!(
-            r#"<!DOCTYPE html>
-<html lang="en">
-<head>
-    <meta http-equiv="content-type" content="text/html; charset=utf-8">
-    <title>Login</title>
-</head>
-<body>
-    {error_html}
-    <form action="/login" method="post">
-        <label>Username
-            <input
-                type="text"
-                placeholder="Enter Username"
-                name="username"
-            >
-        </label>
-        <label>Password
-            <input
-                type="password"
-                placeholder="Enter Password"
-                name="password"
-            >
-        </label>
-        <button type="submit">Login</button>
-    </form>
-</body>
-</html>"#,
-        ))
+        .body(body))
 }
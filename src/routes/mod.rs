@@ -8,18 +8,78 @@ pub use subscriptions::*;
 mod subscriptions_confirm;
 pub use subscriptions_confirm::*;
 
+mod subscriptions_api;
+pub use subscriptions_api::*;
+
+mod widget;
+pub use widget::*;
+
 pub mod newsletters;
 
+mod archive;
+pub use archive::*;
+
+mod feed;
+pub use feed::*;
+
 mod home;
 pub use home::*;
 
 mod login;
 pub use login::*;
 
+mod preferences;
+pub use preferences::*;
+
+mod privacy;
+pub use privacy::*;
+
+mod tracking;
+pub use tracking::*;
+
+mod webhooks;
+pub use webhooks::*;
+
 mod admin;
+pub use admin::add_admin_suppressions;
 pub use admin::admin_dashboard;
+pub use admin::admin_export_subscribers;
+pub use admin::admin_fields;
+pub use admin::admin_issues;
+pub use admin::admin_lists;
+pub use admin::admin_subscriber;
+pub use admin::admin_subscribers;
+pub use admin::admin_suppressions;
+pub use admin::admin_topics;
 pub use admin::change_password;
 pub use admin::change_password_form;
+pub use admin::create_field;
+pub use admin::create_issue;
+pub use admin::create_list;
+pub use admin::create_topic;
+pub use admin::delete_admin_suppression;
+pub use admin::delete_subscriber;
+pub use admin::edit_issue_form;
+pub use admin::edit_list_form;
+pub use admin::erase_subscriber;
+pub use admin::import_errors;
+pub use admin::import_form;
+pub use admin::import_report;
+pub use admin::issue_recipients;
+pub use admin::issue_report;
+pub use admin::issue_revision;
+pub use admin::issue_revisions;
+pub use admin::new_issue_form;
+pub use admin::publish_issue_draft;
+pub use admin::rename_subscriber;
+pub use admin::restore_revision;
+pub use admin::save_issue;
+pub use admin::save_list;
+pub use admin::save_subscriber_attributes;
+pub use admin::set_issue_visibility;
+pub use admin::set_subscriber_status;
+pub use admin::subscriber_data;
+pub use admin::upload_import;
 
 fn error_chain_fmt(
     e: &impl std::error::Error,
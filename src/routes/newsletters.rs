@@ -1,6 +1,19 @@
 //! src/routes/newsletters.rs
+use crate::attributes::{apply_attribute_changes, get_custom_fields, parse_tag, AttributeChanges};
 use crate::authenticate::{self, validate_credentials, Credentials};
-use crate::domain::Person as Subscriber;
+use crate::configuration::{EmailHashKey, HmacSecret};
+use crate::export::{export_response, ExportQuery};
+use crate::issues::IssueContent;
+use crate::lists::get_list_by_slug;
+use crate::privacy::email_hash;
+use crate::publish::{count_recipients, publish_issue};
+use crate::reports::get_issue_report;
+use crate::routes::admin::SubscribersQuery;
+use crate::segments::Segment;
+use crate::startup::ApplicationBaseUrl;
+use crate::subscribers::get_subscriber;
+use crate::suppressions::{get_suppressions, remove_suppression, suppress_addresses};
+use crate::topics::get_topic_by_name;
 use crate::{email::Brevo, routes::error_chain_fmt};
 use actix_web::http::{
     header::{HeaderMap, HeaderValue, WWW_AUTHENTICATE},
@@ -11,11 +24,15 @@ use anyhow::Context;
 use base64::{engine, Engine};
 use secrecy::Secret;
 use sqlx::PgPool;
+use std::collections::HashMap;
+use uuid::Uuid;
 
 #[derive(thiserror::Error)]
 pub enum PublishError {
     #[error("Authentication failed")]
     AuthError(#[source] anyhow::Error),
+    #[error("{0}")]
+    ValidationError(String),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -32,6 +49,7 @@ impl ResponseError for PublishError {
             PublishError::UnexpectedError(_) => {
                 HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
             }
+            PublishError::ValidationError(_) => HttpResponse::new(StatusCode::BAD_REQUEST),
             PublishError::AuthError(_) => {
                 let authentication = (
                     WWW_AUTHENTICATE,
@@ -46,15 +64,84 @@ impl ResponseError for PublishError {
     }
 }
 
+/// Who an issue goes to.
+#[derive(serde::Deserialize)]
+pub struct Audience {
+    /// Name of the topic the issue is about, if any.
+    topic: Option<String>,
+    /// Slugs of the lists to send the issue to. Defaults to the default list.
+    #[serde(default)]
+    lists: Vec<String>,
+    /// Only send to the subscribers matching this segment, e.g.
+    /// `tag = "beta" AND field.plan = "pro"`.
+    segment: Option<String>,
+}
+
+/// An audience whose topic, lists and segment are known to exist.
+struct ResolvedAudience {
+    topic_id: Option<Uuid>,
+    list_ids: Vec<Uuid>,
+    segment: Option<Segment>,
+}
+
+async fn resolve_audience(
+    pool: &PgPool,
+    audience: &Audience,
+) -> Result<ResolvedAudience, PublishError> {
+    let topic_id = match &audience.topic {
+        Some(name) => {
+            let topic = get_topic_by_name(pool, name)
+                .await
+                .context("Failed to look up the topic")?
+                .ok_or_else(|| {
+                    PublishError::ValidationError(format!("There is no topic named {}", name))
+                })?;
+            Some(topic.topic_id)
+        }
+        None => None,
+    };
+
+    let mut list_ids = Vec::with_capacity(audience.lists.len());
+    for slug in &audience.lists {
+        let list = get_list_by_slug(pool, slug)
+            .await
+            .context("Failed to look up the list")?
+            .ok_or_else(|| {
+                PublishError::ValidationError(format!("There is no list named {}", slug))
+            })?;
+        list_ids.push(list.list_id);
+    }
+
+    let segment = match &audience.segment {
+        Some(source) if !source.trim().is_empty() => {
+            let fields = get_custom_fields(pool)
+                .await
+                .context("Failed to retrieve the custom fields")?;
+            let segment = Segment::parse(source, &fields)
+                .map_err(|e| PublishError::ValidationError(e.to_string()))?;
+            Some(segment)
+        }
+        _ => None,
+    };
+
+    Ok(ResolvedAudience {
+        topic_id,
+        list_ids,
+        segment,
+    })
+}
+
 #[derive(serde::Deserialize)]
 pub struct Newsletter {
     title: String,
     body: String,
+    #[serde(flatten)]
+    audience: Audience,
 }
 
 #[tracing::instrument(
     name = "Publish a newsletter issue",
-    skip(pool, email_client, payload, req),
+    skip(pool, email_client, base_url, hmac_secret, payload, req),
     fields(
         username=tracing::field::Empty,
         user_id=tracing::field::Empty)
@@ -62,16 +149,293 @@ pub struct Newsletter {
 pub async fn publish(
     pool: web::Data<PgPool>,
     email_client: web::Data<Brevo>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
     payload: web::Json<Newsletter>,
     req: HttpRequest,
 ) -> Result<HttpResponse, PublishError> {
+    let user_id = authenticate_request(&req, &pool).await?;
+
+    let newsletter: Newsletter = payload.into_inner();
+    let audience = resolve_audience(&pool, &newsletter.audience).await?;
+
+    let content = IssueContent {
+        title: &newsletter.title,
+        html_content: &newsletter.body,
+        topic_id: audience.topic_id,
+        list_ids: &audience.list_ids,
+        segment: audience.segment.as_ref().map(Segment::as_str),
+    };
+    publish_issue(
+        &pool,
+        &email_client,
+        &base_url.0,
+        &hmac_secret,
+        &content,
+        Some(user_id),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// How many subscribers an issue sent to this audience right now would reach.
+#[tracing::instrument(
+    name = "Count recipients",
+    skip(pool, payload, req),
+    fields(
+        username=tracing::field::Empty,
+        user_id=tracing::field::Empty)
+)]
+pub async fn recipient_count(
+    pool: web::Data<PgPool>,
+    payload: web::Json<Audience>,
+    req: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate_request(&req, &pool).await?;
+
+    let audience = resolve_audience(&pool, &payload).await?;
+    let recipients = count_recipients(
+        &pool,
+        &audience.list_ids,
+        audience.topic_id,
+        audience.segment.as_ref(),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "recipients": recipients })))
+}
+
+/// Delivery and engagement counts of a published issue, as shown on its
+/// admin report page.
+#[tracing::instrument(
+    name = "Get issue report",
+    skip(pool, req),
+    fields(
+        username=tracing::field::Empty,
+        user_id=tracing::field::Empty)
+)]
+pub async fn issue_report(
+    pool: web::Data<PgPool>,
+    issue_id: web::Path<Uuid>,
+    req: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate_request(&req, &pool).await?;
+
+    match get_issue_report(&pool, *issue_id).await? {
+        Some(report) => Ok(HttpResponse::Ok().json(report)),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+/// Both parts are optional: missing tags are left as they are, and only the
+/// fields named are changed. `null` clears a field.
+#[derive(serde::Deserialize)]
+pub struct AttributesPayload {
+    tags: Option<Vec<String>>,
+    #[serde(default)]
+    fields: HashMap<String, serde_json::Value>,
+}
+
+#[tracing::instrument(
+    name = "Update subscriber attributes",
+    skip(pool, payload, req),
+    fields(
+        username=tracing::field::Empty,
+        user_id=tracing::field::Empty)
+)]
+pub async fn update_subscriber_attributes(
+    pool: web::Data<PgPool>,
+    subscriber_id: web::Path<Uuid>,
+    payload: web::Json<AttributesPayload>,
+    req: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate_request(&req, &pool).await?;
+
+    let mut changes = AttributeChanges::default();
+
+    if let Some(tags) = &payload.tags {
+        let mut parsed = tags
+            .iter()
+            .map(|tag| parse_tag(tag))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(PublishError::ValidationError)?;
+        parsed.sort();
+        parsed.dedup();
+        changes.tags = Some(parsed);
+    }
+
+    let fields = get_custom_fields(&pool)
+        .await
+        .context("Failed to retrieve the custom fields")?;
+    for (name, value) in &payload.fields {
+        let field = fields
+            .iter()
+            .find(|field| &field.name == name)
+            .ok_or_else(|| {
+                PublishError::ValidationError(format!("There is no field named {}", name))
+            })?;
+        let input = match value {
+            serde_json::Value::Null => String::new(),
+            serde_json::Value::String(value) => value.clone(),
+            serde_json::Value::Number(_) | serde_json::Value::Bool(_) => value.to_string(),
+            _ => {
+                return Err(PublishError::ValidationError(format!(
+                    "{}: expected a string, number or boolean",
+                    name
+                )))
+            }
+        };
+        let value = field
+            .parse_input(&input)
+            .map_err(PublishError::ValidationError)?;
+        changes.fields.push((field.field_id, value));
+    }
+
+    if get_subscriber(&pool, *subscriber_id)
+        .await
+        .context("Failed to look up the subscriber")?
+        .is_none()
+    {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    apply_attribute_changes(&mut transaction, *subscriber_id, &changes)
+        .await
+        .context("Failed to save the subscriber attributes")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the subscriber attributes")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Stream every subscriber, or those matching the filters, as CSV or NDJSON.
+#[tracing::instrument(
+    name = "Export subscribers",
+    skip(pool, req),
+    fields(
+        username=tracing::field::Empty,
+        user_id=tracing::field::Empty)
+)]
+pub async fn export_subscribers(
+    pool: web::Data<PgPool>,
+    query: web::Query<SubscribersQuery>,
+    export: web::Query<ExportQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate_request(&req, &pool).await?;
+
+    let filter = match query.filter() {
+        Ok(filter) => filter,
+        Err(_) => return Ok(HttpResponse::BadRequest().finish()),
+    };
+
+    Ok(export_response(
+        pool.get_ref().clone(),
+        filter,
+        export.format,
+    ))
+}
+
+#[derive(serde::Deserialize)]
+pub struct SuppressionsQuery {
+    email: Option<String>,
+}
+
+/// The newest suppressions, or the one for `?email=`.
+#[tracing::instrument(
+    name = "List suppressions",
+    skip(pool, email_hash_key, query, req),
+    fields(
+        username=tracing::field::Empty,
+        user_id=tracing::field::Empty)
+)]
+pub async fn list_suppressions(
+    pool: web::Data<PgPool>,
+    email_hash_key: web::Data<EmailHashKey>,
+    query: web::Query<SuppressionsQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate_request(&req, &pool).await?;
+
+    let suppressions = get_suppressions(&pool, &email_hash_key, query.email.as_deref(), 1000)
+        .await
+        .context("Failed to retrieve the suppressions")?;
+
+    Ok(HttpResponse::Ok().json(suppressions))
+}
+
+#[derive(serde::Deserialize)]
+pub struct SuppressionsPayload {
+    emails: Vec<String>,
+    reason: Option<String>,
+}
+
+#[tracing::instrument(
+    name = "Add suppressions",
+    skip(pool, email_hash_key, payload, req),
+    fields(
+        username=tracing::field::Empty,
+        user_id=tracing::field::Empty)
+)]
+pub async fn add_suppressions(
+    pool: web::Data<PgPool>,
+    email_hash_key: web::Data<EmailHashKey>,
+    payload: web::Json<SuppressionsPayload>,
+    req: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate_request(&req, &pool).await?;
+
+    let reason = payload.reason.as_deref().unwrap_or("manual");
+    let report = suppress_addresses(&pool, &email_hash_key, &payload.emails, reason, "api")
+        .await
+        .context("Failed to suppress the addresses")?;
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[tracing::instrument(
+    name = "Delete suppression",
+    skip(pool, email_hash_key, email, req),
+    fields(
+        username=tracing::field::Empty,
+        user_id=tracing::field::Empty)
+)]
+pub async fn delete_suppression(
+    pool: web::Data<PgPool>,
+    email_hash_key: web::Data<EmailHashKey>,
+    email: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate_request(&req, &pool).await?;
+
+    let removed = remove_suppression(&pool, &email_hash(&email, &email_hash_key))
+        .await
+        .context("Failed to remove the suppression")?;
+
+    if removed {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Ok(HttpResponse::NotFound().finish())
+    }
+}
+
+/// Check the basic auth credentials of an API request and record who made it
+/// on the current span.
+async fn authenticate_request(req: &HttpRequest, pool: &PgPool) -> Result<Uuid, PublishError> {
     let credentials = extract_credentials(req.headers())
         .context("Failed to extract auth credentials from the header")
         .map_err(PublishError::AuthError)?;
 
     tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
 
-    let user_id = validate_credentials(credentials, &pool)
+    let user_id = validate_credentials(credentials, pool)
         .await
         .map_err(|e| match e {
             authenticate::AuthError::InvalidCredentials(e) => PublishError::AuthError(e),
@@ -80,29 +444,7 @@ pub async fn publish(
 
     tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
 
-    let newsletter: Newsletter = payload.into_inner();
-
-    let confirmed_subscribers = get_confirmed_subscribers(&pool)
-        .await
-        .context("Failed to retrieve confirmed subscribers")?;
-
-    let parsed_subscribers = parse_confirmed_subscribers(confirmed_subscribers);
-
-    for subscriber in parsed_subscribers {
-        let email = email_client
-            .email_builder()
-            .subject(&newsletter.title)
-            .to(&subscriber)
-            .html_content(&newsletter.body)
-            .build();
-
-        email_client
-            .send_email(&email)
-            .await
-            .with_context(|| format!("Failed to send email to {}", subscriber.email))?;
-    }
-
-    Ok(HttpResponse::Ok().finish())
+    Ok(user_id)
 }
 
 fn extract_credentials(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
@@ -142,43 +484,3 @@ fn extract_credentials(headers: &HeaderMap) -> Result<Credentials, anyhow::Error
         password: Secret::new(password),
     })
 }
-
-#[derive(serde::Deserialize)]
-struct Row {
-    name: String,
-    email: String,
-}
-
-#[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
-async fn get_confirmed_subscribers(pool: &PgPool) -> Result<Vec<Row>, sqlx::Error> {
-    sqlx::query_as!(
-        Row,
-        r#"
-        SELECT email, name
-        FROM subscriptions
-        WHERE status = 'confirmed'
-        "#,
-    )
-    .fetch_all(pool)
-    .await
-}
-
-#[tracing::instrument(name = "Parse confirmed subscribers", skip(rows))]
-fn parse_confirmed_subscribers(rows: Vec<Row>) -> Vec<Subscriber> {
-    let mut subscribers = Vec::new();
-
-    for row in rows {
-        let result = Subscriber::parse(row.name, row.email.clone());
-
-        match result {
-            Ok(subscriber) => subscribers.push(subscriber),
-            Err(e) => {
-                tracing::warn!(
-                    error.cause_chain = ?e,
-                    "Skipping confirmed subscriber {} because {}", row.email, e);
-            }
-        }
-    }
-
-    subscribers
-}
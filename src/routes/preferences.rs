@@ -0,0 +1,234 @@
+//! src/routes/preferences.rs
+use crate::configuration::HmacSecret;
+use crate::domain::Person;
+use crate::preferences::{
+    self, get_preferences, pause_deliveries, update_preferences, verify_preferences_token,
+    Frequency, Preferences, MAX_PAUSE_WEEKS,
+};
+use crate::subscribers::StatusError;
+use crate::utils::{e500, see_other};
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use askama::Template;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Template)]
+#[template(path = "preferences.html")]
+struct PreferencesTemplate<'a> {
+    messages: Vec<&'a str>,
+    token: &'a str,
+    preferences: Preferences,
+    max_pause_weeks: u32,
+}
+
+/// Anyone holding a valid token may edit the subscriber's preferences, so a
+/// bad token is answered the same way as an unknown subscriber.
+async fn subscriber_for_token(
+    pool: &PgPool,
+    token: &str,
+    hmac_secret: &HmacSecret,
+) -> Result<Option<Preferences>, actix_web::Error> {
+    match verify_preferences_token(token, hmac_secret) {
+        Ok(subscriber_id) => get_preferences(pool, subscriber_id).await.map_err(e500),
+        Err(_) => Ok(None),
+    }
+}
+
+#[tracing::instrument(
+    name = "GET /preferences/{token}",
+    skip(token, pool, hmac_secret, flash_messages)
+)]
+pub async fn preferences_form(
+    token: web::Path<String>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let preferences = match subscriber_for_token(&pool, &token, &hmac_secret).await? {
+        Some(preferences) => preferences,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let messages = flash_messages.iter().map(|m| m.content()).collect();
+    let body = PreferencesTemplate {
+        messages,
+        token: &token,
+        preferences,
+        max_pause_weeks: MAX_PAUSE_WEEKS,
+    }
+    .render()
+    .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// The form posts one `topic` and one `list` field per ticked checkbox, which
+/// a struct cannot capture, so the fields are read as pairs.
+struct PreferencesForm {
+    name: String,
+    frequency: Frequency,
+    topic_ids: Vec<Uuid>,
+    list_ids: Vec<Uuid>,
+}
+
+impl TryFrom<Vec<(String, String)>> for PreferencesForm {
+    type Error = String;
+
+    fn try_from(fields: Vec<(String, String)>) -> Result<Self, Self::Error> {
+        let mut form = PreferencesForm {
+            name: String::new(),
+            frequency: Frequency::default(),
+            topic_ids: Vec::new(),
+            list_ids: Vec::new(),
+        };
+
+        for (key, value) in fields {
+            match key.as_str() {
+                "name" => form.name = value,
+                "frequency" => {
+                    form.frequency = match value.as_str() {
+                        "every_issue" => Frequency::EveryIssue,
+                        "weekly_digest" => Frequency::WeeklyDigest,
+                        _ => return Err(format!("{} is not a delivery frequency", value)),
+                    }
+                }
+                "topic" => form
+                    .topic_ids
+                    .push(Uuid::parse_str(&value).map_err(|e| e.to_string())?),
+                "list" => form
+                    .list_ids
+                    .push(Uuid::parse_str(&value).map_err(|e| e.to_string())?),
+                _ => {}
+            }
+        }
+
+        Ok(form)
+    }
+}
+
+#[tracing::instrument(
+    name = "POST /preferences/{token}",
+    skip(token, form, pool, hmac_secret)
+)]
+pub async fn save_preferences(
+    token: web::Path<String>,
+    form: web::Form<Vec<(String, String)>>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let current = match subscriber_for_token(&pool, &token, &hmac_secret).await? {
+        Some(preferences) => preferences,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let preferences_page = format!("/preferences/{}", token);
+
+    let form = match PreferencesForm::try_from(form.into_inner()) {
+        Ok(form) => form,
+        Err(_) => return Ok(HttpResponse::BadRequest().finish()),
+    };
+
+    // The name is validated the same way as at sign-up.
+    let person = match Person::parse(form.name, current.email) {
+        Ok(person) => person,
+        Err(e) => {
+            FlashMessage::error(e.to_string()).send();
+            return Ok(see_other(&preferences_page));
+        }
+    };
+
+    update_preferences(
+        &pool,
+        current.subscriber_id,
+        person.name.as_ref(),
+        form.frequency,
+        &form.topic_ids,
+        &form.list_ids,
+    )
+    .await
+    .map_err(e500)?;
+
+    FlashMessage::info("Your preferences have been saved.").send();
+    Ok(see_other(&preferences_page))
+}
+
+#[derive(serde::Deserialize)]
+pub struct PauseForm {
+    weeks: u32,
+}
+
+#[tracing::instrument(
+    name = "POST /preferences/{token}/pause",
+    skip(token, form, pool, hmac_secret)
+)]
+pub async fn pause_subscription(
+    token: web::Path<String>,
+    form: web::Form<PauseForm>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let current = match subscriber_for_token(&pool, &token, &hmac_secret).await? {
+        Some(preferences) => preferences,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let preferences_page = format!("/preferences/{}", token);
+
+    if form.weeks > MAX_PAUSE_WEEKS {
+        FlashMessage::error(format!(
+            "You can pause for at most {} weeks.",
+            MAX_PAUSE_WEEKS
+        ))
+        .send();
+        return Ok(see_other(&preferences_page));
+    }
+
+    pause_deliveries(&pool, current.subscriber_id, form.weeks)
+        .await
+        .map_err(e500)?;
+
+    if form.weeks == 0 {
+        FlashMessage::info("Your subscription has been resumed.").send();
+    } else {
+        FlashMessage::info("Your subscription has been paused.").send();
+    }
+    Ok(see_other(&preferences_page))
+}
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeForm {
+    #[serde(default)]
+    reason: String,
+}
+
+#[tracing::instrument(
+    name = "POST /preferences/{token}/unsubscribe",
+    skip(token, form, pool, hmac_secret)
+)]
+pub async fn unsubscribe(
+    token: web::Path<String>,
+    form: web::Form<UnsubscribeForm>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let current = match subscriber_for_token(&pool, &token, &hmac_secret).await? {
+        Some(preferences) => preferences,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let reason = form.reason.trim();
+    let reason = (!reason.is_empty()).then_some(reason);
+
+    match preferences::unsubscribe(&pool, current.subscriber_id, reason).await {
+        Ok(()) => FlashMessage::info("You have been unsubscribed.").send(),
+        Err(StatusError::IllegalTransition(e)) => FlashMessage::error(format!(
+            "Your subscription is {}, there is nothing to unsubscribe from.",
+            e.from
+        ))
+        .send(),
+        Err(e) => return Err(e500(e)),
+    }
+
+    Ok(see_other(&format!("/preferences/{}", token)))
+}
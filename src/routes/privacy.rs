@@ -0,0 +1,208 @@
+//! src/routes/privacy.rs
+use crate::configuration::{EmailHashKey, HmacSecret};
+use crate::domain::Person;
+use crate::email::Brevo;
+use crate::privacy::{collect_personal_data, erase_personal_data, PersonalData, PrivacyLink};
+use crate::startup::ApplicationBaseUrl;
+use crate::templates::{Overridable, TemplateOverrides};
+use crate::utils::e500;
+use actix_web::http::header::{ContentDisposition, ContentType, DispositionParam, DispositionType};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use askama::Template;
+use sqlx::PgPool;
+
+#[derive(Template, serde::Serialize)]
+#[template(path = "privacy/request.html")]
+struct RequestTemplate {
+    sent: bool,
+}
+
+impl Overridable for RequestTemplate {
+    const PATH: &'static str = "privacy/request.html";
+}
+
+#[derive(Template, serde::Serialize)]
+#[template(path = "privacy/manage.html")]
+struct ManageTemplate<'a> {
+    link: &'a PrivacyLink,
+    query_string: String,
+}
+
+impl Overridable for ManageTemplate<'_> {
+    const PATH: &'static str = "privacy/manage.html";
+}
+
+#[derive(Template, serde::Serialize)]
+#[template(path = "privacy/erased.html")]
+struct ErasedTemplate;
+
+impl Overridable for ErasedTemplate {
+    const PATH: &'static str = "privacy/erased.html";
+}
+
+#[derive(Template)]
+#[template(path = "email/privacy.html")]
+struct PrivacyEmail<'a> {
+    manage_link: &'a str,
+}
+
+pub async fn privacy_form(
+    templates: web::Data<TemplateOverrides>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let body = templates
+        .render(&RequestTemplate { sent: false })
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[derive(serde::Deserialize)]
+pub struct PrivacyRequestForm {
+    email: String,
+}
+
+/// Email a signed link to the address, so only its owner can see or erase
+/// its data. The answer is the same whether or not we know the address.
+#[tracing::instrument(
+    name = "Request a privacy link",
+    skip(form, pool, email_client, hmac_secret, email_hash_key, templates)
+)]
+pub async fn request_privacy_link(
+    form: web::Form<PrivacyRequestForm>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<Brevo>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    email_hash_key: web::Data<EmailHashKey>,
+    templates: web::Data<TemplateOverrides>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let data = collect_personal_data(&pool, &email_hash_key, &form.email)
+        .await
+        .map_err(e500)?;
+
+    if let Some(subscription) = data.subscriptions.first() {
+        let link = PrivacyLink::new(&subscription.email, &hmac_secret);
+        let manage_link = format!("{}/privacy/manage?{}", base_url.0, link.query_string());
+
+        let recipient =
+            Person::parse(subscription.name.clone(), subscription.email.clone()).map_err(e500)?;
+        send_privacy_email(&email_client, &recipient, &manage_link)
+            .await
+            .map_err(e500)?;
+    }
+
+    let body = templates
+        .render(&RequestTemplate { sent: true })
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+async fn send_privacy_email(
+    email_client: &Brevo,
+    recipient: &Person,
+    manage_link: &str,
+) -> Result<(), anyhow::Error> {
+    let html_content = PrivacyEmail { manage_link }
+        .render()
+        .context("Failed to render the privacy email")?;
+
+    let email = email_client
+        .email_builder()
+        .to(recipient)
+        .subject("Your data")
+        .html_content(&html_content)
+        .build();
+
+    email_client.send_email(&email).await?;
+
+    Ok(())
+}
+
+pub async fn manage_personal_data(
+    link: web::Query<PrivacyLink>,
+    hmac_secret: web::Data<HmacSecret>,
+    templates: web::Data<TemplateOverrides>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if link.verify(&hmac_secret).is_err() {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
+    let body = templates
+        .render(&ManageTemplate {
+            link: &link,
+            query_string: link.query_string(),
+        })
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[tracing::instrument(
+    name = "Export personal data",
+    skip(link, pool, hmac_secret, email_hash_key)
+)]
+pub async fn export_personal_data(
+    link: web::Query<PrivacyLink>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    email_hash_key: web::Data<EmailHashKey>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if link.verify(&hmac_secret).is_err() {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
+    let data = collect_personal_data(&pool, &email_hash_key, &link.email)
+        .await
+        .map_err(e500)?;
+
+    personal_data_response(&data)
+}
+
+#[tracing::instrument(
+    name = "Erase personal data on request",
+    skip(link, pool, hmac_secret, email_hash_key, templates)
+)]
+pub async fn erase_own_data(
+    link: web::Form<PrivacyLink>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    email_hash_key: web::Data<EmailHashKey>,
+    templates: web::Data<TemplateOverrides>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if link.verify(&hmac_secret).is_err() {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
+    erase_personal_data(&pool, &email_hash_key, &link.email, "self_service")
+        .await
+        .map_err(e500)?;
+
+    let body = templates.render(&ErasedTemplate).map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// The data as a JSON file download.
+pub(crate) fn personal_data_response(
+    data: &PersonalData,
+) -> Result<HttpResponse, actix_web::Error> {
+    let body = serde_json::to_string_pretty(data).map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("personal-data.json".into())],
+        })
+        .body(body))
+}
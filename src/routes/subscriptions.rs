@@ -1,9 +1,17 @@
 //! src/routes/subscriptions.rs
-use crate::domain::Person;
+use crate::configuration::HmacSecret;
+use crate::domain::{person, ListSubscriptionStatus, Person, SubscriptionStatus};
 use crate::email::Brevo;
+use crate::email_policy::{EmailPolicy, Violation};
+use crate::lists::{get_list_by_slug, insert_list_subscription, List, DEFAULT_LIST};
 use crate::routes::error_chain_fmt;
-use actix_web::{web, HttpResponse, ResponseError, Result};
+use crate::spam::{Rejection, SignupAttempt, SignupProtection};
+use crate::startup::ApplicationBaseUrl;
+use crate::subscribers::{change_status, record_status_event, StatusError};
+use crate::utils::see_other;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError, Result};
 use anyhow::Context;
+use askama::Template;
 use chrono::Utc;
 use rand::Rng;
 use sqlx::{Executor, PgPool, Postgres, Transaction};
@@ -12,7 +20,13 @@ use uuid::Uuid;
 #[derive(thiserror::Error)]
 pub enum SubscribeError {
     #[error("{0}")]
-    ParseError(#[source] Box<dyn std::error::Error>),
+    ParseError(#[source] person::Error),
+    #[error("There is no list named {0}")]
+    UnknownList(String),
+    #[error("{}", .0.message())]
+    Rejected(Rejection),
+    #[error("{}", .0.message())]
+    PolicyViolation(Violation),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -26,7 +40,10 @@ impl std::fmt::Debug for SubscribeError {
 impl ResponseError for SubscribeError {
     fn status_code(&self) -> actix_web::http::StatusCode {
         match self {
-            SubscribeError::ParseError(_) => actix_web::http::StatusCode::BAD_REQUEST,
+            SubscribeError::ParseError(_)
+            | SubscribeError::UnknownList(_)
+            | SubscribeError::PolicyViolation(_) => actix_web::http::StatusCode::BAD_REQUEST,
+            SubscribeError::Rejected(rejection) => rejection.status_code(),
             SubscribeError::UnexpectedError(_) => {
                 actix_web::http::StatusCode::INTERNAL_SERVER_ERROR
             }
@@ -38,11 +55,22 @@ impl ResponseError for SubscribeError {
 pub struct SubscriberForm {
     pub email: String,
     pub name: String,
+    /// Slug of the list to join. Sign-ups without one join the default list.
+    #[serde(default)]
+    pub list: Option<String>,
+    /// The honeypot, see `spam::HONEYPOT_FIELD`.
+    #[serde(default)]
+    pub website: Option<String>,
+    #[serde(default)]
+    pub form_stamp: Option<String>,
+    #[serde(default, alias = "h-captcha-response", alias = "cf-turnstile-response")]
+    pub captcha_token: Option<String>,
 }
 
+#[allow(clippy::too_many_arguments)]
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool),
+    skip(form, pool, email_client, base_url, hmac_secret, protection, email_policy, req),
     fields(
         request_id = %Uuid::new_v4(),
         subscriber_email = %form.email,
@@ -53,21 +81,95 @@ pub async fn subscribe(
     form: web::Form<SubscriberForm>,
     pool: web::Data<PgPool>,
     email_client: web::Data<Brevo>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    protection: web::Data<SignupProtection>,
+    email_policy: web::Data<EmailPolicy>,
+    req: HttpRequest,
 ) -> Result<HttpResponse, SubscribeError> {
-    let subscriber =
-        Person::try_from(form.into_inner()).map_err(|e| SubscribeError::ParseError(e.into()))?;
+    let mut form = form.into_inner();
+    let slug = form
+        .list
+        .clone()
+        .unwrap_or_else(|| DEFAULT_LIST.to_string());
+    let list = get_list_by_slug(&pool, &slug)
+        .await
+        .context("Failed to look up the list")?
+        .ok_or(SubscribeError::UnknownList(slug))?;
+
+    let honeypot = form.website.take();
+    let form_stamp = form.form_stamp.take();
+    let captcha_token = form.captcha_token.take();
+
+    let subscriber = Person::try_from(form).map_err(SubscribeError::ParseError)?;
+
+    let attempt = SignupAttempt {
+        client_ip: protection.client_ip(&req),
+        email: subscriber.email.as_ref(),
+        honeypot: honeypot.as_deref(),
+        form_stamp: form_stamp.as_deref(),
+        requires_stamp: protection.requires_stamp(),
+        captcha_token: captcha_token.as_deref(),
+    };
+    match protection.check(&pool, &hmac_secret, &attempt).await? {
+        None => {}
+        // Bots are answered as if they had signed up, so they don't adapt.
+        Some(Rejection::Honeypot) => return Ok(HttpResponse::Ok().finish()),
+        Some(rejection) => return Err(SubscribeError::Rejected(rejection)),
+    }
+
+    if let Some(violation) = email_policy.check(subscriber.email.as_ref()).await {
+        return Err(SubscribeError::PolicyViolation(violation));
+    }
+
+    add_subscriber(&pool, &email_client, &base_url.0, &subscriber, &list).await?;
+
+    // Forms posting from other sites send people back to a page of their own.
+    match &list.redirect_url {
+        Some(redirect_url) => Ok(see_other(redirect_url)),
+        None => Ok(HttpResponse::Ok().finish()),
+    }
+}
 
+/// Add the subscriber to `list` and send them the opt-in email, shared by the
+/// form and the JSON endpoints.
+/// Returns the status of the membership: signing up again for a list that
+/// was already confirmed changes nothing and sends nothing.
+pub(crate) async fn add_subscriber(
+    pool: &PgPool,
+    email_client: &Brevo,
+    base_url: &str,
+    subscriber: &Person,
+    list: &List,
+) -> Result<ListSubscriptionStatus, anyhow::Error> {
     let mut transaction = pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection")?;
 
-    let id = insert_subscriber(&mut transaction, &subscriber)
+    let (id, status) = insert_subscriber(&mut transaction, subscriber)
         .await
         .context("Failed to insert a new subscriber in the database")?;
 
+    let list_status = insert_list_subscription(
+        &mut transaction,
+        list.list_id,
+        id,
+        ListSubscriptionStatus::PendingConfirmation,
+    )
+    .await
+    .context("Failed to add the subscriber to the list")?;
+
+    if status == SubscriptionStatus::Confirmed && list_status == ListSubscriptionStatus::Confirmed {
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction")?;
+        return Ok(list_status);
+    }
+
     let token = generate_subscription_token();
-    insert_token(&mut transaction, id, &token)
+    insert_token(&mut transaction, id, list.list_id, &token)
         .await
         .context("Failed to insert a new token in the database")?;
 
@@ -76,38 +178,64 @@ pub async fn subscribe(
         .await
         .context("Failed to commit SQL transaction")?;
 
-    send_confirmation_email(&email_client, &subscriber, &token)
+    send_confirmation_email(email_client, subscriber, list, base_url, &token)
         .await
         .context("Failed to send a confirmation email.")?;
 
-    Ok(HttpResponse::Ok().finish())
+    Ok(ListSubscriptionStatus::PendingConfirmation)
 }
 
-#[tracing::instrument(name = "Sending a confirmation email", skip(email_client, subscriber))]
-async fn send_confirmation_email(
+#[derive(Template)]
+#[template(path = "email/confirmation.html")]
+struct ConfirmationEmail<'a> {
+    confirmation_link: &'a str,
+    list_name: &'a str,
+    intro: Option<&'a str>,
+}
+
+/// The email uses the copy and the sender configured for `list`.
+#[tracing::instrument(
+    name = "Sending a confirmation email",
+    skip(email_client, subscriber, list)
+)]
+pub(crate) async fn send_confirmation_email(
     email_client: &Brevo,
     subscriber: &Person,
+    list: &List,
+    base_url: &str,
     token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), anyhow::Error> {
     let confirmation_link = format!(
-        "http://127.0.0.1/subscriptions/confirm?subscription_token={}",
-        token
-    );
-    let html_content = format!(
-        "<p>Thanks for subscribing to our newsletter!</p><br/>Click <a href=\"{}\">here</a> to confirm your subscription.",
-        confirmation_link
+        "{}/subscriptions/confirm?subscription_token={}",
+        base_url, token
     );
+    let html_content = ConfirmationEmail {
+        confirmation_link: &confirmation_link,
+        list_name: &list.name,
+        intro: list.confirmation_html.as_deref(),
+    }
+    .render()
+    .context("Failed to render the confirmation email")?;
 
-    let email = email_client
-        .email_builder()
+    let sender = list.sender();
+    let mut email = email_client.email_builder();
+    if let Some(sender) = &sender {
+        email = email.sender(sender);
+    }
+    let email = email
         .to(subscriber)
-        .subject("Welcome!")
+        .subject(list.confirmation_subject())
         .html_content(&html_content)
         .build();
 
-    email_client.send_email(&email).await
+    email_client.send_email(&email).await?;
+
+    Ok(())
 }
 
+/// Someone signing up with an address we already know keeps their
+/// subscriber record. If they had unsubscribed, they have to confirm again.
+/// Returns the id and the status of the subscriber.
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
     skip(form, transaction)
@@ -115,25 +243,67 @@ async fn send_confirmation_email(
 async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     form: &Person,
-) -> Result<Uuid, sqlx::Error> {
-    let id = Uuid::new_v4();
-
-    let query = sqlx::query!(
+) -> Result<(Uuid, SubscriptionStatus), StatusError> {
+    let inserted = sqlx::query!(
         r#"
-    INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-    VALUES ($1, $2, $3, $4, 'pending_confirmation')
-            "#,
-        id,
+        INSERT INTO subscriptions (id, email, email_key, name, subscribed_at, status)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (email_key) DO NOTHING
+        RETURNING id
+        "#,
+        Uuid::new_v4(),
         form.email.as_ref(),
+        form.email.key(),
         form.name.as_ref(),
-        Utc::now()
-    );
-    transaction.execute(query).await?;
+        Utc::now(),
+        SubscriptionStatus::PendingConfirmation as SubscriptionStatus
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
 
-    Ok(id)
+    if let Some(row) = inserted {
+        let status = SubscriptionStatus::PendingConfirmation;
+        record_status_event(transaction, row.id, None, status, "signup").await?;
+        return Ok((row.id, status));
+    }
+
+    let existing = sqlx::query!(
+        r#"
+        SELECT id, status AS "status: SubscriptionStatus"
+        FROM subscriptions
+        WHERE email_key = $1
+        "#,
+        form.email.key()
+    )
+    .fetch_one(&mut **transaction)
+    .await?;
+
+    if existing.status != SubscriptionStatus::Unsubscribed {
+        return Ok((existing.id, existing.status));
+    }
+
+    change_status(
+        transaction,
+        existing.id,
+        SubscriptionStatus::PendingConfirmation,
+        "signup",
+    )
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET unsubscribed_at = NULL, unsubscribe_reason = NULL
+        WHERE id = $1
+        "#,
+        existing.id
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok((existing.id, SubscriptionStatus::PendingConfirmation))
 }
 
-fn generate_subscription_token() -> String {
+pub(crate) fn generate_subscription_token() -> String {
     let mut rng = rand::thread_rng();
     std::iter::repeat_with(|| rng.sample(rand::distributions::Alphanumeric))
         .map(char::from)
@@ -145,18 +315,21 @@ fn generate_subscription_token() -> String {
     name = "Saving new subscription token in the database",
     skip(transaction)
 )]
-async fn insert_token(
+pub(crate) async fn insert_token(
     transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
+    list_id: Uuid,
     token: &str,
 ) -> Result<(), sqlx::Error> {
     let query = sqlx::query!(
         r#"
-    INSERT INTO subscription_tokens (subscription_token, subscriber_id)
-    VALUES ($1, $2)
+    INSERT INTO subscription_tokens (subscription_token, subscriber_id, list_id, created_at)
+    VALUES ($1, $2, $3, $4)
             "#,
         token,
-        subscriber_id
+        subscriber_id,
+        list_id,
+        Utc::now()
     );
     transaction.execute(query).await?;
 
@@ -0,0 +1,220 @@
+//! src/routes/subscriptions_api.rs
+//!
+//! The JSON twin of the subscription form, for sites that sign people up
+//! from their own scripts.
+use crate::configuration::HmacSecret;
+use crate::domain::{ListSubscriptionStatus, Person};
+use crate::email::Brevo;
+use crate::email_policy::EmailPolicy;
+use crate::lists::{get_list_by_slug, List, DEFAULT_LIST};
+use crate::routes::{add_subscriber, error_chain_fmt};
+use crate::spam::{issue_stamp, Rejection, SignupAttempt, SignupProtection};
+use crate::startup::ApplicationBaseUrl;
+use actix_web::error::JsonPayloadError;
+use actix_web::guard::GuardContext;
+use actix_web::http::header::{CacheControl, CacheDirective, CONTENT_TYPE};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Missing fields are reported like empty ones, with a message per field.
+#[derive(serde::Deserialize)]
+pub struct SubscriptionRequest {
+    #[serde(default)]
+    email: String,
+    #[serde(default)]
+    name: String,
+    /// Slug of the list to join. Sign-ups without one join the default list.
+    list: Option<String>,
+    /// The honeypot, see `spam::HONEYPOT_FIELD`.
+    website: Option<String>,
+    form_stamp: Option<String>,
+    #[serde(alias = "h-captcha-response", alias = "cf-turnstile-response")]
+    captcha_token: Option<String>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum SubscribeApiError {
+    #[error("The request body is not a valid subscription")]
+    InvalidBody(#[source] JsonPayloadError),
+    #[error("Some fields are invalid")]
+    ValidationError(Vec<FieldError>),
+    #[error("{}", .0.message())]
+    Rejected(Rejection),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SubscribeApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SubscribeApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SubscribeApiError::InvalidBody(_) | SubscribeApiError::ValidationError(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            SubscribeApiError::Rejected(rejection) => rejection.status_code(),
+            SubscribeApiError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// `{"message": "...", "errors": [{"field": "email", "message": "..."}]}`.
+    /// Unexpected errors are not detailed.
+    fn error_response(&self) -> HttpResponse {
+        let captcha_error;
+        let (message, errors) = match self {
+            SubscribeApiError::InvalidBody(e) => (format!("{}: {}", self, e), &[][..]),
+            SubscribeApiError::ValidationError(errors) => (self.to_string(), &errors[..]),
+            SubscribeApiError::Rejected(Rejection::CaptchaFailed) => {
+                captcha_error = [FieldError {
+                    field: "captcha_token",
+                    message: self.to_string(),
+                }];
+                (self.to_string(), &captcha_error[..])
+            }
+            SubscribeApiError::Rejected(_) => (self.to_string(), &[][..]),
+            SubscribeApiError::UnexpectedError(_) => (
+                "Something went wrong, please try again later".into(),
+                &[][..],
+            ),
+        };
+
+        HttpResponse::build(self.status_code()).json(serde_json::json!({
+            "message": message,
+            "errors": errors,
+        }))
+    }
+}
+
+/// Requests to `/subscriptions` that carry JSON are routed to `subscribe_json`.
+pub fn is_json(ctx: &GuardContext) -> bool {
+    ctx.head()
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"))
+}
+
+/// Malformed JSON is answered in the same shape as the other errors.
+pub fn json_error_handler(error: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    SubscribeApiError::InvalidBody(error).into()
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Adding a new subscriber from the API",
+    skip(payload, pool, email_client, base_url, hmac_secret, protection, email_policy, req),
+    fields(
+        request_id = %Uuid::new_v4(),
+        subscriber_email = %payload.email,
+        subscriber_name = %payload.name
+    )
+)]
+pub async fn subscribe_json(
+    payload: web::Json<SubscriptionRequest>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<Brevo>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    protection: web::Data<SignupProtection>,
+    email_policy: web::Data<EmailPolicy>,
+    req: HttpRequest,
+) -> Result<HttpResponse, SubscribeApiError> {
+    let SubscriptionRequest {
+        email,
+        name,
+        list,
+        website,
+        form_stamp,
+        captcha_token,
+    } = payload.into_inner();
+
+    let mut errors = Vec::new();
+
+    let subscriber = match Person::parse_each(name, email) {
+        Ok(subscriber) => Some(subscriber),
+        Err(e) => {
+            errors.extend(e.iter().map(|e| FieldError {
+                field: e.field(),
+                message: e.to_string(),
+            }));
+            None
+        }
+    };
+
+    let slug = list.unwrap_or_else(|| DEFAULT_LIST.to_string());
+    let list = get_list_by_slug(&pool, &slug)
+        .await
+        .context("Failed to look up the list")?;
+    if list.is_none() {
+        errors.push(FieldError {
+            field: "list",
+            message: format!("There is no list named {}", slug),
+        });
+    }
+
+    let (subscriber, list) = match (subscriber, list) {
+        (Some(subscriber), Some(list)) => (subscriber, list),
+        _ => return Err(SubscribeApiError::ValidationError(errors)),
+    };
+
+    let attempt = SignupAttempt {
+        client_ip: protection.client_ip(&req),
+        email: subscriber.email.as_ref(),
+        honeypot: website.as_deref(),
+        form_stamp: form_stamp.as_deref(),
+        requires_stamp: protection.requires_stamp(),
+        captcha_token: captcha_token.as_deref(),
+    };
+    match protection.check(&pool, &hmac_secret, &attempt).await? {
+        None => {}
+        // Bots are answered as if they had signed up, so they don't adapt.
+        Some(Rejection::Honeypot) => {
+            return Ok(subscribed(
+                &subscriber,
+                &list,
+                ListSubscriptionStatus::PendingConfirmation,
+            ))
+        }
+        Some(rejection) => return Err(SubscribeApiError::Rejected(rejection)),
+    }
+
+    if let Some(violation) = email_policy.check(subscriber.email.as_ref()).await {
+        return Err(SubscribeApiError::ValidationError(vec![FieldError {
+            field: "email",
+            message: violation.message().to_string(),
+        }]));
+    }
+
+    let status = add_subscriber(&pool, &email_client, &base_url.0, &subscriber, &list).await?;
+
+    Ok(subscribed(&subscriber, &list, status))
+}
+
+fn subscribed(subscriber: &Person, list: &List, status: ListSubscriptionStatus) -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({
+        "email": subscriber.email.as_ref(),
+        "list": list.slug,
+        "status": status,
+    }))
+}
+
+/// A fresh stamp for forms other sites render themselves. Plain form posts
+/// to `/subscriptions` are rejected without one.
+pub async fn form_stamp(hmac_secret: web::Data<HmacSecret>) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(serde_json::json!({ "form_stamp": issue_stamp(&hmac_secret) }))
+}
@@ -1,5 +1,7 @@
 //! src/routes/subscriptions_confirm.rs
 
+use crate::domain::{ListSubscriptionStatus, SubscriptionStatus};
+use crate::subscribers::{change_status, StatusError};
 use actix_web::{web, HttpResponse};
 use serde::Deserialize;
 use uuid::Uuid;
@@ -18,13 +20,13 @@ pub async fn confirm(
     tracing::info!("{:#?}", params);
     let result = get_subscriber_id(&pool, &params.subscription_token).await;
 
-    let subscriber_id = match result {
-        Ok(Some(subscriber_id)) => subscriber_id,
+    let (subscriber_id, list_id) = match result {
+        Ok(Some(ids)) => ids,
         Ok(None) => return HttpResponse::BadRequest().finish(),
         Err(_) => return HttpResponse::InternalServerError().finish(),
     };
 
-    let result = confirm_subscriber(&pool, subscriber_id).await;
+    let result = confirm_subscriber(&pool, subscriber_id, list_id).await;
 
     if result.is_err() {
         return HttpResponse::InternalServerError().finish();
@@ -33,13 +35,14 @@ pub async fn confirm(
     HttpResponse::Ok().finish()
 }
 
+/// The subscriber and the list the token was issued for.
 async fn get_subscriber_id(
     pool: &sqlx::PgPool,
     subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
+) -> Result<Option<(Uuid, Uuid)>, sqlx::Error> {
     sqlx::query!(
         r#"
-        SELECT subscriber_id
+        SELECT subscriber_id, list_id
         FROM subscription_tokens
         WHERE subscription_token = $1
         "#,
@@ -47,19 +50,46 @@ async fn get_subscriber_id(
     )
     .fetch_optional(pool)
     .await
-    .map(|row| row.map(|r| r.subscriber_id))
+    .map(|row| row.map(|r| (r.subscriber_id, r.list_id)))
 }
 
-async fn confirm_subscriber(pool: &sqlx::PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+/// Double opt-in is per list. Confirming the first list also confirms the
+/// subscriber.
+async fn confirm_subscriber(
+    pool: &sqlx::PgPool,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), StatusError> {
+    let mut transaction = pool.begin().await?;
+
     sqlx::query!(
         r#"
-        UPDATE subscriptions
-        SET status = 'confirmed'
-        WHERE id = $1 AND status = 'pending_confirmation'
+        UPDATE list_subscriptions
+        SET status = $3, confirmed_at = now()
+        WHERE list_id = $1 AND subscriber_id = $2 AND status = $4
         "#,
-        subscriber_id
+        list_id,
+        subscriber_id,
+        ListSubscriptionStatus::Confirmed as ListSubscriptionStatus,
+        ListSubscriptionStatus::PendingConfirmation as ListSubscriptionStatus
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    match change_status(
+        &mut transaction,
+        subscriber_id,
+        SubscriptionStatus::Confirmed,
+        "confirmation_link",
     )
-    .execute(pool)
     .await
-    .map(|_| ())
+    {
+        // Following the link again, or to join another list, is fine.
+        Ok(_) | Err(StatusError::IllegalTransition(_)) => {}
+        Err(e) => return Err(e),
+    }
+
+    transaction.commit().await?;
+
+    Ok(())
 }
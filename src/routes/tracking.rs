@@ -0,0 +1,74 @@
+//! src/routes/tracking.rs
+use crate::configuration::HmacSecret;
+use crate::tracking::{record_engagement, verify_token, EngagementKind, TrackedEvent};
+use actix_web::http::header::{CacheControl, CacheDirective, LOCATION, USER_AGENT};
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::PgPool;
+
+/// A transparent 1x1 GIF.
+const PIXEL: [u8; 43] = [
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+/// Failing to record an event must not keep anyone from their link, so
+/// errors are only logged.
+async fn record(pool: &PgPool, kind: EngagementKind, event: &TrackedEvent, req: &HttpRequest) {
+    let user_agent = req
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|value| value.to_str().ok());
+
+    if let Err(e) = record_engagement(pool, kind, event, user_agent).await {
+        tracing::error!(error.cause_chain = ?e, "Failed to record the engagement");
+    }
+}
+
+/// The image is served whatever the token, so that a bad one doesn't show
+/// as a broken image.
+#[tracing::instrument(name = "GET /t/o/{token}", skip(token, req, pool, hmac_secret))]
+pub async fn track_open(
+    token: web::Path<String>,
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    if let Ok(event) = verify_token(EngagementKind::Open, &token, &hmac_secret) {
+        record(&pool, EngagementKind::Open, &event, &req).await;
+    }
+
+    HttpResponse::Ok()
+        .content_type("image/gif")
+        .insert_header(CacheControl(vec![
+            CacheDirective::NoStore,
+            CacheDirective::MaxAge(0),
+        ]))
+        .body(PIXEL.as_slice())
+}
+
+/// Only links we signed are followed, so this can't be used to send people
+/// elsewhere in our name.
+#[tracing::instrument(name = "GET /t/c/{token}", skip(token, req, pool, hmac_secret))]
+pub async fn track_click(
+    token: web::Path<String>,
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    let event = match verify_token(EngagementKind::Click, &token, &hmac_secret) {
+        Ok(event) => event,
+        Err(_) => return HttpResponse::NotFound().finish(),
+    };
+    let url = match event.url.as_deref() {
+        Some(url) if url.starts_with("http://") || url.starts_with("https://") => url.to_string(),
+        _ => return HttpResponse::NotFound().finish(),
+    };
+
+    record(&pool, EngagementKind::Click, &event, &req).await;
+
+    HttpResponse::Found()
+        .insert_header((LOCATION, url))
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .finish()
+}
@@ -0,0 +1,71 @@
+//! src/routes/webhooks.rs
+use crate::bounces::record_feedback;
+use crate::configuration::EmailHashKey;
+use crate::routes::error_chain_fmt;
+use crate::webhooks::{parse_brevo_events, BrevoWebhook};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use sqlx::PgPool;
+
+#[derive(thiserror::Error)]
+pub enum WebhookError {
+    #[error("The webhook is not configured")]
+    NotConfigured,
+    #[error("Authentication failed")]
+    AuthError(#[source] anyhow::Error),
+    #[error("The body is not a webhook payload")]
+    ValidationError(#[source] serde_json::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for WebhookError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            WebhookError::NotConfigured => StatusCode::NOT_FOUND,
+            WebhookError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            WebhookError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            WebhookError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Brevo retries deliveries that don't get a 2xx, so events applied before
+/// are acknowledged like new ones.
+#[tracing::instrument(
+    name = "POST /webhooks/brevo",
+    skip(req, body, pool, email_hash_key, webhook)
+)]
+pub async fn receive_brevo_events(
+    req: HttpRequest,
+    body: web::Bytes,
+    pool: web::Data<PgPool>,
+    email_hash_key: web::Data<EmailHashKey>,
+    webhook: web::Data<Option<BrevoWebhook>>,
+) -> Result<HttpResponse, WebhookError> {
+    let webhook = match webhook.get_ref() {
+        Some(webhook) => webhook,
+        None => return Err(WebhookError::NotConfigured),
+    };
+    webhook
+        .authenticate(&req)
+        .map_err(WebhookError::AuthError)?;
+
+    let events = parse_brevo_events(&body).map_err(WebhookError::ValidationError)?;
+    for feedback in &events {
+        if !record_feedback(&pool, &email_hash_key, "brevo", feedback).await? {
+            tracing::info!(
+                event_key = %feedback.event_key,
+                "Ignoring a Brevo event that was already applied"
+            );
+        }
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,218 @@
+//! src/routes/widget.rs
+//!
+//! The sign-up form other sites embed: a script that inserts an iframe
+//! showing a hosted form for one list.
+use crate::configuration::{CaptchaSettings, HmacSecret};
+use crate::domain::Person;
+use crate::email::Brevo;
+use crate::email_policy::EmailPolicy;
+use crate::lists::{get_list_by_slug, List};
+use crate::routes::add_subscriber;
+use crate::spam::{issue_stamp, Rejection, SignupAttempt, SignupProtection};
+use crate::startup::{AllowedOrigins, ApplicationBaseUrl};
+use crate::utils::e500;
+use actix_web::http::header::{ContentType, CONTENT_SECURITY_POLICY};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use askama::Template;
+use sqlx::PgPool;
+
+#[derive(Template)]
+#[template(path = "widget/widget.js", escape = "none")]
+struct ScriptTemplate {
+    /// A JavaScript string literal, quotes included.
+    base_url: String,
+}
+
+#[derive(Template)]
+#[template(path = "widget/form.html")]
+struct FormTemplate<'a> {
+    list: &'a List,
+    name: &'a str,
+    email: &'a str,
+    errors: Vec<String>,
+    subscribed: bool,
+    form_stamp: String,
+    captcha: Option<&'a CaptchaSettings>,
+}
+
+/// `<script src=".../widget.js" data-list="slug" async></script>` replaces
+/// itself with the list's sign-up form.
+pub async fn widget_script(
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let body = ScriptTemplate {
+        base_url: serde_json::to_string(&base_url.0).map_err(e500)?,
+    }
+    .render()
+    .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/javascript; charset=utf-8")
+        .body(body))
+}
+
+/// Only the allowed origins may show the form in a frame.
+fn form_response(
+    status: StatusCode,
+    allowed_origins: &AllowedOrigins,
+    template: FormTemplate<'_>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let body = template.render().map_err(e500)?;
+    let frame_ancestors = std::iter::once("'self'")
+        .chain(allowed_origins.0.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    Ok(HttpResponse::build(status)
+        .content_type(ContentType::html())
+        .insert_header((
+            CONTENT_SECURITY_POLICY,
+            format!("frame-ancestors {}", frame_ancestors),
+        ))
+        .body(body))
+}
+
+#[tracing::instrument(
+    name = "GET /widget/{slug}",
+    skip(pool, allowed_origins, hmac_secret, protection)
+)]
+pub async fn widget_form(
+    slug: web::Path<String>,
+    pool: web::Data<PgPool>,
+    allowed_origins: web::Data<AllowedOrigins>,
+    hmac_secret: web::Data<HmacSecret>,
+    protection: web::Data<SignupProtection>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let list = match get_list_by_slug(&pool, &slug).await.map_err(e500)? {
+        Some(list) => list,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let template = FormTemplate {
+        list: &list,
+        name: "",
+        email: "",
+        errors: Vec::new(),
+        subscribed: false,
+        form_stamp: issue_stamp(&hmac_secret),
+        captcha: protection.captcha(),
+    };
+    form_response(StatusCode::OK, &allowed_origins, template)
+}
+
+#[derive(serde::Deserialize)]
+pub struct WidgetForm {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    email: String,
+    #[serde(default)]
+    website: Option<String>,
+    #[serde(default)]
+    form_stamp: Option<String>,
+    #[serde(default, alias = "h-captcha-response", alias = "cf-turnstile-response")]
+    captcha_token: Option<String>,
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Adding a new subscriber from the widget",
+    skip(
+        form,
+        pool,
+        email_client,
+        base_url,
+        allowed_origins,
+        hmac_secret,
+        protection,
+        email_policy,
+        req
+    )
+)]
+pub async fn widget_subscribe(
+    slug: web::Path<String>,
+    form: web::Form<WidgetForm>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<Brevo>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    allowed_origins: web::Data<AllowedOrigins>,
+    hmac_secret: web::Data<HmacSecret>,
+    protection: web::Data<SignupProtection>,
+    email_policy: web::Data<EmailPolicy>,
+    req: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let list = match get_list_by_slug(&pool, &slug).await.map_err(e500)? {
+        Some(list) => list,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    // The values are shown again so a typo doesn't mean typing everything anew.
+    let subscriber = match Person::parse_each(form.name.clone(), form.email.clone()) {
+        Ok(subscriber) => subscriber,
+        Err(errors) => {
+            let template = FormTemplate {
+                list: &list,
+                name: &form.name,
+                email: &form.email,
+                errors: errors.iter().map(ToString::to_string).collect(),
+                subscribed: false,
+                form_stamp: issue_stamp(&hmac_secret),
+                captcha: protection.captcha(),
+            };
+            return form_response(StatusCode::BAD_REQUEST, &allowed_origins, template);
+        }
+    };
+
+    let form_with_error = |message: &str| FormTemplate {
+        list: &list,
+        name: &form.name,
+        email: &form.email,
+        errors: vec![message.to_string()],
+        subscribed: false,
+        form_stamp: issue_stamp(&hmac_secret),
+        captcha: protection.captcha(),
+    };
+
+    // Every form we serve carries a stamp, so one missing here means the
+    // post didn't come from our form.
+    let attempt = SignupAttempt {
+        client_ip: protection.client_ip(&req),
+        email: subscriber.email.as_ref(),
+        honeypot: form.website.as_deref(),
+        form_stamp: form.form_stamp.as_deref(),
+        requires_stamp: true,
+        captcha_token: form.captcha_token.as_deref(),
+    };
+    match protection
+        .check(&pool, &hmac_secret, &attempt)
+        .await
+        .map_err(e500)?
+    {
+        None => {
+            if let Some(violation) = email_policy.check(subscriber.email.as_ref()).await {
+                let template = form_with_error(violation.message());
+                return form_response(StatusCode::BAD_REQUEST, &allowed_origins, template);
+            }
+            add_subscriber(&pool, &email_client, &base_url.0, &subscriber, &list)
+                .await
+                .map_err(e500)?;
+        }
+        Some(Rejection::Honeypot) => {}
+        Some(rejection) => {
+            let template = form_with_error(rejection.message());
+            return form_response(rejection.status_code(), &allowed_origins, template);
+        }
+    }
+
+    let template = FormTemplate {
+        list: &list,
+        name: "",
+        email: "",
+        errors: Vec::new(),
+        subscribed: true,
+        form_stamp: String::new(),
+        captcha: None,
+    };
+    form_response(StatusCode::OK, &allowed_origins, template)
+}
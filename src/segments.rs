@@ -0,0 +1,552 @@
+//! src/segments.rs
+//!
+//! Segments restrict an issue to the subscribers matching a condition, e.g.
+//!
+//! ```text
+//! tag = customer AND field.region = "Europe" AND signed_up >= 2024-01-01
+//! ```
+//!
+//! Conditions compare an attribute with a value using `=`, `!=`, `<`, `<=`,
+//! `>` or `>=`, and are combined with `AND`, `OR` and parentheses. `AND` binds
+//! tighter than `OR`. The attributes are:
+//!
+//! - `status`: a subscription status, e.g. `confirmed` or `unsubscribed`.
+//! - `tag`: `tag = vip` matches subscribers tagged `vip`, `tag != vip` those
+//!   who are not.
+//! - `signed_up`: the day the subscriber signed up, as `YYYY-MM-DD`.
+//! - `deliveries`: how many issues the subscriber has received.
+//! - `field.<name>`: a custom field, compared according to its type.
+//!   Subscribers without a value for the field never match.
+use crate::attributes::{CustomField, FieldType, FieldValue};
+use chrono::{DateTime, NaiveDate, Utc};
+use sqlx::PgPool;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+#[error("{0}")]
+pub struct SegmentError(String);
+
+fn error<T>(message: impl Into<String>) -> Result<T, SegmentError> {
+    Err(SegmentError(message.into()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    fn holds(&self, ordering: Option<Ordering>) -> bool {
+        match ordering {
+            Some(ordering) => match self {
+                Op::Eq => ordering == Ordering::Equal,
+                Op::Ne => ordering != Ordering::Equal,
+                Op::Lt => ordering == Ordering::Less,
+                Op::Le => ordering != Ordering::Greater,
+                Op::Gt => ordering == Ordering::Greater,
+                Op::Ge => ordering != Ordering::Less,
+            },
+            None => false,
+        }
+    }
+
+    fn is_equality(&self) -> bool {
+        matches!(self, Op::Eq | Op::Ne)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Op(Op),
+    Word(String),
+    Quoted(String),
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, SegmentError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '=' | '!' | '<' | '>' => {
+                chars.next();
+                let or_equal = chars.next_if_eq(&'=').is_some();
+                let op = match (c, or_equal) {
+                    ('=', false) => Op::Eq,
+                    ('!', true) => Op::Ne,
+                    ('<', false) => Op::Lt,
+                    ('<', true) => Op::Le,
+                    ('>', false) => Op::Gt,
+                    ('>', true) => Op::Ge,
+                    _ => return error(format!("Unexpected {}", c)),
+                };
+                tokens.push(Token::Op(op));
+            }
+            '"' => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(escaped) => value.push(escaped),
+                            None => return error("A quoted value is not closed"),
+                        },
+                        Some(c) => value.push(c),
+                        None => return error("A quoted value is not closed"),
+                    }
+                }
+                tokens.push(Token::Quoted(value));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(c) =
+                    chars.next_if(|c| c.is_alphanumeric() || matches!(c, '_' | '.' | '-' | ':'))
+                {
+                    word.push(c);
+                }
+                if word.is_empty() {
+                    return error(format!("Unexpected {}", c));
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Condition {
+    Status {
+        op: Op,
+        status: String,
+    },
+    Tag {
+        op: Op,
+        tag: String,
+    },
+    SignedUp {
+        op: Op,
+        date: NaiveDate,
+    },
+    Deliveries {
+        op: Op,
+        count: f64,
+    },
+    Field {
+        name: String,
+        op: Op,
+        value: FieldValue,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+    Condition(Condition),
+}
+
+/// A parsed and checked segment definition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    source: String,
+    expr: Expr,
+}
+
+impl Segment {
+    /// Fields are looked up in `fields` so that values can be checked against
+    /// their type.
+    pub fn parse(source: &str, fields: &[CustomField]) -> Result<Self, SegmentError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+            fields,
+        };
+
+        let expr = parser.or()?;
+        if parser.position < tokens.len() {
+            return error("Unexpected input after the end of the segment");
+        }
+
+        Ok(Self {
+            source: source.trim().to_string(),
+            expr,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    pub fn matches(&self, profile: &SubscriberProfile) -> bool {
+        self.expr.matches(profile)
+    }
+}
+
+impl Expr {
+    fn matches(&self, profile: &SubscriberProfile) -> bool {
+        match self {
+            Expr::And(exprs) => exprs.iter().all(|expr| expr.matches(profile)),
+            Expr::Or(exprs) => exprs.iter().any(|expr| expr.matches(profile)),
+            Expr::Condition(condition) => condition.matches(profile),
+        }
+    }
+}
+
+impl Condition {
+    fn matches(&self, profile: &SubscriberProfile) -> bool {
+        match self {
+            Condition::Status { op, status } => {
+                op.holds(Some(profile.status.as_str().cmp(status.as_str())))
+            }
+            Condition::Tag { op, tag } => match op {
+                Op::Eq => profile.tags.contains(tag),
+                _ => !profile.tags.contains(tag),
+            },
+            Condition::SignedUp { op, date } => {
+                op.holds(Some(profile.subscribed_at.date_naive().cmp(date)))
+            }
+            Condition::Deliveries { op, count } => {
+                op.holds((profile.deliveries as f64).partial_cmp(count))
+            }
+            Condition::Field { name, op, value } => match profile.fields.get(name) {
+                Some(actual) => op.holds(actual.partial_cmp(value)),
+                None => false,
+            },
+        }
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    fields: &'a [CustomField],
+}
+
+impl Parser<'_> {
+    fn advance(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.position);
+        self.position += 1;
+        token
+    }
+
+    fn next_is_keyword(&self, keyword: &str) -> bool {
+        matches!(
+            self.tokens.get(self.position),
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword)
+        )
+    }
+
+    fn or(&mut self) -> Result<Expr, SegmentError> {
+        let mut exprs = vec![self.and()?];
+        while self.next_is_keyword("or") {
+            self.position += 1;
+            exprs.push(self.and()?);
+        }
+
+        Ok(match exprs.len() {
+            1 => exprs.remove(0),
+            _ => Expr::Or(exprs),
+        })
+    }
+
+    fn and(&mut self) -> Result<Expr, SegmentError> {
+        let mut exprs = vec![self.operand()?];
+        while self.next_is_keyword("and") {
+            self.position += 1;
+            exprs.push(self.operand()?);
+        }
+
+        Ok(match exprs.len() {
+            1 => exprs.remove(0),
+            _ => Expr::And(exprs),
+        })
+    }
+
+    fn operand(&mut self) -> Result<Expr, SegmentError> {
+        match self.advance().cloned() {
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                match self.advance() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => error("A parenthesis is not closed"),
+                }
+            }
+            Some(Token::Word(attribute)) => self.condition(&attribute).map(Expr::Condition),
+            Some(_) => error("Expected a condition"),
+            None => error("The segment is incomplete"),
+        }
+    }
+
+    fn condition(&mut self, attribute: &str) -> Result<Condition, SegmentError> {
+        let op = match self.advance() {
+            Some(Token::Op(op)) => *op,
+            _ => return error(format!("Expected a comparison after {}", attribute)),
+        };
+        let value = match self.advance() {
+            Some(Token::Word(value) | Token::Quoted(value)) => value.clone(),
+            _ => return error(format!("Expected a value after {}", attribute)),
+        };
+
+        let attribute = attribute.to_lowercase();
+        match attribute.as_str() {
+            "status" if op.is_equality() => Ok(Condition::Status { op, status: value }),
+            "tag" if op.is_equality() => Ok(Condition::Tag {
+                op,
+                tag: value.to_lowercase(),
+            }),
+            "status" | "tag" => error(format!("{} can only be compared with = or !=", attribute)),
+            "signed_up" => match FieldType::Date.parse_value(&value) {
+                Ok(FieldValue::Date(date)) => Ok(Condition::SignedUp { op, date }),
+                _ => error(format!("{} is not a date like 2024-01-31", value)),
+            },
+            "deliveries" => match FieldType::Number.parse_value(&value) {
+                Ok(FieldValue::Number(count)) => Ok(Condition::Deliveries { op, count }),
+                _ => error(format!("{} is not a number", value)),
+            },
+            _ => {
+                let name = match attribute.strip_prefix("field.") {
+                    Some(name) => name,
+                    None => return error(format!("{} is not something to compare", attribute)),
+                };
+                let field = match self.fields.iter().find(|field| field.name == name) {
+                    Some(field) => field,
+                    None => return error(format!("There is no field named {}", name)),
+                };
+                if field.field_type() == FieldType::Boolean && !op.is_equality() {
+                    return error(format!("{} can only be compared with = or !=", name));
+                }
+                let value = field
+                    .field_type()
+                    .parse_value(&value)
+                    .map_err(SegmentError)?;
+
+                Ok(Condition::Field {
+                    name: field.name.clone(),
+                    op,
+                    value,
+                })
+            }
+        }
+    }
+}
+
+/// What segments are evaluated against.
+#[derive(Debug)]
+pub struct SubscriberProfile {
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub tags: HashSet<String>,
+    /// Keyed by field name.
+    pub fields: HashMap<String, FieldValue>,
+    pub deliveries: i64,
+}
+
+/// Profiles of the given subscribers, keyed by subscriber id.
+#[tracing::instrument(name = "Get subscriber profiles", skip(pool, subscriber_ids))]
+pub async fn get_profiles(
+    pool: &PgPool,
+    subscriber_ids: &[Uuid],
+) -> Result<HashMap<Uuid, SubscriberProfile>, sqlx::Error> {
+    let mut profiles: HashMap<Uuid, SubscriberProfile> = sqlx::query!(
+        r#"
+        SELECT s.id, s.status::text AS "status!", s.subscribed_at,
+            (SELECT COUNT(*) FROM newsletter_issue_deliveries d
+             WHERE d.subscriber_id = s.id) AS "deliveries!"
+        FROM subscriptions s
+        WHERE s.id = ANY($1)
+        "#,
+        subscriber_ids
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| {
+        let profile = SubscriberProfile {
+            status: row.status,
+            subscribed_at: row.subscribed_at,
+            tags: HashSet::new(),
+            fields: HashMap::new(),
+            deliveries: row.deliveries,
+        };
+        (row.id, profile)
+    })
+    .collect();
+
+    let tags = sqlx::query!(
+        "SELECT subscriber_id, tag FROM subscriber_tags WHERE subscriber_id = ANY($1)",
+        subscriber_ids
+    )
+    .fetch_all(pool)
+    .await?;
+    for row in tags {
+        if let Some(profile) = profiles.get_mut(&row.subscriber_id) {
+            profile.tags.insert(row.tag);
+        }
+    }
+
+    let values = sqlx::query!(
+        r#"
+        SELECT v.subscriber_id, f.name, f.field_type, v.value
+        FROM subscriber_field_values v
+        JOIN custom_fields f ON f.field_id = v.field_id
+        WHERE v.subscriber_id = ANY($1)
+        "#,
+        subscriber_ids
+    )
+    .fetch_all(pool)
+    .await?;
+    for row in values {
+        let field_type = FieldType::parse(&row.field_type).unwrap_or(FieldType::String);
+        // Values are checked before they are stored, so this only skips
+        // values left behind by a change of type.
+        if let (Some(profile), Ok(value)) = (
+            profiles.get_mut(&row.subscriber_id),
+            field_type.parse_value(&row.value),
+        ) {
+            profile.fields.insert(row.name, value);
+        }
+    }
+
+    Ok(profiles)
+}
+
+/// Keep the subscribers in `subscriber_ids` that match `segment`.
+pub async fn filter_by_segment(
+    pool: &PgPool,
+    segment: &Segment,
+    subscriber_ids: &[Uuid],
+) -> Result<HashSet<Uuid>, sqlx::Error> {
+    let profiles = get_profiles(pool, subscriber_ids).await?;
+
+    Ok(profiles
+        .into_iter()
+        .filter(|(_, profile)| segment.matches(profile))
+        .map(|(subscriber_id, _)| subscriber_id)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn fields() -> Vec<CustomField> {
+        [
+            ("region", "string"),
+            ("score", "number"),
+            ("vip", "boolean"),
+        ]
+        .into_iter()
+        .map(|(name, field_type)| CustomField {
+            field_id: Uuid::new_v4(),
+            name: name.into(),
+            field_type: field_type.into(),
+            created_at: Utc::now(),
+        })
+        .collect()
+    }
+
+    fn profile() -> SubscriberProfile {
+        SubscriberProfile {
+            status: "confirmed".into(),
+            subscribed_at: Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap(),
+            tags: HashSet::from(["customer".to_string()]),
+            fields: HashMap::from([
+                ("region".to_string(), FieldValue::String("Europe".into())),
+                ("score".to_string(), FieldValue::Number(7.0)),
+            ]),
+            deliveries: 3,
+        }
+    }
+
+    fn matches(source: &str) -> bool {
+        Segment::parse(source, &fields())
+            .unwrap()
+            .matches(&profile())
+    }
+
+    #[test]
+    fn conditions_are_compared_by_type() {
+        assert!(matches("tag = customer"));
+        assert!(matches("tag != lead"));
+        assert!(matches("status = confirmed"));
+        assert!(matches(r#"field.region = "Europe""#));
+        assert!(matches("field.score > 6.5"));
+        assert!(matches("signed_up >= 2024-01-01"));
+        assert!(matches("deliveries <= 3"));
+        assert!(!matches("signed_up < 2024-01-01"));
+        assert!(!matches("deliveries > 3"));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert!(matches("tag = lead AND status = pending OR tag = customer"));
+        assert!(!matches(
+            "tag = lead AND (status = pending OR tag = customer)"
+        ));
+    }
+
+    #[test]
+    fn the_customers_in_europe_who_signed_up_this_year() {
+        assert!(matches(
+            r#"tag = customer and field.region = "Europe" and signed_up >= 2024-01-01"#
+        ));
+    }
+
+    #[test]
+    fn missing_field_values_never_match() {
+        assert!(!matches("field.vip = true"));
+        assert!(!matches("field.vip != true"));
+    }
+
+    #[test]
+    fn values_must_fit_the_field_type() {
+        assert!(Segment::parse("field.score > many", &fields()).is_err());
+        assert!(Segment::parse("signed_up > yesterday", &fields()).is_err());
+        assert!(Segment::parse("field.vip > true", &fields()).is_err());
+    }
+
+    #[test]
+    fn unknown_attributes_and_fields_are_rejected() {
+        assert!(Segment::parse("age > 30", &fields()).is_err());
+        assert!(Segment::parse("field.age > 30", &fields()).is_err());
+    }
+
+    #[test]
+    fn incomplete_segments_are_rejected() {
+        for source in [
+            "",
+            "tag =",
+            "tag = a AND",
+            "(tag = a",
+            "tag = a)",
+            r#"tag = "a"#,
+        ] {
+            assert!(Segment::parse(source, &fields()).is_err(), "{}", source);
+        }
+    }
+}
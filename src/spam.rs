@@ -0,0 +1,494 @@
+//! src/spam.rs
+//!
+//! Checks run on every sign-up before anything is stored or sent, so bots
+//! can neither fill the subscribers table nor make us mail strangers.
+use crate::configuration::{CaptchaSettings, EmailHashKey, HmacSecret, SpamProtectionSettings};
+use crate::privacy::email_hash;
+use crate::utils::client_ip;
+use actix_web::http::StatusCode;
+use actix_web::HttpRequest;
+use anyhow::Context;
+use chrono::{Duration, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Hidden from people, so only bots fill it in.
+pub const HONEYPOT_FIELD: &str = "website";
+
+/// Attempts are kept this long for the dashboard, then deleted.
+const RETENTION_DAYS: i64 = 7;
+
+/// Why a sign-up was turned away. Recorded with the attempt and logged as
+/// `spam_check`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    Honeypot,
+    MissingStamp,
+    InvalidStamp,
+    ExpiredStamp,
+    ReusedStamp,
+    TooFast,
+    IpRateLimited,
+    DomainRateLimited,
+    CaptchaFailed,
+}
+
+impl Rejection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rejection::Honeypot => "honeypot",
+            Rejection::MissingStamp => "missing_stamp",
+            Rejection::InvalidStamp => "invalid_stamp",
+            Rejection::ExpiredStamp => "expired_stamp",
+            Rejection::ReusedStamp => "reused_stamp",
+            Rejection::TooFast => "too_fast",
+            Rejection::IpRateLimited => "ip_rate_limited",
+            Rejection::DomainRateLimited => "domain_rate_limited",
+            Rejection::CaptchaFailed => "captcha_failed",
+        }
+    }
+
+    /// Shown to whoever sent the sign-up.
+    pub fn message(&self) -> &'static str {
+        match self {
+            Rejection::Honeypot
+            | Rejection::MissingStamp
+            | Rejection::InvalidStamp
+            | Rejection::ReusedStamp => {
+                "The form could not be checked, please reload the page and try again"
+            }
+            Rejection::ExpiredStamp => "The form has expired, please reload the page and try again",
+            Rejection::TooFast => "That was quick! Please wait a moment and try again",
+            Rejection::IpRateLimited | Rejection::DomainRateLimited => {
+                "Too many sign-ups, please try again later"
+            }
+            Rejection::CaptchaFailed => "Please complete the CAPTCHA",
+        }
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Rejection::IpRateLimited | Rejection::DomainRateLimited => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+/// A signed record of when a form was shown, sent back with the form. Each
+/// stamp signs up one person: its random nonce tells apart forms shown in the
+/// same second.
+pub fn issue_stamp(secret: &HmacSecret) -> String {
+    let issued_at = Utc::now().timestamp();
+    let nonce = Uuid::new_v4().simple().to_string();
+    let signature = stamp_mac(issued_at, &nonce, secret).finalize().into_bytes();
+
+    format!("{}.{}.{}", issued_at, nonce, hex::encode(signature))
+}
+
+/// A stamp we issued.
+#[derive(Debug)]
+struct Stamp<'a> {
+    age: Duration,
+    nonce: &'a str,
+}
+
+/// `None` unless the stamp was issued by us.
+fn read_stamp<'a>(stamp: &'a str, secret: &HmacSecret) -> Option<Stamp<'a>> {
+    let mut parts = stamp.splitn(3, '.');
+    let issued_at: i64 = parts.next()?.parse().ok()?;
+    let nonce = parts.next()?;
+    let signature = hex::decode(parts.next()?).ok()?;
+    stamp_mac(issued_at, nonce, secret)
+        .verify_slice(&signature)
+        .ok()?;
+
+    Some(Stamp {
+        age: Utc::now() - Utc.timestamp_opt(issued_at, 0).single()?,
+        nonce,
+    })
+}
+
+fn stamp_mac(issued_at: i64, nonce: &str, secret: &HmacSecret) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(format!("signup-stamp:{}:{}", issued_at, nonce).as_bytes());
+    mac
+}
+
+/// What a sign-up request says about itself.
+#[derive(Debug)]
+pub struct SignupAttempt<'a> {
+    pub client_ip: Option<String>,
+    pub email: &'a str,
+    pub honeypot: Option<&'a str>,
+    pub form_stamp: Option<&'a str>,
+    /// Sign-ups without a stamp are rejected when set. Stamps that are sent
+    /// are always checked.
+    pub requires_stamp: bool,
+    pub captcha_token: Option<&'a str>,
+}
+
+impl SignupAttempt<'_> {
+    fn email_domain(&self) -> String {
+        self.email
+            .rsplit_once('@')
+            .map(|(_, domain)| domain.to_lowercase())
+            .unwrap_or_default()
+    }
+}
+
+/// Checks a token with hCaptcha, Turnstile or anything answering like them.
+#[derive(Debug)]
+pub struct CaptchaVerifier {
+    http_client: Client,
+    verify_url: String,
+    secret_key: Secret<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct Verification {
+    success: bool,
+}
+
+impl CaptchaVerifier {
+    pub fn new(settings: &CaptchaSettings) -> Self {
+        let http_client = Client::builder()
+            .timeout(std::time::Duration::from_secs(10))
+            .build()
+            .expect("Failed to build reqwest::Client");
+        let verify_url = settings
+            .verify_url
+            .clone()
+            .unwrap_or_else(|| settings.provider.verify_url().to_string());
+
+        Self {
+            http_client,
+            verify_url,
+            secret_key: settings.secret_key.clone(),
+        }
+    }
+
+    pub async fn verify(
+        &self,
+        token: &str,
+        client_ip: Option<&str>,
+    ) -> Result<bool, reqwest::Error> {
+        let mut form = vec![
+            ("secret", self.secret_key.expose_secret().as_str()),
+            ("response", token),
+        ];
+        if let Some(client_ip) = client_ip {
+            form.push(("remoteip", client_ip));
+        }
+
+        let verification: Verification = self
+            .http_client
+            .post(&self.verify_url)
+            .form(&form)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(verification.success)
+    }
+}
+
+/// The checks configured for this installation.
+#[derive(Debug)]
+pub struct SignupProtection {
+    settings: SpamProtectionSettings,
+    verifier: Option<CaptchaVerifier>,
+    email_hash_key: EmailHashKey,
+}
+
+impl SignupProtection {
+    /// Attempts and used stamps keep the hash of their address, so erasing
+    /// the address reaches them.
+    pub fn new(settings: SpamProtectionSettings, email_hash_key: EmailHashKey) -> Self {
+        let verifier = settings.captcha.as_ref().map(CaptchaVerifier::new);
+
+        Self {
+            settings,
+            verifier,
+            email_hash_key,
+        }
+    }
+
+    /// Forms render the challenge of this CAPTCHA, if any.
+    pub fn captcha(&self) -> Option<&CaptchaSettings> {
+        self.settings.captcha.as_ref()
+    }
+
+    /// Whether sign-ups through `/subscriptions` and the JSON API need a
+    /// stamp. Off by default, so plain HTML forms on other sites work without
+    /// JavaScript.
+    pub fn requires_stamp(&self) -> bool {
+        self.settings.require_form_stamp
+    }
+
+    pub fn client_ip(&self, req: &HttpRequest) -> Option<String> {
+        client_ip(req, self.settings.trust_forwarded_for)
+    }
+
+    /// Run every check in turn and record the outcome of the attempt.
+    /// Returns the check that failed, if any.
+    #[tracing::instrument(
+        name = "Check sign-up for spam",
+        skip(self, pool, secret, attempt),
+        fields(
+            client_ip = ?attempt.client_ip,
+            email_domain = %attempt.email_domain(),
+            spam_check = tracing::field::Empty
+        )
+    )]
+    pub async fn check(
+        &self,
+        pool: &PgPool,
+        secret: &HmacSecret,
+        attempt: &SignupAttempt<'_>,
+    ) -> Result<Option<Rejection>, anyhow::Error> {
+        let rejection = self.first_failed_check(pool, secret, attempt).await?;
+
+        let outcome = rejection.map_or("accepted", |rejection| rejection.as_str());
+        tracing::Span::current().record("spam_check", outcome);
+        if let Some(rejection) = rejection {
+            tracing::warn!(spam_check = rejection.as_str(), "Turned away a sign-up");
+        }
+
+        let email_hash = email_hash(attempt.email, &self.email_hash_key);
+        record_attempt(pool, attempt, &email_hash, outcome)
+            .await
+            .context("Failed to record the sign-up attempt")?;
+
+        Ok(rejection)
+    }
+
+    /// Cheap checks come first: the CAPTCHA provider is only asked once
+    /// everything else passed.
+    async fn first_failed_check(
+        &self,
+        pool: &PgPool,
+        secret: &HmacSecret,
+        attempt: &SignupAttempt<'_>,
+    ) -> Result<Option<Rejection>, anyhow::Error> {
+        if attempt.honeypot.is_some_and(|value| !value.is_empty()) {
+            return Ok(Some(Rejection::Honeypot));
+        }
+
+        match attempt.form_stamp.filter(|stamp| !stamp.is_empty()) {
+            Some(stamp) => match read_stamp(stamp, secret) {
+                None => return Ok(Some(Rejection::InvalidStamp)),
+                Some(stamp) if stamp.age < Duration::seconds(self.settings.min_submit_seconds) => {
+                    return Ok(Some(Rejection::TooFast))
+                }
+                Some(stamp)
+                    if stamp.age > Duration::seconds(self.settings.max_stamp_age_seconds) =>
+                {
+                    return Ok(Some(Rejection::ExpiredStamp))
+                }
+                Some(stamp) => {
+                    let first_use = use_stamp(
+                        pool,
+                        stamp.nonce,
+                        &email_hash(attempt.email, &self.email_hash_key),
+                        self.settings.max_stamp_age_seconds,
+                    )
+                    .await
+                    .context("Failed to record the form stamp")?;
+                    if !first_use {
+                        return Ok(Some(Rejection::ReusedStamp));
+                    }
+                }
+            },
+            None if attempt.requires_stamp => return Ok(Some(Rejection::MissingStamp)),
+            None => {}
+        }
+
+        let since = Utc::now() - Duration::seconds(self.settings.rate_limit_window_seconds);
+        let counts = sqlx::query!(
+            r#"
+            SELECT
+                count(*) FILTER (WHERE client_ip = $1) AS "from_ip!",
+                count(*) FILTER (WHERE email_domain = $2) AS "to_domain!"
+            FROM signup_attempts
+            WHERE created_at > $3
+            "#,
+            attempt.client_ip,
+            attempt.email_domain(),
+            since
+        )
+        .fetch_one(pool)
+        .await
+        .context("Failed to count recent sign-up attempts")?;
+        if counts.from_ip >= self.settings.max_signups_per_ip {
+            return Ok(Some(Rejection::IpRateLimited));
+        }
+        if counts.to_domain >= self.settings.max_signups_per_domain {
+            return Ok(Some(Rejection::DomainRateLimited));
+        }
+
+        if let Some(verifier) = &self.verifier {
+            let token = match attempt.captcha_token.filter(|token| !token.is_empty()) {
+                Some(token) => token,
+                None => return Ok(Some(Rejection::CaptchaFailed)),
+            };
+            let solved = verifier
+                .verify(token, attempt.client_ip.as_deref())
+                .await
+                .context("Failed to verify the CAPTCHA")?;
+            if !solved {
+                return Ok(Some(Rejection::CaptchaFailed));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Returns `false` if the stamp with this nonce was used before. Used stamps
+/// are forgotten once they would have expired anyway.
+async fn use_stamp(
+    pool: &PgPool,
+    nonce: &str,
+    email_hash: &str,
+    max_age_seconds: i64,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM used_form_stamps WHERE used_at < $1",
+        Utc::now() - Duration::seconds(max_age_seconds)
+    )
+    .execute(pool)
+    .await?;
+
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO used_form_stamps (nonce, used_at, email_hash)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (nonce) DO NOTHING
+        "#,
+        nonce,
+        Utc::now(),
+        email_hash
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+async fn record_attempt(
+    pool: &PgPool,
+    attempt: &SignupAttempt<'_>,
+    email_hash: &str,
+    outcome: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM signup_attempts WHERE created_at < $1",
+        Utc::now() - Duration::days(RETENTION_DAYS)
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO signup_attempts (
+            attempt_id, client_ip, email_domain, outcome, created_at, email_hash
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        Uuid::new_v4(),
+        attempt.client_ip,
+        attempt.email_domain(),
+        outcome,
+        Utc::now(),
+        email_hash
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// How many sign-ups ended each way over the last day, most frequent first.
+#[tracing::instrument(name = "Count sign-up outcomes", skip(pool))]
+pub async fn get_outcome_counts(pool: &PgPool) -> Result<Vec<(String, i64)>, sqlx::Error> {
+    sqlx::query!(
+        r#"
+        SELECT outcome, count(*) AS "count!"
+        FROM signup_attempts
+        WHERE created_at > $1
+        GROUP BY outcome
+        ORDER BY count(*) DESC, outcome
+        "#,
+        Utc::now() - Duration::days(1)
+    )
+    .fetch_all(pool)
+    .await
+    .map(|rows| {
+        rows.into_iter()
+            .map(|row| (row.outcome, row.count))
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret() -> HmacSecret {
+        HmacSecret(Secret::new("a-very-secret-key".into()))
+    }
+
+    #[test]
+    fn stamps_we_issued_are_accepted() {
+        let stamp = issue_stamp(&secret());
+
+        let stamp = read_stamp(&stamp, &secret()).unwrap();
+        assert!(stamp.age < Duration::seconds(5));
+    }
+
+    #[test]
+    fn stamps_issued_in_the_same_second_differ() {
+        let first = issue_stamp(&secret());
+        let second = issue_stamp(&secret());
+
+        assert_ne!(
+            read_stamp(&first, &secret()).unwrap().nonce,
+            read_stamp(&second, &secret()).unwrap().nonce
+        );
+    }
+
+    #[test]
+    fn stamps_with_another_time_are_rejected() {
+        let stamp = issue_stamp(&secret());
+        let (_, rest) = stamp.split_once('.').unwrap();
+        let backdated = format!("{}.{}", Utc::now().timestamp() - 3600, rest);
+
+        assert!(read_stamp(&backdated, &secret()).is_none());
+    }
+
+    #[test]
+    fn stamps_with_another_nonce_are_rejected() {
+        let stamp = issue_stamp(&secret());
+        let (issued_at, rest) = stamp.split_once('.').unwrap();
+        let (_, signature) = rest.split_once('.').unwrap();
+        let replayed = format!("{}.{}.{}", issued_at, Uuid::new_v4().simple(), signature);
+
+        assert!(read_stamp(&replayed, &secret()).is_none());
+    }
+
+    #[test]
+    fn stamps_signed_with_another_secret_are_rejected() {
+        let other = HmacSecret(Secret::new("another-key".into()));
+
+        assert!(read_stamp(&issue_stamp(&other), &secret()).is_none());
+        assert!(read_stamp("not a stamp", &secret()).is_none());
+    }
+}
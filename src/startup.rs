@@ -1,16 +1,35 @@
 //! src/startup.rs
-use crate::configuration::{HmacSecret, Settings};
+use crate::configuration::{EmailHashKey, HmacSecret, Settings};
 use crate::email::Brevo;
-use crate::routes::{admin_dashboard, newsletters};
+use crate::email_policy::EmailPolicy;
 use crate::routes::{
-    change_password, change_password_form, confirm, health_check, home, login, login_form,
-    subscribe,
+    add_admin_suppressions, admin_dashboard, admin_export_subscribers, admin_fields, admin_issues,
+    admin_lists, admin_subscriber, admin_subscribers, admin_suppressions, admin_topics,
+    create_field, create_issue, create_list, create_topic, delete_admin_suppression,
+    delete_subscriber, edit_issue_form, edit_list_form, erase_subscriber, import_errors,
+    import_form, import_report, issue_recipients, issue_report, issue_revision, issue_revisions,
+    new_issue_form, newsletters, publish_issue_draft, rename_subscriber, restore_revision,
+    save_issue, save_list, save_subscriber_attributes, set_issue_visibility, set_subscriber_status,
+    subscriber_data, upload_import,
 };
+use crate::routes::{
+    archive, archive_issue, atom_feed, change_password, change_password_form, confirm,
+    erase_own_data, export_personal_data, form_stamp, health_check, home, is_json,
+    json_error_handler, list_archive, login, login_form, manage_personal_data, pause_subscription,
+    preferences_form, privacy_form, receive_brevo_events, request_privacy_link, rss_feed,
+    save_preferences, subscribe, subscribe_json, track_click, track_open, unsubscribe, widget_form,
+    widget_script, widget_subscribe,
+};
+use crate::spam::SignupProtection;
+use crate::templates::TemplateOverrides;
+use crate::webhooks::BrevoWebhook;
+use actix_cors::Cors;
 use actix_session::storage::RedisSessionStore;
 use actix_session::SessionMiddleware;
 use actix_web::cookie::Key;
 use actix_web::dev::Server;
-use actix_web::{web, App, HttpServer};
+use actix_web::http::header::CONTENT_TYPE;
+use actix_web::{guard, web, App, HttpServer};
 use actix_web_flash_messages::storage::CookieMessageStore;
 use actix_web_flash_messages::FlashMessagesFramework;
 use secrecy::{ExposeSecret, Secret};
@@ -18,6 +37,15 @@ use sqlx::PgPool;
 use std::net::TcpListener;
 use tracing_actix_web::TracingLogger;
 
+/// Absolute URL the application is reachable at, used to build links in emails.
+#[derive(Debug, Clone)]
+pub struct ApplicationBaseUrl(pub String);
+
+/// Origins of the sites allowed to call the subscription API and embed the
+/// sign-up form.
+#[derive(Debug, Clone)]
+pub struct AllowedOrigins(pub Vec<String>);
+
 pub struct Application {
     port: u16,
     server: Server,
@@ -40,34 +68,80 @@ pub async fn build(config: Settings) -> Result<Application, anyhow::Error> {
     let connection = PgPool::connect_lazy(config.database.connection_string().expose_secret())
         .expect("Failed to connect to Postgres.");
 
-    let email_client = Brevo::from(config.email.unwrap());
-
     let hmac_secret = config.application.hmac_secret.expect("Missing HMAC secret");
+    let email_hash_key = config
+        .application
+        .email_hash_key
+        .expect("Missing email hash key");
+
+    let email_client = Brevo::from_settings(
+        config.email.unwrap(),
+        connection.clone(),
+        email_hash_key.clone(),
+    );
 
     let redis_uri = config.application.redis_uri;
 
+    let base_url = ApplicationBaseUrl(config.application.base_url);
+
+    let allowed_origins = AllowedOrigins(config.application.allowed_origins);
+
+    let templates = TemplateOverrides::new(config.application.template_dir.as_deref());
+
+    let protection = SignupProtection::new(config.spam_protection, email_hash_key.clone());
+
+    let email_policy = EmailPolicy::from_settings(config.email_policy)?;
+
+    let brevo_webhook = config
+        .webhooks
+        .brevo
+        .map(BrevoWebhook::from_settings)
+        .transpose()?;
+
     let server = run(
         tcp_listener,
         connection,
         email_client,
         hmac_secret,
+        email_hash_key,
         redis_uri,
+        base_url,
+        allowed_origins,
+        templates,
+        protection,
+        email_policy,
+        brevo_webhook,
     )
     .await?;
 
     Ok(Application { port, server })
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn run(
     listener: TcpListener,
     connection: PgPool,
     email_client: Brevo,
     hmac_secret: HmacSecret,
+    email_hash_key: EmailHashKey,
     redis_uri: Secret<String>,
+    base_url: ApplicationBaseUrl,
+    allowed_origins: AllowedOrigins,
+    templates: TemplateOverrides,
+    protection: SignupProtection,
+    email_policy: EmailPolicy,
+    brevo_webhook: Option<BrevoWebhook>,
 ) -> Result<Server, anyhow::Error> {
     let connection = web::Data::new(connection);
     let email_client = web::Data::new(email_client);
     let hmac_secret = web::Data::new(hmac_secret);
+    let email_hash_key = web::Data::new(email_hash_key);
+    let base_url = web::Data::new(base_url);
+    let allowed_origins = web::Data::new(allowed_origins);
+    let templates = web::Data::new(templates);
+    let protection = web::Data::new(protection);
+    let email_policy = web::Data::new(email_policy);
+    let brevo_webhook = web::Data::new(brevo_webhook);
 
     let secret_key = Key::try_from(hmac_secret.0.expose_secret().as_bytes())
         .expect("Error creating key from HMAC secret");
@@ -85,9 +159,85 @@ pub async fn run(
                 secret_key.clone(),
             ))
             .route("/health_check", web::get().to(health_check))
-            .route("/subscriptions", web::post().to(subscribe))
+            // JSON sign-ups get JSON answers, form posts keep the plain responses
+            .service(
+                web::resource("/subscriptions")
+                    .wrap(cors(&allowed_origins))
+                    .app_data(web::JsonConfig::default().error_handler(json_error_handler))
+                    .route(
+                        web::post()
+                            .guard(guard::fn_guard(is_json))
+                            .to(subscribe_json),
+                    )
+                    .route(web::post().to(subscribe)),
+            )
+            .service(
+                web::resource("/api/v1/subscriptions")
+                    .wrap(cors(&allowed_origins))
+                    .app_data(web::JsonConfig::default().error_handler(json_error_handler))
+                    .route(web::post().to(subscribe_json)),
+            )
+            .service(
+                web::resource("/api/v1/form-stamp")
+                    .wrap(cors(&allowed_origins))
+                    .route(web::get().to(form_stamp)),
+            )
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route("/webhooks/brevo", web::post().to(receive_brevo_events))
             .route("/newsletters", web::post().to(newsletters::publish))
+            .route(
+                "/newsletters/subscribers",
+                web::get().to(newsletters::export_subscribers),
+            )
+            .route(
+                "/newsletters/subscribers/{subscriber_id}/attributes",
+                web::put().to(newsletters::update_subscriber_attributes),
+            )
+            .route(
+                "/newsletters/recipients/count",
+                web::post().to(newsletters::recipient_count),
+            )
+            .route(
+                "/newsletters/issues/{issue_id}/report",
+                web::get().to(newsletters::issue_report),
+            )
+            .route(
+                "/newsletters/suppressions",
+                web::get().to(newsletters::list_suppressions),
+            )
+            .route(
+                "/newsletters/suppressions",
+                web::post().to(newsletters::add_suppressions),
+            )
+            .route(
+                "/newsletters/suppressions/{email}",
+                web::delete().to(newsletters::delete_suppression),
+            )
+            .route("/widget.js", web::get().to(widget_script))
+            .route("/widget/{slug}", web::get().to(widget_form))
+            .route("/widget/{slug}", web::post().to(widget_subscribe))
+            .route("/archive", web::get().to(archive))
+            .route("/archive/{slug}", web::get().to(archive_issue))
+            .route("/lists/{slug}/archive", web::get().to(list_archive))
+            .route("/feed.rss", web::get().to(rss_feed))
+            .route("/feed.atom", web::get().to(atom_feed))
+            .route("/privacy", web::get().to(privacy_form))
+            .route("/privacy", web::post().to(request_privacy_link))
+            .route("/privacy/manage", web::get().to(manage_personal_data))
+            .route("/privacy/export", web::get().to(export_personal_data))
+            .route("/privacy/erase", web::post().to(erase_own_data))
+            .route("/t/o/{token}", web::get().to(track_open))
+            .route("/t/c/{token}", web::get().to(track_click))
+            .route("/preferences/{token}", web::get().to(preferences_form))
+            .route("/preferences/{token}", web::post().to(save_preferences))
+            .route(
+                "/preferences/{token}/pause",
+                web::post().to(pause_subscription),
+            )
+            .route(
+                "/preferences/{token}/unsubscribe",
+                web::post().to(unsubscribe),
+            )
             // serving HTML files
             .route("/", web::get().to(home))
             .route("/login", web::get().to(login_form))
@@ -95,12 +245,124 @@ pub async fn run(
             .route("/admin/dashboard", web::get().to(admin_dashboard))
             .route("/admin/password", web::get().to(change_password_form))
             .route("/admin/password", web::post().to(change_password))
+            .route("/admin/issues", web::get().to(admin_issues))
+            .route("/admin/issues", web::post().to(create_issue))
+            .route("/admin/issues/new", web::get().to(new_issue_form))
+            .route("/admin/issues/{issue_id}", web::get().to(edit_issue_form))
+            .route("/admin/issues/{issue_id}", web::post().to(save_issue))
+            .route(
+                "/admin/issues/{issue_id}/publish",
+                web::post().to(publish_issue_draft),
+            )
+            .route(
+                "/admin/issues/{issue_id}/report",
+                web::get().to(issue_report),
+            )
+            .route(
+                "/admin/issues/{issue_id}/report/recipients.csv",
+                web::get().to(issue_recipients),
+            )
+            .route(
+                "/admin/issues/{issue_id}/revisions",
+                web::get().to(issue_revisions),
+            )
+            .route(
+                "/admin/issues/{issue_id}/revisions/{revision_id}",
+                web::get().to(issue_revision),
+            )
+            .route(
+                "/admin/issues/{issue_id}/revisions/{revision_id}/restore",
+                web::post().to(restore_revision),
+            )
+            .route("/admin/subscribers", web::get().to(admin_subscribers))
+            .route(
+                "/admin/subscribers/export",
+                web::get().to(admin_export_subscribers),
+            )
+            .route("/admin/subscribers/import", web::get().to(import_form))
+            .route("/admin/subscribers/import", web::post().to(upload_import))
+            .route(
+                "/admin/subscribers/imports/{import_id}",
+                web::get().to(import_report),
+            )
+            .route(
+                "/admin/subscribers/imports/{import_id}/errors.csv",
+                web::get().to(import_errors),
+            )
+            .route(
+                "/admin/subscribers/{subscriber_id}",
+                web::get().to(admin_subscriber),
+            )
+            .route(
+                "/admin/subscribers/{subscriber_id}/status",
+                web::post().to(set_subscriber_status),
+            )
+            .route(
+                "/admin/subscribers/{subscriber_id}/name",
+                web::post().to(rename_subscriber),
+            )
+            .route(
+                "/admin/subscribers/{subscriber_id}/attributes",
+                web::post().to(save_subscriber_attributes),
+            )
+            .route(
+                "/admin/subscribers/{subscriber_id}/delete",
+                web::post().to(delete_subscriber),
+            )
+            .route(
+                "/admin/subscribers/{subscriber_id}/data",
+                web::get().to(subscriber_data),
+            )
+            .route(
+                "/admin/subscribers/{subscriber_id}/erase",
+                web::post().to(erase_subscriber),
+            )
+            .route("/admin/lists", web::get().to(admin_lists))
+            .route("/admin/lists", web::post().to(create_list))
+            .route("/admin/lists/{list_id}", web::get().to(edit_list_form))
+            .route("/admin/lists/{list_id}", web::post().to(save_list))
+            .route("/admin/fields", web::get().to(admin_fields))
+            .route("/admin/fields", web::post().to(create_field))
+            .route("/admin/topics", web::get().to(admin_topics))
+            .route("/admin/topics", web::post().to(create_topic))
+            .route("/admin/suppressions", web::get().to(admin_suppressions))
+            .route(
+                "/admin/suppressions",
+                web::post().to(add_admin_suppressions),
+            )
+            .route(
+                "/admin/suppressions/{email_hash}/delete",
+                web::post().to(delete_admin_suppression),
+            )
+            .route(
+                "/admin/issues/{issue_id}/visibility",
+                web::post().to(set_issue_visibility),
+            )
             .app_data(connection.clone())
             .app_data(email_client.clone())
             .app_data(hmac_secret.clone())
+            .app_data(email_hash_key.clone())
+            .app_data(base_url.clone())
+            .app_data(allowed_origins.clone())
+            .app_data(templates.clone())
+            .app_data(protection.clone())
+            .app_data(email_policy.clone())
+            .app_data(brevo_webhook.clone())
     })
     .listen(listener)?
     .run();
 
     Ok(server)
 }
+
+/// Cross-origin requests are only answered for the allowed origins. The
+/// subscription API uses no cookies, so credentials are never allowed.
+fn cors(allowed_origins: &AllowedOrigins) -> Cors {
+    allowed_origins
+        .0
+        .iter()
+        .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+        .allowed_methods(["GET", "POST"])
+        .allowed_header(CONTENT_TYPE)
+        .max_age(3600)
+}
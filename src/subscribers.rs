@@ -0,0 +1,518 @@
+//! src/subscribers.rs
+use crate::configuration::EmailHashKey;
+use crate::domain::{ListSubscriptionStatus, SubscriptionStatus, TransitionError};
+use crate::privacy::email_hash;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+#[derive(Debug)]
+pub struct Subscriber {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: SubscriptionStatus,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub struct SubscriptionToken {
+    pub subscription_token: String,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug)]
+pub struct StatusEvent {
+    pub from_status: Option<SubscriptionStatus>,
+    pub to_status: SubscriptionStatus,
+    pub cause: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub struct Delivery {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub delivered_at: DateTime<Utc>,
+    pub message_id: Option<String>,
+}
+
+/// Every field is optional; an empty filter matches every subscriber.
+#[derive(Debug, Clone, Default)]
+pub struct SubscriberFilter {
+    /// Matched case-insensitively against both the email and the name.
+    pub search: Option<String>,
+    pub status: Option<String>,
+    pub subscribed_from: Option<DateTime<Utc>>,
+    pub subscribed_until: Option<DateTime<Utc>>,
+}
+
+/// Escape the LIKE wildcards so a search for `100%` is taken literally.
+pub(crate) fn like_pattern(search: &str) -> String {
+    let escaped = search
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+/// Newest subscribers first.
+#[tracing::instrument(name = "Search subscribers", skip(pool))]
+pub async fn search_subscribers(
+    pool: &PgPool,
+    filter: &SubscriberFilter,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<Subscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, name, status AS "status: SubscriptionStatus", subscribed_at
+        FROM subscriptions
+        WHERE ($1::text IS NULL OR email ILIKE $1 OR name ILIKE $1)
+            AND ($2::text IS NULL OR status::text = $2)
+            AND ($3::timestamptz IS NULL OR subscribed_at >= $3)
+            AND ($4::timestamptz IS NULL OR subscribed_at < $4)
+        ORDER BY subscribed_at DESC
+        LIMIT $5 OFFSET $6
+        "#,
+        filter.search.as_deref().map(like_pattern),
+        filter.status,
+        filter.subscribed_from,
+        filter.subscribed_until,
+        limit,
+        offset
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[tracing::instrument(name = "Get subscriber", skip(pool))]
+pub async fn get_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<Subscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, name, status AS "status: SubscriptionStatus", subscribed_at
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Newest first. Tokens issued before their creation time was recorded come last.
+#[tracing::instrument(name = "Get subscription tokens", skip(pool))]
+pub async fn get_tokens(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<SubscriptionToken>, sqlx::Error> {
+    sqlx::query_as!(
+        SubscriptionToken,
+        r#"
+        SELECT subscription_token, created_at
+        FROM subscription_tokens
+        WHERE subscriber_id = $1
+        ORDER BY created_at DESC NULLS LAST
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Newest first.
+#[tracing::instrument(name = "Get subscriber deliveries", skip(pool))]
+pub async fn get_deliveries(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<Delivery>, sqlx::Error> {
+    sqlx::query_as!(
+        Delivery,
+        r#"
+        SELECT d.newsletter_issue_id, i.title, d.delivered_at, d.message_id
+        FROM newsletter_issue_deliveries d
+        JOIN newsletter_issues i ON i.newsletter_issue_id = d.newsletter_issue_id
+        WHERE d.subscriber_id = $1
+        ORDER BY d.delivered_at DESC
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// `message_id` and `provider_message_id` identify the email the issue went
+/// out in, which a digest shares with other issues.
+#[tracing::instrument(name = "Record newsletter issue delivery", skip(pool))]
+pub async fn record_delivery(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    message_id: &str,
+    provider_message_id: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_deliveries (
+            newsletter_issue_id, subscriber_id, delivered_at, message_id, provider_message_id
+        )
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id,
+        subscriber_id,
+        Utc::now(),
+        message_id,
+        provider_message_id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// An email carrying the issue that Brevo refused, counted as failed in the
+/// issue report. Nothing is recorded as delivered, and the subscriber is left
+/// as they are: the next issue may well get through.
+#[tracing::instrument(
+    name = "Record failed newsletter issue send",
+    skip(pool, email_hash, reason)
+)]
+pub async fn record_send_failure(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    email_hash: &str,
+    message_id: &str,
+    reason: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO delivery_feedback (
+            feedback_id, source, event_key, kind, email_hash, message_id, reason, received_at,
+            newsletter_issue_id
+        )
+        VALUES ($1, 'send', $2, 'send_failed', $3, $4, $5, $6, $7)
+        ON CONFLICT (source, event_key) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        format!("{}:{}", message_id, newsletter_issue_id),
+        email_hash,
+        message_id,
+        reason,
+        Utc::now(),
+        newsletter_issue_id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum StatusError {
+    #[error("There is no subscriber with id {0}")]
+    UnknownSubscriber(Uuid),
+    #[error(transparent)]
+    IllegalTransition(#[from] TransitionError),
+    #[error("Failed to change the status of the subscriber")]
+    DatabaseError(#[from] sqlx::Error),
+}
+
+/// Move the subscriber to `to`, if their current status allows it, and
+/// record the change with its cause.
+/// Returns the status the subscriber had before.
+#[tracing::instrument(name = "Change subscriber status", skip(transaction))]
+pub async fn change_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    to: SubscriptionStatus,
+    cause: &str,
+) -> Result<SubscriptionStatus, StatusError> {
+    let from = sqlx::query!(
+        r#"
+        SELECT status AS "status: SubscriptionStatus"
+        FROM subscriptions
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await?
+    .ok_or(StatusError::UnknownSubscriber(subscriber_id))?
+    .status;
+    from.transition_to(to)?;
+
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = $2::subscription_status,
+            confirmed_at = CASE WHEN $2::subscription_status = $3 THEN now() ELSE confirmed_at END
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        to as SubscriptionStatus,
+        SubscriptionStatus::Confirmed as SubscriptionStatus
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    record_status_event(transaction, subscriber_id, Some(from), to, cause).await?;
+
+    Ok(from)
+}
+
+/// `from` is `None` for subscribers who were just created.
+#[tracing::instrument(name = "Record subscription event", skip(transaction))]
+pub async fn record_status_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    from: Option<SubscriptionStatus>,
+    to: SubscriptionStatus,
+    cause: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_events (
+            event_id, subscriber_id, from_status, to_status, cause, created_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        Uuid::new_v4(),
+        subscriber_id,
+        from as Option<SubscriptionStatus>,
+        to as SubscriptionStatus,
+        cause,
+        Utc::now()
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// Oldest first.
+#[tracing::instrument(name = "Get subscription events", skip(pool))]
+pub async fn get_status_events(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<StatusEvent>, sqlx::Error> {
+    sqlx::query_as!(
+        StatusEvent,
+        r#"
+        SELECT from_status AS "from_status: SubscriptionStatus",
+            to_status AS "to_status: SubscriptionStatus",
+            cause, created_at
+        FROM subscription_events
+        WHERE subscriber_id = $1
+        ORDER BY created_at, event_id
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Confirming a subscriber also confirms the lists they are waiting to join.
+#[tracing::instrument(name = "Set subscriber status", skip(pool))]
+pub async fn set_status(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    status: SubscriptionStatus,
+    cause: &str,
+) -> Result<(), StatusError> {
+    let mut transaction = pool.begin().await?;
+
+    change_status(&mut transaction, subscriber_id, status, cause).await?;
+
+    if status == SubscriptionStatus::Confirmed {
+        sqlx::query!(
+            r#"
+            UPDATE list_subscriptions
+            SET status = $2, confirmed_at = now()
+            WHERE subscriber_id = $1 AND status = $3
+            "#,
+            subscriber_id,
+            ListSubscriptionStatus::Confirmed as ListSubscriptionStatus,
+            ListSubscriptionStatus::PendingConfirmation as ListSubscriptionStatus
+        )
+        .execute(&mut *transaction)
+        .await?;
+    }
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Returns `false` if no subscriber matches `subscriber_id`.
+#[tracing::instrument(name = "Update subscriber name", skip(pool))]
+pub async fn update_name(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    name: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET name = $2
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        name
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Remove a subscriber together with everything stored about them, as
+/// `delete_subscriber_rows` does.
+/// Returns `false` if no subscriber matches `subscriber_id`.
+#[tracing::instrument(name = "Delete subscriber", skip(pool, email_hash_key))]
+pub async fn delete_subscriber(
+    pool: &PgPool,
+    email_hash_key: &EmailHashKey,
+    subscriber_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let email = match sqlx::query!(
+        "SELECT email FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await?
+    {
+        Some(row) => row.email,
+        None => return Ok(false),
+    };
+
+    delete_subscriber_rows(
+        &mut transaction,
+        &email_hash(&email, email_hash_key),
+        &[subscriber_id],
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(true)
+}
+
+/// Delete the subscribers with their tokens, preferences, list memberships,
+/// deliveries, engagement and status history, and the sign-up attempts and
+/// used form stamps of their address. Delivery feedback stays in the issue
+/// reports, stripped of anything naming the address.
+/// Deleting and erasing both go through here, so a new table holding
+/// subscriber data only needs to be added once.
+/// Returns the number of subscriptions deleted.
+pub(crate) async fn delete_subscriber_rows(
+    transaction: &mut Transaction<'_, Postgres>,
+    email_hash: &str,
+    subscriber_ids: &[Uuid],
+) -> Result<u64, sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE delivery_feedback
+        SET email_hash = NULL, message_id = NULL, reason = NULL,
+            event_key = 'erased:' || feedback_id
+        WHERE email_hash = $1
+            OR message_id IN (
+                SELECT message_id FROM newsletter_issue_deliveries
+                WHERE subscriber_id = ANY($2)
+                UNION
+                SELECT provider_message_id FROM newsletter_issue_deliveries
+                WHERE subscriber_id = ANY($2)
+            )
+        "#,
+        email_hash,
+        subscriber_ids
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM signup_attempts WHERE email_hash = $1",
+        email_hash
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM used_form_stamps WHERE email_hash = $1",
+        email_hash
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = ANY($1)",
+        subscriber_ids
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM newsletter_issue_deliveries WHERE subscriber_id = ANY($1)",
+        subscriber_ids
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM engagement_events WHERE subscriber_id = ANY($1)",
+        subscriber_ids
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM subscriber_topic_opt_outs WHERE subscriber_id = ANY($1)",
+        subscriber_ids
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM list_subscriptions WHERE subscriber_id = ANY($1)",
+        subscriber_ids
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM subscriber_tags WHERE subscriber_id = ANY($1)",
+        subscriber_ids
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM subscriber_field_values WHERE subscriber_id = ANY($1)",
+        subscriber_ids
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM subscription_events WHERE subscriber_id = ANY($1)",
+        subscriber_ids
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM subscriptions WHERE id = ANY($1)",
+        subscriber_ids
+    )
+    .execute(&mut **transaction)
+    .await
+    .map(|result| result.rows_affected())
+}
@@ -0,0 +1,232 @@
+//! src/suppressions.rs
+//!
+//! Addresses that must never be mailed again, whatever put them there: an
+//! erasure request, a hard bounce, a complaint or an admin. Only a hash of
+//! each address is kept.
+use crate::configuration::EmailHashKey;
+use crate::domain::Person;
+use crate::privacy::email_hash;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashSet;
+
+#[derive(Debug, Serialize)]
+pub struct Suppression {
+    pub email_hash: String,
+    pub reason: String,
+    pub source: String,
+    pub created_at: DateTime<Utc>,
+    /// Emails that were not sent because of this entry.
+    pub suppressed_sends: i64,
+    pub last_suppressed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Default)]
+pub struct SuppressionStats {
+    pub entries: i64,
+    pub suppressed_sends: i64,
+    /// Suppressed addresses something tried to email in the last 30 days.
+    pub recently_hit: i64,
+}
+
+/// Newest first. `email` narrows the list down to that address.
+#[tracing::instrument(name = "Get suppressions", skip(pool, email_hash_key, email))]
+pub async fn get_suppressions(
+    pool: &PgPool,
+    email_hash_key: &EmailHashKey,
+    email: Option<&str>,
+    limit: i64,
+) -> Result<Vec<Suppression>, sqlx::Error> {
+    sqlx::query_as!(
+        Suppression,
+        r#"
+        SELECT email_hash, reason, source, created_at, suppressed_sends, last_suppressed_at
+        FROM suppressions
+        WHERE ($1::text IS NULL OR email_hash = $1)
+        ORDER BY created_at DESC
+        LIMIT $2
+        "#,
+        email.map(|email| email_hash(email, email_hash_key)),
+        limit
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[tracing::instrument(name = "Get suppression stats", skip(pool))]
+pub async fn get_suppression_stats(pool: &PgPool) -> Result<SuppressionStats, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT count(*) AS "entries!",
+            COALESCE(sum(suppressed_sends), 0)::bigint AS "suppressed_sends!",
+            count(*) FILTER (
+                WHERE last_suppressed_at > now() - interval '30 days'
+            ) AS "recently_hit!"
+        FROM suppressions
+        "#
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(SuppressionStats {
+        entries: row.entries,
+        suppressed_sends: row.suppressed_sends,
+        recently_hit: row.recently_hit,
+    })
+}
+
+/// Returns `false` if the address was already suppressed, in which case the
+/// existing entry is kept as it is.
+pub async fn insert_suppression(
+    transaction: &mut Transaction<'_, Postgres>,
+    email_hash_key: &EmailHashKey,
+    email: &str,
+    reason: &str,
+    source: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO suppressions (email_hash, reason, source, created_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (email_hash) DO NOTHING
+        "#,
+        email_hash(email, email_hash_key),
+        reason,
+        source,
+        Utc::now()
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// The hashes among `hashes` that belong to suppressed addresses.
+pub async fn suppressed_hashes(
+    pool: &PgPool,
+    hashes: &[String],
+) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query!(
+        "SELECT email_hash FROM suppressions WHERE email_hash = ANY($1)",
+        hashes
+    )
+    .fetch_all(pool)
+    .await
+    .map(|rows| rows.into_iter().map(|row| row.email_hash).collect())
+}
+
+/// What became of a batch of addresses given to `suppress_addresses`.
+#[derive(Debug, Default, Serialize)]
+pub struct SuppressionReport {
+    pub added: usize,
+    pub already_suppressed: usize,
+    /// Entries that don't look like an email address.
+    pub invalid: Vec<String>,
+}
+
+#[tracing::instrument(name = "Suppress addresses", skip(pool, email_hash_key, emails))]
+pub async fn suppress_addresses(
+    pool: &PgPool,
+    email_hash_key: &EmailHashKey,
+    emails: &[String],
+    reason: &str,
+    source: &str,
+) -> Result<SuppressionReport, sqlx::Error> {
+    let mut report = SuppressionReport::default();
+    let mut transaction = pool.begin().await?;
+
+    for email in emails {
+        let email = email.trim();
+        if email.is_empty() {
+            continue;
+        }
+        if !email.contains('@') {
+            report.invalid.push(email.to_string());
+            continue;
+        }
+
+        if insert_suppression(&mut transaction, email_hash_key, email, reason, source).await? {
+            report.added += 1;
+        } else {
+            report.already_suppressed += 1;
+        }
+    }
+
+    transaction.commit().await?;
+
+    Ok(report)
+}
+
+/// The address can be mailed again. Returns `false` if it was not suppressed.
+#[tracing::instrument(name = "Remove suppression", skip(pool))]
+pub async fn remove_suppression(pool: &PgPool, email_hash: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!("DELETE FROM suppressions WHERE email_hash = $1", email_hash)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Consulted by the email client before every send.
+#[derive(Debug, Clone)]
+pub struct SuppressionList {
+    pool: PgPool,
+    email_hash_key: EmailHashKey,
+}
+
+impl SuppressionList {
+    pub fn new(pool: PgPool, email_hash_key: EmailHashKey) -> Self {
+        Self {
+            pool,
+            email_hash_key,
+        }
+    }
+
+    /// The hash this list keeps of `email`.
+    pub fn email_hash(&self, email: &str) -> String {
+        email_hash(email, &self.email_hash_key)
+    }
+
+    /// The recipients that may be mailed. The others are counted against
+    /// their suppression.
+    #[tracing::instrument(name = "Check suppression list", skip(self, recipients))]
+    pub async fn allowed<'a>(
+        &self,
+        recipients: &[&'a Person],
+    ) -> Result<Vec<&'a Person>, sqlx::Error> {
+        let hashes: Vec<String> = recipients
+            .iter()
+            .map(|recipient| self.email_hash(recipient.email.as_ref()))
+            .collect();
+
+        let suppressed: HashSet<String> = sqlx::query!(
+            r#"
+            UPDATE suppressions
+            SET suppressed_sends = suppressed_sends + 1, last_suppressed_at = now()
+            WHERE email_hash = ANY($1)
+            RETURNING email_hash
+            "#,
+            &hashes
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| row.email_hash)
+        .collect();
+
+        if !suppressed.is_empty() {
+            tracing::info!(
+                suppressed = suppressed.len(),
+                "Not sending to suppressed addresses"
+            );
+        }
+
+        Ok(recipients
+            .iter()
+            .zip(&hashes)
+            .filter(|(_, hash)| !suppressed.contains(*hash))
+            .map(|(recipient, _)| *recipient)
+            .collect())
+    }
+}
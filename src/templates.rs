@@ -0,0 +1,64 @@
+//! src/templates.rs
+use minijinja::{path_loader, Environment, ErrorKind};
+use serde::Serialize;
+use std::path::Path;
+
+/// The bundled layout, so overrides can extend it instead of copying it.
+const BASE_LAYOUT: &str = include_str!("../templates/base.html");
+
+/// A page whose template can be replaced from the override directory.
+pub trait Overridable: askama::Template + Serialize {
+    /// Where the override is looked up, relative to the override directory.
+    /// The same as the bundled template's path.
+    const PATH: &'static str;
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum RenderError {
+    #[error("Failed to render the bundled template")]
+    Bundled(#[from] askama::Error),
+    #[error("Failed to render the overriding template")]
+    Override(#[from] minijinja::Error),
+}
+
+/// Templates read at runtime from the directory set in
+/// `application.template_dir`, in place of the bundled ones.
+///
+/// Overrides are Jinja templates, rendered with the same data as the page
+/// they replace. They may extend `base.html`, which is looked up in the
+/// override directory first, then among the bundled templates. Files are
+/// read the first time they are needed: restart to pick up changes.
+pub struct TemplateOverrides {
+    environment: Option<Environment<'static>>,
+}
+
+impl TemplateOverrides {
+    pub fn new(template_dir: Option<&Path>) -> Self {
+        let environment = template_dir.map(|template_dir| {
+            let overrides = path_loader(template_dir);
+            let mut environment = Environment::new();
+            environment.set_loader(move |name| match overrides(name)? {
+                Some(source) => Ok(Some(source)),
+                None if name == "base.html" => Ok(Some(BASE_LAYOUT.to_string())),
+                None => Ok(None),
+            });
+            environment
+        });
+
+        Self { environment }
+    }
+
+    /// Render `page` from its override when there is one, from the bundled
+    /// template otherwise.
+    pub fn render<T: Overridable>(&self, page: &T) -> Result<String, RenderError> {
+        if let Some(environment) = &self.environment {
+            match environment.get_template(T::PATH) {
+                Ok(template) => return Ok(template.render(page)?),
+                Err(e) if e.kind() == ErrorKind::TemplateNotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(page.render()?)
+    }
+}
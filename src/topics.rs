@@ -0,0 +1,61 @@
+//! src/topics.rs
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// What an issue is about. Subscribers can opt out of topics they don't want.
+#[derive(Debug)]
+pub struct Topic {
+    pub topic_id: Uuid,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// In alphabetical order.
+#[tracing::instrument(name = "Get topics", skip(pool))]
+pub async fn get_topics(pool: &PgPool) -> Result<Vec<Topic>, sqlx::Error> {
+    sqlx::query_as!(
+        Topic,
+        r#"
+        SELECT topic_id, name, created_at
+        FROM topics
+        ORDER BY name
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[tracing::instrument(name = "Get topic by name", skip(pool))]
+pub async fn get_topic_by_name(pool: &PgPool, name: &str) -> Result<Option<Topic>, sqlx::Error> {
+    sqlx::query_as!(
+        Topic,
+        r#"
+        SELECT topic_id, name, created_at
+        FROM topics
+        WHERE name = $1
+        "#,
+        name
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Returns `None` if a topic with this name already exists.
+#[tracing::instrument(name = "Store topic", skip(pool))]
+pub async fn insert_topic(pool: &PgPool, name: &str) -> Result<Option<Topic>, sqlx::Error> {
+    sqlx::query_as!(
+        Topic,
+        r#"
+        INSERT INTO topics (topic_id, name, created_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (name) DO NOTHING
+        RETURNING topic_id, name, created_at
+        "#,
+        Uuid::new_v4(),
+        name,
+        Utc::now()
+    )
+    .fetch_optional(pool)
+    .await
+}
@@ -0,0 +1,403 @@
+//! src/tracking.rs
+//!
+//! Opens and clicks of the issues sent to lists that track them. Links in
+//! the email go through a redirect that records the click, and an invisible
+//! image records the open. Both are signed so that nobody can record events
+//! for someone else, or use the redirect to send people anywhere they like.
+use crate::configuration::HmacSecret;
+use anyhow::Context;
+use base64::{engine, Engine};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Events this soon after the delivery are made by link scanners and image
+/// prefetchers rather than by readers.
+const AUTOMATED_WITHIN_SECONDS: f64 = 10.0;
+
+/// The same event again within this time, e.g. an image fetched by both a
+/// proxy and the mail client, is only recorded once.
+const REPEAT_WITHIN_SECONDS: f64 = 60.0;
+
+/// Found in the user agent of link scanners, crawlers and the like.
+const AUTOMATED_USER_AGENTS: [&str; 12] = [
+    "bot",
+    "spider",
+    "crawl",
+    "preview",
+    "scanner",
+    "proofpoint",
+    "mimecast",
+    "barracuda",
+    "safelinks",
+    "headless",
+    "python-requests",
+    "curl",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngagementKind {
+    Open,
+    Click,
+}
+
+impl EngagementKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EngagementKind::Open => "open",
+            EngagementKind::Click => "click",
+        }
+    }
+}
+
+/// Who a tracking link was made for, and for clicks where it leads.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TrackedEvent {
+    pub newsletter_issue_id: Uuid,
+    pub subscriber_id: Uuid,
+    pub url: Option<String>,
+}
+
+/// Signs the tracking links of one issue for one subscriber.
+pub struct Tracker<'a> {
+    pub base_url: &'a str,
+    pub newsletter_issue_id: Uuid,
+    pub subscriber_id: Uuid,
+    pub secret: &'a HmacSecret,
+}
+
+impl Tracker<'_> {
+    pub fn open_url(&self) -> String {
+        let signature = self.signature(EngagementKind::Open, "");
+        format!(
+            "{}/t/o/{}.{}.{}",
+            self.base_url, self.newsletter_issue_id, self.subscriber_id, signature
+        )
+    }
+
+    pub fn click_url(&self, url: &str) -> String {
+        let signature = self.signature(EngagementKind::Click, url);
+        format!(
+            "{}/t/c/{}.{}.{}.{}",
+            self.base_url,
+            self.newsletter_issue_id,
+            self.subscriber_id,
+            engine::general_purpose::URL_SAFE_NO_PAD.encode(url),
+            signature
+        )
+    }
+
+    /// `html` with its web links going through the click redirect, and the
+    /// open image at the end. Links back to this site, like the preferences
+    /// link, are left as they are.
+    pub fn track(&self, html: &str) -> String {
+        let mut tracked = rewrite_links(html, |url| {
+            let is_web = url.starts_with("http://") || url.starts_with("https://");
+            (is_web && !url.starts_with(self.base_url)).then(|| self.click_url(url))
+        });
+        tracked.push_str(&format!(
+            r#"<img src="{}" width="1" height="1" alt="">"#,
+            self.open_url()
+        ));
+        tracked
+    }
+
+    fn signature(&self, kind: EngagementKind, url: &str) -> String {
+        hex::encode(
+            mac(
+                kind,
+                self.newsletter_issue_id,
+                self.subscriber_id,
+                url,
+                self.secret,
+            )
+            .finalize()
+            .into_bytes(),
+        )
+    }
+}
+
+/// The event a token from `open_url` or `click_url` was made for.
+pub fn verify_token(
+    kind: EngagementKind,
+    token: &str,
+    secret: &HmacSecret,
+) -> Result<TrackedEvent, anyhow::Error> {
+    let parts: Vec<&str> = token.split('.').collect();
+    let (issue_id, subscriber_id, url, signature) = match (kind, parts.as_slice()) {
+        (EngagementKind::Open, [issue_id, subscriber_id, signature]) => {
+            (issue_id, subscriber_id, None, signature)
+        }
+        (EngagementKind::Click, [issue_id, subscriber_id, url, signature]) => {
+            let url = engine::general_purpose::URL_SAFE_NO_PAD.decode(url)?;
+            (
+                issue_id,
+                subscriber_id,
+                Some(String::from_utf8(url)?),
+                signature,
+            )
+        }
+        _ => anyhow::bail!("The token is malformed"),
+    };
+    let newsletter_issue_id = Uuid::parse_str(issue_id)?;
+    let subscriber_id = Uuid::parse_str(subscriber_id)?;
+    let signature = hex::decode(signature)?;
+
+    mac(
+        kind,
+        newsletter_issue_id,
+        subscriber_id,
+        url.as_deref().unwrap_or_default(),
+        secret,
+    )
+    .verify_slice(&signature)
+    .map_err(|_| anyhow::anyhow!("The token signature is invalid"))?;
+
+    Ok(TrackedEvent {
+        newsletter_issue_id,
+        subscriber_id,
+        url,
+    })
+}
+
+fn mac(
+    kind: EngagementKind,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    url: &str,
+    secret: &HmacSecret,
+) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(
+        format!(
+            "{}:{}:{}:{}",
+            kind.as_str(),
+            newsletter_issue_id,
+            subscriber_id,
+            url
+        )
+        .as_bytes(),
+    );
+    mac
+}
+
+/// Replace the target of every `href` in `html` for which `replace` returns
+/// a new one. `replace` is given the target unescaped.
+fn rewrite_links(html: &str, mut replace: impl FnMut(&str) -> Option<String>) -> String {
+    // Lowercasing ASCII keeps every byte where it was.
+    let lowercase = html.to_ascii_lowercase();
+    let mut rewritten = String::with_capacity(html.len());
+    let mut copied = 0;
+
+    for (position, _) in lowercase.match_indices("href=") {
+        let start = position + "href=".len();
+        if start < copied {
+            continue;
+        }
+        let quote = match html[start..].chars().next() {
+            Some(quote @ ('"' | '\'')) => quote,
+            _ => continue,
+        };
+        let end = match html[start + 1..].find(quote) {
+            Some(length) => start + 1 + length,
+            None => continue,
+        };
+
+        let target = &html[start + 1..end];
+        let unescaped = htmlescape::decode_html(target).unwrap_or_else(|_| target.to_string());
+        if let Some(replacement) = replace(unescaped.trim()) {
+            rewritten.push_str(&html[copied..start + 1]);
+            rewritten.push_str(&htmlescape::encode_minimal(&replacement));
+            copied = end;
+        }
+    }
+    rewritten.push_str(&html[copied..]);
+
+    rewritten
+}
+
+/// Issues sent to several lists are only tracked if every one of them
+/// tracks engagement.
+#[tracing::instrument(name = "Check whether the issue is tracked", skip(pool))]
+pub async fn is_tracked(pool: &PgPool, newsletter_issue_id: Uuid) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT bool_and(l.track_engagement) AS tracked
+        FROM newsletter_issue_lists il
+        JOIN lists l ON l.list_id = il.list_id
+        WHERE il.newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.tracked.unwrap_or(false))
+}
+
+fn is_automated_user_agent(user_agent: Option<&str>) -> bool {
+    match user_agent {
+        Some(user_agent) if !user_agent.trim().is_empty() => {
+            let user_agent = user_agent.to_ascii_lowercase();
+            AUTOMATED_USER_AGENTS
+                .iter()
+                .any(|marker| user_agent.contains(marker))
+        }
+        _ => true,
+    }
+}
+
+/// Only events for issues the subscriber was sent are recorded, and repeats
+/// are dropped. Returns `false` if the event was not recorded.
+#[tracing::instrument(name = "Record engagement", skip(pool, event, user_agent))]
+pub async fn record_engagement(
+    pool: &PgPool,
+    kind: EngagementKind,
+    event: &TrackedEvent,
+    user_agent: Option<&str>,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO engagement_events (
+            event_id, kind, newsletter_issue_id, subscriber_id, url, automated, occurred_at
+        )
+        SELECT $1, $2, d.newsletter_issue_id, d.subscriber_id, $5,
+            $6 OR d.delivered_at > now() - make_interval(secs => $7),
+            now()
+        FROM newsletter_issue_deliveries d
+        WHERE d.newsletter_issue_id = $3 AND d.subscriber_id = $4
+            AND NOT EXISTS (
+                SELECT 1 FROM engagement_events e
+                WHERE e.newsletter_issue_id = $3 AND e.subscriber_id = $4 AND e.kind = $2
+                    AND e.url IS NOT DISTINCT FROM $5
+                    AND e.occurred_at > now() - make_interval(secs => $8)
+            )
+        "#,
+        Uuid::new_v4(),
+        kind.as_str(),
+        event.newsletter_issue_id,
+        event.subscriber_id,
+        event.url,
+        is_automated_user_agent(user_agent),
+        AUTOMATED_WITHIN_SECONDS,
+        REPEAT_WITHIN_SECONDS
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record the engagement")?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secrecy::Secret;
+
+    fn tracker(secret: &HmacSecret) -> Tracker<'_> {
+        Tracker {
+            base_url: "https://letter.example",
+            newsletter_issue_id: Uuid::new_v4(),
+            subscriber_id: Uuid::new_v4(),
+            secret,
+        }
+    }
+
+    fn token(url: &str) -> &str {
+        url.rsplit('/').next().unwrap()
+    }
+
+    #[test]
+    fn tokens_round_trip() {
+        let secret = HmacSecret(Secret::new("secret".into()));
+        let tracker = tracker(&secret);
+
+        let open = verify_token(EngagementKind::Open, token(&tracker.open_url()), &secret).unwrap();
+        let click = verify_token(
+            EngagementKind::Click,
+            token(&tracker.click_url("https://example.com/a?b=c&d=e")),
+            &secret,
+        )
+        .unwrap();
+
+        assert_eq!(open.subscriber_id, tracker.subscriber_id);
+        assert_eq!(open.url, None);
+        assert_eq!(click.newsletter_issue_id, tracker.newsletter_issue_id);
+        assert_eq!(click.url.as_deref(), Some("https://example.com/a?b=c&d=e"));
+    }
+
+    #[test]
+    fn tampered_tokens_are_rejected() {
+        let secret = HmacSecret(Secret::new("secret".into()));
+        let other = HmacSecret(Secret::new("other".into()));
+        let tracker = tracker(&secret);
+        let click = tracker.click_url("https://example.com");
+        let parts: Vec<&str> = token(&click).split('.').collect();
+        let redirected = format!(
+            "{}.{}.{}.{}",
+            parts[0],
+            parts[1],
+            engine::general_purpose::URL_SAFE_NO_PAD.encode("https://evil.example"),
+            parts[3]
+        );
+
+        assert!(verify_token(EngagementKind::Click, &redirected, &secret).is_err());
+        assert!(verify_token(EngagementKind::Click, token(&click), &other).is_err());
+        // A click token is no open token
+        assert!(verify_token(EngagementKind::Open, token(&click), &secret).is_err());
+    }
+
+    #[test]
+    fn only_links_to_other_sites_are_tracked() {
+        let secret = HmacSecret(Secret::new("secret".into()));
+        let tracker = tracker(&secret);
+        let html = r#"<p><a href="https://example.com/?a=1&amp;b=2">Read</a>
+            <A HREF='http://example.org'>More</A>
+            <a href="mailto:ursula@example.com">Write</a>
+            <a href="https://letter.example/preferences/token">Preferences</a></p>"#;
+
+        let tracked = tracker.track(html);
+
+        let click_urls: Vec<String> = tracked
+            .match_indices("https://letter.example/t/c/")
+            .map(|(start, _)| {
+                let end = start + tracked[start..].find(['"', '\'']).unwrap();
+                tracked[start..end].to_string()
+            })
+            .collect();
+        let targets: Vec<Option<String>> = click_urls
+            .iter()
+            .map(|url| {
+                verify_token(EngagementKind::Click, token(url), &secret)
+                    .unwrap()
+                    .url
+            })
+            .collect();
+        assert_eq!(
+            targets,
+            [
+                Some("https://example.com/?a=1&b=2".to_string()),
+                Some("http://example.org".to_string())
+            ]
+        );
+        assert!(tracked.contains(r#"href="mailto:ursula@example.com""#));
+        assert!(tracked.contains(r#"href="https://letter.example/preferences/token""#));
+        assert!(tracked.ends_with(&format!(
+            r#"<img src="{}" width="1" height="1" alt="">"#,
+            tracker.open_url()
+        )));
+    }
+
+    #[test]
+    fn scanners_are_told_apart_from_mail_clients() {
+        assert!(is_automated_user_agent(None));
+        assert!(is_automated_user_agent(Some("Barracuda Sentinel (EE)")));
+        assert!(is_automated_user_agent(Some(
+            "Mozilla/5.0 (compatible; bingbot/2.0)"
+        )));
+        assert!(!is_automated_user_agent(Some(
+            "Mozilla/5.0 (Windows NT 5.1; rv:11.0) Gecko Firefox/11.0 (via ggpht.com GoogleImageProxy)"
+        )));
+    }
+}
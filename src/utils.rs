@@ -1,4 +1,6 @@
 //! src/utils.rs
+use actix_web::{HttpRequest, HttpResponse};
+use std::net::SocketAddr;
 
 // Return an opaque 500 while preserving the error's root cause for logging.
 pub fn e500<T>(e: T) -> actix_web::Error
@@ -8,3 +10,22 @@ where
     actix_web::error::ErrorInternalServerError(e)
 }
 
+pub fn see_other(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header(("Location", location))
+        .finish()
+}
+
+/// Address of whoever sent the request. Forwarding headers are only read
+/// when `trust_forwarded_for` is set, as anyone can send them.
+pub fn client_ip(req: &HttpRequest, trust_forwarded_for: bool) -> Option<String> {
+    if trust_forwarded_for {
+        let address = req.connection_info().realip_remote_addr()?.to_string();
+        // Without a forwarding header, the peer address comes with a port.
+        return Some(match address.parse::<SocketAddr>() {
+            Ok(address) => address.ip().to_string(),
+            Err(_) => address,
+        });
+    }
+    req.peer_addr().map(|address| address.ip().to_string())
+}
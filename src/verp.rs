@@ -0,0 +1,108 @@
+//! src/verp.rs
+//!
+//! Variable envelope return paths: the envelope sender of an email names its
+//! recipient and the issue it carried, as in
+//! `bounces+4bf1c6e0d2a14ea6b1d0b1f3b2ad6c41.ursula=example.com@letter.example`,
+//! so that a bounce tells who and what it is about even when the rest of it
+//! is mangled. Relays that add VERP themselves only name the recipient.
+use uuid::Uuid;
+
+/// What a VERP address says about the email that bounced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verp {
+    pub recipient: String,
+    pub newsletter_issue_id: Option<Uuid>,
+}
+
+/// The envelope sender naming `recipient` and the issue, at `domain`. `None`
+/// if `recipient` is not an email address.
+pub fn encode(recipient: &str, newsletter_issue_id: Uuid, domain: &str) -> Option<String> {
+    let (user, recipient_domain) = recipient.trim().rsplit_once('@')?;
+
+    Some(format!(
+        "bounces+{}.{}={}@{}",
+        newsletter_issue_id.simple(),
+        user,
+        recipient_domain,
+        domain
+    ))
+}
+
+/// `None` if `address` isn't a VERP address.
+pub fn decode(address: &str) -> Option<Verp> {
+    let (local, _) = address.trim().rsplit_once('@')?;
+    let (_, encoded) = local.split_once('+')?;
+    let (newsletter_issue_id, encoded) = match encoded.split_once('.') {
+        Some((issue, rest)) if issue.len() == 32 => match Uuid::try_parse(issue) {
+            Ok(newsletter_issue_id) => (Some(newsletter_issue_id), rest),
+            Err(_) => (None, encoded),
+        },
+        _ => (None, encoded),
+    };
+    let (user, domain) = encoded.rsplit_once('=')?;
+    if user.is_empty() || !domain.contains('.') {
+        return None;
+    }
+
+    Some(Verp {
+        recipient: format!("{}@{}", user, domain),
+        newsletter_issue_id,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recipient(address: &str) -> Option<String> {
+        decode(address).map(|verp| verp.recipient)
+    }
+
+    #[test]
+    fn the_recipient_is_read_from_the_local_part() {
+        assert_eq!(
+            recipient("bounces+ursula_le_guin=gmail.com@letter.example").as_deref(),
+            Some("ursula_le_guin@gmail.com")
+        );
+        assert_eq!(
+            recipient("bounces+a+b=mail.example.org@letter.example").as_deref(),
+            Some("a+b@mail.example.org")
+        );
+        assert_eq!(
+            decode("bounces+ursula.le.guin=gmail.com@letter.example")
+                .unwrap()
+                .newsletter_issue_id,
+            None
+        );
+    }
+
+    #[test]
+    fn encoded_addresses_decode_to_the_recipient_and_the_issue() {
+        let newsletter_issue_id = Uuid::new_v4();
+
+        let encoded = encode(
+            "a.b+c@mail.example.org",
+            newsletter_issue_id,
+            "letter.example",
+        );
+
+        assert_eq!(
+            decode(&encoded.unwrap()),
+            Some(Verp {
+                recipient: "a.b+c@mail.example.org".into(),
+                newsletter_issue_id: Some(newsletter_issue_id),
+            })
+        );
+        assert_eq!(
+            encode("not an address", newsletter_issue_id, "letter.example"),
+            None
+        );
+    }
+
+    #[test]
+    fn plain_addresses_are_not_verp() {
+        assert_eq!(decode("bounces@letter.example"), None);
+        assert_eq!(decode("ursula+news@gmail.com"), None);
+        assert_eq!(decode("bounces+=gmail.com@letter.example"), None);
+    }
+}
@@ -0,0 +1,202 @@
+//! src/webhooks.rs
+//!
+//! Reports email providers send us about the emails we handed to them.
+use crate::bounces::{Feedback, FeedbackKind};
+use crate::configuration::BrevoWebhookSettings;
+use crate::utils::client_ip;
+use actix_web::HttpRequest;
+use anyhow::Context;
+use base64::{engine, Engine};
+use ipnet::IpNet;
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+
+/// Tells whether a request to the Brevo webhook was sent by Brevo.
+#[derive(Debug)]
+pub struct BrevoWebhook {
+    secret: Option<Secret<String>>,
+    allowed_ips: Vec<IpNet>,
+    trust_forwarded_for: bool,
+}
+
+impl BrevoWebhook {
+    pub fn from_settings(settings: BrevoWebhookSettings) -> Result<Self, anyhow::Error> {
+        if settings.secret.is_none() && settings.allowed_ips.is_empty() {
+            anyhow::bail!("The Brevo webhook needs a secret, allowed IPs or both");
+        }
+
+        Ok(Self {
+            secret: settings.secret,
+            allowed_ips: settings.allowed_ips,
+            trust_forwarded_for: settings.trust_forwarded_for,
+        })
+    }
+
+    pub fn authenticate(&self, req: &HttpRequest) -> Result<(), anyhow::Error> {
+        if !self.allowed_ips.is_empty() {
+            let ip: IpAddr = client_ip(req, self.trust_forwarded_for)
+                .and_then(|ip| ip.parse().ok())
+                .context("The address of the client is unknown")?;
+            if !self.allowed_ips.iter().any(|range| range.contains(&ip)) {
+                anyhow::bail!("{} is not an allowed address", ip);
+            }
+        }
+
+        if let Some(secret) = &self.secret {
+            let presented = presented_secret(req).context("The request carries no secret")?;
+            if !constant_time_eq(presented.as_bytes(), secret.expose_secret().as_bytes()) {
+                anyhow::bail!("The secret is wrong");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Brevo sends the secret as a bearer token or, when it is part of the
+/// webhook URL, as the password of basic auth.
+fn presented_secret(req: &HttpRequest) -> Option<String> {
+    let header = req.headers().get("Authorization")?.to_str().ok()?;
+    if let Some(token) = header.strip_prefix("Bearer ") {
+        return Some(token.trim().to_string());
+    }
+
+    let encoded = header.strip_prefix("Basic ")?;
+    let decoded = engine::general_purpose::STANDARD.decode(encoded).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    decoded
+        .split_once(':')
+        .map(|(_, password)| password.to_string())
+}
+
+/// Takes as long whatever the first differing byte, so the secret can't be
+/// guessed one byte at a time.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// One event of a Brevo webhook, transactional or marketing.
+#[derive(serde::Deserialize, Debug)]
+struct BrevoEvent {
+    event: String,
+    email: String,
+    #[serde(rename = "message-id")]
+    message_id: Option<String>,
+    reason: Option<String>,
+}
+
+impl BrevoEvent {
+    fn kind(&self) -> Option<FeedbackKind> {
+        match self.event.as_str() {
+            "hard_bounce" | "hardBounce" => Some(FeedbackKind::HardBounce),
+            "soft_bounce" | "softBounce" => Some(FeedbackKind::SoftBounce),
+            "spam" => Some(FeedbackKind::Complaint),
+            "unsubscribed" | "unsubscribe" => Some(FeedbackKind::Unsubscribe),
+            "blocked" => Some(FeedbackKind::Blocked),
+            _ => None,
+        }
+    }
+}
+
+/// The reports in the body of a Brevo webhook, which holds a single event
+/// or, for batched webhooks, a list of them. Events we don't act upon, like
+/// `delivered` or `opened`, are left out, and so are malformed ones: failing
+/// the whole request would only make Brevo send them again.
+pub fn parse_brevo_events(body: &[u8]) -> Result<Vec<Feedback>, serde_json::Error> {
+    let events = match serde_json::from_slice(body)? {
+        serde_json::Value::Array(events) => events,
+        event => vec![event],
+    };
+
+    let feedback = events
+        .into_iter()
+        .filter_map(|event| {
+            // Brevo sends the same bytes when it retries a delivery.
+            let event_key = hex::encode(Sha256::digest(event.to_string().as_bytes()));
+            let event: BrevoEvent = match serde_json::from_value(event) {
+                Ok(event) => event,
+                Err(e) => {
+                    tracing::warn!(error.cause_chain = ?e, "Ignoring a malformed Brevo event");
+                    return None;
+                }
+            };
+
+            Some(Feedback {
+                kind: event.kind()?,
+                email: event.email,
+                event_key,
+                message_id: event.message_id,
+                newsletter_issue_id: None,
+                reason: event.reason,
+            })
+        })
+        .collect();
+
+    Ok(feedback)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn batches_and_single_events_are_parsed() {
+        let single = br#"{"event": "spam", "email": "a@example.com", "ts_epoch": 1}"#;
+        let batch = br#"[
+            {"event": "hard_bounce", "email": "a@example.com", "message-id": "<1@x>", "reason": "unknown user"},
+            {"event": "delivered", "email": "b@example.com"},
+            {"event": "unsubscribe", "email": "c@example.com"}
+        ]"#;
+
+        let single = parse_brevo_events(single).unwrap();
+        assert_eq!(single.len(), 1);
+        assert_eq!(single[0].kind, FeedbackKind::Complaint);
+
+        let batch = parse_brevo_events(batch).unwrap();
+        let kinds: Vec<_> = batch.iter().map(|feedback| feedback.kind).collect();
+        assert_eq!(kinds, [FeedbackKind::HardBounce, FeedbackKind::Unsubscribe]);
+        assert_eq!(batch[0].message_id.as_deref(), Some("<1@x>"));
+        assert_eq!(batch[0].reason.as_deref(), Some("unknown user"));
+    }
+
+    #[test]
+    fn repeated_events_have_the_same_key() {
+        let body = br#"{"event": "hard_bounce", "email": "a@example.com", "ts_epoch": 1}"#;
+        let later = br#"{"event": "hard_bounce", "email": "a@example.com", "ts_epoch": 2}"#;
+
+        let first = parse_brevo_events(body).unwrap();
+        let again = parse_brevo_events(body).unwrap();
+        let later = parse_brevo_events(later).unwrap();
+
+        assert_eq!(first[0].event_key, again[0].event_key);
+        assert_ne!(first[0].event_key, later[0].event_key);
+    }
+
+    #[test]
+    fn malformed_events_are_skipped() {
+        let body = br#"[{"event": "spam"}, {"event": "spam", "email": "a@example.com"}]"#;
+
+        assert_eq!(parse_brevo_events(body).unwrap().len(), 1);
+        assert!(parse_brevo_events(b"not json").is_err());
+    }
+
+    #[test]
+    fn the_secret_is_read_from_bearer_and_basic_auth() {
+        let bearer = TestRequest::default()
+            .insert_header(("Authorization", "Bearer s3cret"))
+            .to_http_request();
+        // brevo:s3cret
+        let basic = TestRequest::default()
+            .insert_header(("Authorization", "Basic YnJldm86czNjcmV0"))
+            .to_http_request();
+
+        assert_eq!(presented_secret(&bearer).as_deref(), Some("s3cret"));
+        assert_eq!(presented_secret(&basic).as_deref(), Some("s3cret"));
+        assert_eq!(
+            presented_secret(&TestRequest::default().to_http_request()),
+            None
+        );
+    }
+}
@@ -0,0 +1,17 @@
+// templates/widget/widget.js
+(function () {
+  var script = document.currentScript;
+  if (!script) {
+    return;
+  }
+
+  var list = script.getAttribute("data-list") || "default";
+  var frame = document.createElement("iframe");
+  frame.src = {{ base_url }} + "/widget/" + encodeURIComponent(list);
+  frame.title = "Newsletter sign-up";
+  frame.style.border = "0";
+  frame.style.width = "100%";
+  frame.style.height = script.getAttribute("data-height") || "240px";
+
+  script.parentNode.insertBefore(frame, script.nextSibling);
+})();
@@ -1,6 +1,11 @@
 //! src/tests/api/admin.rs
 
-use crate::helpers::{assert_is_redirect_to, setup};
+use crate::helpers::{
+    assert_is_redirect_to, publish_issue, seed_delivery, seed_open, seed_subscriber,
+    seed_unsubscribe, setup,
+};
+use chrono::{Duration, Utc};
+use letter::domain::SubscriptionStatus;
 
 #[tokio::test]
 async fn you_must_be_logged_in_to_access_the_admin_dashboard() {
@@ -63,3 +68,73 @@ async fn go_through_burp_proxy() {
     // Assert
     assert_is_redirect_to(&response, "/login");
 }
+
+#[tokio::test]
+async fn the_dashboard_shows_how_the_list_grows() {
+    // Arrange
+    let app = setup().await;
+    for (email, status, days_ago) in [
+        ("a@example.com", SubscriptionStatus::Confirmed, 10),
+        ("b@example.com", SubscriptionStatus::Confirmed, 5),
+        ("c@example.com", SubscriptionStatus::Confirmed, 0),
+        ("d@example.com", SubscriptionStatus::PendingConfirmation, 2),
+        // Too long ago to count towards the confirmation rate
+        ("f@example.com", SubscriptionStatus::Confirmed, 200),
+    ] {
+        seed_subscriber(&app, email, status, days_ago).await;
+    }
+    let leaver = seed_subscriber(&app, "e@example.com", SubscriptionStatus::Confirmed, 20).await;
+    seed_unsubscribe(&app, leaver, 1).await;
+    app.login(&app.user.username, &app.user.password).await;
+
+    // Act
+    let page = app.get_text("/admin/dashboard").await;
+
+    // Assert
+    assert!(page.contains("<li>Confirmed: 4</li>"));
+    assert!(page.contains("<li>Waiting to confirm: 1</li>"));
+    assert!(page.contains("Confirmation rate over the last 90 days: 80.0%"));
+    assert!(page.contains("<svg"));
+    assert_eq!(page.matches("<rect ").count(), 2 * 90);
+    let today = Utc::now().date_naive();
+    assert!(page.contains(&format!("{}: 1 sign-ups, 0 unsubscribes", today)));
+    assert!(page.contains(&format!(
+        "{}: 0 sign-ups, 1 unsubscribes",
+        today - Duration::days(1)
+    )));
+}
+
+#[tokio::test]
+async fn the_dashboard_shows_open_rates_and_churn_of_the_latest_issues() {
+    // Arrange
+    let app = setup().await;
+    sqlx::query!("UPDATE lists SET track_engagement = TRUE")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let (issue_id, _) = publish_issue(&app, "Tracked issue", "<p>Hello</p>").await;
+    let mut subscribers = vec![];
+    for email in [
+        "a@example.com",
+        "b@example.com",
+        "c@example.com",
+        "d@example.com",
+    ] {
+        let subscriber_id = seed_subscriber(&app, email, SubscriptionStatus::Confirmed, 30).await;
+        seed_delivery(&app, issue_id, subscriber_id, 3).await;
+        subscribers.push(subscriber_id);
+    }
+    seed_open(&app, issue_id, subscribers[0]).await;
+    seed_open(&app, issue_id, subscribers[1]).await;
+    seed_unsubscribe(&app, subscribers[3], 1).await;
+    app.login(&app.user.username, &app.user.password).await;
+
+    // Act
+    let page = app.get_text("/admin/dashboard").await;
+
+    // Assert
+    assert!(page.contains("Tracked issue</a></td>"));
+    assert!(page.contains("<td>4</td>"));
+    assert!(page.contains("<td>50.0%</td>"));
+    assert!(page.contains("<td>1 (25.0%)</td>"));
+}
@@ -0,0 +1,140 @@
+//! tests/api/archive.rs
+
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, publish_issue, setup, setup_with, Email,
+};
+use uuid::Uuid;
+
+#[tokio::test]
+async fn published_issues_are_listed_in_the_archive() {
+    // Arrange
+    let app = setup().await;
+
+    // Act
+    let (_, slug) = publish_issue(&app, "Hello, World!", "<p>Newsletter body</p>").await;
+
+    // Assert
+    assert_eq!(slug, "hello-world");
+
+    let html_page = app.get_text("/archive").await;
+    assert!(html_page.contains(r#"<a href="/archive/hello-world">Hello, World!</a>"#));
+
+    let response = app.get("/archive/hello-world").await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("<p>Newsletter body</p>"));
+}
+
+#[tokio::test]
+async fn issues_with_the_same_title_get_distinct_slugs() {
+    // Arrange
+    let app = setup().await;
+
+    // Act
+    let (_, first_slug) = publish_issue(&app, "Weekly update", "First").await;
+    let (_, second_slug) = publish_issue(&app, "Weekly update", "Second").await;
+
+    // Assert
+    assert_eq!(first_slug, "weekly-update");
+    assert_eq!(second_slug, "weekly-update-2");
+}
+
+#[tokio::test]
+async fn unknown_issues_return_a_404() {
+    // Arrange
+    let app = setup().await;
+
+    // Act
+    let response = app.get("/archive/does-not-exist").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn newsletter_emails_contain_a_view_in_browser_link() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    publish_issue(&app, "Newsletter title", "Newsletter body").await;
+
+    // Assert
+    let requests = app.email_server.received_requests().await.unwrap();
+    let email: Email = serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+    assert!(email
+        .html_content
+        .contains("http://127.0.0.1/archive/newsletter-title"));
+}
+
+#[tokio::test]
+async fn private_issues_are_hidden_from_the_archive() {
+    // Arrange
+    let app = setup().await;
+    let (issue_id, slug) = publish_issue(&app, "Secret plans", "Newsletter body").await;
+    app.login(&app.user.username, &app.user.password).await;
+
+    // Act
+    let response = app
+        .post_form(
+            &format!("/admin/issues/{}/visibility", issue_id),
+            &[("is_public", "false")],
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/issues");
+
+    let html_page = app.get_text("/archive").await;
+    assert!(!html_page.contains("Secret plans"));
+
+    let response = app.get(&format!("/archive/{}", slug)).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_change_issue_visibility() {
+    // Arrange
+    let app = setup().await;
+    let (issue_id, _) = publish_issue(&app, "Newsletter title", "Newsletter body").await;
+
+    // Act
+    let response = app
+        .post_form(
+            &format!("/admin/issues/{}/visibility", issue_id),
+            &[("is_public", "false")],
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_archive_can_be_rebranded_from_the_template_directory() {
+    // Arrange
+    let template_dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+    std::fs::create_dir_all(template_dir.join("archive")).unwrap();
+    std::fs::write(
+        template_dir.join("archive/list.html"),
+        r#"{% extends "base.html" %}
+{% block content %}<h1>Our letters</h1>
+{% for issue in issues %}<p class="branded">{{ issue.title }}</p>{% endfor %}
+{% endblock %}"#,
+    )
+    .unwrap();
+    let app = setup_with(|config| config.application.template_dir = Some(template_dir)).await;
+    publish_issue(&app, "Fish & Chips", "Newsletter body").await;
+
+    // Act
+    let archive = app.get_text("/archive").await;
+    let home = app.get_text("/").await;
+
+    // Assert
+    assert!(archive.contains("<h1>Our letters</h1>"));
+    assert!(archive.contains(r#"<p class="branded">Fish &amp; Chips</p>"#));
+    assert!(archive.contains("<!DOCTYPE html>"));
+    // Pages without an override keep the bundled template
+    assert!(home.contains("Welcome to our newsletter!"));
+}
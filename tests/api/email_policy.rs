@@ -0,0 +1,102 @@
+//! tests/api/email_policy.rs
+
+use crate::helpers::{setup, setup_with, Test};
+use letter::configuration::ResolverSettings;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+async fn sign_up(app: &Test, email: &str) -> reqwest::Response {
+    app.client
+        .post(format!("{}/api/v1/subscriptions", app.address))
+        .json(&serde_json::json!({ "name": "le guin", "email": email }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn disposable_addresses_are_rejected() {
+    // Arrange
+    let app = setup().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula%40mailinator.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(response.text().await.unwrap().contains("Disposable"));
+}
+
+#[tokio::test]
+async fn role_addresses_are_reported_on_the_email_field() {
+    // Arrange
+    let app = setup().await;
+
+    // Act
+    let response = sign_up(&app, "noreply@example.com").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["errors"][0]["field"], "email");
+    assert!(body["errors"][0]["message"]
+        .as_str()
+        .unwrap()
+        .contains("personal address"));
+}
+
+#[tokio::test]
+async fn domains_without_mail_hosts_are_rejected() {
+    // Arrange
+    let app = setup_with(|config| {
+        config.email_policy.check_mail_domain = true;
+        config.email_policy.resolver = ResolverSettings::Static(vec!["gmail.com".into()]);
+    })
+    .await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let typo = sign_up(&app, "ursula_le_guin@gmial.com").await;
+    let valid = sign_up(&app, "ursula_le_guin@gmail.com").await;
+
+    // Assert
+    assert_eq!(typo.status().as_u16(), 400);
+    let body: serde_json::Value = typo.json().await.unwrap();
+    assert_eq!(body["errors"][0]["field"], "email");
+    assert_eq!(valid.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn policies_that_are_turned_off_let_addresses_through() {
+    // Arrange
+    let app = setup_with(|config| {
+        config.email_policy.block_disposable = false;
+        config.email_policy.block_role_accounts = false;
+    })
+    .await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = sign_up(&app, "postmaster@yopmail.com").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
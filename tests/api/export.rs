@@ -0,0 +1,128 @@
+//! tests/api/export.rs
+
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_unconfirmed_subscriber, setup,
+};
+
+#[tokio::test]
+async fn admins_can_export_subscribers_as_csv() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    app.login(&app.user.username, &app.user.password).await;
+
+    // Act
+    let response = app.get("/admin/subscribers/export?format=csv").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "text/csv; charset=utf-8"
+    );
+
+    let body = response.text().await.unwrap();
+    let mut lines = body.lines();
+    assert_eq!(
+        lines.next().unwrap(),
+        "id,email,name,status,subscribed_at,confirmed_at,consent_source,tags"
+    );
+    let row = lines.next().unwrap();
+    assert!(row.contains(",ursula_le_guin@gmail.com,le guin,confirmed,"));
+    assert!(lines.next().is_none());
+}
+
+#[tokio::test]
+async fn exports_can_be_filtered_by_status() {
+    // Arrange
+    let app = setup().await;
+    create_unconfirmed_subscriber(&app).await;
+    app.login(&app.user.username, &app.user.password).await;
+
+    // Act
+    let pending = app
+        .get_text("/admin/subscribers/export?format=ndjson&status=pending_confirmation")
+        .await;
+    let confirmed = app
+        .get_text("/admin/subscribers/export?format=ndjson&status=confirmed")
+        .await;
+
+    // Assert
+    let subscriber: serde_json::Value = serde_json::from_str(pending.trim_end()).unwrap();
+    assert_eq!(subscriber["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(subscriber["status"], "pending_confirmation");
+    assert!(subscriber["confirmed_at"].is_null());
+
+    assert!(confirmed.is_empty());
+}
+
+#[tokio::test]
+async fn large_exports_include_every_subscriber() {
+    // Arrange
+    let app = setup().await;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, email_key, name, subscribed_at, status)
+        SELECT gen_random_uuid(), 'reader' || n || '@example.com', 'reader' || n || '@example.com',
+            'Reader', now(), 'confirmed'
+        FROM generate_series(1, 2500) AS n
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.login(&app.user.username, &app.user.password).await;
+
+    // Act
+    let body = app
+        .get_text("/admin/subscribers/export?format=ndjson")
+        .await;
+
+    // Assert
+    let emails: std::collections::HashSet<String> = body
+        .lines()
+        .map(|line| {
+            let subscriber: serde_json::Value = serde_json::from_str(line).unwrap();
+            subscriber["email"].as_str().unwrap().to_string()
+        })
+        .collect();
+    assert_eq!(emails.len(), 2500);
+}
+
+#[tokio::test]
+async fn the_api_export_requires_credentials() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let anonymous = app.get("/newsletters/subscribers").await;
+    let authenticated = app
+        .client
+        .get(format!("{}/newsletters/subscribers", app.address))
+        .basic_auth(&app.user.username, Some(&app.user.password))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(anonymous.status().as_u16(), 401);
+    assert_eq!(authenticated.status().as_u16(), 200);
+    assert!(authenticated
+        .text()
+        .await
+        .unwrap()
+        .contains("ursula_le_guin@gmail.com"));
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_export_subscribers() {
+    // Arrange
+    let app = setup().await;
+
+    // Act
+    let response = app.get("/admin/subscribers/export").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
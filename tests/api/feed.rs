@@ -0,0 +1,193 @@
+//! tests/api/feed.rs
+
+use crate::helpers::{publish_issue, setup, Test};
+
+const ATOM_NS: &str = "http://www.w3.org/2005/Atom";
+
+fn child<'a, 'input>(node: roxmltree::Node<'a, 'input>, name: &str) -> roxmltree::Node<'a, 'input> {
+    node.children()
+        .find(|n| n.is_element() && n.tag_name().name() == name)
+        .unwrap_or_else(|| {
+            panic!(
+                "<{}> is missing a <{}> element",
+                node.tag_name().name(),
+                name
+            )
+        })
+}
+
+fn children<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = roxmltree::Node<'a, 'input>> {
+    node.children()
+        .filter(move |n| n.is_element() && n.tag_name().name() == name)
+}
+
+async fn get_feed(app: &Test, path: &str, headers: &[(&str, &str)]) -> reqwest::Response {
+    let mut request = app.client.get(format!("{}{}", app.address, path));
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    request.send().await.expect("Failed to fetch the feed.")
+}
+
+#[tokio::test]
+async fn rss_feed_follows_the_rss_2_0_structure() {
+    // Arrange
+    let app = setup().await;
+    let (issue_id, slug) = publish_issue(&app, "Newsletter title", "<p>Newsletter body</p>").await;
+
+    // Act
+    let response = get_feed(&app, "/feed.rss", &[]).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("application/rss+xml"));
+
+    let text = response.text().await.unwrap();
+    let document = roxmltree::Document::parse(&text).expect("The feed is not valid XML.");
+    let rss = document.root_element();
+    assert_eq!(rss.tag_name().name(), "rss");
+    assert_eq!(rss.attribute("version"), Some("2.0"));
+
+    let channel = child(rss, "channel");
+    for required in ["title", "link", "description"] {
+        child(channel, required);
+    }
+
+    let items: Vec<_> = children(channel, "item").collect();
+    assert_eq!(items.len(), 1);
+    let item = items[0];
+    assert_eq!(child(item, "title").text(), Some("Newsletter title"));
+    assert_eq!(
+        child(item, "link").text(),
+        Some(format!("http://127.0.0.1/archive/{}", slug).as_str())
+    );
+    let guid = child(item, "guid");
+    assert_eq!(guid.attribute("isPermaLink"), Some("false"));
+    assert_eq!(guid.text(), Some(format!("urn:uuid:{}", issue_id).as_str()));
+    chrono::DateTime::parse_from_rfc2822(child(item, "pubDate").text().unwrap())
+        .expect("pubDate is not an RFC 822 date.");
+    assert_eq!(
+        child(item, "description").text(),
+        Some("<p>Newsletter body</p>")
+    );
+}
+
+#[tokio::test]
+async fn atom_feed_follows_the_atom_1_0_structure() {
+    // Arrange
+    let app = setup().await;
+    let (issue_id, _) = publish_issue(&app, "Newsletter title", "<p>Newsletter body</p>").await;
+
+    // Act
+    let response = get_feed(&app, "/feed.atom", &[]).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("application/atom+xml"));
+
+    let text = response.text().await.unwrap();
+    let document = roxmltree::Document::parse(&text).expect("The feed is not valid XML.");
+    let feed = document.root_element();
+    assert_eq!(feed.tag_name().name(), "feed");
+    assert_eq!(feed.tag_name().namespace(), Some(ATOM_NS));
+
+    for required in ["id", "title", "updated", "author"] {
+        child(feed, required);
+    }
+    chrono::DateTime::parse_from_rfc3339(child(feed, "updated").text().unwrap())
+        .expect("updated is not an RFC 3339 date.");
+
+    let entries: Vec<_> = children(feed, "entry").collect();
+    assert_eq!(entries.len(), 1);
+    let entry = entries[0];
+    assert_eq!(
+        child(entry, "id").text(),
+        Some(format!("urn:uuid:{}", issue_id).as_str())
+    );
+    assert_eq!(child(entry, "title").text(), Some("Newsletter title"));
+    chrono::DateTime::parse_from_rfc3339(child(entry, "updated").text().unwrap())
+        .expect("updated is not an RFC 3339 date.");
+    let content = child(entry, "content");
+    assert_eq!(content.attribute("type"), Some("html"));
+    assert_eq!(content.text(), Some("<p>Newsletter body</p>"));
+}
+
+#[tokio::test]
+async fn feeds_are_valid_before_the_first_issue() {
+    // Arrange
+    let app = setup().await;
+
+    for path in ["/feed.rss", "/feed.atom"] {
+        // Act
+        let response = get_feed(&app, path, &[]).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        let text = response.text().await.unwrap();
+        roxmltree::Document::parse(&text).expect("The feed is not valid XML.");
+    }
+}
+
+#[tokio::test]
+async fn a_matching_etag_returns_a_304() {
+    // Arrange
+    let app = setup().await;
+    publish_issue(&app, "Newsletter title", "Newsletter body").await;
+
+    for path in ["/feed.rss", "/feed.atom"] {
+        let response = get_feed(&app, path, &[]).await;
+        let etag = response.headers()["ETag"].to_str().unwrap().to_owned();
+
+        // Act
+        let response = get_feed(&app, path, &[("If-None-Match", &etag)]).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 304);
+        assert_eq!(response.headers()["ETag"].to_str().unwrap(), etag);
+    }
+}
+
+#[tokio::test]
+async fn an_unchanged_feed_since_last_modified_returns_a_304() {
+    // Arrange
+    let app = setup().await;
+    publish_issue(&app, "Newsletter title", "Newsletter body").await;
+
+    let response = get_feed(&app, "/feed.rss", &[]).await;
+    let last_modified = response.headers()["Last-Modified"]
+        .to_str()
+        .unwrap()
+        .to_owned();
+
+    // Act
+    let response = get_feed(&app, "/feed.rss", &[("If-Modified-Since", &last_modified)]).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 304);
+}
+
+#[tokio::test]
+async fn publishing_an_issue_invalidates_the_etag() {
+    // Arrange
+    let app = setup().await;
+    publish_issue(&app, "First issue", "Newsletter body").await;
+    let response = get_feed(&app, "/feed.atom", &[]).await;
+    let etag = response.headers()["ETag"].to_str().unwrap().to_owned();
+
+    // Act
+    publish_issue(&app, "Second issue", "Newsletter body").await;
+    let response = get_feed(&app, "/feed.atom", &[("If-None-Match", &etag)]).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_ne!(response.headers()["ETag"].to_str().unwrap(), etag);
+}
@@ -0,0 +1,234 @@
+//! tests/api/feed_poller.rs
+
+use crate::helpers::{create_confirmed_subscriber, setup, Email, Test};
+use letter::configuration::FeedPollerMode;
+use letter::feed_poller::poll_feed;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn rss(items: &[(&str, &str)]) -> String {
+    let items: String = items
+        .iter()
+        .map(|(id, title)| {
+            format!(
+                r#"<item>
+                    <title>{title}</title>
+                    <link>https://blog.example.com/{id}</link>
+                    <guid>https://blog.example.com/{id}</guid>
+                    <description>Body of {title}</description>
+                </item>"#
+            )
+        })
+        .collect();
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+        <rss version="2.0">
+            <channel>
+                <title>Our blog</title>
+                <link>https://blog.example.com</link>
+                <description>Posts</description>
+                {items}
+            </channel>
+        </rss>"#
+    )
+}
+
+struct Blog {
+    server: MockServer,
+}
+
+impl Blog {
+    async fn start() -> Self {
+        Self {
+            server: MockServer::start().await,
+        }
+    }
+
+    fn feed_url(&self) -> String {
+        format!("{}/feed.xml", self.server.uri())
+    }
+
+    async fn serve(&self, items: &[(&str, &str)]) {
+        self.server.reset().await;
+        Mock::given(method("GET"))
+            .and(path("/feed.xml"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Content-Type", "application/rss+xml")
+                    .set_body_string(rss(items)),
+            )
+            .mount(&self.server)
+            .await;
+    }
+}
+
+async fn poll(app: &Test, blog: &Blog, mode: FeedPollerMode) -> usize {
+    poll_feed(
+        &app.db_pool,
+        &reqwest::Client::new(),
+        &app.email_client,
+        "http://127.0.0.1",
+        &app.hmac_secret,
+        &blog.feed_url(),
+        mode,
+    )
+    .await
+    .expect("Failed to poll the feed.")
+}
+
+async fn sent_newsletters(app: &Test) -> Vec<Email> {
+    // The first email is the subscription confirmation.
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .skip(1)
+        .map(|request| serde_json::from_slice(&request.body).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn the_first_poll_only_records_existing_entries() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    let blog = Blog::start().await;
+    blog.serve(&[("1", "Old post"), ("2", "Older post")]).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let published = poll(&app, &blog, FeedPollerMode::Digest).await;
+
+    // Assert
+    assert_eq!(published, 0);
+}
+
+#[tokio::test]
+async fn new_entries_are_sent_as_a_single_digest() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    let blog = Blog::start().await;
+    blog.serve(&[("1", "Old post")]).await;
+    poll(&app, &blog, FeedPollerMode::Digest).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    blog.serve(&[("1", "Old post"), ("2", "First post"), ("3", "Second post")])
+        .await;
+
+    // Act
+    let published = poll(&app, &blog, FeedPollerMode::Digest).await;
+
+    // Assert
+    assert_eq!(published, 1);
+    let emails = sent_newsletters(&app).await;
+    assert!(emails[0].html_content.contains("First post"));
+    assert!(emails[0].html_content.contains("Second post"));
+    assert!(!emails[0].html_content.contains("Old post"));
+}
+
+#[tokio::test]
+async fn new_entries_are_sent_one_issue_per_post() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    let blog = Blog::start().await;
+    blog.serve(&[]).await;
+    poll(&app, &blog, FeedPollerMode::PerPost).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    blog.serve(&[("1", "First post"), ("2", "Second post")])
+        .await;
+
+    // Act
+    let published = poll(&app, &blog, FeedPollerMode::PerPost).await;
+
+    // Assert
+    assert_eq!(published, 2);
+    let issues = sqlx::query!("SELECT title FROM newsletter_issues ORDER BY title")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let titles: Vec<_> = issues.into_iter().map(|issue| issue.title).collect();
+    assert_eq!(titles, vec!["First post", "Second post"]);
+}
+
+#[tokio::test]
+async fn entries_are_never_sent_twice() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    let blog = Blog::start().await;
+    blog.serve(&[]).await;
+    poll(&app, &blog, FeedPollerMode::Digest).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    blog.serve(&[("1", "First post")]).await;
+    poll(&app, &blog, FeedPollerMode::Digest).await;
+
+    // Act
+    let published = poll(&app, &blog, FeedPollerMode::Digest).await;
+
+    // Assert
+    assert_eq!(published, 0);
+}
+
+#[tokio::test]
+async fn deliveries_cut_short_are_finished_without_a_new_issue() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    let blog = Blog::start().await;
+    blog.serve(&[]).await;
+    poll(&app, &blog, FeedPollerMode::Digest).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    blog.serve(&[("1", "First post")]).await;
+    poll(&app, &blog, FeedPollerMode::Digest).await;
+    // As if the poller stopped before the email was recorded as sent.
+    sqlx::query!("UPDATE feed_entries SET sent_at = NULL")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query!("DELETE FROM newsletter_issue_deliveries")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let published = poll(&app, &blog, FeedPollerMode::Digest).await;
+    poll(&app, &blog, FeedPollerMode::Digest).await;
+
+    // Assert
+    assert_eq!(published, 0);
+    let issues = sqlx::query!(r#"SELECT count(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issues.count, 1);
+    let emails = sent_newsletters(&app).await;
+    assert_eq!(emails.len(), 2);
+    assert!(emails[1].html_content.contains("First post"));
+}
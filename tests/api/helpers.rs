@@ -2,7 +2,10 @@
 
 use argon2::password_hash::SaltString;
 use argon2::{Argon2, PasswordHasher};
-use letter::configuration::get_configuration;
+use chrono::{Duration, Utc};
+use letter::configuration::{get_configuration, EmailHashKey, HmacSecret, Settings};
+use letter::domain::SubscriptionStatus;
+use letter::email::Brevo;
 use letter::startup::build;
 use letter::telemetry::{get_subscriber, init_subscriber};
 use once_cell::sync::Lazy;
@@ -10,7 +13,8 @@ use reqwest::Url;
 use secrecy::ExposeSecret;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
-use wiremock::MockServer;
+use wiremock::matchers::{any, method};
+use wiremock::{Mock, MockServer, ResponseTemplate};
 
 static TRACING: Lazy<()> = Lazy::new(|| {
     let default_filter_level = "info".to_string();
@@ -32,6 +36,9 @@ pub struct Test {
     pub address: String,
     pub db_pool: PgPool,
     pub email_server: MockServer,
+    pub email_client: Brevo,
+    pub hmac_secret: HmacSecret,
+    pub email_hash_key: EmailHashKey,
     pub user: User,
     pub client: reqwest::Client,
 }
@@ -55,6 +62,15 @@ impl Test {
             .expect("Failed to get text response")
     }
 
+    /// The stamp a form page hands out, to send back with the form.
+    pub async fn get_form_stamp(&self, path: &str) -> String {
+        let page = self.get_text(path).await;
+        let marker = r#"name="form_stamp" value=""#;
+        let start = page.find(marker).expect("No form stamp on the page.") + marker.len();
+        let end = start + page[start..].find('"').unwrap();
+        page[start..end].to_string()
+    }
+
     pub async fn login(&self, username: &str, password: &str) -> reqwest::Response {
         let form = [("username", username), ("password", password)];
 
@@ -76,6 +92,20 @@ impl Test {
             .expect("Failed to execute request.")
     }
 
+    /// Post the subscription form with a fresh stamp, as a browser would.
+    pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
+        let stamp: serde_json::Value = self
+            .get("/api/v1/form-stamp")
+            .await
+            .json()
+            .await
+            .expect("Failed to get a form stamp.");
+        let stamp = stamp["form_stamp"].as_str().unwrap();
+
+        self.post_body("/subscriptions", format!("{}&form_stamp={}", body, stamp))
+            .await
+    }
+
     pub async fn post_newsletter(&self, body: serde_json::Value) -> reqwest::Response {
         self.post_request("/newsletters")
             .header("Content-Type", "application/json")
@@ -154,12 +184,24 @@ impl User {
     }
 }
 
+/// The one site allowed to call the subscription API from a browser.
+pub const ALLOWED_ORIGIN: &str = "https://marketing.example.com";
+
 pub async fn setup() -> Test {
+    setup_with(|_| {}).await
+}
+
+/// Like `setup`, with the chance to change the settings before the app starts.
+pub async fn setup_with(configure: impl FnOnce(&mut Settings)) -> Test {
     Lazy::force(&TRACING);
 
     let mut config = get_configuration().expect("Failed to read configuration.");
     config.application.port = 0;
     config.database.database_name = Uuid::new_v4().to_string();
+    config.application.allowed_origins = vec![ALLOWED_ORIGIN.into()];
+    // Tests send the forms back as soon as they get them
+    config.spam_protection.min_submit_seconds = 0;
+    configure(&mut config);
 
     // Create database
     let mut connection = PgConnection::connect(
@@ -194,6 +236,11 @@ pub async fn setup() -> Test {
     // Start email server
     let email_server = MockServer::start().await;
     config.set_email_url(email_server.uri());
+    let email_client = Brevo::from_settings(
+        config.email.clone().unwrap(),
+        db_pool.clone(),
+        config.application.email_hash_key.clone().unwrap(),
+    );
 
     // Create HTTP client
     let client = reqwest::Client::builder()
@@ -218,6 +265,9 @@ pub async fn setup() -> Test {
         address,
         db_pool,
         email_server,
+        email_client,
+        hmac_secret: config.application.hmac_secret.clone().unwrap(),
+        email_hash_key: config.application.email_hash_key.clone().unwrap(),
         user,
         client,
     }
@@ -242,6 +292,177 @@ pub fn extract_link_path(s: &str) -> String {
     }
 }
 
+/// Use the public API of the application under test to create
+/// an unconfirmed subscriber.
+pub async fn create_unconfirmed_subscriber(app: &Test) -> Email {
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    let _mock_guard = Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+
+    app.received_email().await
+}
+
+pub async fn create_confirmed_subscriber(app: &Test) {
+    let email = create_unconfirmed_subscriber(app).await;
+    let confirmation_link = extract_link_path(&email.html_content);
+    app.get(&confirmation_link).await;
+}
+
+/// The id of the only subscriber.
+pub async fn subscriber_id(app: &Test) -> Uuid {
+    sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the subscriber.")
+        .id
+}
+
+/// Every email the email server received, oldest first.
+pub async fn sent_emails<T: serde::de::DeserializeOwned>(app: &Test) -> Vec<T> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| serde_json::from_slice(&request.body).unwrap())
+        .collect()
+}
+
+/// Publish an issue through the API and return its id and slug.
+pub async fn publish_issue(app: &Test, title: &str, body: &str) -> (Uuid, String) {
+    let _mock_guard = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletter(serde_json::json!({
+            "title": title,
+            "body": body,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let issue = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, slug
+        FROM newsletter_issues
+        WHERE title = $1
+        ORDER BY published_at DESC
+        LIMIT 1
+        "#,
+        title
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch the published issue.");
+
+    (issue.newsletter_issue_id, issue.slug)
+}
+
+/// A subscriber who signed up `days_ago`, written straight to the database
+/// to give the dashboard some history. Confirmed subscribers confirmed an
+/// hour after signing up.
+pub async fn seed_subscriber(
+    app: &Test,
+    email: &str,
+    status: SubscriptionStatus,
+    days_ago: i64,
+) -> Uuid {
+    let subscriber_id = Uuid::new_v4();
+    let subscribed_at = Utc::now() - Duration::days(days_ago);
+    let confirmed_at = (status != SubscriptionStatus::PendingConfirmation)
+        .then(|| subscribed_at + Duration::hours(1));
+
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, email_key, name, status, subscribed_at, confirmed_at)
+        VALUES ($1, $2, $3, 'seeded', $4, $5, $6)
+        "#,
+        subscriber_id,
+        email,
+        email.to_lowercase(),
+        status as SubscriptionStatus,
+        subscribed_at,
+        confirmed_at
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to seed the subscriber.");
+
+    subscriber_id
+}
+
+/// The subscriber unsubscribed `days_ago`, with the event that records it.
+pub async fn seed_unsubscribe(app: &Test, subscriber_id: Uuid, days_ago: i64) {
+    let unsubscribed_at = Utc::now() - Duration::days(days_ago);
+
+    sqlx::query!(
+        "UPDATE subscriptions SET status = 'unsubscribed', unsubscribed_at = $2 WHERE id = $1",
+        subscriber_id,
+        unsubscribed_at
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to seed the unsubscribe.");
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_events (
+            event_id, subscriber_id, from_status, to_status, cause, created_at
+        )
+        VALUES ($1, $2, 'confirmed', 'unsubscribed', 'seeded', $3)
+        "#,
+        Uuid::new_v4(),
+        subscriber_id,
+        unsubscribed_at
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to seed the unsubscribe.");
+}
+
+/// The issue reached the subscriber `days_ago`.
+pub async fn seed_delivery(app: &Test, issue_id: Uuid, subscriber_id: Uuid, days_ago: i64) {
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_deliveries (newsletter_issue_id, subscriber_id, delivered_at)
+        VALUES ($1, $2, $3)
+        "#,
+        issue_id,
+        subscriber_id,
+        Utc::now() - Duration::days(days_ago)
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to seed the delivery.");
+}
+
+/// The subscriber opened the issue, as a reader rather than a link scanner.
+pub async fn seed_open(app: &Test, issue_id: Uuid, subscriber_id: Uuid) {
+    sqlx::query!(
+        r#"
+        INSERT INTO engagement_events (
+            event_id, kind, newsletter_issue_id, subscriber_id, automated, occurred_at
+        )
+        VALUES ($1, 'open', $2, $3, FALSE, now())
+        "#,
+        Uuid::new_v4(),
+        issue_id,
+        subscriber_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to seed the open.");
+}
+
 #[derive(serde::Deserialize)]
 pub struct Email {
     #[serde(rename = "htmlContent")]
@@ -0,0 +1,232 @@
+//! tests/api/import.rs
+
+use crate::helpers::{assert_is_redirect_to, setup, Test};
+use letter::confirmation_queue::{try_execute_task, ExecutionOutcome};
+use letter::domain::SubscriptionStatus;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+async fn upload(app: &Test, csv: &str, mode: &str, consent_source: &str) -> reqwest::Response {
+    // The file comes last, as in the form
+    let form = reqwest::multipart::Form::new()
+        .text("email_column", "E-mail")
+        .text("name_column", "Full name")
+        .text("mode", mode.to_string())
+        .text("consent_source", consent_source.to_string())
+        .part(
+            "file",
+            reqwest::multipart::Part::text(csv.to_string()).file_name("subscribers.csv"),
+        );
+
+    app.client
+        .post(format!("{}/admin/subscribers/import", app.address))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn send_queued_confirmations(app: &Test) {
+    while let ExecutionOutcome::TaskCompleted =
+        try_execute_task(&app.db_pool, &app.email_client, "http://127.0.0.1")
+            .await
+            .unwrap()
+    {}
+}
+
+const CSV: &str = "\
+Full name,E-mail,Country
+Ursula Le Guin,ursula@example.com,US
+Ursula Again,URSULA@example.com,US
+No Address,,UK
+Terry Pratchett,terry@example.com,UK
+";
+
+#[tokio::test]
+async fn confirmed_subscribers_are_imported_without_emails() {
+    // Arrange
+    let app = setup().await;
+    app.login(&app.user.username, &app.user.password).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = upload(&app, CSV, "confirmed", "2019 conference signups").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 303);
+
+    let saved =
+        sqlx::query!(r#"SELECT email, status AS "status: SubscriptionStatus", consent_source FROM subscriptions ORDER BY email"#)
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(saved.len(), 2);
+    assert_eq!(saved[0].email, "terry@example.com");
+    assert_eq!(saved[1].email, "ursula@example.com");
+    for subscriber in saved {
+        assert_eq!(subscriber.status, SubscriptionStatus::Confirmed);
+        assert_eq!(
+            subscriber.consent_source.as_deref(),
+            Some("2019 conference signups")
+        );
+    }
+
+    let location = response
+        .headers()
+        .get("Location")
+        .unwrap()
+        .to_str()
+        .unwrap();
+    let html_page = app.get_text(location).await;
+    assert!(html_page.contains("Imported: 2"));
+    assert!(html_page.contains("Skipped duplicates: 1"));
+    assert!(html_page.contains("Errors: 1"));
+}
+
+#[tokio::test]
+async fn pending_subscribers_are_sent_an_opt_in_email() {
+    // Arrange
+    let app = setup().await;
+    app.login(&app.user.username, &app.user.password).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1
+    upload(&app, CSV, "pending_confirmation", "").await;
+
+    // Assert - Part 1 - The emails are queued
+    let received = app.email_server.received_requests().await.unwrap();
+    assert!(received.is_empty());
+
+    // Act - Part 2
+    send_queued_confirmations(&app).await;
+
+    // Assert - Part 2
+    let saved = sqlx::query!(r#"SELECT status AS "status: SubscriptionStatus" FROM subscriptions"#)
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 2);
+    assert!(saved
+        .iter()
+        .all(|s| s.status == SubscriptionStatus::PendingConfirmation));
+}
+
+#[tokio::test]
+async fn addresses_already_subscribed_are_skipped() {
+    // Arrange
+    let app = setup().await;
+    app.login(&app.user.username, &app.user.password).await;
+    upload(&app, CSV, "confirmed", "first import").await;
+
+    // Act
+    let response = upload(&app, CSV, "confirmed", "second import").await;
+
+    // Assert
+    let location = response
+        .headers()
+        .get("Location")
+        .unwrap()
+        .to_str()
+        .unwrap();
+    let html_page = app.get_text(location).await;
+    assert!(html_page.contains("Imported: 0"));
+    assert!(html_page.contains("Skipped duplicates: 3"));
+}
+
+#[tokio::test]
+async fn rejected_rows_can_be_downloaded_as_a_report() {
+    // Arrange
+    let app = setup().await;
+    app.login(&app.user.username, &app.user.password).await;
+    let response = upload(&app, CSV, "confirmed", "2019 conference signups").await;
+    let location = response
+        .headers()
+        .get("Location")
+        .unwrap()
+        .to_str()
+        .unwrap();
+
+    // Act
+    let response = app.get(&format!("{}/errors.csv", location)).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "text/csv; charset=utf-8"
+    );
+    let report = response.text().await.unwrap();
+    assert_eq!(report, "line,email,reason\n4,,Empty email\n");
+}
+
+#[tokio::test]
+async fn confirmed_imports_need_a_consent_source() {
+    // Arrange
+    let app = setup().await;
+    app.login(&app.user.username, &app.user.password).await;
+
+    // Act
+    let response = upload(&app, CSV, "confirmed", "").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/subscribers/import");
+
+    let html_page = app.get_text("/admin/subscribers/import").await;
+    assert!(html_page.contains("Confirmed subscribers need a consent source."));
+}
+
+#[tokio::test]
+async fn options_sent_after_the_file_are_rejected() {
+    // Arrange
+    let app = setup().await;
+    app.login(&app.user.username, &app.user.password).await;
+    let form = reqwest::multipart::Form::new()
+        .part(
+            "file",
+            reqwest::multipart::Part::text(CSV).file_name("subscribers.csv"),
+        )
+        .text("mode", "confirmed")
+        .text("consent_source", "2019 conference signups");
+
+    // Act
+    let response = app
+        .client
+        .post(format!("{}/admin/subscribers/import", app.address))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/subscribers/import");
+
+    let html_page = app.get_text("/admin/subscribers/import").await;
+    assert!(html_page.contains("The import options must come before the file in the form."));
+    let subscribers = sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscribers.count, 0);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_import_subscribers() {
+    // Arrange
+    let app = setup().await;
+
+    // Act
+    let response = upload(&app, CSV, "confirmed", "2019 conference signups").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,237 @@
+//! tests/api/lists.rs
+
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, extract_link_path, sent_emails, setup, Test,
+};
+use letter::domain::ListSubscriptionStatus;
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+const SUBSCRIBER: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+async fn create_list(app: &Test, name: &str) -> Uuid {
+    app.login(&app.user.username, &app.user.password).await;
+    let response = app.post_form("/admin/lists", &[("name", name)]).await;
+    assert_eq!(response.status().as_u16(), 303);
+
+    sqlx::query!("SELECT list_id FROM lists WHERE name = $1", name)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .list_id
+}
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_list_is_rejected() {
+    // Arrange
+    let app = setup().await;
+
+    // Act
+    let response = app
+        .post_subscriptions(format!("{}&list=nope", SUBSCRIBER))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn each_list_is_confirmed_separately() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    let list_id = create_list(&app, "Release notes").await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Join a second list
+    let response = app
+        .post_subscriptions(format!("{}&list=release-notes", SUBSCRIBER))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Assert - Part 1 - One subscriber, waiting to confirm the new list only
+    let memberships = sqlx::query!(
+        r#"
+        SELECT list_id, status AS "status: ListSubscriptionStatus"
+        FROM list_subscriptions
+        ORDER BY subscribed_at
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(memberships.len(), 2);
+    assert_eq!(memberships[0].status, ListSubscriptionStatus::Confirmed);
+    assert_eq!(memberships[1].list_id, list_id);
+    assert_eq!(
+        memberships[1].status,
+        ListSubscriptionStatus::PendingConfirmation
+    );
+
+    // Act - Part 2 - Confirm it
+    let emails: Vec<serde_json::Value> = sent_emails(&app).await;
+    let html_content = emails.last().unwrap()["htmlContent"].as_str().unwrap();
+    app.get(&extract_link_path(html_content)).await;
+
+    // Assert - Part 2
+    let membership = sqlx::query!(
+        r#"SELECT status AS "status: ListSubscriptionStatus" FROM list_subscriptions WHERE list_id = $1"#,
+        list_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(membership.status, ListSubscriptionStatus::Confirmed);
+}
+
+#[tokio::test]
+async fn signing_up_again_for_a_confirmed_list_sends_nothing() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions(SUBSCRIBER.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    // Mock verifies on Drop that no confirmation email was sent
+}
+
+#[tokio::test]
+async fn issues_only_go_to_the_lists_they_target() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    create_list(&app, "Release notes").await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletter(serde_json::json!({
+            "title": "Version 2",
+            "body": "<p>News</p>",
+            "lists": ["release-notes"],
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app
+        .post_newsletter(serde_json::json!({
+            "title": "Weekly update",
+            "body": "<p>News</p>",
+            "lists": ["default", "release-notes"],
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Assert
+    let archive = app.get_text("/lists/release-notes/archive").await;
+    assert!(archive.contains("Version 2"));
+    assert!(archive.contains("Weekly update"));
+
+    let archive = app.get_text("/lists/default/archive").await;
+    assert!(!archive.contains("Version 2"));
+    assert!(archive.contains("Weekly update"));
+
+    // Mock verifies on Drop that only the second issue was sent
+}
+
+#[tokio::test]
+async fn publishing_to_an_unknown_list_is_rejected() {
+    // Arrange
+    let app = setup().await;
+
+    // Act
+    let response = app
+        .post_newsletter(serde_json::json!({
+            "title": "Version 2",
+            "body": "<p>News</p>",
+            "lists": ["nope"],
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn lists_send_with_their_own_sender_and_copy() {
+    // Arrange
+    let app = setup().await;
+    let list_id = create_list(&app, "Release notes").await;
+    let edit_page = format!("/admin/lists/{}", list_id);
+
+    let response = app
+        .post_form(
+            &edit_page,
+            &[
+                ("name", "Release notes"),
+                ("sender_name", "Release bot"),
+                ("sender_email", "releases@example.com"),
+                ("confirmation_subject", "Confirm your release notes"),
+                ("confirmation_html", "<p>Only the big releases.</p>"),
+            ],
+        )
+        .await;
+    assert_is_redirect_to(&response, &edit_page);
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions(format!("{}&list=release-notes", SUBSCRIBER))
+        .await;
+
+    // Assert
+    let emails: Vec<serde_json::Value> = sent_emails(&app).await;
+    let email = &emails[0];
+    assert_eq!(email["sender"]["email"], "releases@example.com");
+    assert_eq!(email["subject"], "Confirm your release notes");
+    assert!(email["htmlContent"]
+        .as_str()
+        .unwrap()
+        .contains("Only the big releases."));
+}
+
+#[tokio::test]
+async fn a_list_sender_needs_a_name_and_an_address() {
+    // Arrange
+    let app = setup().await;
+    let list_id = create_list(&app, "Release notes").await;
+    let edit_page = format!("/admin/lists/{}", list_id);
+
+    // Act
+    let response = app
+        .post_form(
+            &edit_page,
+            &[("name", "Release notes"), ("sender_name", "Release bot")],
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, &edit_page);
+    assert!(app
+        .get_text(&edit_page)
+        .await
+        .contains("A sender needs both a name and an email address."));
+}
@@ -0,0 +1,174 @@
+//! tests/api/mailbox.rs
+
+use crate::helpers::{
+    create_confirmed_subscriber, publish_issue, sent_emails, setup, setup_with, Test,
+};
+use letter::domain::SubscriptionStatus;
+use letter::mailbox::process_mailbox;
+use letter::privacy::email_hash;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+const HARD_BOUNCE: &str = include_str!("fixtures/mail/hard_bounce.eml");
+const COMPLAINT: &str = include_str!("fixtures/mail/complaint.eml");
+
+/// An empty Maildir in the temporary directory.
+fn maildir() -> PathBuf {
+    let path = std::env::temp_dir().join(format!("maildir-{}", Uuid::new_v4()));
+    for folder in ["new", "cur", "tmp"] {
+        std::fs::create_dir_all(path.join(folder)).unwrap();
+    }
+    path
+}
+
+async fn subscriber_status(app: &Test) -> SubscriptionStatus {
+    sqlx::query!(r#"SELECT status AS "status: SubscriptionStatus" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+#[tokio::test]
+async fn bounces_in_a_maildir_are_applied_and_marked_as_seen() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    let maildir = maildir();
+    std::fs::write(maildir.join("new/1705917600.1.mx"), HARD_BOUNCE).unwrap();
+
+    // Act
+    let report = process_mailbox(&app.db_pool, &app.email_hash_key, &maildir)
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!((report.messages, report.applied), (1, 1));
+    assert_eq!(subscriber_status(&app).await, SubscriptionStatus::Bounced);
+    assert!(maildir.join("cur/1705917600.1.mx:2,S").exists());
+    assert!(!maildir.join("new/1705917600.1.mx").exists());
+
+    let feedback = sqlx::query!("SELECT source, kind, message_id FROM delivery_feedback")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(feedback.source, "mailbox");
+    assert_eq!(feedback.kind, "hard_bounce");
+    assert_eq!(
+        feedback.message_id.as_deref(),
+        Some("<issue-1.ursula@letter.example>")
+    );
+
+    let suppressed = sqlx::query!(
+        "SELECT reason FROM suppressions WHERE email_hash = $1",
+        email_hash("ursula_le_guin@gmail.com", &app.email_hash_key)
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(suppressed.reason, "hard_bounce");
+
+    std::fs::remove_dir_all(maildir).unwrap();
+}
+
+#[tokio::test]
+async fn bounces_are_traced_back_to_the_issue_by_message_id() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    let (issue_id, _) = publish_issue(&app, "Newsletter title", "Newsletter body").await;
+    // The fixture returns the headers of an email with this Message-ID
+    sqlx::query!(
+        "UPDATE newsletter_issue_deliveries SET message_id = '<issue-1.ursula@letter.example>'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let maildir = maildir();
+    std::fs::write(maildir.join("new/1705917600.1.mx"), HARD_BOUNCE).unwrap();
+
+    // Act
+    process_mailbox(&app.db_pool, &app.email_hash_key, &maildir)
+        .await
+        .unwrap();
+
+    // Assert
+    let feedback = sqlx::query!("SELECT newsletter_issue_id FROM delivery_feedback")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(feedback.newsletter_issue_id, Some(issue_id));
+
+    std::fs::remove_dir_all(maildir).unwrap();
+}
+
+#[tokio::test]
+async fn bounces_are_traced_back_to_the_issue_by_their_verp_address() {
+    // Arrange
+    let app = setup_with(|config| {
+        config.email.as_mut().unwrap().verp_domain = Some("letter.example".into());
+    })
+    .await;
+    create_confirmed_subscriber(&app).await;
+    let (issue_id, _) = publish_issue(&app, "Newsletter title", "Newsletter body").await;
+    let sent: serde_json::Value = sent_emails(&app).await.pop().unwrap();
+    let return_path = sent["headers"]["Return-Path"].as_str().unwrap();
+    // The returned headers carry a Message-ID that matches no delivery.
+    let bounce = HARD_BOUNCE.replace(
+        "bounces+ursula_le_guin=gmail.com@letter.example",
+        return_path,
+    );
+    let maildir = maildir();
+    std::fs::write(maildir.join("new/1705917600.1.mx"), bounce).unwrap();
+
+    // Act
+    process_mailbox(&app.db_pool, &app.email_hash_key, &maildir)
+        .await
+        .unwrap();
+
+    // Assert
+    let feedback = sqlx::query!("SELECT newsletter_issue_id FROM delivery_feedback")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(feedback.newsletter_issue_id, Some(issue_id));
+    assert_eq!(subscriber_status(&app).await, SubscriptionStatus::Bounced);
+
+    std::fs::remove_dir_all(maildir).unwrap();
+}
+
+#[tokio::test]
+async fn complaints_in_an_mbox_are_applied_once_however_often_it_is_read() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    let mbox = std::env::temp_dir().join(format!("mbox-{}", Uuid::new_v4()));
+    let content = format!(
+        "From fbl@isp.example Mon Jan 22 12:00:00 2024\n{}\n\
+        From ursula_le_guin@gmail.com Mon Jan 22 12:30:00 2024\n\
+        From: ursula_le_guin@gmail.com\n\
+        Subject: Out of office\n\
+        \n\
+        Back on Monday.\n",
+        COMPLAINT
+    );
+    std::fs::write(&mbox, content).unwrap();
+
+    // Act
+    let first = process_mailbox(&app.db_pool, &app.email_hash_key, &mbox)
+        .await
+        .unwrap();
+    let again = process_mailbox(&app.db_pool, &app.email_hash_key, &mbox)
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!((first.messages, first.applied, first.ignored), (2, 1, 1));
+    assert_eq!((again.messages, again.applied, again.ignored), (2, 0, 1));
+    assert_eq!(
+        subscriber_status(&app).await,
+        SubscriptionStatus::Complained
+    );
+
+    std::fs::remove_file(mbox).unwrap();
+}
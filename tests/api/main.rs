@@ -1,9 +1,29 @@
 //! tests/api/main.rs
 
 mod admin;
+mod archive;
+mod email_policy;
+mod export;
+mod feed;
+mod feed_poller;
 mod health_check;
 mod helpers;
+mod import;
+mod lists;
 mod login;
+mod mailbox;
 mod newsletters;
+mod preferences;
+mod privacy;
+mod reports;
+mod revisions;
+mod segments;
+mod spam;
+mod subscribers;
 mod subscriptions;
+mod subscriptions_api;
 mod subscriptions_confirm;
+mod suppressions;
+mod tracking;
+mod webhooks;
+mod widget;
@@ -1,35 +1,8 @@
 //! tests/api/newsletters.rs
 
-use crate::helpers::{extract_link_path, setup, Email, Test};
+use crate::helpers::{create_confirmed_subscriber, create_unconfirmed_subscriber, setup};
 use uuid::Uuid;
-use wiremock::{
-    matchers::{any, method},
-    Mock, ResponseTemplate,
-};
-
-/// Use the public API of the application under test to create
-/// an unconfirmed subscriber.
-async fn create_unconfirmed_subscriber(app: &Test) -> Email {
-    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
-
-    let _mock_guard = Mock::given(any())
-        .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
-        .expect(1)
-        .mount_as_scoped(&app.email_server)
-        .await;
-
-    app.post_body("/subscriptions", body.into()).await;
-
-    app.received_email().await
-}
-
-async fn create_confirmed_subscriber(app: &Test) {
-    let email = create_unconfirmed_subscriber(app).await;
-    let confirmation_link = extract_link_path(&email.html_content);
-    app.get(&confirmation_link).await;
-}
+use wiremock::{matchers::any, Mock, ResponseTemplate};
 
 #[tokio::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
@@ -0,0 +1,412 @@
+//! tests/api/preferences.rs
+
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, publish_issue, sent_emails, setup,
+    subscriber_id, Email, Test,
+};
+use letter::digest::send_due_digests;
+use letter::domain::SubscriptionStatus;
+use letter::preferences::preferences_token;
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+async fn preferences_page(app: &Test) -> String {
+    let subscriber_id = subscriber_id(app).await;
+    format!(
+        "/preferences/{}",
+        preferences_token(subscriber_id, &app.hmac_secret)
+    )
+}
+
+async fn default_list_id(app: &Test) -> String {
+    sqlx::query!("SELECT list_id FROM lists WHERE slug = 'default'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .list_id
+        .to_string()
+}
+
+async fn create_topic(app: &Test, name: &str) -> Uuid {
+    app.login(&app.user.username, &app.user.password).await;
+    let response = app.post_form("/admin/topics", &[("name", name)]).await;
+    assert_is_redirect_to(&response, "/admin/topics");
+
+    sqlx::query!("SELECT topic_id FROM topics WHERE name = $1", name)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .topic_id
+}
+
+#[tokio::test]
+async fn issue_emails_link_to_the_preference_center() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    publish_issue(&app, "Issue #1", "<p>Hello</p>").await;
+
+    // Assert
+    let emails: Vec<Email> = sent_emails(&app).await;
+    let issue_email = emails.last().unwrap();
+    let link = linkify::LinkFinder::new()
+        .links(&issue_email.html_content)
+        .map(|link| link.as_str().to_string())
+        .find(|link| link.contains("/preferences/"))
+        .expect("No preferences link in the issue email");
+    let path = reqwest::Url::parse(&link).unwrap().path().to_string();
+
+    let page = app.get_text(&path).await;
+    assert!(page.contains("ursula_le_guin@gmail.com"));
+}
+
+#[tokio::test]
+async fn preference_links_with_a_bad_signature_are_rejected() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber_id = subscriber_id(&app).await;
+
+    // Act
+    let response = app
+        .get(&format!(
+            "/preferences/{}.{}",
+            subscriber_id,
+            "00".repeat(32)
+        ))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn subscribers_do_not_receive_topics_they_opted_out_of() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    let events = create_topic(&app, "Events").await;
+    let releases = create_topic(&app, "Releases").await;
+    let page = preferences_page(&app).await;
+    let list = default_list_id(&app).await;
+
+    // Act - Part 1 - Keep releases only
+    let response = app
+        .post_form(
+            &page,
+            &[
+                ("name", "Ursula K. Le Guin"),
+                ("frequency", "every_issue"),
+                ("topic", &releases.to_string()),
+                ("list", &list),
+            ],
+        )
+        .await;
+    assert_is_redirect_to(&response, &page);
+
+    // Act - Part 2 - Publish an issue about each topic
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    for (title, topic) in [("Meetup", "Events"), ("Version 2", "Releases")] {
+        let response = app
+            .post_newsletter(serde_json::json!({
+                "title": title,
+                "body": "<p>News</p>",
+                "topic": topic,
+            }))
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    // Assert
+    let subscriber = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.name, "Ursula K. Le Guin");
+
+    let opt_outs = sqlx::query!("SELECT topic_id FROM subscriber_topic_opt_outs")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(opt_outs.len(), 1);
+    assert_eq!(opt_outs[0].topic_id, events);
+}
+
+#[tokio::test]
+async fn publishing_to_an_unknown_topic_is_rejected() {
+    // Arrange
+    let app = setup().await;
+
+    // Act
+    let response = app
+        .post_newsletter(serde_json::json!({
+            "title": "Meetup",
+            "body": "<p>News</p>",
+            "topic": "Nope",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn paused_subscribers_receive_nothing_until_they_resume() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    let page = preferences_page(&app).await;
+    let pause = format!("{}/pause", page);
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Pause
+    let response = app.post_form(&pause, &[("weeks", "4")]).await;
+    assert_is_redirect_to(&response, &page);
+    app.post_newsletter(serde_json::json!({"title": "While away", "body": "<p>Hi</p>"}))
+        .await;
+
+    // Act - Part 2 - Resume
+    app.post_form(&pause, &[("weeks", "0")]).await;
+    app.post_newsletter(serde_json::json!({"title": "Welcome back", "body": "<p>Hi</p>"}))
+        .await;
+
+    // Mock verifies on Drop that only the second issue was sent
+}
+
+#[tokio::test]
+async fn pauses_longer_than_a_year_are_refused() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    let page = preferences_page(&app).await;
+
+    // Act
+    app.post_form(&format!("{}/pause", page), &[("weeks", "53")])
+        .await;
+
+    // Assert
+    let subscriber = sqlx::query!("SELECT paused_until FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(subscriber.paused_until.is_none());
+    assert!(app
+        .get_text(&page)
+        .await
+        .contains("You can pause for at most 52 weeks."));
+}
+
+#[tokio::test]
+async fn subscribers_can_unsubscribe_with_a_reason() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    let page = preferences_page(&app).await;
+
+    // Act
+    let response = app
+        .post_form(
+            &format!("{}/unsubscribe", page),
+            &[("reason", "Too many emails")],
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, &page);
+
+    let subscriber =
+        sqlx::query!(r#"SELECT status AS "status: SubscriptionStatus", unsubscribed_at, unsubscribe_reason FROM subscriptions"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(subscriber.status, SubscriptionStatus::Unsubscribed);
+    assert!(subscriber.unsubscribed_at.is_some());
+    assert_eq!(
+        subscriber.unsubscribe_reason.as_deref(),
+        Some("Too many emails")
+    );
+
+    assert!(app.get_text(&page).await.contains("You are unsubscribed"));
+}
+
+#[tokio::test]
+async fn weekly_digest_subscribers_get_issues_together() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    let page = preferences_page(&app).await;
+    let list = default_list_id(&app).await;
+    app.post_form(
+        &page,
+        &[
+            ("name", "le guin"),
+            ("frequency", "weekly_digest"),
+            ("list", &list),
+        ],
+    )
+    .await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let before = sent_emails::<Email>(&app).await.len();
+
+    // Act - Part 1 - Issues are not sent on their own
+    publish_issue(&app, "Issue #1", "<p>First</p>").await;
+    publish_issue(&app, "Issue #2", "<p>Second</p>").await;
+    assert_eq!(sent_emails::<Email>(&app).await.len(), before);
+
+    // Act - Part 2 - Nothing is due within the first week
+    let sent = send_due_digests(
+        &app.db_pool,
+        &app.email_client,
+        "http://127.0.0.1",
+        &app.hmac_secret,
+    )
+    .await
+    .unwrap();
+    assert_eq!(sent, 0);
+
+    // Act - Part 3 - A week later
+    sqlx::query!("UPDATE subscriptions SET last_digest_at = now() - interval '8 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let sent = send_due_digests(
+        &app.db_pool,
+        &app.email_client,
+        "http://127.0.0.1",
+        &app.hmac_secret,
+    )
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(sent, 1);
+    let emails: Vec<Email> = sent_emails(&app).await;
+    assert_eq!(emails.len(), before + 1);
+    let digest = &emails.last().unwrap().html_content;
+    assert!(digest.contains("<p>First</p>"));
+    assert!(digest.contains("<p>Second</p>"));
+
+    let deliveries = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issue_deliveries")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(deliveries.len(), 2);
+}
+
+#[tokio::test]
+async fn digests_are_sent_as_the_list_sender() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!(
+        r#"
+        UPDATE lists
+        SET sender_name = 'Release bot', sender_email = 'releases@example.com'
+        WHERE slug = 'default'
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET frequency = 'weekly_digest', last_digest_at = now() - interval '8 days'
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    publish_issue(&app, "Issue #1", "<p>First</p>").await;
+
+    // Act
+    let sent = send_due_digests(
+        &app.db_pool,
+        &app.email_client,
+        "http://127.0.0.1",
+        &app.hmac_secret,
+    )
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(sent, 1);
+    let emails: Vec<serde_json::Value> = sent_emails(&app).await;
+    let digest = emails.last().unwrap();
+    assert_eq!(digest["sender"]["email"], "releases@example.com");
+}
+
+#[tokio::test]
+async fn digests_brevo_refuses_are_tried_again() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET frequency = 'weekly_digest', last_digest_at = now() - interval '8 days'
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    publish_issue(&app, "Issue #1", "<p>First</p>").await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let sent = send_due_digests(
+        &app.db_pool,
+        &app.email_client,
+        "http://127.0.0.1",
+        &app.hmac_secret,
+    )
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(sent, 0);
+    let subscriber = sqlx::query!(
+        r#"
+        SELECT last_digest_at <= now() - interval '7 days' AS "still_due!"
+        FROM subscriptions
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(subscriber.still_due);
+    let deliveries =
+        sqlx::query!(r#"SELECT count(*) AS "count!" FROM newsletter_issue_deliveries"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(deliveries.count, 0);
+}
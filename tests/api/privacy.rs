@@ -0,0 +1,274 @@
+//! tests/api/privacy.rs
+
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_unconfirmed_subscriber,
+    extract_link_path, setup, subscriber_id, Email, Test,
+};
+use letter::privacy::email_hash;
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+/// Ask for a privacy link for the test subscriber and return its path.
+async fn request_privacy_link(app: &Test) -> String {
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_form("/privacy", &[("email", "Ursula_Le_Guin@gmail.com")])
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let email: Email = serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+    extract_link_path(&email.html_content)
+}
+
+#[tokio::test]
+async fn admins_can_download_a_subscribers_data() {
+    // Arrange
+    let app = setup().await;
+    create_unconfirmed_subscriber(&app).await;
+    let subscriber_id = subscriber_id(&app).await;
+    app.login(&app.user.username, &app.user.password).await;
+
+    // Act
+    let response = app
+        .get(&format!("/admin/subscribers/{}/data", subscriber_id))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["email"], "ursula_le_guin@gmail.com");
+    let subscription = &data["subscriptions"][0];
+    assert_eq!(subscription["name"], "le guin");
+    assert_eq!(subscription["status"], "pending_confirmation");
+    assert_eq!(subscription["tokens"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn erasing_a_subscriber_removes_their_data_and_suppresses_the_address() {
+    // Arrange
+    let app = setup().await;
+    create_unconfirmed_subscriber(&app).await;
+    let subscriber_id = subscriber_id(&app).await;
+    app.login(&app.user.username, &app.user.password).await;
+
+    // Act
+    let response = app
+        .post_form(
+            &format!("/admin/subscribers/{}/erase", subscriber_id),
+            &[("", "")],
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/subscribers");
+
+    let subscriptions = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(subscriptions.is_empty());
+
+    let tokens = sqlx::query!("SELECT subscription_token FROM subscription_tokens")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(tokens.is_empty());
+
+    let suppression = sqlx::query!("SELECT email_hash, reason, source FROM suppressions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(suppression.email_hash, "ursula_le_guin@gmail.com");
+    assert_eq!(suppression.reason, "erased");
+    assert_eq!(suppression.source, "admin");
+}
+
+#[tokio::test]
+async fn erasing_reaches_what_only_the_hash_of_the_address_links_to() {
+    // Arrange
+    let app = setup().await;
+    create_unconfirmed_subscriber(&app).await;
+    let subscriber_id = subscriber_id(&app).await;
+    let email_hash = email_hash("ursula_le_guin@gmail.com", &app.email_hash_key);
+    sqlx::query!(
+        r#"
+        INSERT INTO delivery_feedback (
+            feedback_id, source, event_key, kind, email_hash, message_id, reason, received_at
+        )
+        VALUES ($1, 'mailbox', 'digest:ursula_le_guin@gmail.com', 'hard_bounce', $2,
+            '<issue@letter.example>', '550 ursula_le_guin@gmail.com: no such user', now())
+        "#,
+        Uuid::new_v4(),
+        email_hash
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "INSERT INTO used_form_stamps (nonce, used_at, email_hash) VALUES ('nonce', now(), $1)",
+        email_hash
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.login(&app.user.username, &app.user.password).await;
+
+    // Act
+    app.post_form(
+        &format!("/admin/subscribers/{}/erase", subscriber_id),
+        &[("", "")],
+    )
+    .await;
+
+    // Assert
+    let attempts = sqlx::query!("SELECT attempt_id FROM signup_attempts")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(attempts.is_empty());
+
+    let stamps = sqlx::query!("SELECT nonce FROM used_form_stamps")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(stamps.is_empty());
+
+    let feedback = sqlx::query!(
+        "SELECT event_key, kind, email_hash, message_id, reason FROM delivery_feedback"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(feedback.event_key.starts_with("erased:"));
+    assert_eq!(feedback.kind, "hard_bounce");
+    assert_eq!(feedback.email_hash, None);
+    assert_eq!(feedback.message_id, None);
+    assert_eq!(feedback.reason, None);
+}
+
+#[tokio::test]
+async fn erased_addresses_are_not_imported_again() {
+    // Arrange
+    let app = setup().await;
+    create_unconfirmed_subscriber(&app).await;
+    let subscriber_id = subscriber_id(&app).await;
+    app.login(&app.user.username, &app.user.password).await;
+    app.post_form(
+        &format!("/admin/subscribers/{}/erase", subscriber_id),
+        &[("", "")],
+    )
+    .await;
+
+    let form = reqwest::multipart::Form::new()
+        .part(
+            "file",
+            reqwest::multipart::Part::text("email,name\nURSULA_LE_GUIN@gmail.com,le guin\n")
+                .file_name("subscribers.csv"),
+        )
+        .text("mode", "confirmed")
+        .text("consent_source", "old list");
+
+    // Act
+    app.client
+        .post(format!("{}/admin/subscribers/import", app.address))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    let subscriptions = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(subscriptions.is_empty());
+}
+
+#[tokio::test]
+async fn subscribers_can_download_and_erase_their_own_data() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act - Part 1 - Follow the emailed link
+    let manage_link = request_privacy_link(&app).await;
+    let page = app.get_text(&manage_link).await;
+    assert!(page.contains("Erase my data"));
+
+    // Act - Part 2 - Download
+    let export_link = manage_link.replace("/privacy/manage", "/privacy/export");
+    let data: serde_json::Value = app.get(&export_link).await.json().await.unwrap();
+    assert_eq!(
+        data["subscriptions"][0]["email"],
+        "ursula_le_guin@gmail.com"
+    );
+    assert_eq!(data["subscriptions"][0]["status"], "confirmed");
+
+    // Act - Part 3 - Erase
+    let query = manage_link.split_once('?').unwrap().1;
+    let response = app.post_body("/privacy/erase", query.to_string()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let subscriptions = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(subscriptions.is_empty());
+
+    let suppression = sqlx::query!("SELECT source FROM suppressions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(suppression.source, "self_service");
+}
+
+#[tokio::test]
+async fn unknown_addresses_get_the_same_answer_but_no_email() {
+    // Arrange
+    let app = setup().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_form("/privacy", &[("email", "nobody@example.com")])
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("If we have your address"));
+}
+
+#[tokio::test]
+async fn tampered_privacy_links_are_rejected() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    let manage_link = request_privacy_link(&app).await;
+    let tampered = manage_link.replace("ursula_le_guin", "someone_else");
+
+    // Act
+    let manage = app.get(&tampered).await;
+    let export = app
+        .get(&tampered.replace("/privacy/manage", "/privacy/export"))
+        .await;
+
+    // Assert
+    assert_eq!(manage.status().as_u16(), 403);
+    assert_eq!(export.status().as_u16(), 403);
+}
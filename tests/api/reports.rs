@@ -0,0 +1,203 @@
+//! tests/api/reports.rs
+
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, publish_issue, setup, Test,
+};
+use letter::mailbox::process_mailbox;
+use letter::preferences::preferences_token;
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+const BODY: &str = r#"<p>Read <a href="https://example.com/story">the story</a>.</p>"#;
+const HARD_BOUNCE: &str = include_str!("fixtures/mail/hard_bounce.eml");
+
+async fn get_report(app: &Test, issue_id: Uuid) -> reqwest::Response {
+    app.client
+        .get(format!(
+            "{}/newsletters/issues/{}/report",
+            app.address, issue_id
+        ))
+        .basic_auth(&app.user.username, Some(&app.user.password))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+/// A tracked issue, delivered an hour ago so that the events that follow are
+/// not taken for link scanners.
+async fn publish_tracked_issue(app: &Test) -> Uuid {
+    sqlx::query!("UPDATE lists SET track_engagement = TRUE")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let (issue_id, _) = publish_issue(app, "Newsletter title", BODY).await;
+    sqlx::query!("UPDATE newsletter_issue_deliveries SET delivered_at = now() - interval '1 hour'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    issue_id
+}
+
+async fn click_the_story(app: &Test) {
+    let requests = app.email_server.received_requests().await.unwrap();
+    let email: serde_json::Value = serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+    let html = email["htmlContent"].as_str().unwrap();
+    let start = html.find("/t/c/").unwrap();
+    let end = start + html[start..].find('"').unwrap();
+
+    let response = app
+        .client
+        .get(format!("{}{}", app.address, &html[start..end]))
+        .header(
+            "User-Agent",
+            "Mozilla/5.0 (X11; Linux x86_64; rv:121.0) Firefox/121.0",
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 302);
+}
+
+#[tokio::test]
+async fn reports_count_what_happened_to_an_issue() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = publish_tracked_issue(&app).await;
+    click_the_story(&app).await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    app.post_form(
+        &format!(
+            "/preferences/{}/unsubscribe",
+            preferences_token(subscriber_id, &app.hmac_secret)
+        ),
+        &[("reason", "Too many emails")],
+    )
+    .await;
+
+    // Act
+    let response = get_report(&app, issue_id).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        report["totals"],
+        serde_json::json!({
+            "sent": 1,
+            "failed": 0,
+            "bounced": 0,
+            "opened": 1,
+            "clicked": 1,
+            "unsubscribed": 1,
+        })
+    );
+    assert_eq!(report["queued"], 0);
+    assert_eq!(report["granularity"], "hour");
+    assert_eq!(
+        report["top_links"],
+        serde_json::json!([{ "url": "https://example.com/story", "clicks": 1, "subscribers": 1 }])
+    );
+    let timeline = report["timeline"].as_array().unwrap();
+    assert_eq!(timeline.first().unwrap()["sent"], 1);
+    assert_eq!(timeline.last().unwrap()["clicked"], 1);
+}
+
+#[tokio::test]
+async fn recipients_can_be_downloaded_with_their_bounces() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = publish_tracked_issue(&app).await;
+    // The fixture returns the headers of an email with this Message-ID
+    sqlx::query!(
+        "UPDATE newsletter_issue_deliveries SET message_id = '<issue-1.ursula@letter.example>'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let maildir = std::env::temp_dir().join(format!("maildir-{}", Uuid::new_v4()));
+    for folder in ["new", "cur", "tmp"] {
+        std::fs::create_dir_all(maildir.join(folder)).unwrap();
+    }
+    std::fs::write(maildir.join("new/1705917600.1.mx"), HARD_BOUNCE).unwrap();
+    process_mailbox(&app.db_pool, &app.email_hash_key, &maildir)
+        .await
+        .unwrap();
+    app.login(&app.user.username, &app.user.password).await;
+
+    // Act
+    let csv = app
+        .get_text(&format!("/admin/issues/{}/report/recipients.csv", issue_id))
+        .await;
+
+    // Assert
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(
+        lines[0],
+        "id,email,name,delivered_at,message_id,feedback,opened_at,clicks,unsubscribed_at"
+    );
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1].contains("ursula_le_guin@gmail.com"));
+    assert!(lines[1].contains(",hard_bounce,"));
+
+    let report: serde_json::Value = get_report(&app, issue_id).await.json().await.unwrap();
+    assert_eq!(report["totals"]["bounced"], 1);
+    let page = app
+        .get_text(&format!("/admin/issues/{}/report", issue_id))
+        .await;
+    assert!(page.contains("<th>Bounced</th><td>1</td><td>100.0%</td>"));
+
+    std::fs::remove_dir_all(maildir).unwrap();
+}
+
+#[tokio::test]
+async fn emails_brevo_refuses_are_reported_as_failed() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletter(serde_json::json!({
+            "title": "Newsletter title",
+            "body": BODY,
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+    let report: serde_json::Value = get_report(&app, issue_id).await.json().await.unwrap();
+    assert_eq!(report["totals"]["sent"], 0);
+    assert_eq!(report["totals"]["failed"], 1);
+}
+
+#[tokio::test]
+async fn reports_are_only_for_published_issues_and_admins() {
+    // Arrange
+    let app = setup().await;
+
+    // Act
+    let api = get_report(&app, Uuid::new_v4()).await;
+    let page = app
+        .get(&format!("/admin/issues/{}/report", Uuid::new_v4()))
+        .await;
+
+    // Assert
+    assert_eq!(api.status().as_u16(), 404);
+    assert_is_redirect_to(&page, "/login");
+}
@@ -0,0 +1,180 @@
+//! tests/api/revisions.rs
+
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, setup, Test};
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+/// Create a draft through the composer and return its id.
+async fn create_draft(app: &Test, title: &str, body: &str) -> Uuid {
+    let response = app
+        .post_form("/admin/issues", &[("title", title), ("html_content", body)])
+        .await;
+    assert_eq!(response.status().as_u16(), 303);
+
+    let location = response
+        .headers()
+        .get("Location")
+        .unwrap()
+        .to_str()
+        .unwrap();
+    location
+        .trim_start_matches("/admin/issues/")
+        .parse()
+        .expect("Failed to parse the issue id.")
+}
+
+async fn revision_ids(app: &Test, issue_id: Uuid) -> Vec<Uuid> {
+    sqlx::query!(
+        r#"
+        SELECT revision_id
+        FROM newsletter_issue_revisions
+        WHERE newsletter_issue_id = $1
+        ORDER BY created_at
+        "#,
+        issue_id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .expect("Failed to fetch revisions.")
+    .into_iter()
+    .map(|r| r.revision_id)
+    .collect()
+}
+
+#[tokio::test]
+async fn every_save_is_stored_as_a_revision() {
+    // Arrange
+    let app = setup().await;
+    app.login(&app.user.username, &app.user.password).await;
+    let issue_id = create_draft(&app, "Draft title", "First body").await;
+
+    // Act
+    let response = app
+        .post_form(
+            &format!("/admin/issues/{}", issue_id),
+            &[("title", "Draft title"), ("html_content", "Second body")],
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, &format!("/admin/issues/{}", issue_id));
+    assert_eq!(revision_ids(&app, issue_id).await.len(), 2);
+
+    let html_page = app
+        .get_text(&format!("/admin/issues/{}/revisions", issue_id))
+        .await;
+    assert!(html_page.contains(&app.user.username));
+}
+
+#[tokio::test]
+async fn a_revision_is_shown_as_a_diff_against_the_previous_one() {
+    // Arrange
+    let app = setup().await;
+    app.login(&app.user.username, &app.user.password).await;
+    let issue_id = create_draft(&app, "Draft title", "Kept line\nOld line").await;
+    app.post_form(
+        &format!("/admin/issues/{}", issue_id),
+        &[
+            ("title", "Draft title"),
+            ("html_content", "Kept line\nNew line"),
+        ],
+    )
+    .await;
+    let revisions = revision_ids(&app, issue_id).await;
+
+    // Act
+    let html_page = app
+        .get_text(&format!(
+            "/admin/issues/{}/revisions/{}",
+            issue_id, revisions[1]
+        ))
+        .await;
+
+    // Assert
+    assert!(html_page.contains(r#"<span class="unchanged">  Kept line</span>"#));
+    assert!(html_page.contains(r#"<span class="removed">- Old line</span>"#));
+    assert!(html_page.contains(r#"<span class="added">+ New line</span>"#));
+}
+
+#[tokio::test]
+async fn restoring_a_revision_saves_its_content_as_a_new_revision() {
+    // Arrange
+    let app = setup().await;
+    app.login(&app.user.username, &app.user.password).await;
+    let issue_id = create_draft(&app, "First title", "First body").await;
+    app.post_form(
+        &format!("/admin/issues/{}", issue_id),
+        &[("title", "Second title"), ("html_content", "Second body")],
+    )
+    .await;
+    let revisions = revision_ids(&app, issue_id).await;
+
+    // Act
+    let response = app
+        .post_form(
+            &format!(
+                "/admin/issues/{}/revisions/{}/restore",
+                issue_id, revisions[0]
+            ),
+            &[("", "")],
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, &format!("/admin/issues/{}", issue_id));
+
+    let issue = sqlx::query!(
+        "SELECT title, html_content FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(issue.title, "First title");
+    assert_eq!(issue.html_content, "First body");
+    assert_eq!(revision_ids(&app, issue_id).await.len(), 3);
+}
+
+#[tokio::test]
+async fn drafts_are_only_sent_once_published() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    app.login(&app.user.username, &app.user.password).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = create_draft(&app, "Draft title", "Draft body").await;
+    let html_page = app.get_text("/archive").await;
+    assert!(!html_page.contains("Draft title"));
+
+    // Act
+    let response = app
+        .post_form(&format!("/admin/issues/{}/publish", issue_id), &[("", "")])
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/issues");
+
+    let html_page = app.get_text("/archive").await;
+    assert!(html_page.contains("Draft title"));
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_revisions() {
+    // Arrange
+    let app = setup().await;
+
+    // Act
+    let response = app
+        .get(&format!("/admin/issues/{}/revisions", Uuid::new_v4()))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
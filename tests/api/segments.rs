@@ -0,0 +1,258 @@
+//! tests/api/segments.rs
+
+use crate::helpers::{create_confirmed_subscriber, setup, subscriber_id, Test};
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+async fn create_field(app: &Test, name: &str, field_type: &str) {
+    app.login(&app.user.username, &app.user.password).await;
+    let response = app
+        .post_form(
+            "/admin/fields",
+            &[("name", name), ("field_type", field_type)],
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 303);
+}
+
+async fn put_attributes(
+    app: &Test,
+    subscriber_id: Uuid,
+    body: serde_json::Value,
+) -> reqwest::Response {
+    app.client
+        .put(format!(
+            "{}/newsletters/subscribers/{}/attributes",
+            app.address, subscriber_id
+        ))
+        .basic_auth(&app.user.username, Some(&app.user.password))
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn count_recipients(app: &Test, body: serde_json::Value) -> reqwest::Response {
+    app.client
+        .post(format!("{}/newsletters/recipients/count", app.address))
+        .basic_auth(&app.user.username, Some(&app.user.password))
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn issues_only_go_to_subscribers_matching_their_segment() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    create_field(&app, "plan", "string").await;
+    let subscriber_id = subscriber_id(&app).await;
+
+    let response = put_attributes(
+        &app,
+        subscriber_id,
+        serde_json::json!({ "tags": ["Beta"], "fields": { "plan": "pro" } }),
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletter(serde_json::json!({
+            "title": "Pro news",
+            "body": "<p>News</p>",
+            "segment": r#"tag = "beta" AND field.plan = "pro""#,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app
+        .post_newsletter(serde_json::json!({
+            "title": "Free news",
+            "body": "<p>News</p>",
+            "segment": r#"field.plan = "free" OR tag != "beta""#,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Assert
+    // Mock verifies on Drop that only the first issue was sent
+}
+
+#[tokio::test]
+async fn invalid_segments_are_rejected() {
+    // Arrange
+    let app = setup().await;
+    create_field(&app, "seats", "number").await;
+
+    for segment in [
+        "field.unknown = 1",
+        r#"field.seats > "many""#,
+        "tag > 3",
+        "status = ",
+    ] {
+        // Act
+        let response = app
+            .post_newsletter(serde_json::json!({
+                "title": "News",
+                "body": "<p>News</p>",
+                "segment": segment,
+            }))
+            .await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The segment `{}` was accepted",
+            segment
+        );
+    }
+}
+
+#[tokio::test]
+async fn the_recipient_count_applies_the_segment() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    create_field(&app, "seats", "number").await;
+    let subscriber_id = subscriber_id(&app).await;
+    put_attributes(
+        &app,
+        subscriber_id,
+        serde_json::json!({ "fields": { "seats": 12 } }),
+    )
+    .await;
+
+    for (segment, expected) in [("field.seats >= 10", 1), ("field.seats < 10", 0)] {
+        // Act
+        let response = count_recipients(&app, serde_json::json!({ "segment": segment })).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["recipients"], expected, "{}", segment);
+    }
+}
+
+#[tokio::test]
+async fn attributes_are_checked_against_the_custom_fields() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    create_field(&app, "renews_on", "date").await;
+    let subscriber_id = subscriber_id(&app).await;
+
+    // Act
+    let unknown_field = put_attributes(
+        &app,
+        subscriber_id,
+        serde_json::json!({ "fields": { "plan": "pro" } }),
+    )
+    .await;
+    let wrong_type = put_attributes(
+        &app,
+        subscriber_id,
+        serde_json::json!({ "fields": { "renews_on": "next week" } }),
+    )
+    .await;
+    let unknown_subscriber = put_attributes(
+        &app,
+        Uuid::new_v4(),
+        serde_json::json!({ "fields": { "renews_on": "2024-02-01" } }),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(unknown_field.status().as_u16(), 400);
+    assert_eq!(wrong_type.status().as_u16(), 400);
+    assert_eq!(unknown_subscriber.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn tags_and_fields_can_be_edited_from_the_admin() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    create_field(&app, "plan", "string").await;
+    let subscriber_id = subscriber_id(&app).await;
+    let subscriber_page = format!("/admin/subscribers/{}", subscriber_id);
+
+    // Act
+    let response = app
+        .post_form(
+            &format!("{}/attributes", subscriber_page),
+            &[("tags", "VIP; beta, vip"), ("field.plan", "pro")],
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 303);
+    let tags: Vec<String> = sqlx::query!("SELECT tag FROM subscriber_tags ORDER BY tag")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|row| row.tag)
+        .collect();
+    assert_eq!(tags, ["beta", "vip"]);
+
+    let page = app.get_text(&subscriber_page).await;
+    assert!(page.contains(r#"value="beta, vip""#));
+    assert!(page.contains(r#"value="pro""#));
+}
+
+#[tokio::test]
+async fn imports_read_tags_and_custom_field_columns() {
+    // Arrange
+    let app = setup().await;
+    create_field(&app, "seats", "number").await;
+
+    let csv = "\
+name,email,tags,seats
+Ursula Le Guin,ursula@example.com,beta;vip,12
+Terry Pratchett,terry@example.com,,lots
+";
+    // The file comes last, as in the form
+    let form = reqwest::multipart::Form::new()
+        .text("mode", "confirmed")
+        .text("consent_source", "Conference sign-up sheet")
+        .part(
+            "file",
+            reqwest::multipart::Part::text(csv).file_name("subscribers.csv"),
+        );
+
+    // Act
+    let response = app
+        .client
+        .post(format!("{}/admin/subscribers/import", app.address))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 303);
+    let subscriber = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.email, "ursula@example.com");
+
+    let count = count_recipients(
+        &app,
+        serde_json::json!({ "segment": r#"tag = "vip" AND field.seats = 12"# }),
+    )
+    .await;
+    let body: serde_json::Value = count.json().await.unwrap();
+    assert_eq!(body["recipients"], 1);
+}
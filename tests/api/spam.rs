@@ -0,0 +1,348 @@
+//! tests/api/spam.rs
+
+use crate::helpers::{setup, setup_with, Test};
+use letter::configuration::{CaptchaProvider, CaptchaSettings};
+use secrecy::Secret;
+use wiremock::matchers::{any, body_string_contains, method};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+async fn sign_up(app: &Test, email: &str, extra: serde_json::Value) -> reqwest::Response {
+    let mut body = serde_json::json!({ "name": "le guin", "email": email });
+    body.as_object_mut()
+        .unwrap()
+        .extend(extra.as_object().unwrap().clone());
+
+    app.client
+        .post(format!("{}/api/v1/subscriptions", app.address))
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn subscriber_count(app: &Test) -> i64 {
+    sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn filled_honeypots_are_silently_ignored() {
+    // Arrange
+    let app = setup().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_body(
+            "/subscriptions",
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&website=http%3A%2F%2Fspam.example.com"
+                .into(),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn forms_sent_back_too_quickly_are_rejected() {
+    // Arrange
+    let app = setup_with(|config| config.spam_protection.min_submit_seconds = 60).await;
+    let form_stamp = app.get_form_stamp("/widget/default").await;
+
+    // Act
+    let response = app
+        .post_form(
+            "/widget/default",
+            &[
+                ("name", "le guin"),
+                ("email", "ursula_le_guin@gmail.com"),
+                ("form_stamp", &form_stamp),
+            ],
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(response.text().await.unwrap().contains("Please wait"));
+    assert_eq!(subscriber_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn missing_or_forged_stamps_are_rejected() {
+    // Arrange
+    let app = setup().await;
+
+    for stamp in [None, Some("1700000000.deadbeef")] {
+        let mut form = vec![("name", "le guin"), ("email", "ursula_le_guin@gmail.com")];
+        form.extend(stamp.map(|stamp| ("form_stamp", stamp)));
+
+        // Act
+        let response = app.post_form("/widget/default", &form).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400, "{:?}", stamp);
+    }
+    assert_eq!(subscriber_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn plain_forms_need_no_stamp_by_default() {
+    // Arrange
+    let app = setup().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_body(
+            "/subscriptions",
+            "name=le%20guin&email=ursula_le_guin%40gmail.com".into(),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_count(&app).await, 1);
+}
+
+#[tokio::test]
+async fn forms_and_json_need_a_stamp_when_stamps_are_required() {
+    // Arrange
+    let app = setup_with(|config| config.spam_protection.require_form_stamp = true).await;
+
+    // Act
+    let form = app
+        .post_body(
+            "/subscriptions",
+            "name=le%20guin&email=ursula_le_guin%40gmail.com".into(),
+        )
+        .await;
+    let json = sign_up(&app, "ursula_le_guin@gmail.com", serde_json::json!({})).await;
+
+    // Assert
+    assert_eq!(form.status().as_u16(), 400);
+    assert_eq!(json.status().as_u16(), 400);
+    assert_eq!(subscriber_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn json_sign_ups_with_a_stamp_are_accepted_when_stamps_are_required() {
+    // Arrange
+    let app = setup_with(|config| config.spam_protection.require_form_stamp = true).await;
+    let stamp: serde_json::Value = app.get("/api/v1/form-stamp").await.json().await.unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = sign_up(
+        &app,
+        "ursula_le_guin@gmail.com",
+        serde_json::json!({ "form_stamp": stamp["form_stamp"] }),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_count(&app).await, 1);
+}
+
+#[tokio::test]
+async fn stamps_are_only_accepted_once() {
+    // Arrange
+    let app = setup().await;
+    let form_stamp = app.get_form_stamp("/widget/default").await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let mut statuses = Vec::new();
+    for email in ["one@example.com", "two@example.org"] {
+        let body = format!("name=le%20guin&email={}&form_stamp={}", email, form_stamp);
+        statuses.push(
+            app.post_body("/subscriptions", body)
+                .await
+                .status()
+                .as_u16(),
+        );
+    }
+
+    // Assert
+    assert_eq!(statuses, [200, 400]);
+    assert_eq!(subscriber_count(&app).await, 1);
+}
+
+#[tokio::test]
+async fn stamps_expire() {
+    // Arrange
+    let app = setup_with(|config| config.spam_protection.max_stamp_age_seconds = 1).await;
+    let form_stamp = app.get_form_stamp("/widget/default").await;
+    tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+
+    // Act
+    let response = app
+        .post_form(
+            "/widget/default",
+            &[
+                ("name", "le guin"),
+                ("email", "ursula_le_guin@gmail.com"),
+                ("form_stamp", &form_stamp),
+            ],
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("The form has expired"));
+    assert_eq!(subscriber_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn sign_ups_from_one_address_are_rate_limited() {
+    // Arrange
+    let app = setup_with(|config| config.spam_protection.max_signups_per_ip = 2).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let mut statuses = Vec::new();
+    for email in ["one@example.com", "two@example.org", "three@example.net"] {
+        statuses.push(
+            sign_up(&app, email, serde_json::json!({}))
+                .await
+                .status()
+                .as_u16(),
+        );
+    }
+
+    // Assert
+    assert_eq!(statuses, [200, 200, 429]);
+}
+
+#[tokio::test]
+async fn sign_ups_for_one_domain_are_rate_limited() {
+    // Arrange
+    let app = setup_with(|config| config.spam_protection.max_signups_per_domain = 1).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let first = sign_up(&app, "one@example.com", serde_json::json!({})).await;
+    let same_domain = sign_up(&app, "two@EXAMPLE.com", serde_json::json!({})).await;
+    let other_domain = sign_up(&app, "three@example.org", serde_json::json!({})).await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(same_domain.status().as_u16(), 429);
+    assert_eq!(other_domain.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn sign_ups_must_solve_the_captcha_when_one_is_configured() {
+    // Arrange
+    let captcha_server = MockServer::start().await;
+    let verify_url = format!("{}/siteverify", captcha_server.uri());
+    let app = setup_with(|config| {
+        config.spam_protection.captcha = Some(CaptchaSettings {
+            provider: CaptchaProvider::Turnstile,
+            site_key: "site-key".into(),
+            secret_key: Secret::new("secret-key".into()),
+            verify_url: Some(verify_url),
+        })
+    })
+    .await;
+
+    Mock::given(method("POST"))
+        .and(body_string_contains("response=solved"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({ "success": true })),
+        )
+        .mount(&captcha_server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({ "success": false })),
+        )
+        .mount(&captcha_server)
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let page = app.get_text("/widget/default").await;
+    let missing = sign_up(&app, "one@example.com", serde_json::json!({})).await;
+    let wrong = sign_up(
+        &app,
+        "two@example.com",
+        serde_json::json!({ "captcha_token": "guessed" }),
+    )
+    .await;
+    let solved = sign_up(
+        &app,
+        "three@example.com",
+        serde_json::json!({ "cf-turnstile-response": "solved" }),
+    )
+    .await;
+
+    // Assert
+    assert!(page.contains(r#"class="cf-turnstile" data-sitekey="site-key""#));
+    assert_eq!(missing.status().as_u16(), 400);
+    let body: serde_json::Value = wrong.json().await.unwrap();
+    assert_eq!(body["errors"][0]["field"], "captcha_token");
+    assert_eq!(solved.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn the_dashboard_counts_sign_up_outcomes() {
+    // Arrange
+    let app = setup().await;
+    app.post_body(
+        "/subscriptions",
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&website=spam".into(),
+    )
+    .await;
+
+    // Act
+    app.login(&app.user.username, &app.user.password).await;
+    let page = app.get_text("/admin/dashboard").await;
+
+    // Assert
+    assert!(page.contains("Sign-ups in the last 24 hours"));
+    assert!(page.contains("honeypot: 1"));
+}
@@ -0,0 +1,291 @@
+//! tests/api/subscribers.rs
+
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_unconfirmed_subscriber,
+    publish_issue, setup, subscriber_id,
+};
+use letter::domain::SubscriptionStatus;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn subscribers_can_be_searched_by_email_or_name() {
+    // Arrange
+    let app = setup().await;
+    create_unconfirmed_subscriber(&app).await;
+    app.login(&app.user.username, &app.user.password).await;
+
+    // Act
+    let by_email = app.get_text("/admin/subscribers?q=URSULA").await;
+    let by_name = app.get_text("/admin/subscribers?q=guin").await;
+    let no_match = app.get_text("/admin/subscribers?q=tolkien").await;
+
+    // Assert
+    assert!(by_email.contains("ursula_le_guin@gmail.com"));
+    assert!(by_name.contains("ursula_le_guin@gmail.com"));
+    assert!(!no_match.contains("ursula_le_guin@gmail.com"));
+}
+
+#[tokio::test]
+async fn subscribers_can_be_filtered_by_status_and_signup_date() {
+    // Arrange
+    let app = setup().await;
+    create_unconfirmed_subscriber(&app).await;
+    app.login(&app.user.username, &app.user.password).await;
+
+    // Act
+    let pending = app
+        .get_text("/admin/subscribers?status=pending_confirmation")
+        .await;
+    let confirmed = app.get_text("/admin/subscribers?status=confirmed").await;
+    let before = app.get_text("/admin/subscribers?from=&to=2000-01-01").await;
+
+    // Assert
+    assert!(pending.contains("ursula_le_guin@gmail.com"));
+    assert!(!confirmed.contains("ursula_le_guin@gmail.com"));
+    assert!(!before.contains("ursula_le_guin@gmail.com"));
+}
+
+#[tokio::test]
+async fn the_subscriber_page_shows_tokens_and_deliveries() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    publish_issue(&app, "Newsletter title", "Newsletter body").await;
+    app.login(&app.user.username, &app.user.password).await;
+    let id = subscriber_id(&app).await;
+
+    let token = sqlx::query!("SELECT subscription_token FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .subscription_token;
+
+    // Act
+    let html_page = app.get_text(&format!("/admin/subscribers/{}", id)).await;
+
+    // Assert
+    assert!(html_page.contains(&token));
+    assert!(html_page.contains("Newsletter title"));
+}
+
+#[tokio::test]
+async fn admins_can_confirm_a_subscriber_manually() {
+    // Arrange
+    let app = setup().await;
+    create_unconfirmed_subscriber(&app).await;
+    app.login(&app.user.username, &app.user.password).await;
+    let id = subscriber_id(&app).await;
+
+    // Act
+    let response = app
+        .post_form(
+            &format!("/admin/subscribers/{}/status", id),
+            &[("status", "confirmed")],
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{}", id));
+
+    let saved = sqlx::query!(r#"SELECT status AS "status: SubscriptionStatus" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, SubscriptionStatus::Confirmed);
+}
+
+#[tokio::test]
+async fn admins_cannot_make_illegal_status_changes() {
+    // Arrange
+    let app = setup().await;
+    create_unconfirmed_subscriber(&app).await;
+    app.login(&app.user.username, &app.user.password).await;
+    let id = subscriber_id(&app).await;
+
+    // Act
+    let response = app
+        .post_form(
+            &format!("/admin/subscribers/{}/status", id),
+            &[("status", "unsubscribed")],
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{}", id));
+
+    let html_page = app.get_text(&format!("/admin/subscribers/{}", id)).await;
+    assert!(html_page.contains("can&#x27;t go from pending_confirmation to unsubscribed"));
+
+    let saved = sqlx::query!(r#"SELECT status AS "status: SubscriptionStatus" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, SubscriptionStatus::PendingConfirmation);
+}
+
+#[tokio::test]
+async fn status_changes_are_recorded_with_their_cause() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    app.login(&app.user.username, &app.user.password).await;
+    let id = subscriber_id(&app).await;
+
+    // Act
+    app.post_form(
+        &format!("/admin/subscribers/{}/status", id),
+        &[("status", "unsubscribed")],
+    )
+    .await;
+
+    // Assert
+    let events = sqlx::query!(
+        r#"
+        SELECT from_status AS "from_status: SubscriptionStatus",
+            to_status AS "to_status: SubscriptionStatus",
+            cause
+        FROM subscription_events
+        WHERE subscriber_id = $1
+        ORDER BY created_at
+        "#,
+        id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|event| (event.from_status, event.to_status, event.cause))
+    .collect::<Vec<_>>();
+
+    use SubscriptionStatus::*;
+    assert_eq!(
+        events,
+        [
+            (None, PendingConfirmation, "signup".to_string()),
+            (
+                Some(PendingConfirmation),
+                Confirmed,
+                "confirmation_link".to_string()
+            ),
+            (Some(Confirmed), Unsubscribed, "admin".to_string()),
+        ]
+    );
+
+    let html_page = app.get_text(&format!("/admin/subscribers/{}", id)).await;
+    assert!(html_page.contains("Status history"));
+}
+
+#[tokio::test]
+async fn an_invalid_name_is_rejected() {
+    // Arrange
+    let app = setup().await;
+    create_unconfirmed_subscriber(&app).await;
+    app.login(&app.user.username, &app.user.password).await;
+    let id = subscriber_id(&app).await;
+
+    // Act
+    let response = app
+        .post_form(
+            &format!("/admin/subscribers/{}/name", id),
+            &[("name", "<script>")],
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{}", id));
+
+    let html_page = app.get_text(&format!("/admin/subscribers/{}", id)).await;
+    assert!(html_page.contains("A name must not contain any of the following characters"));
+
+    let saved = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "le guin");
+}
+
+#[tokio::test]
+async fn admins_can_delete_a_subscriber() {
+    // Arrange
+    let app = setup().await;
+    create_unconfirmed_subscriber(&app).await;
+    app.login(&app.user.username, &app.user.password).await;
+    let id = subscriber_id(&app).await;
+
+    // Act
+    let response = app
+        .post_form(&format!("/admin/subscribers/{}/delete", id), &[("", "")])
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/subscribers");
+
+    let saved = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_none());
+}
+
+#[tokio::test]
+async fn deleting_a_subscriber_strips_the_feedback_about_their_emails() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    publish_issue(&app, "Newsletter title", "Newsletter body").await;
+    app.login(&app.user.username, &app.user.password).await;
+    let id = subscriber_id(&app).await;
+    let delivery =
+        sqlx::query!("SELECT newsletter_issue_id, message_id FROM newsletter_issue_deliveries")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    // Reported for a forwarding address, so only the message id matches.
+    sqlx::query!(
+        r#"
+        INSERT INTO delivery_feedback (
+            feedback_id, source, event_key, kind, email_hash, message_id, reason, received_at,
+            newsletter_issue_id
+        )
+        VALUES ($1, 'brevo', '42', 'hard_bounce', 'forwarded', $2, 'no such user', now(), $3)
+        "#,
+        Uuid::new_v4(),
+        delivery.message_id,
+        delivery.newsletter_issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    app.post_form(&format!("/admin/subscribers/{}/delete", id), &[("", "")])
+        .await;
+
+    // Assert
+    let feedback = sqlx::query!(
+        "SELECT kind, email_hash, message_id, reason, newsletter_issue_id FROM delivery_feedback"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(feedback.kind, "hard_bounce");
+    assert_eq!(feedback.email_hash, None);
+    assert_eq!(feedback.message_id, None);
+    assert_eq!(feedback.reason, None);
+    assert_eq!(
+        feedback.newsletter_issue_id,
+        Some(delivery.newsletter_issue_id)
+    );
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_subscribers() {
+    // Arrange
+    let app = setup().await;
+
+    // Act
+    let response = app.get("/admin/subscribers").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
@@ -1,6 +1,7 @@
 //! tests/api/subscriptions.rs
 
-use crate::helpers::{extract_link_path, setup};
+use crate::helpers::{extract_link_path, setup, setup_with};
+use letter::domain::SubscriptionStatus;
 use wiremock::{
     matchers::{any, method},
     Mock, ResponseTemplate,
@@ -11,9 +12,14 @@ async fn subscribe_returns_a_200_for_valid_form_data() {
     // Arrange
     let test = setup().await;
 
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test.email_server)
+        .await;
+
     // Act
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
-    let response = test.post_body("/subscriptions", body.into()).await;
+    let response = test.post_subscriptions(body.into()).await;
 
     // Assert
     assert_eq!(200, response.status().as_u16());
@@ -26,17 +32,19 @@ async fn subscribe_persists_the_new_subscriber() {
 
     // Act
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
-    let _ = test.post_body("/subscriptions", body.into()).await;
+    let _ = test.post_subscriptions(body.into()).await;
 
     // Assert
-    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions",)
-        .fetch_one(&test.db_pool)
-        .await
-        .expect("Failed to fetch saved subscription.");
+    let saved = sqlx::query!(
+        r#"SELECT email, name, status AS "status: SubscriptionStatus" FROM subscriptions"#
+    )
+    .fetch_one(&test.db_pool)
+    .await
+    .expect("Failed to fetch saved subscription.");
 
     assert_eq!(saved.email, "ursula_le_guin@gmail.com");
     assert_eq!(saved.name, "le guin");
-    assert_eq!(saved.status, "pending_confirmation");
+    assert_eq!(saved.status, SubscriptionStatus::PendingConfirmation);
 }
 
 #[tokio::test]
@@ -53,7 +61,7 @@ async fn subscribe_sends_email_for_valid_form_data() {
 
     // Act
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
-    let response = test.post_body("/subscriptions", body.into()).await;
+    let response = test.post_subscriptions(body.into()).await;
 
     // Assert
     assert_eq!(200, response.status().as_u16());
@@ -73,7 +81,7 @@ async fn subscribe_sends_email_with_a_link() {
 
     // Act
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
-    let _ = test.post_body("/subscriptions", body.into()).await;
+    let _ = test.post_subscriptions(body.into()).await;
 
     // Assert
     let email = test.received_email().await;
@@ -93,7 +101,7 @@ async fn subscribe_returns_a_400_when_data_is_missing() {
 
     for (body, error_message) in test_cases {
         // Act
-        let response = test.post_body("/subscriptions", body.into()).await;
+        let response = test.post_subscriptions(body.into()).await;
 
         // Assert
         assert_eq!(
@@ -120,7 +128,7 @@ async fn subscribe_returns_a_400_when_name_is_invalid() {
 
     for (body, error_message) in test_cases {
         // Act
-        let response = test.post_body("/subscriptions", body.into()).await;
+        let response = test.post_subscriptions(body.into()).await;
 
         // Assert
         assert_eq!(
@@ -144,7 +152,7 @@ async fn subscribe_returns_a_400_when_email_is_invalid() {
 
     for (body, error_message) in test_cases {
         // Act
-        let response = test.post_body("/subscriptions", body.into()).await;
+        let response = test.post_subscriptions(body.into()).await;
 
         // Assert
         assert_eq!(
@@ -170,7 +178,7 @@ async fn subscribe_fails_if_sth_wrong_with_subscriptions_table() {
         .unwrap();
 
     // Act
-    let response = app.post_body("/subscriptions", body.into()).await;
+    let response = app.post_subscriptions(body.into()).await;
 
     // Assert
     assert_eq!(response.status().as_u16(), 500);
@@ -183,13 +191,14 @@ async fn subscribe_fails_if_sth_wrong_with_subscriptions_token_table() {
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
 
     // Sabotage the database
-    sqlx::query!("ALTER TABLE subscription_tokens DROP COLUMN subscription_token;",)
+    // The confirmation email queue refers to the column.
+    sqlx::query!("ALTER TABLE subscription_tokens DROP COLUMN subscription_token CASCADE;",)
         .execute(&app.db_pool)
         .await
         .unwrap();
 
     // Act
-    let response = app.post_body("/subscriptions", body.into()).await;
+    let response = app.post_subscriptions(body.into()).await;
 
     // Assert
     assert_eq!(response.status().as_u16(), 500);
@@ -202,7 +211,7 @@ async fn subscribe_fails_if_sth_wrong_with_name() {
     let body = "name=le%20guin%7B&email=ursula_le_guin@gmail.com";
 
     // Act
-    let response = app.post_body("/subscriptions", body.into()).await;
+    let response = app.post_subscriptions(body.into()).await;
 
     // Assert
     assert_eq!(response.status().as_u16(), 400);
@@ -215,8 +224,56 @@ async fn subscribe_fails_if_sth_wrong_with_email() {
     let body = "name=le%20guin&email=ursula_le_guin";
 
     // Act
-    let response = app.post_body("/subscriptions", body.into()).await;
+    let response = app.post_subscriptions(body.into()).await;
 
     // Assert
     assert_eq!(response.status().as_u16(), 400);
 }
+
+#[tokio::test]
+async fn addresses_differing_only_in_case_are_one_subscriber() {
+    // Arrange
+    let app = setup().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    for email in ["Ursula_Le_Guin%40Gmail.com", "ursula_le_guin%40GMAIL.COM"] {
+        let body = format!("name=le%20guin&email={}", email);
+        let response = app.post_subscriptions(body).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    // Assert
+    let saved = sqlx::query!("SELECT email, email_key FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email, "Ursula_Le_Guin@gmail.com");
+    assert_eq!(saved[0].email_key, "ursula_le_guin@gmail.com");
+}
+
+#[tokio::test]
+async fn non_ascii_local_parts_are_only_accepted_when_allowed() {
+    // Arrange
+    let body = "name=le%20guin&email=j%C3%B6rg%40example.com";
+    let strict = setup().await;
+    let allowing = setup_with(|config| config.email_policy.allow_smtputf8 = true).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&allowing.email_server)
+        .await;
+
+    // Act
+    let rejected = strict.post_subscriptions(body.into()).await;
+    let accepted = allowing.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(rejected.status().as_u16(), 400);
+    assert_eq!(accepted.status().as_u16(), 200);
+}
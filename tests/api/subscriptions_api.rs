@@ -0,0 +1,130 @@
+//! tests/api/subscriptions_api.rs
+
+use crate::helpers::{create_confirmed_subscriber, setup, Test};
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+async fn post_json(app: &Test, path: &str, body: serde_json::Value) -> reqwest::Response {
+    app.client
+        .post(format!("{}{}", app.address, path))
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn json_signups_are_accepted_on_both_endpoints() {
+    // Arrange
+    let app = setup().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    for (path, email) in [
+        ("/subscriptions", "ursula_le_guin@gmail.com"),
+        ("/api/v1/subscriptions", "terry@example.com"),
+    ] {
+        // Act
+        let response = post_json(
+            &app,
+            path,
+            serde_json::json!({ "name": "le guin", "email": email }),
+        )
+        .await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["email"], email);
+        assert_eq!(body["list"], "default");
+        assert_eq!(body["status"], "pending_confirmation");
+    }
+}
+
+#[tokio::test]
+async fn every_invalid_field_is_reported() {
+    // Arrange
+    let app = setup().await;
+
+    // Act
+    let response = post_json(
+        &app,
+        "/api/v1/subscriptions",
+        serde_json::json!({ "name": "{le guin}", "email": "not an email", "list": "nope" }),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let fields: Vec<&str> = body["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| error["field"].as_str().unwrap())
+        .collect();
+    assert_eq!(fields, ["name", "email", "list"]);
+    assert!(body["errors"][2]["message"]
+        .as_str()
+        .unwrap()
+        .contains("nope"));
+}
+
+#[tokio::test]
+async fn missing_fields_are_reported_as_empty() {
+    // Arrange
+    let app = setup().await;
+
+    // Act
+    let response = post_json(&app, "/subscriptions", serde_json::json!({})).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["errors"].as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn malformed_json_gets_a_json_error() {
+    // Arrange
+    let app = setup().await;
+
+    // Act
+    let response = app
+        .client
+        .post(format!("{}/api/v1/subscriptions", app.address))
+        .header("Content-Type", "application/json")
+        .body("{\"name\": ")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["message"].is_string());
+}
+
+#[tokio::test]
+async fn signing_up_again_reports_the_confirmed_status() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let response = post_json(
+        &app,
+        "/api/v1/subscriptions",
+        serde_json::json!({ "name": "le guin", "email": "ursula_le_guin@gmail.com" }),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "confirmed");
+}
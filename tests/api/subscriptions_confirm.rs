@@ -1,6 +1,7 @@
 //! tests/api/subscriptions_confirm.rs
 
 use crate::helpers::{extract_link_path, setup};
+use letter::domain::SubscriptionStatus;
 use wiremock::{
     matchers::{any, method},
     Mock, ResponseTemplate,
@@ -32,7 +33,7 @@ async fn link_returns_a_200_if_clicked() {
 
     // Act
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
-    let _ = test.post_body("/subscriptions", body.into()).await;
+    let _ = test.post_subscriptions(body.into()).await;
 
     // Assert
     let email = test.received_email().await;
@@ -57,19 +58,21 @@ async fn clicking_the_link_confirms_the_subscription() {
 
     // Act
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
-    let _ = test.post_body("/subscriptions", body.into()).await;
+    let _ = test.post_subscriptions(body.into()).await;
 
     // Assert
     let email = test.received_email().await;
     let link_path = extract_link_path(&email.html_content.as_str());
     let _ = test.get(&link_path).await;
 
-    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions")
-        .fetch_one(&test.db_pool)
-        .await
-        .unwrap();
+    let saved = sqlx::query!(
+        r#"SELECT email, name, status AS "status: SubscriptionStatus" FROM subscriptions"#
+    )
+    .fetch_one(&test.db_pool)
+    .await
+    .unwrap();
 
     assert_eq!(saved.email, "ursula_le_guin@gmail.com");
     assert_eq!(saved.name, "le guin");
-    assert_eq!(saved.status, "confirmed");
+    assert_eq!(saved.status, SubscriptionStatus::Confirmed);
 }
@@ -0,0 +1,201 @@
+//! tests/api/suppressions.rs
+
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, setup, Test};
+use letter::privacy::email_hash;
+use wiremock::{matchers::any, Mock, ResponseTemplate};
+
+async fn suppress(app: &Test, email: &str) {
+    let response = app
+        .client
+        .post(format!("{}/newsletters/suppressions", app.address))
+        .basic_auth(&app.user.username, Some(&app.user.password))
+        .json(&serde_json::json!({ "emails": [email], "reason": "test" }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+async fn suppressed_sends(app: &Test, email: &str) -> Option<i64> {
+    sqlx::query!(
+        "SELECT suppressed_sends FROM suppressions WHERE email_hash = $1",
+        email_hash(email, &app.email_hash_key)
+    )
+    .fetch_optional(&app.db_pool)
+    .await
+    .unwrap()
+    .map(|row| row.suppressed_sends)
+}
+
+#[tokio::test]
+async fn suppressed_addresses_get_no_confirmation_email() {
+    // Arrange
+    let app = setup().await;
+    suppress(&app, "Ursula_Le_Guin@gmail.com").await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        suppressed_sends(&app, "ursula_le_guin@gmail.com").await,
+        Some(1)
+    );
+}
+
+#[tokio::test]
+async fn newsletters_skip_suppressed_subscribers() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    suppress(&app, "ursula_le_guin@gmail.com").await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletter(serde_json::json!({
+            "title": "Newsletter title",
+            "body": "Newsletter body",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let deliveries =
+        sqlx::query!(r#"SELECT count(*) AS "count!" FROM newsletter_issue_deliveries"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .count;
+    assert_eq!(deliveries, 0);
+    assert_eq!(
+        suppressed_sends(&app, "ursula_le_guin@gmail.com").await,
+        Some(1)
+    );
+}
+
+#[tokio::test]
+async fn the_api_reports_what_it_suppressed_and_removes_entries() {
+    // Arrange
+    let app = setup().await;
+    suppress(&app, "a@example.com").await;
+
+    // Act
+    let report: serde_json::Value = app
+        .client
+        .post(format!("{}/newsletters/suppressions", app.address))
+        .basic_auth(&app.user.username, Some(&app.user.password))
+        .json(&serde_json::json!({ "emails": ["A@example.com", "b@example.com", "nope"] }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let delete = |email: &str| {
+        app.client
+            .delete(format!(
+                "{}/newsletters/suppressions/{}",
+                app.address, email
+            ))
+            .basic_auth(&app.user.username, Some(&app.user.password))
+            .send()
+    };
+    let deleted = delete("b@example.com").await.unwrap();
+    let missing = delete("c@example.com").await.unwrap();
+
+    // Assert
+    assert_eq!(
+        report,
+        serde_json::json!({ "added": 1, "already_suppressed": 1, "invalid": ["nope"] })
+    );
+    assert_eq!(deleted.status().as_u16(), 204);
+    assert_eq!(missing.status().as_u16(), 404);
+    assert_eq!(suppressed_sends(&app, "b@example.com").await, None);
+    assert_eq!(suppressed_sends(&app, "a@example.com").await, Some(0));
+}
+
+#[tokio::test]
+async fn the_api_requires_credentials() {
+    // Arrange
+    let app = setup().await;
+
+    // Act
+    let response = app
+        .client
+        .get(format!("{}/newsletters/suppressions", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn admins_can_bulk_suppress_and_remove_addresses() {
+    // Arrange
+    let app = setup().await;
+    app.login(&app.user.username, &app.user.password).await;
+
+    // Act - Part 1 - Add
+    let response = app
+        .post_form(
+            "/admin/suppressions",
+            &[
+                ("emails", "a@example.com\r\n\r\nb@example.com\r\nnope"),
+                ("reason", ""),
+            ],
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/suppressions");
+
+    // Assert - Part 1
+    let html = app.get_text("/admin/suppressions").await;
+    assert!(html.contains("2 addresses suppressed, 0 already were."));
+    assert!(html.contains("These are not email addresses: nope"));
+    assert!(html.contains(&email_hash("a@example.com", &app.email_hash_key)));
+    let reason = sqlx::query!(
+        "SELECT reason, source FROM suppressions WHERE email_hash = $1",
+        email_hash("b@example.com", &app.email_hash_key)
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        (reason.reason.as_str(), reason.source.as_str()),
+        ("manual", "admin")
+    );
+
+    // Act - Part 2 - Remove
+    let response = app
+        .post_form(
+            &format!(
+                "/admin/suppressions/{}/delete",
+                email_hash("a@example.com", &app.email_hash_key)
+            ),
+            &[("", "")],
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/suppressions");
+
+    // Assert - Part 2
+    let html = app
+        .get_text("/admin/suppressions?email=a%40example.com")
+        .await;
+    assert!(html.contains("The address can be emailed again."));
+    assert!(html.contains("a@example.com is not suppressed."));
+    assert_eq!(suppressed_sends(&app, "a@example.com").await, None);
+}
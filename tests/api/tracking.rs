@@ -0,0 +1,165 @@
+//! tests/api/tracking.rs
+
+use crate::helpers::{create_confirmed_subscriber, publish_issue, setup, Test};
+
+const BODY: &str = r#"<p>Read <a href="https://example.com/story?a=1&amp;b=2">the story</a>.</p>"#;
+const MAIL_CLIENT: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15";
+
+async fn track_engagement(app: &Test) {
+    sqlx::query!("UPDATE lists SET track_engagement = TRUE")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+async fn last_email_html(app: &Test) -> String {
+    let requests = app.email_server.received_requests().await.unwrap();
+    let email: serde_json::Value = serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+    email["htmlContent"].as_str().unwrap().to_string()
+}
+
+/// The path of the first tracking link starting with `prefix`.
+fn tracking_path(html: &str, prefix: &str) -> String {
+    let start = html.find(prefix).expect("No tracking link in the email.");
+    let end = start + html[start..].find('"').unwrap();
+    html[start..end].to_string()
+}
+
+async fn get_as(app: &Test, path: &str, user_agent: &str) -> reqwest::Response {
+    app.client
+        .get(format!("{}{}", app.address, path))
+        .header("User-Agent", user_agent)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn links_are_only_tracked_on_lists_that_track_engagement() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act - Part 1 - Tracking is off by default
+    publish_issue(&app, "Untracked", BODY).await;
+
+    // Assert - Part 1
+    let html = last_email_html(&app).await;
+    assert!(html.contains(r#"href="https://example.com/story?a=1&amp;b=2""#));
+    assert!(!html.contains("/t/"));
+
+    // Act - Part 2
+    track_engagement(&app).await;
+    publish_issue(&app, "Tracked", BODY).await;
+
+    // Assert - Part 2
+    let html = last_email_html(&app).await;
+    assert!(!html.contains("https://example.com/story"));
+    assert!(html.contains("/t/c/"));
+    assert!(html.contains("/t/o/"));
+    // The preferences link is left alone
+    assert!(html.contains("/preferences/"));
+}
+
+#[tokio::test]
+async fn clicks_are_recorded_and_redirected_to_the_link() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    track_engagement(&app).await;
+    let (issue_id, _) = publish_issue(&app, "Tracked", BODY).await;
+    let click = tracking_path(&last_email_html(&app).await, "/t/c/");
+    // Clicks right after the delivery are taken for link scanners
+    sqlx::query!("UPDATE newsletter_issue_deliveries SET delivered_at = now() - interval '1 hour'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = get_as(&app, &click, MAIL_CLIENT).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 302);
+    assert_eq!(
+        response.headers().get("Location").unwrap(),
+        "https://example.com/story?a=1&b=2"
+    );
+
+    let event =
+        sqlx::query!("SELECT kind, newsletter_issue_id, url, automated FROM engagement_events")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(event.kind, "click");
+    assert_eq!(event.newsletter_issue_id, issue_id);
+    assert_eq!(
+        event.url.as_deref(),
+        Some("https://example.com/story?a=1&b=2")
+    );
+    assert!(!event.automated);
+}
+
+#[tokio::test]
+async fn tampered_click_links_are_not_followed() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    track_engagement(&app).await;
+    publish_issue(&app, "Tracked", BODY).await;
+    let click = tracking_path(&last_email_html(&app).await, "/t/c/");
+    let mut parts: Vec<&str> = click.split('.').collect();
+    // https://evil.example, base64 encoded
+    parts[2] = "aHR0cHM6Ly9ldmlsLmV4YW1wbGU";
+    let tampered = parts.join(".");
+
+    // Act
+    let response = get_as(&app, &tampered, MAIL_CLIENT).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+    let events = sqlx::query!("SELECT event_id FROM engagement_events")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(events.is_empty());
+}
+
+#[tokio::test]
+async fn repeated_opens_are_recorded_once_and_scanners_are_flagged() {
+    // Arrange
+    let app = setup().await;
+    create_confirmed_subscriber(&app).await;
+    track_engagement(&app).await;
+    publish_issue(&app, "Tracked", BODY).await;
+    let pixel = tracking_path(&last_email_html(&app).await, "/t/o/");
+
+    // Act
+    let first = get_as(&app, &pixel, "Barracuda Sentinel (EE)").await;
+    let again = get_as(&app, &pixel, "Barracuda Sentinel (EE)").await;
+
+    // Assert
+    for response in [first, again] {
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.headers().get("Content-Type").unwrap(), "image/gif");
+    }
+    let events = sqlx::query!("SELECT kind, automated FROM engagement_events")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].kind, "open");
+    assert!(events[0].automated);
+}
+
+#[tokio::test]
+async fn bad_open_tokens_still_get_the_image() {
+    // Arrange
+    let app = setup().await;
+
+    // Act
+    let response = get_as(&app, "/t/o/not-a-token", MAIL_CLIENT).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers().get("Content-Type").unwrap(), "image/gif");
+}
@@ -0,0 +1,309 @@
+//! tests/api/webhooks.rs
+
+use crate::helpers::{create_confirmed_subscriber, sent_emails, setup, setup_with, Test};
+use letter::configuration::BrevoWebhookSettings;
+use letter::domain::SubscriptionStatus;
+use letter::privacy::email_hash;
+use secrecy::Secret;
+use wiremock::{matchers::any, Mock, ResponseTemplate};
+
+const SECRET: &str = "webhook-secret";
+
+const HARD_BOUNCE: &str = include_str!("fixtures/brevo/hard_bounce.json");
+const SPAM: &str = include_str!("fixtures/brevo/spam.json");
+const UNSUBSCRIBED: &str = include_str!("fixtures/brevo/unsubscribed.json");
+const BATCH: &str = include_str!("fixtures/brevo/batch.json");
+
+async fn setup_with_webhook() -> Test {
+    setup_with(|config| {
+        config.webhooks.brevo = Some(BrevoWebhookSettings {
+            secret: Some(Secret::new(SECRET.into())),
+            allowed_ips: vec![],
+            trust_forwarded_for: false,
+        })
+    })
+    .await
+}
+
+async fn replay(app: &Test, payload: &str) -> reqwest::Response {
+    app.client
+        .post(format!("{}/webhooks/brevo", app.address))
+        .bearer_auth(SECRET)
+        .header("Content-Type", "application/json")
+        .body(payload.to_string())
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn subscriber_status(app: &Test) -> SubscriptionStatus {
+    sqlx::query!(r#"SELECT status AS "status: SubscriptionStatus" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+async fn suppression_reason(app: &Test, email: &str) -> Option<String> {
+    sqlx::query!(
+        "SELECT reason FROM suppressions WHERE email_hash = $1",
+        email_hash(email, &app.email_hash_key)
+    )
+    .fetch_optional(&app.db_pool)
+    .await
+    .unwrap()
+    .map(|row| row.reason)
+}
+
+#[tokio::test]
+async fn hard_bounces_mark_the_subscriber_bounced_and_suppress_the_address() {
+    // Arrange
+    let app = setup_with_webhook().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let response = replay(&app, HARD_BOUNCE).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, SubscriptionStatus::Bounced);
+    assert_eq!(
+        suppression_reason(&app, "ursula_le_guin@gmail.com")
+            .await
+            .as_deref(),
+        Some("hard_bounce")
+    );
+
+    let cause =
+        sqlx::query!("SELECT cause FROM subscription_events ORDER BY created_at DESC LIMIT 1")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .cause;
+    assert_eq!(cause, "brevo:hard_bounce");
+}
+
+#[tokio::test]
+async fn bounces_are_traced_back_to_the_issue_by_brevos_message_id() {
+    // Arrange
+    let app = setup_with_webhook().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(201).set_body_json(serde_json::json!({
+            "messageId": "<202401221015.70211438601@smtp-relay.mailin.fr>"
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletter(serde_json::json!({
+        "title": "New issue",
+        "body": "Newsletter body",
+    }))
+    .await;
+
+    let delivery = sqlx::query!(
+        "SELECT newsletter_issue_id, message_id, provider_message_id FROM newsletter_issue_deliveries"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    // The first email is the subscription confirmation.
+    let sent: serde_json::Value = sent_emails(&app).await.pop().unwrap();
+    assert_eq!(
+        delivery.message_id.as_deref(),
+        sent["headers"]["Message-ID"].as_str()
+    );
+    assert_eq!(
+        delivery.provider_message_id.as_deref(),
+        Some("<202401221015.70211438601@smtp-relay.mailin.fr>")
+    );
+
+    // Act
+    replay(&app, HARD_BOUNCE).await;
+
+    // Assert
+    let feedback = sqlx::query!("SELECT newsletter_issue_id FROM delivery_feedback")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        feedback.newsletter_issue_id,
+        Some(delivery.newsletter_issue_id)
+    );
+    assert_eq!(subscriber_status(&app).await, SubscriptionStatus::Bounced);
+}
+
+#[tokio::test]
+async fn repeated_deliveries_are_only_applied_once() {
+    // Arrange
+    let app = setup_with_webhook().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let first = replay(&app, HARD_BOUNCE).await;
+    let again = replay(&app, HARD_BOUNCE).await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(again.status().as_u16(), 200);
+
+    let feedback = sqlx::query!(r#"SELECT count(*) AS "count!" FROM delivery_feedback"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(feedback, 1);
+
+    let bounces = sqlx::query!(
+        r#"SELECT count(*) AS "count!" FROM subscription_events WHERE to_status = 'bounced'"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(bounces, 1);
+}
+
+#[tokio::test]
+async fn spam_complaints_are_matched_whatever_the_case_of_the_address() {
+    // Arrange
+    let app = setup_with_webhook().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    replay(&app, SPAM).await;
+
+    // Assert
+    assert_eq!(
+        subscriber_status(&app).await,
+        SubscriptionStatus::Complained
+    );
+    assert_eq!(
+        suppression_reason(&app, "ursula_le_guin@gmail.com")
+            .await
+            .as_deref(),
+        Some("complaint")
+    );
+}
+
+#[tokio::test]
+async fn unsubscribes_through_brevo_unsubscribe_without_suppressing() {
+    // Arrange
+    let app = setup_with_webhook().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    replay(&app, UNSUBSCRIBED).await;
+
+    // Assert
+    assert_eq!(
+        subscriber_status(&app).await,
+        SubscriptionStatus::Unsubscribed
+    );
+    let unsubscribed_at = sqlx::query!("SELECT unsubscribed_at FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .unsubscribed_at;
+    assert!(unsubscribed_at.is_some());
+    assert_eq!(
+        suppression_reason(&app, "ursula_le_guin@gmail.com").await,
+        None
+    );
+}
+
+#[tokio::test]
+async fn batched_events_are_applied_one_by_one() {
+    // Arrange
+    let app = setup_with_webhook().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let response = replay(&app, BATCH).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    // A soft bounce is recorded but leaves the subscriber alone
+    assert_eq!(subscriber_status(&app).await, SubscriptionStatus::Confirmed);
+    // Addresses we don't know are suppressed all the same
+    assert_eq!(
+        suppression_reason(&app, "terry@example.com")
+            .await
+            .as_deref(),
+        Some("blocked")
+    );
+
+    let kinds: Vec<String> = sqlx::query!("SELECT kind FROM delivery_feedback ORDER BY kind")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|row| row.kind)
+        .collect();
+    assert_eq!(kinds, ["blocked", "soft_bounce"]);
+}
+
+#[tokio::test]
+async fn requests_without_the_secret_are_rejected() {
+    // Arrange
+    let app = setup_with_webhook().await;
+    create_confirmed_subscriber(&app).await;
+
+    for secret in [None, Some("guessed")] {
+        let mut request = app
+            .client
+            .post(format!("{}/webhooks/brevo", app.address))
+            .header("Content-Type", "application/json")
+            .body(HARD_BOUNCE);
+        if let Some(secret) = secret {
+            request = request.basic_auth("brevo", Some(secret));
+        }
+
+        // Act
+        let response = request.send().await.unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401, "{:?}", secret);
+    }
+    assert_eq!(subscriber_status(&app).await, SubscriptionStatus::Confirmed);
+}
+
+#[tokio::test]
+async fn requests_from_outside_the_allowed_ranges_are_rejected() {
+    for (range, expected) in [("10.0.0.0/8", 401), ("127.0.0.0/8", 200)] {
+        // Arrange
+        let app = setup_with(|config| {
+            config.webhooks.brevo = Some(BrevoWebhookSettings {
+                secret: None,
+                allowed_ips: vec![range.parse().unwrap()],
+                trust_forwarded_for: false,
+            })
+        })
+        .await;
+
+        // Act
+        let response = app
+            .client
+            .post(format!("{}/webhooks/brevo", app.address))
+            .header("Content-Type", "application/json")
+            .body(HARD_BOUNCE)
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), expected, "{}", range);
+    }
+}
+
+#[tokio::test]
+async fn the_webhook_is_disabled_until_it_is_configured() {
+    // Arrange
+    let app = setup().await;
+
+    // Act
+    let response = replay(&app, HARD_BOUNCE).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
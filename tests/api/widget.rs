@@ -0,0 +1,208 @@
+//! tests/api/widget.rs
+
+use crate::helpers::{assert_is_redirect_to, setup, Test, ALLOWED_ORIGIN};
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+async fn preflight(app: &Test, origin: &str) -> reqwest::Response {
+    app.client
+        .request(
+            reqwest::Method::OPTIONS,
+            format!("{}/api/v1/subscriptions", app.address),
+        )
+        .header("Origin", origin)
+        .header("Access-Control-Request-Method", "POST")
+        .header("Access-Control-Request-Headers", "content-type")
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn customise_default_list(app: &Test, settings: &[(&str, &str)]) {
+    app.login(&app.user.username, &app.user.password).await;
+    let list_id = sqlx::query!("SELECT list_id FROM lists WHERE slug = 'default'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .list_id;
+
+    let mut form = vec![("name", "Newsletter")];
+    form.extend_from_slice(settings);
+    let edit_page = format!("/admin/lists/{}", list_id);
+    let response = app.post_form(&edit_page, &form).await;
+    assert_is_redirect_to(&response, &edit_page);
+}
+
+#[tokio::test]
+async fn preflight_requests_are_only_answered_for_allowed_origins() {
+    // Arrange
+    let app = setup().await;
+
+    // Act
+    let allowed = preflight(&app, ALLOWED_ORIGIN).await;
+    let other = preflight(&app, "https://elsewhere.example.com").await;
+
+    // Assert
+    assert!(allowed.status().is_success());
+    assert_eq!(
+        allowed
+            .headers()
+            .get("Access-Control-Allow-Origin")
+            .unwrap(),
+        ALLOWED_ORIGIN
+    );
+    assert!(other.headers().get("Access-Control-Allow-Origin").is_none());
+}
+
+#[tokio::test]
+async fn cross_origin_json_signups_can_read_the_response() {
+    // Arrange
+    let app = setup().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .client
+        .post(format!("{}/api/v1/subscriptions", app.address))
+        .header("Origin", ALLOWED_ORIGIN)
+        .json(&serde_json::json!({ "name": "le guin", "email": "ursula_le_guin@gmail.com" }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response
+            .headers()
+            .get("Access-Control-Allow-Origin")
+            .unwrap(),
+        ALLOWED_ORIGIN
+    );
+}
+
+#[tokio::test]
+async fn the_script_embeds_the_hosted_form() {
+    // Arrange
+    let app = setup().await;
+
+    // Act
+    let response = app.get("/widget.js").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .headers()
+        .get("Content-Type")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with("application/javascript"));
+    let script = response.text().await.unwrap();
+    // The base URL is written as a string literal
+    assert!(script.contains(r#"frame.src = "http"#));
+    assert!(script.contains(r#"" + "/widget/" + "#));
+}
+
+#[tokio::test]
+async fn the_hosted_form_uses_the_list_copy() {
+    // Arrange
+    let app = setup().await;
+    customise_default_list(
+        &app,
+        &[
+            ("widget_heading", "Get the weekly letter"),
+            ("widget_button_label", "Count me in"),
+            ("widget_success_message", "Almost there, check your inbox."),
+        ],
+    )
+    .await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Show the form
+    let response = app.get("/widget/default").await;
+
+    // Assert - Part 1
+    let frame_ancestors = response
+        .headers()
+        .get("Content-Security-Policy")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert!(frame_ancestors.contains(ALLOWED_ORIGIN));
+    let page = response.text().await.unwrap();
+    assert!(page.contains("Get the weekly letter"));
+    assert!(page.contains("Count me in"));
+
+    // Act - Part 2 - Sign up
+    let form_stamp = app.get_form_stamp("/widget/default").await;
+    let response = app
+        .post_form(
+            "/widget/default",
+            &[
+                ("name", "le guin"),
+                ("email", "ursula_le_guin@gmail.com"),
+                ("website", ""),
+                ("form_stamp", &form_stamp),
+            ],
+        )
+        .await;
+
+    // Assert - Part 2
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Almost there, check your inbox."));
+}
+
+#[tokio::test]
+async fn the_hosted_form_shows_what_is_wrong() {
+    // Arrange
+    let app = setup().await;
+
+    // Act
+    let response = app
+        .post_form("/widget/default", &[("name", "le guin"), ("email", "nope")])
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let page = response.text().await.unwrap();
+    assert!(page.contains(r#"value="le guin""#));
+}
+
+#[tokio::test]
+async fn plain_forms_are_redirected_when_the_list_has_a_redirect() {
+    // Arrange
+    let app = setup().await;
+    customise_default_list(
+        &app,
+        &[("redirect_url", "https://marketing.example.com/thanks")],
+    )
+    .await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "https://marketing.example.com/thanks");
+}